@@ -0,0 +1,35 @@
+//! Print the current net-flow, then every event as it arrives.
+//!
+//! ```sh
+//! cargo run -p pol-indexer-client --example follow -- http://127.0.0.1:8080
+//! ```
+//!
+//! Set `API_KEY` when the server requires one. Try it against `pol-indexer demo`.
+
+use pol_indexer_client::{Client, Event};
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let base_url = std::env::args().nth(1).unwrap_or_else(|| "http://127.0.0.1:8080".into());
+    let mut client = Client::new(&base_url);
+    if let Ok(key) = std::env::var("API_KEY") {
+        client = client.with_api_key(key);
+    }
+
+    let netflow = client.netflow().await?;
+    println!("block {}: cumulative {} ({} tokens)", netflow.block_number, netflow.cumulative_netflow_raw, netflow.cumulative_token);
+    for t in client.latest_transfers(None, Some(5)).await?.iter().rev() {
+        println!("  {} {:?} {} -> {}: {}", t.block_number, t.direction, t.from, t.to, t.value);
+    }
+
+    let mut events = client.subscribe().await?;
+    while let Some(event) = events.next().await? {
+        match event {
+            Event::Transfer(t) => println!("transfer   {} {:?} {}", t.block_number, t.direction, t.value),
+            Event::Netflow(b) => println!("netflow    {} in {} out {} cumulative {}", b.block_number, b.inflow, b.outflow, b.cumulative),
+            Event::ProvisionalNetflow(p) => println!("provisional {} cumulative {}", p.block_number, p.cumulative),
+        }
+    }
+    println!("the server closed the stream");
+    Ok(())
+}
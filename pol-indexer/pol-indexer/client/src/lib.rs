@@ -0,0 +1,261 @@
+//! Typed client for the pol-indexer HTTP API.
+//!
+//! ```no_run
+//! # async fn demo() -> eyre::Result<()> {
+//! let client = pol_indexer_client::Client::new("http://127.0.0.1:8080");
+//! println!("cumulative {}", client.netflow().await?.cumulative_netflow_raw);
+//! let mut events = client.subscribe().await?;
+//! while let Some(event) = events.next().await? {
+//!     println!("{event:?}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The types mirror what the server serializes; the server's tests decode
+//! its own responses with them, so the two can't drift apart unnoticed.
+//! Events missed while no subscription is open are replayed on request:
+//! reopen with [`Client::subscribe_since`] and the subscription's
+//! [`last_seq`](Subscription::last_seq).
+
+use eyre::{eyre, Result, WrapErr};
+use serde::{de::DeserializeOwned, Deserialize};
+
+mod types;
+
+pub use types::*;
+
+/// Envelope version this client reads (`v` in every event).
+pub const EVENT_VERSION: u32 = 2;
+
+/// Something the indexer published on `GET /events`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A matched transfer was stored
+    Transfer(Transfer),
+    /// A block with matched transfers moved the aggregates
+    Netflow(NetflowBlock),
+    /// A new block's logs have matched transfers; not yet confirmed or stored
+    ProvisionalNetflow(ProvisionalNetflow),
+}
+
+#[derive(Deserialize)]
+struct RawEnvelope {
+    v: u32,
+    #[serde(rename = "type")]
+    kind: String,
+    data: serde_json::Value,
+}
+
+impl Event {
+    /// Parse an event envelope (`{"v":2,"type":"transfer","data":{…}}`).
+    /// `None` for event types newer than this client; an error for an
+    /// envelope version it doesn't read.
+    pub fn decode(json: &str) -> Result<Option<Event>> {
+        let env: RawEnvelope = serde_json::from_str(json).wrap_err("event envelope")?;
+        if env.v != EVENT_VERSION {
+            return Err(eyre!("unsupported event version {} (this client reads v{EVENT_VERSION})", env.v));
+        }
+        let event = match env.kind.as_str() {
+            "transfer" => Event::Transfer(serde_json::from_value(env.data)?),
+            "netflow" => Event::Netflow(serde_json::from_value(env.data)?),
+            "provisional_netflow" => Event::ProvisionalNetflow(serde_json::from_value(env.data)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+/// Server-side filter of a subscription. `types` applies to every event;
+/// the rest narrow transfers only. Lists are comma-separated.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// `transfer`, `netflow`, `provisional_netflow`
+    pub types: Option<String>,
+    /// Smallest transfer value, in raw units
+    pub min_value: Option<String>,
+    /// `in`, `out`, `internal`, `none`
+    pub direction: Option<String>,
+    /// Either side of the transfer
+    pub address: Option<String>,
+    /// Token contract
+    pub token: Option<String>,
+}
+
+impl EventFilter {
+    fn query(&self) -> Vec<(&'static str, String)> {
+        [("types", &self.types), ("min_value", &self.min_value), ("direction", &self.direction), ("address", &self.address), ("token", &self.token)]
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value.clone()?)))
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// `base_url` is where the API listens (`HTTP_BIND`), e.g. `http://127.0.0.1:8080`.
+    pub fn new(base_url: &str) -> Self {
+        Self { http: reqwest::Client::new(), base_url: base_url.trim_end_matches('/').to_string(), api_key: None }
+    }
+
+    /// Send `key` on every request, for servers with `REQUIRE_API_KEY`.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        let req = self.http.get(format!("{}{path}", self.base_url));
+        match &self.api_key {
+            Some(key) => req.header("x-api-key", key),
+            None => req,
+        }
+    }
+
+    async fn send(&self, path: &str, query: &[(&str, String)]) -> Result<reqwest::Response> {
+        let res = self.get(path).query(query).send().await.wrap_err_with(|| format!("GET {path}"))?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(eyre!("GET {path}: {status}: {}", body.trim()));
+        }
+        Ok(res)
+    }
+
+    async fn json<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        self.send(path, query).await?.json().await.wrap_err_with(|| format!("GET {path}: decoding the response"))
+    }
+
+    /// `GET /netflow`
+    pub async fn netflow(&self) -> Result<NetflowSnapshot> {
+        self.json("/netflow", &[]).await
+    }
+
+    /// `GET /transfers`: the newest stored transfers, up to `limit` (server default 100).
+    pub async fn transfers(&self, limit: Option<u32>) -> Result<Vec<Transfer>> {
+        let query: Vec<_> = limit.map(|l| ("limit", l.to_string())).into_iter().collect();
+        self.json("/transfers", &query).await
+    }
+
+    /// `GET /transfers/latest`: the newest transfers, optionally only those involving `address`.
+    pub async fn latest_transfers(&self, address: Option<&str>, limit: Option<u32>) -> Result<Vec<Transfer>> {
+        let query: Vec<_> = address.map(|a| ("address", a.to_string()))
+            .into_iter()
+            .chain(limit.map(|l| ("limit", l.to_string())))
+            .collect();
+        self.json("/transfers/latest", &query).await
+    }
+
+    /// Open `GET /events`.
+    pub async fn subscribe(&self) -> Result<Subscription> {
+        self.subscribe_filtered(&EventFilter::default()).await
+    }
+
+    /// Open `GET /events`, receiving only the events `filter` lets through.
+    pub async fn subscribe_filtered(&self, filter: &EventFilter) -> Result<Subscription> {
+        Ok(Subscription { res: self.send("/events", &filter.query()).await?, parser: Parser::default() })
+    }
+
+    /// Open `GET /events?since_seq=`: every logged event after `since_seq`,
+    /// then live ones. Nothing in between is lost.
+    pub async fn subscribe_since(&self, filter: &EventFilter, since_seq: u64) -> Result<Subscription> {
+        let mut query = filter.query();
+        query.push(("since_seq", since_seq.to_string()));
+        let parser = Parser { last_seq: Some(since_seq), ..Default::default() };
+        Ok(Subscription { res: self.send("/events", &query).await?, parser })
+    }
+}
+
+/// An open `GET /events` stream.
+pub struct Subscription {
+    res: reqwest::Response,
+    parser: Parser,
+}
+
+impl Subscription {
+    /// The next event, waiting for it; `None` once the server closes the stream.
+    pub async fn next(&mut self) -> Result<Option<Event>> {
+        loop {
+            if let Some(event) = self.parser.next_event()? {
+                return Ok(Some(event));
+            }
+            match self.res.chunk().await.wrap_err("reading /events")? {
+                Some(chunk) => self.parser.push(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// `seq` of the last logged event received (or the one resumed from);
+    /// pass it to [`Client::subscribe_since`] after a reconnect.
+    pub fn last_seq(&self) -> Option<u64> {
+        self.parser.last_seq
+    }
+}
+
+/// Splits the SSE byte stream into events; chunks may end anywhere.
+#[derive(Default)]
+struct Parser {
+    buf: Vec<u8>,
+    /// From the SSE `id:` lines
+    last_seq: Option<u64>,
+}
+
+impl Parser {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buf.extend(chunk.iter().filter(|&&b| b != b'\r'));
+    }
+
+    /// The next complete event buffered, skipping keep-alives and event types this client doesn't know.
+    fn next_event(&mut self) -> Result<Option<Event>> {
+        while let Some(end) = self.buf.windows(2).position(|w| w == b"\n\n") {
+            let block: Vec<u8> = self.buf.drain(..end + 2).collect();
+            let text = std::str::from_utf8(&block)?;
+            let field = |name: &'static str| text.lines()
+                .filter_map(move |line| line.strip_prefix(name))
+                .map(|d| d.strip_prefix(' ').unwrap_or(d));
+            if let Some(seq) = field("id:").next_back().and_then(|id| id.parse().ok()) {
+                self.last_seq = Some(seq);
+            }
+            let data: Vec<&str> = field("data:").collect();
+            if data.is_empty() {
+                continue;
+            }
+            if let Some(event) = Event::decode(&data.join("\n"))? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_reassembles_events_across_chunks() {
+        let netflow = r#"{"v":2,"type":"netflow","data":{"block_number":7,"ts_unix":1,"inflow":"5","outflow":"0","cumulative":"5","transfer_count":1,"price_usd_micro":null,"cumulative_usd_micro":null}}"#;
+        let stream = format!(
+            ": keep-alive\n\nevent: reorg\ndata: {{\"v\":2,\"type\":\"reorg\",\"data\":{{}}}}\n\nevent: netflow\r\nid: 12\r\ndata: {netflow}\r\n\r\n"
+        );
+        let mut parser = Parser::default();
+        let (head, tail) = stream.as_bytes().split_at(stream.len() - 40);
+        parser.push(head);
+        assert_eq!(parser.next_event().unwrap(), None);
+        parser.push(tail);
+        let Some(Event::Netflow(block)) = parser.next_event().unwrap() else { panic!("expected a netflow event") };
+        assert_eq!((block.block_number, block.cumulative.as_str(), block.cumulative_token.as_str()), (7, "5", ""));
+        assert_eq!(parser.last_seq, Some(12));
+        assert_eq!(parser.next_event().unwrap(), None);
+
+        parser.push(b"data: {\"v\":3,\"type\":\"netflow\",\"data\":{}}\n\n");
+        assert!(parser.next_event().is_err());
+    }
+}
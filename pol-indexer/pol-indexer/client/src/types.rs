@@ -0,0 +1,138 @@
+//! Response and event payloads, field for field as the server sends them.
+//!
+//! Amounts are strings in the `?format=` the client asked for (raw decimal
+//! units by default); nets are `-`-prefixed when negative.
+
+use serde::{Deserialize, Serialize};
+
+/// `GET /netflow`: the latest cumulative net-flow and what's derived from it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetflowSnapshot {
+    pub block_number: u64,
+    pub cumulative_netflow_raw: String,
+    /// The same in whole tokens
+    pub cumulative_token: String,
+    /// Changes of the cumulative valued at each block's price, in USD; `None` without a price feed
+    pub cumulative_usd: Option<String>,
+    /// First block with a recorded price; the USD cumulative covers changes from there on
+    pub usd_since_block: Option<u64>,
+    pub updated_at_unix: i64,
+    /// Accounting epoch: the cumulative is the net-flow since this block
+    pub start_block: Option<u64>,
+    pub velocity: Velocity,
+    /// The cumulative as a share of total supply; `None` until the indexer has read the supply
+    pub supply: Option<SupplyShare>,
+}
+
+/// Net-flow over a sliding window ending at `to_ts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Velocity {
+    pub window_secs: i64,
+    pub to_ts: i64,
+    pub net: String,
+    /// `net` per hour
+    pub per_hour: String,
+    /// Change in `per_hour` versus the previous window, per hour
+    pub acceleration_per_hour: String,
+    /// Transfers above this were left out (`VELOCITY_VALUE_CAP`)
+    #[serde(default)]
+    pub value_cap: Option<String>,
+    /// Transfers above `value_cap` in the current window
+    #[serde(default)]
+    pub excluded_transfers: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SupplyShare {
+    pub total_supply: String,
+    /// Block the supply was read at
+    pub block_number: u64,
+    /// The cumulative as a percentage of `total_supply`
+    pub pct: Option<f64>,
+}
+
+/// Which way a transfer moved relative to the tracked addresses. Only `In`
+/// and `Out` count towards the net-flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+    Internal,
+    None,
+}
+
+/// Receipt status of the transaction that emitted a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    Success,
+    Reverted,
+}
+
+/// A stored transfer (`GET /transfers`, `transfer` events). Addresses are
+/// EIP-55 checksummed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transfer {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub log_index: u64,
+    pub token: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub direction: Direction,
+    /// `None` when the receipt status wasn't checked (e.g. imported rows)
+    pub status: Option<TxStatus>,
+    /// Accounting-rule bucket, for transfers kept out of the net-flow that way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// What the other side of an inflow or outflow is, when the server tags contracts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty_kind: Option<CounterpartyKind>,
+    /// Known contract the counterparty's bytecode matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty_label: Option<String>,
+}
+
+/// Whether a transfer's counterparty had code when first seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CounterpartyKind {
+    Eoa,
+    Contract,
+}
+
+/// Aggregates of a block with matched transfers (`netflow` events).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetflowBlock {
+    pub block_number: u64,
+    pub ts_unix: i64,
+    pub inflow: String,
+    pub outflow: String,
+    pub cumulative: String,
+    pub transfer_count: u64,
+    /// `cumulative` in whole tokens
+    #[serde(default)]
+    pub cumulative_token: String,
+    /// Token price at this block, micro-USD
+    pub price_usd_micro: Option<i64>,
+    /// USD cumulative after this block, micro-USD
+    pub cumulative_usd_micro: Option<i64>,
+}
+
+/// Net-flow of a new block published before it is confirmed and stored
+/// (`provisional_netflow` events, with `FAST_PATH`). A `netflow` event for
+/// the same block follows unless the block fails or is orphaned.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvisionalNetflow {
+    pub block_number: u64,
+    pub block_hash: Option<String>,
+    pub inflow: String,
+    pub outflow: String,
+    /// The stored cumulative with this block's flows applied
+    pub cumulative: String,
+    pub transfer_count: u64,
+    /// When the indexer had the block's logs, Unix milliseconds
+    pub received_at_ms: i64,
+}
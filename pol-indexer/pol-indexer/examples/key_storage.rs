@@ -0,0 +1,267 @@
+//! Benchmark behind the README's note on `TEXT` keys in `erc20_transfers`:
+//! the same table with addresses and hashes as lowercase hex `TEXT` (as
+//! stored) and as raw `BLOB`s, written through the statements
+//! `db::insert_transfer` runs and read back as the API renders them.
+//!
+//! ```text
+//! cargo run --release --example key_storage -- [transfers, default 1000000]
+//! ```
+//!
+//! Reports insert rate, heap allocations per transfer, file size, the time to
+//! render the newest 100k rows as hex, and an address lookup. Each variant
+//! runs twice; the databases go to the temp directory and are removed.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Counts every heap allocation, to compare allocations per stored transfer.
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Transfers per block, and per transaction, as a busy hot wallet sees them
+const PER_BLOCK: u64 = 20;
+const READ_ROWS: u32 = 100_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Keys {
+    Text,
+    Blob,
+}
+
+impl Keys {
+    fn column_type(self) -> &'static str {
+        match self {
+            Keys::Text => "TEXT",
+            Keys::Blob => "BLOB",
+        }
+    }
+}
+
+/// Deterministic pseudo-random bytes (xorshift), so both variants store the same keys.
+fn bytes<const N: usize>(seed: u64) -> [u8; N] {
+    let mut x = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut out = [0u8; N];
+    for b in &mut out {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        *b = x as u8;
+    }
+    out
+}
+
+/// `0x`-prefixed lowercase hex on the stack, like `models::KeyStr`.
+struct Hex<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Hex<N> {
+    fn new(bytes: &[u8]) -> Self {
+        let mut out = Self { buf: [0; N], len: 0 };
+        out.write_str("0x").expect("fits");
+        for b in bytes {
+            write!(out, "{b:02x}").expect("fits");
+        }
+        out
+    }
+
+    fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.buf[..self.len]).expect("hex is ASCII")
+    }
+}
+
+impl<const N: usize> std::fmt::Write for Hex<N> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let end = self.len + s.len();
+        self.buf.get_mut(self.len..end).ok_or(std::fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// `erc20_transfers` and its indexes as in `db::SCHEMA_SQL`, keys typed `keys`.
+fn schema(keys: Keys) -> String {
+    let ty = keys.column_type();
+    let empty = if keys == Keys::Text { "''" } else { "x''" };
+    format!(
+        "CREATE TABLE erc20_transfers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, block_number INTEGER NOT NULL, tx_hash {ty} NOT NULL,
+            log_index INTEGER NOT NULL, token {ty} NOT NULL, sender {ty} NOT NULL, recipient {ty} NOT NULL,
+            value TEXT NOT NULL, direction TEXT NOT NULL, status INTEGER, block_hash {ty} NOT NULL DEFAULT {empty},
+            chain_id INTEGER NOT NULL DEFAULT 0, bucket TEXT, UNIQUE(tx_hash, log_index, block_hash, chain_id)
+        );
+        CREATE INDEX idx_transfers_block ON erc20_transfers(block_number, log_index);
+        CREATE INDEX idx_transfers_sender ON erc20_transfers(sender);
+        CREATE INDEX idx_transfers_recipient ON erc20_transfers(recipient);"
+    )
+}
+
+struct Run {
+    insert: Duration,
+    allocations_per_row: f64,
+    file_mib: f64,
+    read: Duration,
+    lookup: Duration,
+}
+
+fn run(keys: Keys, transfers: u64) -> rusqlite::Result<Run> {
+    let path = std::env::temp_dir().join(format!("pol-indexer-key-storage-{}.sqlite", keys.column_type()));
+    let remove = || {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    };
+    remove();
+    let mut conn = Connection::open(&path)?;
+    conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL;")?;
+    conn.execute_batch(&schema(keys))?;
+
+    let (known_sql, claim_sql) = match keys {
+        Keys::Text => (
+            "SELECT 1 FROM erc20_transfers WHERE tx_hash=?1 AND log_index=?2 AND (block_hash=?3 OR (?3='' AND block_number=?4))",
+            "UPDATE erc20_transfers SET block_hash=?3 WHERE tx_hash=?1 AND log_index=?2 AND block_hash='' AND block_number=?4",
+        ),
+        Keys::Blob => (
+            "SELECT 1 FROM erc20_transfers WHERE tx_hash=?1 AND log_index=?2 AND (block_hash=?3 OR (?3=x'' AND block_number=?4))",
+            "UPDATE erc20_transfers SET block_hash=?3 WHERE tx_hash=?1 AND log_index=?2 AND block_hash=x'' AND block_number=?4",
+        ),
+    };
+    let insert_sql = "INSERT OR IGNORE INTO erc20_transfers (block_number, tx_hash, log_index, token, sender, recipient, value, direction, status, block_hash)
+                      VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)";
+
+    let token: [u8; 20] = bytes(7);
+    let hot_wallet: [u8; 20] = bytes(8);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for block in 0..transfers / PER_BLOCK {
+        let tx = conn.transaction()?;
+        let block_hash: [u8; 32] = bytes(1_000_000_000 + block);
+        for log_index in 0..PER_BLOCK {
+            let n = block * PER_BLOCK + log_index;
+            let tx_hash: [u8; 32] = bytes(n);
+            let other: [u8; 20] = bytes(2_000_000_000 + n % 10_000);
+            let (from, to) = if n.is_multiple_of(2) { (other, hot_wallet) } else { (hot_wallet, other) };
+            let mut value = Hex::<80> { buf: [0; 80], len: 0 };
+            write!(value, "{}", 1_000_000_000_000_000_000_u128 + n as u128).expect("fits");
+
+            let (block_number, log_index) = (block as i64, log_index as i64);
+            match keys {
+                Keys::Text => {
+                    let (tx_hash, block_hash) = (Hex::<66>::new(&tx_hash), Hex::<66>::new(&block_hash));
+                    let (token, from, to) = (Hex::<42>::new(&token), Hex::<42>::new(&from), Hex::<42>::new(&to));
+                    let key = params![tx_hash.as_str(), log_index, block_hash.as_str(), block_number];
+                    let known = tx.prepare_cached(known_sql)?.query_row(key, |_| Ok(())).optional()?;
+                    assert!(known.is_none() && tx.prepare_cached(claim_sql)?.execute(key)? == 0);
+                    tx.prepare_cached(insert_sql)?.execute(params![
+                        block_number, tx_hash.as_str(), log_index, token.as_str(), from.as_str(), to.as_str(),
+                        value.as_str(), "in", 1, block_hash.as_str(),
+                    ])?;
+                }
+                Keys::Blob => {
+                    let key = params![&tx_hash[..], log_index, &block_hash[..], block_number];
+                    let known = tx.prepare_cached(known_sql)?.query_row(key, |_| Ok(())).optional()?;
+                    assert!(known.is_none() && tx.prepare_cached(claim_sql)?.execute(key)? == 0);
+                    tx.prepare_cached(insert_sql)?.execute(params![
+                        block_number, &tx_hash[..], log_index, &token[..], &from[..], &to[..],
+                        value.as_str(), "in", 1, &block_hash[..],
+                    ])?;
+                }
+            }
+        }
+        tx.commit()?;
+    }
+    let insert = started.elapsed();
+    let allocations_per_row = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / transfers as f64;
+    conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+    let file_mib = std::fs::metadata(&path).map(|m| m.len()).unwrap_or_default() as f64 / (1024.0 * 1024.0);
+
+    // The API and exports render keys as hex text
+    let started = Instant::now();
+    let mut stmt = conn.prepare(
+        "SELECT tx_hash, sender, recipient, block_hash FROM erc20_transfers ORDER BY block_number DESC, log_index DESC LIMIT ?1",
+    )?;
+    let mut rows = stmt.query([READ_ROWS])?;
+    let mut rendered = 0;
+    while let Some(row) = rows.next()? {
+        for column in 0..4 {
+            let text: String = match keys {
+                Keys::Text => row.get(column)?,
+                Keys::Blob => {
+                    let raw: Vec<u8> = row.get(column)?;
+                    let mut text = String::with_capacity(2 + 2 * raw.len());
+                    text.push_str("0x");
+                    for b in &raw {
+                        write!(text, "{b:02x}").expect("writing to a String");
+                    }
+                    text
+                }
+            };
+            rendered += text.len();
+        }
+    }
+    let read = started.elapsed();
+    assert!(rendered > 0);
+    drop(rows);
+    drop(stmt);
+
+    // `GET /address/:addr/transfers`
+    let started = Instant::now();
+    let probe: [u8; 20] = bytes(2_000_001_234);
+    let lookup_sql = "SELECT COUNT(*) FROM erc20_transfers WHERE sender=?1 OR recipient=?1";
+    let _: i64 = match keys {
+        Keys::Text => conn.query_row(lookup_sql, [Hex::<42>::new(&probe).as_str()], |r| r.get(0))?,
+        Keys::Blob => conn.query_row(lookup_sql, [&probe[..]], |r| r.get(0))?,
+    };
+    let lookup = started.elapsed();
+
+    drop(conn);
+    remove();
+    Ok(Run { insert, allocations_per_row, file_mib, read, lookup })
+}
+
+fn main() -> rusqlite::Result<()> {
+    let transfers: u64 = match std::env::args().nth(1) {
+        Some(n) => n.parse().expect("usage: key_storage [transfers]"),
+        None => 1_000_000,
+    };
+    let transfers = transfers.max(PER_BLOCK) / PER_BLOCK * PER_BLOCK;
+    println!("{transfers} transfers, {PER_BLOCK} per transaction; read renders the newest {READ_ROWS}");
+    println!("{:<5} {:>12} {:>11} {:>10} {:>10} {:>11}", "keys", "inserts/s", "allocs/row", "file MiB", "read ms", "lookup ms");
+    for _ in 0..2 {
+        for keys in [Keys::Text, Keys::Blob] {
+            let r = run(keys, transfers)?;
+            println!(
+                "{:<5} {:>12.0} {:>11.2} {:>10.1} {:>10.1} {:>11.2}",
+                keys.column_type(),
+                transfers as f64 / r.insert.as_secs_f64(),
+                r.allocations_per_row,
+                r.file_mib,
+                r.read.as_secs_f64() * 1e3,
+                r.lookup.as_secs_f64() * 1e3,
+            );
+        }
+    }
+    Ok(())
+}
@@ -13,16 +13,152 @@ CREATE TABLE IF NOT EXISTS erc20_transfers (
     sender TEXT NOT NULL,
     recipient TEXT NOT NULL,
     value TEXT NOT NULL,
-    is_binance_in BOOLEAN NOT NULL,
-    is_binance_out BOOLEAN NOT NULL,
-    UNIQUE(tx_hash, log_index)
+    direction TEXT NOT NULL CHECK (direction IN ('in', 'out', 'internal', 'none')),
+    status INTEGER,
+    block_hash TEXT NOT NULL DEFAULT '',
+    UNIQUE(tx_hash, log_index, block_hash)
 );
+CREATE INDEX IF NOT EXISTS idx_transfers_block ON erc20_transfers(block_number, log_index);
+CREATE INDEX IF NOT EXISTS idx_transfers_sender ON erc20_transfers(sender);
+CREATE INDEX IF NOT EXISTS idx_transfers_recipient ON erc20_transfers(recipient);
 CREATE TABLE IF NOT EXISTS cumulative_netflow (
     id INTEGER PRIMARY KEY CHECK (id = 1),
     block_number INTEGER NOT NULL,
     value TEXT NOT NULL,
-    updated_at_unix INTEGER NOT NULL
+    updated_at_unix INTEGER NOT NULL,
+    value_token TEXT,
+    value_usd_micro INTEGER
 );
+CREATE TABLE IF NOT EXISTS netflow_blocks (
+    block_number INTEGER PRIMARY KEY,
+    ts_unix INTEGER NOT NULL,
+    inflow TEXT NOT NULL,
+    outflow TEXT NOT NULL,
+    cumulative TEXT NOT NULL,
+    transfer_count INTEGER NOT NULL,
+    cumulative_token TEXT,
+    price_usd_micro INTEGER,
+    cumulative_usd_micro INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_netflow_blocks_ts ON netflow_blocks(ts_unix);
+
+CREATE TABLE IF NOT EXISTS volume_blocks (
+    block_number INTEGER PRIMARY KEY,
+    ts_unix INTEGER NOT NULL,
+    volume TEXT NOT NULL,
+    transfer_count INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_volume_blocks_ts ON volume_blocks(ts_unix);
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    exchanges TEXT NOT NULL,
+    tokens TEXT NOT NULL,
+    created_at_unix INTEGER NOT NULL,
+    revoked_at_unix INTEGER
+);
+CREATE TABLE IF NOT EXISTS token_implementations (
+    token TEXT NOT NULL,
+    from_block INTEGER NOT NULL,
+    implementation TEXT NOT NULL,
+    observed_at_unix INTEGER NOT NULL,
+    PRIMARY KEY (token, from_block)
+);
+CREATE TABLE IF NOT EXISTS deposit_addresses (
+    address TEXT PRIMARY KEY,
+    hot_wallet TEXT NOT NULL,
+    first_seen_block INTEGER NOT NULL,
+    sweep_tx_hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS reorgs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    detected_at_unix INTEGER NOT NULL,
+    new_head INTEGER NOT NULL,
+    new_head_hash TEXT NOT NULL,
+    fork_block INTEGER NOT NULL,
+    depth INTEGER NOT NULL,
+    orphaned_hashes TEXT NOT NULL,
+    removed_transfers TEXT NOT NULL,
+    removed_inflow TEXT NOT NULL,
+    removed_outflow TEXT NOT NULL,
+    cumulative_before TEXT NOT NULL,
+    cumulative_after TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS native_transfers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    block_number INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL UNIQUE,
+    sender TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    value TEXT NOT NULL,
+    direction TEXT NOT NULL CHECK (direction IN ('in', 'out', 'internal', 'none')),
+    status INTEGER
+);
+CREATE TABLE IF NOT EXISTS native_netflow_blocks (
+    block_number INTEGER PRIMARY KEY,
+    ts_unix INTEGER NOT NULL,
+    inflow TEXT NOT NULL,
+    outflow TEXT NOT NULL,
+    cumulative_inflow TEXT NOT NULL,
+    cumulative_outflow TEXT NOT NULL,
+    transfer_count INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS failed_blocks (
+    block_number INTEGER PRIMARY KEY,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    first_failed_at_unix INTEGER NOT NULL,
+    last_failed_at_unix INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at_unix INTEGER NOT NULL,
+    ended_at_unix INTEGER NOT NULL,
+    exit_reason TEXT NOT NULL,
+    blocks_processed INTEGER NOT NULL,
+    transfers_indexed INTEGER NOT NULL,
+    reorgs INTEGER NOT NULL,
+    first_block INTEGER,
+    last_block INTEGER,
+    first_block_ts_unix INTEGER,
+    last_block_ts_unix INTEGER,
+    final_cumulative TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tx_hash TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    note TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    created_at_unix INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_annotations_transfer ON annotations(tx_hash, log_index);
+CREATE TABLE IF NOT EXISTS alert_queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sink TEXT NOT NULL,
+    alert TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at_unix INTEGER NOT NULL,
+    last_error TEXT,
+    created_at_unix INTEGER NOT NULL,
+    dead_at_unix INTEGER
+);
+CREATE INDEX IF NOT EXISTS idx_alert_queue_due ON alert_queue(dead_at_unix, next_attempt_at_unix);
+CREATE TABLE IF NOT EXISTS raw_logs (
+    block_number INTEGER PRIMARY KEY,
+    block_hash TEXT NOT NULL,
+    ts_unix INTEGER NOT NULL,
+    log_count INTEGER NOT NULL,
+    logs BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS entities (
+    address TEXT PRIMARY KEY,
+    entity TEXT NOT NULL,
+    added_at_unix INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_entities_entity ON entities(entity);
+
 CREATE TABLE IF NOT EXISTS state (
     key TEXT PRIMARY KEY,
     value TEXT NOT NULL
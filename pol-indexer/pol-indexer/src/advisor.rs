@@ -0,0 +1,295 @@
+//! `db analyze`: query plans of the API's reads against a real database, for
+//! tuning large deployments.
+//!
+//! Each canned query is the `db` call behind a route, run with sample
+//! arguments from the data itself: the last day of blocks, a recent transfer
+//! and its sender. SQLite's profile hook records every statement the call
+//! runs and how long it took, so the list can't drift from the SQL the API
+//! uses. Each statement then gets `EXPLAIN QUERY PLAN`. A full scan of a
+//! table with at least `min_rows` rows is a hotspot. So is a sort through a
+//! temporary B-tree over such a table, which is fine over a small window and
+//! slow over a large one. A scan under a `LIMIT` that needs no sort reads rows in index order
+//! and stops early, so it is not flagged. Full scans come with a suggested
+//! index on the columns the statement filters on, then orders by. This is a
+//! starting point, not a guarantee, and a filter with `OR` gets no
+//! suggestion.
+
+use std::{sync::Mutex, time::Duration};
+
+use eyre::Result;
+use once_cell::sync::Lazy;
+use rusqlite::Connection;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{auth, db, velocity};
+
+/// Statements run since the last [`take`], with their run time.
+static PROFILED: Lazy<Mutex<Vec<(String, Duration)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Roughly a day of Polygon blocks, like `/blocks/{n}/eta`'s default.
+const SAMPLE_BLOCKS: u64 = 43_200;
+const SAMPLE_SECS: i64 = 86_400;
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub route: &'static str,
+    /// `ok`, `full scan` or `temp b-tree`
+    pub verdict: &'static str,
+    pub table: Option<String>,
+    pub table_rows: Option<u64>,
+    pub calls: u32,
+    pub ms: f64,
+    pub plan: Vec<String>,
+    pub suggestion: Option<String>,
+    pub statement: String,
+}
+
+struct Sample {
+    through: u64,
+    from_block: u64,
+    from_ts: i64,
+    to_ts: i64,
+    tx_hash: String,
+    log_index: u64,
+    address: String,
+}
+
+type Canned = (&'static str, fn(&Connection, &Sample) -> Result<()>);
+
+/// The reads behind each route, with the arguments a typical request passes.
+const CANNED: &[Canned] = &[
+    ("/netflow", |c, _| { db::get_latest_cumulative(c)?; velocity::compute(c, 3_600, None)?; Ok(()) }),
+    ("/netflow/range", |c, s| {
+        db::get_netflow_blocks_by_range(c, s.from_block, s.through)?;
+        db::get_volume_blocks_by_range(c, s.from_block, s.through)?;
+        Ok(())
+    }),
+    ("/netflow/history", |c, s| {
+        db::get_netflow_blocks_by_time(c, s.from_ts, s.to_ts)?;
+        db::get_volume_blocks_by_time(c, s.from_ts, s.to_ts)?;
+        db::annotations_by_time(c, s.from_ts, s.to_ts)?;
+        Ok(())
+    }),
+    ("/netflow/by-token", |c, _| db::get_transfers_by_range(c, 0, u64::MAX).map(drop)),
+    ("/netflow/by-address", |c, s| db::address_netflows(c, s.through).map(drop)),
+    ("/counterparties, /entities, /netflow/by-tier", |c, s| db::get_transfers_by_range(c, s.from_block, s.through).map(drop)),
+    ("/transfers", |c, s| db::list_transfers(c, None, s.through, 100).map(drop)),
+    ("/transfers/latest", |c, s| {
+        db::latest_transfers(c, None, s.through, 20)?;
+        db::latest_transfers(c, Some(&s.address), s.through, 20)?;
+        Ok(())
+    }),
+    ("/transfers/:id/annotations", |c, s| {
+        db::transfer_exists(c, &s.tx_hash, s.log_index, None)?;
+        db::annotations_for_transfer(c, &s.tx_hash, s.log_index)?;
+        Ok(())
+    }),
+    ("/tx/:hash", |c, s| db::transfers_by_tx(c, &s.tx_hash).map(drop)),
+    ("/address/:addr/transfers", |c, s| db::address_transfers(c, &s.address, None, 0, 100).map(drop)),
+    ("/validate/addresses", |c, s| db::get_contract(c, &s.address).map(drop)),
+    ("/blocks/:number/eta", |c, s| {
+        db::stored_block_at_or_before(c, s.through)?;
+        db::stored_block_at_or_before(c, s.through.saturating_sub(SAMPLE_BLOCKS))?;
+        db::first_stored_block(c)?;
+        Ok(())
+    }),
+    ("/buckets", |c, s| db::bucketed_transfers(c, s.through).map(drop)),
+    ("/config/history", |c, _| db::list_address_changes(c, None, 100).map(drop)),
+    ("/export/transfers.csv", |c, s| db::for_each_transfer(c, s.from_block, s.through, |_| Ok(()))),
+    ("/supply-events", |c, s| {
+        db::supply_totals_at(c, s.through)?;
+        db::list_supply_events(c, 0, s.through, 100)?;
+        Ok(())
+    }),
+    ("/reorgs", |c, _| db::list_reorgs(c, 100).map(drop)),
+    ("/balances", |c, s| db::address_flows(c, &s.address, 0, s.through).map(drop)),
+    ("/nfts/transfers", |c, s| db::list_nft_transfers(c, None, 0, s.through, 100).map(drop)),
+    ("/nfts/inventory", |c, s| db::nft_flows(c, None, s.through).map(drop)),
+    ("/events?since_seq=", |c, _| db::events_after(c, 0, 500).map(drop)),
+    ("/stats/blocks", |c, s| db::list_block_stats(c, s.from_block, s.through, 100).map(drop)),
+    ("/status", |c, _| db::list_failed_blocks(c).map(drop)),
+    ("API key check", |c, _| db::find_api_key(c, &auth::hash_key("pk_sample")).map(drop)),
+];
+
+/// Run every canned query on `conn` and explain what it ran.
+pub fn analyze(conn: &mut Connection, min_rows: u64) -> Result<Vec<Finding>> {
+    let sample = sample(conn)?;
+    if !is_table(conn, "sqlite_stat1") {
+        warn!("No ANALYZE statistics in this database; SQLite may plan differently after `sqlite3 DB ANALYZE`");
+    }
+    let mut findings = Vec::new();
+    for (route, read) in CANNED {
+        take();
+        conn.profile(Some(record));
+        let result = read(conn, &sample);
+        conn.profile(None);
+        result?;
+
+        let mut statements: Vec<(String, u32, Duration)> = Vec::new();
+        for (sql, elapsed) in take() {
+            match statements.iter_mut().find(|(s, _, _)| *s == sql) {
+                Some((_, calls, total)) => { *calls += 1; *total += elapsed; }
+                None => statements.push((sql, 1, elapsed)),
+            }
+        }
+        for (sql, calls, elapsed) in statements.into_iter().filter(|(s, _, _)| is_read(s)) {
+            findings.push(explain(conn, route, &sql, calls, elapsed, min_rows)?);
+        }
+    }
+    Ok(findings)
+}
+
+fn record(sql: &str, elapsed: Duration) {
+    PROFILED.lock().expect("profile lock").push((sql.to_string(), elapsed));
+}
+
+fn take() -> Vec<(String, Duration)> {
+    std::mem::take(&mut *PROFILED.lock().expect("profile lock"))
+}
+
+fn is_read(sql: &str) -> bool {
+    let head = sql.trim_start().to_ascii_uppercase();
+    head.starts_with("SELECT") || head.starts_with("WITH")
+}
+
+fn sample(conn: &Connection) -> Result<Sample> {
+    let through = match db::indexed_through_block(conn)? {
+        u64::MAX => db::stored_block_at_or_before(conn, u64::MAX)?.map_or(0, |(n, _)| n),
+        n => n,
+    };
+    let to_ts = db::latest_block_ts(conn)?.unwrap_or(0);
+    let recent = db::latest_transfers(conn, None, through, 1)?.into_iter().next();
+    Ok(Sample {
+        through,
+        from_block: through.saturating_sub(SAMPLE_BLOCKS),
+        from_ts: to_ts - SAMPLE_SECS,
+        to_ts,
+        tx_hash: recent.as_ref().map(|t| t.tx_hash.clone()).unwrap_or_default(),
+        log_index: recent.as_ref().map_or(0, |t| t.log_index),
+        address: recent.map(|t| t.from).unwrap_or_default(),
+    })
+}
+
+fn explain(conn: &Connection, route: &'static str, sql: &str, calls: u32, elapsed: Duration, min_rows: u64) -> Result<Finding> {
+    // Parameters stay unbound (NULL); the plan doesn't depend on their values
+    let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}"))?;
+    let mut rows = stmt.raw_query();
+    let mut plan = Vec::new();
+    while let Some(row) = rows.next()? {
+        plan.push(row.get::<_, String>(3)?);
+    }
+
+    let mut finding = Finding {
+        route,
+        verdict: "ok",
+        table: None,
+        table_rows: None,
+        calls,
+        ms: (elapsed.as_secs_f64() * 1e6).round() / 1e3,
+        plan,
+        suggestion: None,
+        statement: sql.split_whitespace().collect::<Vec<_>>().join(" "),
+    };
+    let sorts = finding.plan.iter().any(|d| d.starts_with("USE TEMP B-TREE"));
+    let stops_early = !sorts && finding.statement.to_ascii_uppercase().contains(" LIMIT ");
+    let scanned = finding.plan.iter()
+        .filter(|_| !stops_early)
+        .filter_map(|d| d.strip_prefix("SCAN "))
+        .filter(|rest| !rest.contains("USING"))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .find_map(|name| table_of(conn, &finding.statement, name));
+    if let Some(table) = scanned {
+        let rows = row_count(conn, &table)?;
+        if rows >= min_rows {
+            finding.verdict = "full scan";
+            finding.suggestion = suggest_index(conn, &table, &finding.statement)?;
+        }
+        finding.table_rows = Some(rows);
+        finding.table = Some(table);
+    } else if sorts {
+        // The sort is as big as the first table read
+        let table = finding.plan.iter()
+            .filter_map(|d| d.strip_prefix("SEARCH ").or_else(|| d.strip_prefix("SCAN ")))
+            .filter_map(|rest| rest.split_whitespace().next())
+            .find_map(|name| table_of(conn, &finding.statement, name));
+        if let Some(table) = table {
+            let rows = row_count(conn, &table)?;
+            if rows >= min_rows {
+                finding.verdict = "temp b-tree";
+            }
+            finding.table_rows = Some(rows);
+            finding.table = Some(table);
+        }
+    }
+    Ok(finding)
+}
+
+/// The table `name` refers to in `sql`: itself, or the table it aliases
+/// (`FROM erc20_transfers t`).
+fn table_of(conn: &Connection, sql: &str, name: &str) -> Option<String> {
+    if is_table(conn, name) {
+        return Some(name.to_string());
+    }
+    let words: Vec<&str> = sql.split_whitespace().collect();
+    words.windows(2)
+        .find(|w| w[1] == name && is_table(conn, w[0]))
+        .map(|w| w[0].to_string())
+}
+
+fn is_table(conn: &Connection, name: &str) -> bool {
+    conn.query_row("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?", [name], |_| Ok(()))
+        .is_ok()
+}
+
+fn row_count(conn: &Connection, table: &str) -> Result<u64> {
+    Ok(conn.query_row(&format!("SELECT COUNT(*) FROM \"{table}\""), [], |row| row.get::<_, i64>(0))? as u64)
+}
+
+/// `CREATE INDEX` over the columns of `table` that `sql` filters on, then
+/// those it orders or groups by, in the order they appear.
+fn suggest_index(conn: &Connection, table: &str, sql: &str) -> Result<Option<String>> {
+    if sql.to_ascii_uppercase().contains(" OR ") {
+        return Ok(None);
+    }
+    let columns: Vec<String> = conn.prepare(&format!("PRAGMA table_info(\"{table}\")"))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<_>>()?;
+    let upper = sql.to_ascii_uppercase();
+    let Some(where_at) = upper.find(" WHERE ").or_else(|| upper.find(" ORDER BY ")).or_else(|| upper.find(" GROUP BY ")) else {
+        return Ok(None);
+    };
+    let mut picked: Vec<&str> = Vec::new();
+    for word in sql[where_at..].split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')) {
+        let name = word.rsplit('.').next().unwrap_or(word);
+        if let Some(col) = columns.iter().find(|c| c.eq_ignore_ascii_case(name)) {
+            if !picked.contains(&col.as_str()) {
+                picked.push(col);
+            }
+        }
+    }
+    if picked.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!("CREATE INDEX idx_{table}_{} ON {table} ({})", picked.join("_"), picked.join(", "))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_scans_of_large_tables_only() {
+        let mut conn = db::init(":memory:").unwrap();
+        let findings = analyze(&mut conn, 1).unwrap();
+        assert!(findings.iter().any(|f| f.route == "/tx/:hash"));
+        // Nothing is large in an empty database
+        assert!(analyze(&mut conn, u64::MAX).unwrap().iter().all(|f| f.verdict != "full scan"));
+
+        conn.execute_batch("CREATE TABLE big (a INTEGER, b TEXT); INSERT INTO big VALUES (1, 'x'), (2, 'y');").unwrap();
+        let f = explain(&conn, "test", "SELECT b FROM big WHERE a = ?1 ORDER BY b", 1, Duration::ZERO, 2).unwrap();
+        assert_eq!(f.verdict, "full scan");
+        assert_eq!(f.table_rows, Some(2));
+        assert_eq!(f.suggestion.as_deref(), Some("CREATE INDEX idx_big_a_b ON big (a, b)"));
+    }
+}
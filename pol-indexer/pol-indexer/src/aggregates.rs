@@ -0,0 +1,246 @@
+//! Recomputes derived tables (`netflow_blocks`, `cumulative_netflow`) from
+//! `erc20_transfers`, for when transfers are added out of order (imports).
+//! Transfers from reverted transactions, and copies read from a block that is
+//! no longer the stored one (left by a reorg), are ignored. USD values reuse the
+//! prices already stored per block; blocks without one add nothing in USD.
+//!
+//! The cumulative is signed: a net outflow beyond the running total takes it
+//! below zero. It is stored as a decimal string with a leading `-` when
+//! negative, which every renderer (`ValueFormat::render`) accepts.
+
+use std::collections::BTreeMap;
+
+use eyre::Result;
+use ethers::types::{I256, U256};
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::{db, models::{Clock, Direction, NativeNetflowBlock, NetflowBlock, ValueFormat, POL_DECIMALS}};
+
+/// One block's net-flow, `inflow - outflow`, as a sign and a 256-bit
+/// magnitude. It is exact for any pair of `U256` sums, including values above
+/// `i128::MAX`, which can occur for an 18-decimal token with a huge supply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Delta {
+    negative: bool,
+    magnitude: U256,
+}
+
+impl Delta {
+    pub fn new(inflow: U256, outflow: U256) -> Self {
+        if inflow >= outflow {
+            Delta { negative: false, magnitude: inflow - outflow }
+        } else {
+            Delta { negative: true, magnitude: outflow - inflow }
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_zero()
+    }
+
+    /// Apply to the signed running cumulative, which may go negative; only
+    /// the `I256` bounds saturate. Shared by the live indexer and [`rebuild`].
+    pub fn apply(self, acc: I256) -> I256 {
+        let magnitude = I256::try_from(self.magnitude).unwrap_or(I256::MAX);
+        if self.negative {
+            acc.saturating_sub(magnitude)
+        } else {
+            acc.saturating_add(magnitude)
+        }
+    }
+
+    /// Value in micro-USD at `price_usd_micro` per whole token, rounded toward
+    /// zero and saturating at `i64`.
+    pub fn usd_micro(self, price_usd_micro: i64) -> i64 {
+        let value = self.magnitude.saturating_mul(U256::from(price_usd_micro.max(0) as u64))
+            / U256::exp10(POL_DECIMALS as usize);
+        let value = value.min(U256::from(i64::MAX as u64)).as_u64() as i64;
+        if self.negative { -value } else { value }
+    }
+}
+
+/// Raw units, `-`-prefixed when negative.
+impl std::fmt::Display for Delta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.negative && !self.is_zero() { "-" } else { "" };
+        write!(f, "{sign}{}", self.magnitude)
+    }
+}
+
+/// A stored cumulative (`cumulative_netflow.value`, `netflow_blocks.cumulative`,
+/// a checkpoint's): signed decimal raw units. Unparseable text counts as zero.
+pub fn parse_cumulative(raw: &str) -> I256 {
+    I256::from_dec_str(raw).unwrap_or_default()
+}
+
+#[derive(Default)]
+struct BlockFlows {
+    /// `None` for transfers imported without a timestamp (or with `0`)
+    ts_unix: Option<i64>,
+    inflow: U256,
+    outflow: U256,
+    count: u64,
+}
+
+pub fn rebuild(conn: &Connection, clock: Clock) -> Result<()> {
+    let mut blocks: BTreeMap<u64, BlockFlows> = BTreeMap::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT t.block_number, b.ts_unix, t.value, t.direction
+             FROM erc20_transfers t LEFT JOIN blocks b ON b.block_number = t.block_number
+             WHERE (t.status IS NULL OR t.status != 0)
+               AND (t.block_hash = '' OR COALESCE(b.block_hash, '') IN ('', t.block_hash))"
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let number = row.get::<_, i64>(0)? as u64;
+            let value = U256::from_dec_str(&row.get::<_, String>(2)?).unwrap_or_default();
+            let direction: String = row.get(3)?;
+
+            let entry = blocks.entry(number).or_default();
+            entry.ts_unix = row.get::<_, Option<i64>>(1)?.filter(|ts| *ts > 0);
+            entry.count += 1;
+            match Direction::parse(&direction) {
+                Some(Direction::In) => entry.inflow = entry.inflow.saturating_add(value),
+                Some(Direction::Out) => entry.outflow = entry.outflow.saturating_add(value),
+                _ => {}
+            }
+        }
+    }
+
+    // A database restored from a checkpoint counts on from its values
+    let base = db::get_checkpoint_base(conn)?;
+    if let Some(base) = &base {
+        blocks.retain(|number, _| *number > base.block_number);
+    }
+    let prices = db::netflow_block_prices(conn)?;
+    db::clear_netflow_blocks(conn)?;
+    let mut acc = base.as_ref().map_or(I256::zero(), |b| parse_cumulative(&b.cumulative));
+    let mut usd: Option<i64> = base.as_ref().and_then(|b| b.cumulative_usd_micro);
+    let mut changed_at = base.as_ref().map_or(0, |b| b.block_number);
+    let mut changed_ts = 0;
+    for (number, flows) in &blocks {
+        let ts_unix = match flows.ts_unix {
+            Some(ts) => ts,
+            None => db::interpolated_ts(conn, *number)?.unwrap_or(0),
+        };
+        if flows.inflow != flows.outflow {
+            changed_at = *number;
+            changed_ts = ts_unix;
+        }
+        let delta = Delta::new(flows.inflow, flows.outflow);
+        acc = delta.apply(acc);
+        let price = prices.get(number).copied();
+        if let Some(p) = price {
+            usd = Some(usd.unwrap_or(0).saturating_add(delta.usd_micro(p)));
+        }
+        db::insert_netflow_block(conn, &NetflowBlock {
+            block_number: *number,
+            ts_unix,
+            inflow: flows.inflow.to_string(),
+            outflow: flows.outflow.to_string(),
+            cumulative: acc.to_string(),
+            transfer_count: flows.count,
+            cumulative_token: ValueFormat::Scaled.render(&acc.to_string()),
+            price_usd_micro: price,
+            cumulative_usd_micro: usd,
+        })?;
+    }
+    db::update_cumulative(conn, changed_at, &acc.to_string(), usd, clock.stamp(changed_ts))?;
+    info!(blocks = blocks.len(), cumulative = %acc, "Aggregates rebuilt");
+    rebuild_native(conn)
+}
+
+/// Recompute `native_netflow_blocks` from `native_transfers`.
+fn rebuild_native(conn: &Connection) -> Result<()> {
+    let mut blocks: BTreeMap<u64, BlockFlows> = BTreeMap::new();
+    for (t, ts_unix) in db::counted_native_transfers(conn)? {
+        let value = U256::from_dec_str(&t.value).unwrap_or_default();
+        let entry = blocks.entry(t.block_number).or_default();
+        entry.ts_unix = Some(ts_unix);
+        entry.count += 1;
+        match t.direction {
+            Direction::In => entry.inflow = entry.inflow.saturating_add(value),
+            Direction::Out => entry.outflow = entry.outflow.saturating_add(value),
+            Direction::Internal | Direction::None => {}
+        }
+    }
+
+    db::clear_native_netflow_blocks(conn)?;
+    let (mut cum_in, mut cum_out) = (U256::zero(), U256::zero());
+    for (number, flows) in &blocks {
+        cum_in = cum_in.saturating_add(flows.inflow);
+        cum_out = cum_out.saturating_add(flows.outflow);
+        db::insert_native_netflow_block(conn, &NativeNetflowBlock {
+            block_number: *number,
+            ts_unix: flows.ts_unix.unwrap_or(0),
+            inflow: flows.inflow.to_string(),
+            outflow: flows.outflow.to_string(),
+            cumulative_inflow: cum_in.to_string(),
+            cumulative_outflow: cum_out.to_string(),
+            transfer_count: flows.count,
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big() -> U256 {
+        // Well above i128::MAX (~1.7e38)
+        U256::from(u128::MAX) * U256::from(1_000_000u64)
+    }
+
+    fn signed(v: U256) -> I256 {
+        I256::try_from(v).unwrap()
+    }
+
+    #[test]
+    fn inflow_above_i128_max_is_exact() {
+        let delta = Delta::new(big(), U256::zero());
+        assert_eq!(delta.apply(I256::zero()), signed(big()));
+        assert_eq!(delta.to_string(), big().to_string());
+    }
+
+    #[test]
+    fn outflow_above_i128_max_is_exact() {
+        let acc = signed(big() * 3);
+        let delta = Delta::new(U256::one(), big());
+        assert_eq!(delta.apply(acc), acc - signed(big()) + I256::one());
+        assert_eq!(delta.to_string(), format!("-{}", big() - 1));
+    }
+
+    #[test]
+    fn net_of_large_flows_is_exact() {
+        let (inflow, outflow) = (big() + 7, big());
+        assert_eq!(Delta::new(inflow, outflow).apply(I256::from(5)), I256::from(12));
+        assert!(Delta::new(big(), big()).is_zero());
+        assert_eq!(Delta::new(big(), big()).to_string(), "0");
+    }
+
+    #[test]
+    fn outflow_past_the_total_goes_negative() {
+        let after = Delta::new(U256::zero(), big()).apply(I256::from(10));
+        assert_eq!(after, I256::from(10) - signed(big()));
+        assert_eq!(after.to_string(), format!("-{}", big() - 10));
+        assert_eq!(parse_cumulative(&after.to_string()), after, "stored text reads back");
+        // Back above zero from there
+        assert_eq!(Delta::new(big(), U256::zero()).apply(after), I256::from(10));
+
+        assert_eq!(Delta::new(U256::MAX, U256::zero()).apply(signed(big())), I256::MAX);
+        assert_eq!(Delta::new(U256::zero(), U256::MAX).apply(I256::zero()), I256::MIN + I256::one());
+        assert_eq!(Delta::new(U256::zero(), U256::MAX).to_string(), format!("-{}", U256::MAX));
+    }
+
+    #[test]
+    fn usd_value_is_signed_and_saturates() {
+        let pol = U256::exp10(POL_DECIMALS as usize);
+        // 2.5 POL at $0.412345
+        assert_eq!(Delta::new(pol * 5 / 2, U256::zero()).usd_micro(412_345), 1_030_862);
+        assert_eq!(Delta::new(U256::zero(), pol * 5 / 2).usd_micro(412_345), -1_030_862);
+        assert_eq!(Delta::new(U256::MAX, U256::zero()).usd_micro(1_000_000), i64::MAX);
+    }
+}
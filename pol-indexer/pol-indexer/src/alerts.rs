@@ -0,0 +1,333 @@
+//! Alert delivery to Discord webhooks and Telegram bots.
+//!
+//! The indexer pushes [`Alert`]s into an unbounded channel and a background task
+//! queues one delivery per configured sink in `alert_queue`, so slow or failing
+//! endpoints never stall block processing. A failed delivery is retried with
+//! exponential backoff, across restarts, until `ALERT_MAX_ATTEMPTS`; then it
+//! stays in the table as a dead letter (`GET /admin/alerts/dead`) until
+//! retried by hand.
+//!
+//! Delivery is the `alerts` feature. Without it a configured sink is a
+//! startup error rather than alerts silently going nowhere.
+
+use std::time::Duration;
+
+use ethers::{types::{Address, H256, U256}, utils::format_units};
+use eyre::Result;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::models::POL_DECIMALS;
+#[cfg(feature = "alerts")]
+use {
+    crate::{db, metrics},
+    serde_json::json,
+    tokio::sync::Mutex,
+    tracing::{error, warn},
+};
+
+const POLYGONSCAN: &str = "https://polygonscan.com";
+
+/// Wait before the first retry; doubles with each failure up to `MAX_BACKOFF`.
+const BASE_BACKOFF_SECS: i64 = 5;
+const MAX_BACKOFF_SECS: i64 = 3_600;
+
+/// Longest a queue with nothing due sleeps before looking again.
+const IDLE_POLL: Duration = Duration::from_secs(60);
+
+/// Deliveries attempted per pass.
+const BATCH: u32 = 50;
+
+/// Per-request limit, so one hung endpoint can't hold up the queue.
+const SEND_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Default `ALERT_MAX_ATTEMPTS`: about two and a half hours of retries.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Alert {
+    LargeTransfer {
+        block_number: u64,
+        tx_hash: String,
+        from: Address,
+        to: Address,
+        value: U256,
+        /// true = into Binance, false = out of Binance
+        inflow: bool,
+    },
+    /// The token proxy now points at a different implementation
+    ImplementationChanged {
+        block_number: u64,
+        token: Address,
+        previous: Address,
+        implementation: Address,
+    },
+    /// The RPC provider's head is more than `REFERENCE_MAX_DRIFT_BLOCKS` from the reference endpoint's
+    ProviderDrift {
+        provider_head: u64,
+        reference_head: u64,
+        /// Host of the reference endpoint
+        reference: String,
+    },
+    /// The RPC provider and the reference endpoint have different blocks at a settled height
+    ProviderForked {
+        block_number: u64,
+        provider_hash: H256,
+        reference_hash: H256,
+        reference: String,
+    },
+    /// Writes to the indexer's database fail with busy, locked or disk full; new heads are buffered meanwhile
+    DatabaseUnavailable {
+        block_number: u64,
+        error: String,
+    },
+}
+
+impl Alert {
+    fn title(&self) -> String {
+        match self {
+            Alert::LargeTransfer { inflow: true, .. } => "Large POL inflow to Binance".to_string(),
+            Alert::LargeTransfer { inflow: false, .. } => "Large POL outflow from Binance".to_string(),
+            Alert::ImplementationChanged { .. } => "POL token implementation changed".to_string(),
+            Alert::ProviderDrift { provider_head, reference_head, .. } if provider_head < reference_head => "RPC provider is behind the reference".to_string(),
+            Alert::ProviderDrift { .. } => "RPC provider is ahead of the reference".to_string(),
+            Alert::ProviderForked { .. } => "RPC provider is on a different fork from the reference".to_string(),
+            Alert::DatabaseUnavailable { .. } => "Indexer database unavailable".to_string(),
+        }
+    }
+
+    fn markdown(&self) -> String {
+        match self {
+            Alert::LargeTransfer { block_number, tx_hash, from, to, value, .. } => format!(
+                "**{}**: {} POL\n`{from:?}` → `{to:?}`\nBlock {block_number} · [tx]({POLYGONSCAN}/tx/{tx_hash})",
+                self.title(),
+                pol_amount(*value),
+            ),
+            Alert::ImplementationChanged { block_number, token, previous, implementation } => format!(
+                "**{}**: [`{token:?}`]({POLYGONSCAN}/address/{token:?})\n`{previous:?}` → `{implementation:?}`\nObserved at block {block_number}",
+                self.title(),
+            ),
+            Alert::ProviderDrift { provider_head, reference_head, reference } => format!(
+                "**{}** by {} blocks\nProvider head {provider_head} · `{reference}` head {reference_head}",
+                self.title(),
+                provider_head.abs_diff(*reference_head),
+            ),
+            Alert::ProviderForked { block_number, provider_hash, reference_hash, reference } => format!(
+                "**{}** at block {block_number}\nProvider `{provider_hash:?}`\n`{reference}` `{reference_hash:?}`",
+                self.title(),
+            ),
+            Alert::DatabaseUnavailable { block_number, error } => format!(
+                "**{}** at block {block_number}; retrying\n`{error}`",
+                self.title(),
+            ),
+        }
+    }
+
+    fn html(&self) -> String {
+        match self {
+            Alert::LargeTransfer { block_number, tx_hash, from, to, value, .. } => format!(
+                "<b>{}</b>: {} POL\n<code>{from:?}</code> → <code>{to:?}</code>\nBlock {block_number} · <a href=\"{POLYGONSCAN}/tx/{tx_hash}\">tx</a>",
+                self.title(),
+                pol_amount(*value),
+            ),
+            Alert::ImplementationChanged { block_number, token, previous, implementation } => format!(
+                "<b>{}</b>: <a href=\"{POLYGONSCAN}/address/{token:?}\">{token:?}</a>\n<code>{previous:?}</code> → <code>{implementation:?}</code>\nObserved at block {block_number}",
+                self.title(),
+            ),
+            Alert::ProviderDrift { provider_head, reference_head, reference } => format!(
+                "<b>{}</b> by {} blocks\nProvider head {provider_head} · <code>{reference}</code> head {reference_head}",
+                self.title(),
+                provider_head.abs_diff(*reference_head),
+            ),
+            Alert::ProviderForked { block_number, provider_hash, reference_hash, reference } => format!(
+                "<b>{}</b> at block {block_number}\nProvider <code>{provider_hash:?}</code>\n<code>{reference}</code> <code>{reference_hash:?}</code>",
+                self.title(),
+            ),
+            Alert::DatabaseUnavailable { block_number, error } => format!(
+                "<b>{}</b> at block {block_number}; retrying\n<code>{error}</code>",
+                self.title(),
+            ),
+        }
+    }
+}
+
+fn pol_amount(value: U256) -> String {
+    format_units(value, POL_DECIMALS).unwrap_or_else(|_| value.to_string())
+}
+
+#[derive(Debug, Clone)]
+pub enum Sink {
+    Discord { webhook_url: String },
+    Telegram { bot_token: String, chat_id: String },
+}
+
+impl Sink {
+    #[cfg(feature = "alerts")]
+    async fn send(&self, http: &reqwest::Client, alert: &Alert) -> eyre::Result<()> {
+        let req = match self {
+            Sink::Discord { webhook_url } => http
+                .post(webhook_url)
+                .json(&json!({ "content": alert.markdown() })),
+            Sink::Telegram { bot_token, chat_id } => http
+                .post(format!("https://api.telegram.org/bot{bot_token}/sendMessage"))
+                .json(&json!({
+                    "chat_id": chat_id,
+                    "text": alert.html(),
+                    "parse_mode": "HTML",
+                    "disable_web_page_preview": true,
+                })),
+        };
+        // Both URLs embed a credential; keep them out of the error
+        req.send().await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(reqwest::Error::without_url)?;
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Discord { .. } => "discord",
+            Sink::Telegram { .. } => "telegram",
+        }
+    }
+}
+
+/// Alerting thresholds and destinations.
+#[derive(Debug, Clone, Default)]
+pub struct AlertConfig {
+    /// Minimum raw transfer value that triggers a `LargeTransfer` alert
+    pub min_value: Option<U256>,
+    pub sinks: Vec<Sink>,
+    /// Deliveries tried per alert and sink before it is dead-lettered
+    pub max_attempts: u32,
+}
+
+/// Cheap-to-clone handle used by the indexer to raise alerts.
+#[derive(Clone)]
+pub struct Alerter {
+    config: AlertConfig,
+    tx: mpsc::UnboundedSender<Alert>,
+}
+
+impl Alerter {
+    /// Spawns the delivery task on its own connection to `db_path`, which
+    /// also resumes deliveries queued by an earlier run. Returns `None` when
+    /// no sinks are configured.
+    pub fn spawn(config: AlertConfig, db_path: &str) -> Result<Option<Alerter>> {
+        if config.sinks.is_empty() {
+            return Ok(None);
+        }
+        if !cfg!(feature = "alerts") {
+            eyre::bail!("alert sinks are configured, but this build has no `alerts` feature");
+        }
+        let conn = Connection::open(db_path)?;
+        let (tx, rx) = mpsc::unbounded_channel::<Alert>();
+        info!(sinks = config.sinks.len(), "Alerting enabled");
+        #[cfg(feature = "alerts")]
+        tokio::spawn(deliver(conn, config.clone(), rx));
+        #[cfg(not(feature = "alerts"))]
+        drop((conn, rx));
+        Ok(Some(Alerter { config, tx }))
+    }
+
+    pub fn is_large(&self, value: U256) -> bool {
+        self.config.min_value.is_some_and(|min| value >= min)
+    }
+
+    pub fn send(&self, alert: Alert) {
+        let _ = self.tx.send(alert);
+    }
+}
+
+/// Queue what arrives on `rx`, then work through due deliveries, forever.
+#[cfg(feature = "alerts")]
+async fn deliver(conn: Connection, config: AlertConfig, mut rx: mpsc::UnboundedReceiver<Alert>) {
+    let conn = Mutex::new(conn);
+    let http = reqwest::Client::builder().timeout(SEND_TIMEOUT).build().unwrap_or_default();
+    let mut open = true;
+    loop {
+        if let Err(e) = deliver_due(&conn, &config, &http).await {
+            error!(?e, "Alert queue unavailable");
+        }
+        let next_due = db::next_alert_due(&*conn.lock().await);
+        let wait = match next_due {
+            Ok(Some(at)) => Duration::from_secs((at - now()).clamp(0, IDLE_POLL.as_secs() as i64) as u64),
+            _ => IDLE_POLL,
+        };
+        tokio::select! {
+            alert = rx.recv(), if open => match alert {
+                Some(alert) => {
+                    let queued = enqueue(&*conn.lock().await, &config.sinks, &alert);
+                    if let Err(e) = queued {
+                        // Most likely the database itself is what the alert is about
+                        warn!(?e, ?alert, "Could not queue alert; sending it once without retries");
+                        for sink in &config.sinks {
+                            if let Err(e) = sink.send(&http, &alert).await {
+                                error!(sink = sink.name(), error = %format!("{e:#}"), "Unqueued alert delivery failed");
+                            }
+                        }
+                    }
+                }
+                None => open = false,
+            },
+            () = tokio::time::sleep(wait) => {}
+        }
+    }
+}
+
+#[cfg(feature = "alerts")]
+fn enqueue(conn: &Connection, sinks: &[Sink], alert: &Alert) -> Result<()> {
+    let body = serde_json::to_string(alert)?;
+    let now = now();
+    for sink in sinks {
+        db::enqueue_alert(conn, sink.name(), &body, now)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "alerts")]
+async fn deliver_due(conn: &Mutex<Connection>, config: &AlertConfig, http: &reqwest::Client) -> Result<()> {
+    loop {
+        let due = db::due_alerts(&*conn.lock().await, now(), BATCH)?;
+        for q in &due {
+            let sink = config.sinks.iter().find(|s| s.name() == q.sink);
+            let result = match (sink, serde_json::from_value::<Alert>(q.alert.clone())) {
+                (Some(sink), Ok(alert)) => sink.send(http, &alert).await.map_err(|e| format!("{e:#}")),
+                // Queued by a run with other sinks configured; retrying can't help
+                (None, _) => Err(format!("sink {} is no longer configured", q.sink)),
+                (_, Err(e)) => Err(format!("unreadable alert: {e}")),
+            };
+            match result {
+                Ok(()) => db::delete_queued_alert(&*conn.lock().await, q.id)?,
+                Err(err) => {
+                    let attempts = q.attempts + 1;
+                    let retry = (sink.is_some() && attempts < config.max_attempts).then(|| now() + backoff_secs(attempts));
+                    match retry {
+                        Some(at) => warn!(sink = %q.sink, id = q.id, attempts, retry_in = at - now(), error = %err, "Alert delivery failed"),
+                        None => error!(sink = %q.sink, id = q.id, attempts, error = %err, "Alert delivery failed; dead-lettered"),
+                    }
+                    db::fail_queued_alert(&*conn.lock().await, q.id, &err, retry, now())?;
+                }
+            }
+        }
+        let (pending, dead) = db::alert_queue_counts(&*conn.lock().await)?;
+        metrics::ALERT_QUEUE_PENDING.set(pending as i64);
+        metrics::ALERT_QUEUE_DEAD.set(dead as i64);
+        if due.len() < BATCH as usize {
+            return Ok(());
+        }
+    }
+}
+
+/// Wait after the `attempts`-th failure: 5s, 10s, 20s… up to an hour.
+fn backoff_secs(attempts: u32) -> i64 {
+    BASE_BACKOFF_SECS.saturating_mul(1i64 << attempts.saturating_sub(1).min(20)).min(MAX_BACKOFF_SECS)
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
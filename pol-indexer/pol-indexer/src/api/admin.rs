@@ -0,0 +1,285 @@
+//! `/admin/*` — mutating operations, kept apart from the read-only API.
+//!
+//! Served on `ADMIN_BIND` when it is set, so the public listener never
+//! routes them. Otherwise they are mounted on the public listener only if
+//! `ADMIN_TOKEN` is set. The admin token is separate from API keys: no key,
+//! whatever its scope, reaches these routes, and the token is accepted only in
+//! a header (`Authorization: Bearer` or `x-admin-token`), never in the URL.
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{internal_error, AppState};
+use crate::{auth, control::{self, Control}, db, logging, maintenance, models::{self, ValueFormat}};
+
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/admin/keys", get(list_keys).post(create_key))
+        .route("/admin/keys/:id", delete(revoke_key).patch(update_key))
+        .route("/admin/maintenance", post(run_maintenance))
+        .route("/admin/alerts/dead", get(dead_alerts))
+        .route("/admin/alerts/:id/retry", post(retry_alert))
+        .route("/admin/log-level", get(log_level).put(set_log_level))
+        .route("/admin/indexer", get(|state| control(state, Control::state)))
+        .route("/admin/indexer/pause", post(|state| control(state, Control::pause)))
+        .route("/admin/indexer/step", post(|state| control(state, Control::step)))
+        .route("/admin/indexer/resume", post(|state| control(state, Control::resume)))
+        .route("/admin/entities/:name", put(set_entity).delete(delete_entity))
+        .layer(middleware::from_fn_with_state(state, require_admin_token))
+}
+
+async fn require_admin_token(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    // A dedicated admin listener may run without a token
+    let Some(expected) = &state.config.admin_token_hash else {
+        return next.run(req).await;
+    };
+    let headers = req.headers();
+    let presented = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-admin-token").and_then(|v| v.to_str().ok()))
+        .map(str::trim);
+    match presented {
+        None => (StatusCode::UNAUTHORIZED, "missing admin token").into_response(),
+        Some(t) if auth::hash_key(t) != *expected => (StatusCode::UNAUTHORIZED, "invalid admin token").into_response(),
+        Some(_) => next.run(req).await,
+    }
+}
+
+async fn list_keys(State(state): State<AppState>) -> Response {
+    let keys = {
+        let conn = state.db.lock().await;
+        db::list_api_keys(&conn)
+    };
+    match keys {
+        Ok(keys) => Json(keys).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateKey {
+    name: String,
+    /// Comma-separated, as for `keys create`; default `*`
+    #[serde(default)]
+    exchanges: String,
+    #[serde(default)]
+    tokens: String,
+    /// Units for requests that don't pass `?format=`
+    #[serde(default)]
+    default_format: Option<ValueFormat>,
+}
+
+#[derive(Serialize)]
+struct CreatedKey {
+    id: i64,
+    /// Shown once; only its hash is stored
+    key: String,
+}
+
+async fn create_key(State(state): State<AppState>, Json(body): Json<CreateKey>) -> Response {
+    let exchanges = auth::parse_scope(&body.exchanges);
+    let tokens = auth::parse_scope(&body.tokens);
+    if let Some(bad) = tokens.iter().find(|t| *t != "*" && models::parse_address(t).is_err()) {
+        return (StatusCode::BAD_REQUEST, format!("invalid token address {bad}")).into_response();
+    }
+    let key = auth::generate_key();
+    let id = {
+        let conn = state.db.lock().await;
+        db::insert_api_key(&conn, &body.name, &auth::hash_key(&key), &exchanges, &tokens, body.default_format)
+    };
+    match id {
+        Ok(id) => {
+            tracing::info!(id, name = %body.name, "API key created via admin API");
+            (StatusCode::CREATED, Json(CreatedKey { id, key })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn revoke_key(State(state): State<AppState>, Path(id): Path<i64>) -> Response {
+    let revoked = {
+        let conn = state.db.lock().await;
+        db::revoke_api_key(&conn, id)
+    };
+    match revoked {
+        Ok(true) => {
+            tracing::info!(id, "API key revoked via admin API");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("no active API key with id {id}")).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateKey {
+    /// `null` returns the key to decimal raw units
+    default_format: Option<ValueFormat>,
+}
+
+async fn update_key(State(state): State<AppState>, Path(id): Path<i64>, Json(body): Json<UpdateKey>) -> Response {
+    let updated = {
+        let conn = state.db.lock().await;
+        db::set_api_key_format(&conn, id, body.default_format)
+    };
+    match updated {
+        Ok(true) => {
+            tracing::info!(id, format = body.default_format.unwrap_or_default().as_str(), "API key default format changed via admin API");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("no active API key with id {id}")).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Integrity check and incremental vacuum now, instead of at the next `MAINTENANCE_AT`.
+async fn run_maintenance(State(state): State<AppState>) -> Response {
+    let path = state.db_path.clone();
+    match tokio::task::spawn_blocking(move || maintenance::run_once(&path)).await {
+        Ok(Ok(())) => StatusCode::NO_CONTENT.into_response(),
+        Ok(Err(e)) => internal_error(e),
+        Err(e) => internal_error(eyre::eyre!("maintenance panicked: {e}")),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeadQuery {
+    #[serde(default = "default_dead_limit")]
+    limit: u32,
+}
+
+fn default_dead_limit() -> u32 {
+    100
+}
+
+/// Alert deliveries that ran out of retries, newest first, with the last error.
+async fn dead_alerts(State(state): State<AppState>, Query(q): Query<DeadQuery>) -> Response {
+    let rows = {
+        let conn = state.db.lock().await;
+        db::dead_alerts(&conn, q.limit.min(1_000))
+    };
+    match rows {
+        Ok(rows) => Json(rows).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Put a dead letter back in the queue with a fresh set of attempts; the
+/// delivery task picks it up within a minute.
+async fn retry_alert(State(state): State<AppState>, Path(id): Path<i64>) -> Response {
+    let requeued = {
+        let conn = state.db.lock().await;
+        db::requeue_alert(&conn, id, OffsetDateTime::now_utc().unix_timestamp())
+    };
+    match requeued {
+        Ok(true) => {
+            tracing::info!(id, "Dead-lettered alert requeued via admin API");
+            StatusCode::ACCEPTED.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("no dead-lettered alert with id {id}")).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct LogLevel {
+    /// `RUST_LOG` directives, e.g. `info,pol_indexer::indexer=debug`
+    filter: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<String>,
+}
+
+async fn log_level() -> Response {
+    match logging::current() {
+        Ok(filter) => Json(LogLevel { filter, previous: None }).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Swap the log filter without a restart. It lasts until the process exits
+/// (or the next SIGHUP with `LOG_FILTER_FILE`).
+async fn set_log_level(Json(body): Json<LogLevel>) -> Response {
+    match logging::set(&body.filter).and_then(|previous| Ok((logging::current()?, previous))) {
+        Ok((filter, previous)) => Json(LogLevel { filter, previous: Some(previous) }).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, format!("{e:#}")).into_response(),
+    }
+}
+
+/// Read or change the hold on the indexer running in this process (`STOP_AT_BLOCK`).
+async fn control(State(state): State<AppState>, op: fn(&Control) -> control::State) -> Response {
+    match &state.config.control {
+        Some(control) => Json(op(control)).into_response(),
+        None => (StatusCode::NOT_FOUND, "no indexer runs in this process").into_response(),
+    }
+}
+
+/// Longest entity name accepted.
+const MAX_ENTITY_NAME: usize = 64;
+
+#[derive(Deserialize)]
+pub struct SetEntity {
+    addresses: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct EntityMembers {
+    entity: String,
+    addresses: Vec<String>,
+}
+
+/// Define (or redefine) an entity as exactly `addresses`. An address already
+/// in another entity moves to this one.
+async fn set_entity(State(state): State<AppState>, Path(name): Path<String>, Json(body): Json<SetEntity>) -> Response {
+    let name = name.trim().to_string();
+    if name.is_empty() || name.len() > MAX_ENTITY_NAME {
+        return (StatusCode::BAD_REQUEST, format!("entity name must be 1-{MAX_ENTITY_NAME} characters")).into_response();
+    }
+    let mut addresses = Vec::with_capacity(body.addresses.len());
+    for a in &body.addresses {
+        match models::parse_address(a.trim()) {
+            Ok(addr) => addresses.push(models::address_key(&addr)),
+            Err(_) => return (StatusCode::BAD_REQUEST, format!("invalid address {a}")).into_response(),
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+    if addresses.is_empty() {
+        return (StatusCode::BAD_REQUEST, "an entity needs at least one address").into_response();
+    }
+    let stored = {
+        let conn = state.db.lock().await;
+        db::set_entity(&conn, &name, &addresses)
+    };
+    match stored {
+        Ok(()) => {
+            tracing::info!(entity = %name, addresses = addresses.len(), "Entity set via admin API");
+            let addresses = addresses.iter().map(|a| models::checksummed(a)).collect();
+            Json(EntityMembers { entity: name, addresses }).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+async fn delete_entity(State(state): State<AppState>, Path(name): Path<String>) -> Response {
+    let deleted = {
+        let conn = state.db.lock().await;
+        db::delete_entity(&conn, name.trim())
+    };
+    match deleted {
+        Ok(true) => {
+            tracing::info!(entity = %name, "Entity deleted via admin API");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, format!("no entity named {name}")).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
@@ -0,0 +1,94 @@
+//! Analyst notes on transfers, e.g. "Binance cold wallet rotation".
+//!
+//! `POST /transfers/{id}/annotations` with `{"note": "…", "tags": ["…"]}`
+//! stores one and returns it (`201`); `GET` lists a transfer's annotations.
+//! `{id}` is `<tx_hash>-<log_index>`, which stays valid when a rollback or
+//! rebuild re-inserts the row. Annotations also appear in transfer exports and
+//! in `/netflow/history` buckets.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use super::{internal_error, Db};
+use crate::db;
+
+const MAX_NOTE_LEN: usize = 4_096;
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LEN: usize = 64;
+
+#[derive(Deserialize)]
+pub struct NewAnnotation {
+    note: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+pub async fn list(State(conn): State<Db>, Path(id): Path<String>) -> Response {
+    let Some((tx_hash, log_index)) = parse_id(&id) else {
+        return bad_id(&id);
+    };
+    let found = {
+        let conn = conn.lock().await;
+        db::transfer_exists(&conn, &tx_hash, log_index, None)
+            .and_then(|exists| exists.then(|| db::annotations_for_transfer(&conn, &tx_hash, log_index)).transpose())
+    };
+    match found {
+        Ok(Some(rows)) => Json(rows).into_response(),
+        Ok(None) => not_found(&id),
+        Err(e) => internal_error(e),
+    }
+}
+
+pub async fn create(State(conn): State<Db>, Path(id): Path<String>, Json(body): Json<NewAnnotation>) -> Response {
+    let Some((tx_hash, log_index)) = parse_id(&id) else {
+        return bad_id(&id);
+    };
+    let note = body.note.trim();
+    if note.is_empty() || note.len() > MAX_NOTE_LEN {
+        return (StatusCode::BAD_REQUEST, format!("note must be 1-{MAX_NOTE_LEN} bytes")).into_response();
+    }
+    let mut tags: Vec<String> = Vec::new();
+    for tag in body.tags.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > MAX_TAGS || tags.iter().any(|t| t.len() > MAX_TAG_LEN || t.contains(',')) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("at most {MAX_TAGS} tags of up to {MAX_TAG_LEN} bytes, without commas"),
+        ).into_response();
+    }
+
+    let stored = {
+        let conn = conn.lock().await;
+        db::transfer_exists(&conn, &tx_hash, log_index, None)
+            .and_then(|exists| exists.then(|| db::insert_annotation(&conn, &tx_hash, log_index, note, &tags)).transpose())
+    };
+    match stored {
+        Ok(Some(annotation)) => (StatusCode::CREATED, Json(annotation)).into_response(),
+        Ok(None) => not_found(&id),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// `<tx_hash>-<log_index>` → lowercase hash and index.
+fn parse_id(id: &str) -> Option<(String, u64)> {
+    let (hash, index) = id.rsplit_once('-')?;
+    let hash = hash.to_ascii_lowercase();
+    let valid = hash.strip_prefix("0x").is_some_and(|h| h.len() == 64 && h.bytes().all(|b| b.is_ascii_hexdigit()));
+    Some((hash, index.parse().ok()?)).filter(|_| valid)
+}
+
+fn bad_id(id: &str) -> Response {
+    (StatusCode::BAD_REQUEST, format!("invalid transfer id {id:?}; expected <tx_hash>-<log_index>")).into_response()
+}
+
+fn not_found(id: &str) -> Response {
+    (StatusCode::NOT_FOUND, format!("no indexed transfer {id}")).into_response()
+}
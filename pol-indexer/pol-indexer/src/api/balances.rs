@@ -0,0 +1,122 @@
+//! `GET /balances` — implied token balance of each tracked address: its
+//! baseline `balanceOf` (recorded by `run` before the first block indexed for
+//! it) plus what it received minus what it sent in the stored transfers since.
+//!
+//! Compared with `balanceOf` at the watermark block, a difference means
+//! transfers are missing or miscounted. `?format=` renders every amount.
+//!
+//! `?as_of_block=N` implies the balances after block N instead, leaving out
+//! addresses whose baseline is later; a block past the watermark is `400`.
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{as_of, internal_error, range::signed_net, snapshot, with_watermark, Db};
+use crate::{db, models::ValueFormat};
+
+#[derive(Deserialize)]
+pub struct BalancesQuery {
+    #[serde(default)]
+    format: ValueFormat,
+    /// The balances after this block instead of at the watermark
+    as_of_block: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct ImpliedBalance {
+    address: String,
+    baseline_block: u64,
+    baseline: String,
+    /// Stored transfers after `baseline_block`, reverted ones excluded
+    received: String,
+    sent: String,
+    /// `baseline + received - sent`; negative only when transfers are miscounted
+    implied_balance: String,
+}
+
+#[derive(Serialize)]
+pub struct Balances {
+    /// Block the balances are implied at (the watermark unless `as_of_block`);
+    /// `None` before anything is indexed
+    block_number: Option<u64>,
+    addresses: Vec<ImpliedBalance>,
+    total_implied: String,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<BalancesQuery>) -> Response {
+    let found = {
+        let mut conn = conn.lock().await;
+        snapshot(&mut conn, |conn, through| {
+            let at = match as_of(q.as_of_block, through) {
+                Ok(at) => at,
+                Err(rejected) => return Ok(Err(rejected)),
+            };
+            let rows = db::list_balance_baselines(conn)?
+                .into_iter()
+                .filter(|b| b.block_number <= at)
+                .map(|b| {
+                    let (received, sent) = db::address_flows(conn, &b.address, b.block_number, at)?;
+                    Ok((b, received, sent))
+                })
+                .collect::<eyre::Result<Vec<_>>>()?;
+            Ok(Ok((rows, at)))
+        })
+    };
+    let ((rows, at), through) = match found {
+        Ok((Ok(found), through)) => (found, through),
+        Ok((Err(rejected), _)) => return rejected.into_response(),
+        Err(e) => return internal_error(e),
+    };
+    let fmt = q.format;
+    let (mut total_in, mut total_out) = (U256::zero(), U256::zero());
+    let mut addresses = Vec::new();
+    for (b, received, sent) in rows {
+        let credited = U256::from_dec_str(&b.balance).unwrap_or_default().saturating_add(received);
+        total_in = total_in.saturating_add(credited);
+        total_out = total_out.saturating_add(sent);
+        addresses.push(ImpliedBalance {
+            address: b.address,
+            baseline_block: b.block_number,
+            baseline: fmt.render(&b.balance),
+            received: fmt.render(&received.to_string()),
+            sent: fmt.render(&sent.to_string()),
+            implied_balance: fmt.render(&signed_net(credited, sent)),
+        });
+    }
+    with_watermark(Json(Balances {
+        block_number: (at != u64::MAX).then_some(at),
+        addresses,
+        total_implied: fmt.render(&signed_net(total_in, total_out)),
+    }), through)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db, models::{Direction, TxStatus}};
+
+    #[test]
+    fn flows_count_after_the_baseline_block() {
+        let scratch = db::Scratch::new("balances");
+        let conn = db::init(scratch.path()).unwrap();
+        let transfers = [
+            (10_u64, "0xa", "0xhot", 5, None),
+            (11, "0xa", "0xhot", 7, Some(TxStatus::Success)),
+            (11, "0xhot", "0xb", 3, None),
+            (12, "0xhot", "0xcold", 2, None),
+            (12, "0xa", "0xhot", 100, Some(TxStatus::Reverted)),
+        ];
+        for (i, (block, from, to, value, status)) in transfers.into_iter().enumerate() {
+            let hash = format!("0x{i:064x}");
+            db::insert_transfer(&conn, block, &hash, &hash, 0, "0xt", from, to, &value.to_string(), Direction::In, status).unwrap();
+        }
+        let (received, sent) = db::address_flows(&conn, "0xhot", 10, 12).unwrap();
+        assert_eq!((received.as_u64(), sent.as_u64()), (7, 5), "block 10 is in the baseline; reverted moved nothing");
+        let (received, _) = db::address_flows(&conn, "0xhot", 9, 11).unwrap();
+        assert_eq!(received.as_u64(), 12);
+    }
+}
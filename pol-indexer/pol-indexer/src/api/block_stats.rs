@@ -0,0 +1,102 @@
+//! `GET /stats/blocks` — what indexing each block took, newest first, for
+//! capacity planning: logs fetched, transfers matched, processing time and
+//! the part of it spent waiting on the RPC.
+//!
+//! `from_block`/`to_block` bound the list and `limit` caps it (default 100,
+//! max 1000). `summary` aggregates the blocks returned.
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{internal_error, Db};
+use crate::{db, models::BlockStats};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct BlockStatsQuery {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    blocks: u64,
+    logs_fetched: u64,
+    transfers_matched: u64,
+    avg_processing_ms: u64,
+    p95_processing_ms: u64,
+    max_processing_ms: u64,
+    avg_rpc_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct BlockStatsResponse {
+    summary: Summary,
+    blocks: Vec<BlockStats>,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<BlockStatsQuery>) -> Response {
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let rows = {
+        let conn = conn.lock().await;
+        db::list_block_stats(&conn, q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX), limit)
+    };
+    match rows {
+        Ok(blocks) => Json(BlockStatsResponse { summary: summarize(&blocks), blocks }).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+fn summarize(rows: &[BlockStats]) -> Summary {
+    if rows.is_empty() {
+        return Summary::default();
+    }
+    let n = rows.len() as u64;
+    let mut processing: Vec<u64> = rows.iter().map(|r| r.processing_ms).collect();
+    processing.sort_unstable();
+    // Nearest rank
+    let p95 = processing[(processing.len() * 95).div_ceil(100) - 1];
+    Summary {
+        blocks: n,
+        logs_fetched: rows.iter().map(|r| r.logs_fetched).sum(),
+        transfers_matched: rows.iter().map(|r| r.transfers_matched).sum(),
+        avg_processing_ms: processing.iter().sum::<u64>() / n,
+        p95_processing_ms: p95,
+        max_processing_ms: *processing.last().expect("not empty"),
+        avg_rpc_ms: rows.iter().map(|r| r.rpc_ms).sum::<u64>() / n,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_the_returned_blocks() {
+        let rows: Vec<BlockStats> = (1..=20).map(|i| BlockStats {
+            block_number: i,
+            logs_fetched: 3,
+            transfers_matched: i % 2,
+            processing_ms: i * 10,
+            rpc_ms: i * 5,
+            indexed_at_unix: 0,
+        }).collect();
+        assert_eq!(summarize(&rows), Summary {
+            blocks: 20,
+            logs_fetched: 60,
+            transfers_matched: 10,
+            avg_processing_ms: 105,
+            p95_processing_ms: 190,
+            max_processing_ms: 200,
+            avg_rpc_ms: 52,
+        });
+        assert_eq!(summarize(&[]), Summary::default());
+    }
+}
@@ -0,0 +1,51 @@
+//! `GET /buckets` — totals of the transfers accounting rules put in custom
+//! buckets (see `ACCOUNTING_RULES_FILE`), which the net-flow leaves out.
+//!
+//! Reverted transfers aren't counted; `?format=` renders the totals.
+
+use axum::{
+    extract::{Query, State},
+    response::Response,
+    Json,
+};
+use ethers::types::U256;
+use serde::Serialize;
+
+use super::{internal_error, snapshot, with_watermark, Db, FormatQuery};
+use crate::db;
+
+#[derive(Serialize)]
+pub struct Bucket {
+    name: String,
+    transfer_count: u64,
+    total: String,
+    first_block: u64,
+    last_block: u64,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<FormatQuery>) -> Response {
+    let found = {
+        let mut conn = conn.lock().await;
+        snapshot(&mut conn, db::bucketed_transfers)
+    };
+    let (rows, through) = match found {
+        Ok(found) => found,
+        Err(e) => return internal_error(e),
+    };
+    let mut buckets: Vec<(Bucket, U256)> = Vec::new();
+    for (name, value, block) in rows {
+        let value = U256::from_dec_str(&value).unwrap_or_default();
+        match buckets.last_mut() {
+            Some((b, total)) if b.name == name => {
+                b.transfer_count += 1;
+                b.last_block = block;
+                *total = total.saturating_add(value);
+            }
+            _ => buckets.push((Bucket { name, transfer_count: 1, total: String::new(), first_block: block, last_block: block }, value)),
+        }
+    }
+    let buckets: Vec<Bucket> = buckets.into_iter()
+        .map(|(b, total)| Bucket { total: q.format.render(&total.to_string()), ..b })
+        .collect();
+    with_watermark(Json(buckets), through)
+}
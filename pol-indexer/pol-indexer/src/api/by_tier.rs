@@ -0,0 +1,147 @@
+//! `GET /netflow/by-tier` — flows per wallet tier (hot, cold, deposit) rather
+//! than for the address set as a whole, e.g. cold-wallet accumulation versus
+//! hot-wallet churn.
+//!
+//! Configured addresses are hot unless `WALLET_TIERS` tags them otherwise, and
+//! discovered deposit addresses are deposit. A tier's `inflow`/`outflow` count
+//! transfers crossing its boundary, including those from or to other tiers;
+//! `between_tiers` breaks those internal movements down. `volume` is
+//! `inflow + outflow`. Optional `?from_block=&to_block=` bounds (inclusive).
+//! Non-reverted stored transfers only. Runs on the heavy-query worker.
+
+use std::{collections::{BTreeMap, HashMap}, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{range::signed_net, worker::HeavyWorker, ApiConfig};
+use crate::{db, models::{self, TxStatus, ValueFormat, WalletTier}};
+
+#[derive(Deserialize)]
+pub struct TierQuery {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct TierFlows {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    tiers: Vec<TierFlow>,
+    between_tiers: Vec<TierTransfer>,
+}
+
+#[derive(Serialize)]
+pub struct TierFlow {
+    tier: WalletTier,
+    addresses: usize,
+    inflow: String,
+    outflow: String,
+    net: String,
+    volume: String,
+    transfer_count: u64,
+}
+
+#[derive(Serialize)]
+pub struct TierTransfer {
+    from: WalletTier,
+    to: WalletTier,
+    value: String,
+    transfer_count: u64,
+}
+
+#[derive(Default)]
+struct Acc {
+    addresses: usize,
+    inflow: U256,
+    outflow: U256,
+    transfer_count: u64,
+}
+
+pub async fn by_tier(
+    State(worker): State<HeavyWorker>,
+    State(config): State<Arc<ApiConfig>>,
+    Query(q): Query<TierQuery>,
+) -> Response {
+    let (from, to) = (q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX));
+    let result = worker.run(move |conn| {
+        let tiers = tier_map(conn, &config)?;
+        let mut by_tier: BTreeMap<WalletTier, Acc> = BTreeMap::new();
+        for tier in tiers.values() {
+            by_tier.entry(*tier).or_default().addresses += 1;
+        }
+        let mut between: BTreeMap<(WalletTier, WalletTier), (U256, u64)> = BTreeMap::new();
+        for t in db::get_transfers_by_range(conn, from, to)? {
+            let (sender, recipient) = (tiers.get(&t.from).copied(), tiers.get(&t.to).copied());
+            if t.status == Some(TxStatus::Reverted) || sender == recipient {
+                continue;
+            }
+            let value = U256::from_dec_str(&t.value).unwrap_or_default();
+            if let Some(tier) = recipient {
+                let acc = by_tier.entry(tier).or_default();
+                acc.inflow = acc.inflow.saturating_add(value);
+                acc.transfer_count += 1;
+            }
+            if let Some(tier) = sender {
+                let acc = by_tier.entry(tier).or_default();
+                acc.outflow = acc.outflow.saturating_add(value);
+                acc.transfer_count += 1;
+            }
+            if let (Some(s), Some(r)) = (sender, recipient) {
+                let e = between.entry((s, r)).or_default();
+                e.0 = e.0.saturating_add(value);
+                e.1 += 1;
+            }
+        }
+        Ok((by_tier, between))
+    }).await;
+    let (by_tier, between) = match result {
+        Ok(r) => r,
+        Err(e) => return e.into_response(),
+    };
+
+    let fmt = q.format;
+    Json(TierFlows {
+        from_block: q.from_block,
+        to_block: q.to_block,
+        tiers: by_tier.into_iter().map(|(tier, a)| TierFlow {
+            tier,
+            addresses: a.addresses,
+            inflow: fmt.render(&a.inflow.to_string()),
+            outflow: fmt.render(&a.outflow.to_string()),
+            net: fmt.render(&signed_net(a.inflow, a.outflow)),
+            volume: fmt.render(&a.inflow.saturating_add(a.outflow).to_string()),
+            transfer_count: a.transfer_count,
+        }).collect(),
+        between_tiers: between.into_iter().map(|((from, to), (value, n))| TierTransfer {
+            from,
+            to,
+            value: fmt.render(&value.to_string()),
+            transfer_count: n,
+        }).collect(),
+    }).into_response()
+}
+
+/// Stored address (lowercase) → tier: recorded hot wallets, then discovered
+/// deposit addresses, then the `WALLET_TIERS` overrides.
+fn tier_map(conn: &rusqlite::Connection, config: &ApiConfig) -> eyre::Result<HashMap<String, WalletTier>> {
+    let mut tiers = HashMap::new();
+    let configured = db::get_dataset_binance_addresses(conn)?.unwrap_or_default();
+    for addr in configured.split(',').filter(|a| !a.is_empty()) {
+        tiers.insert(addr.to_string(), WalletTier::Hot);
+    }
+    for addr in db::list_deposit_addresses(conn)? {
+        tiers.insert(addr, WalletTier::Deposit);
+    }
+    for (addr, tier) in &config.wallet_tiers.0 {
+        tiers.insert(models::address_key(addr), *tier);
+    }
+    Ok(tiers)
+}
@@ -0,0 +1,84 @@
+//! `GET /netflow/by-token` — inflow/outflow/net per token contract, rather
+//! than the single figure `/netflow` reports for the configured token.
+//!
+//! Sums are exact (`U256`) over stored, non-reverted transfers. The configured
+//! token also carries `cumulative`, the signed running value `/netflow`
+//! serves. Amounts of different tokens aren't summed: a cross-token total needs
+//! prices, which this build doesn't have. Runs on the heavy-query worker.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::Serialize;
+
+use super::{range::signed_net, worker::HeavyWorker, ApiConfig, FormatQuery};
+use crate::{db, models::{self, Direction, TxStatus}};
+
+#[derive(Serialize)]
+pub struct TokenFlow {
+    token: String,
+    inflow: String,
+    outflow: String,
+    net: String,
+    transfer_count: u64,
+    /// Running cumulative as served by `/netflow`; only for the configured token
+    cumulative: Option<String>,
+}
+
+#[derive(Default)]
+struct Acc {
+    inflow: U256,
+    outflow: U256,
+    transfer_count: u64,
+}
+
+pub async fn by_token(
+    State(worker): State<HeavyWorker>,
+    State(config): State<Arc<ApiConfig>>,
+    Query(q): Query<FormatQuery>,
+) -> Response {
+    let tracked = models::address_key(&config.pol_token);
+    let result = worker.run(move |conn| {
+        let mut by_token: BTreeMap<String, Acc> = BTreeMap::new();
+        by_token.entry(tracked.clone()).or_default();
+        for t in db::get_transfers_by_range(conn, 0, u64::MAX)? {
+            let inflow = match t.direction {
+                Direction::In => true,
+                Direction::Out => false,
+                Direction::Internal | Direction::None => continue,
+            };
+            if t.status == Some(TxStatus::Reverted) {
+                continue;
+            }
+            let value = U256::from_dec_str(&t.value).unwrap_or_default();
+            let acc = by_token.entry(t.token).or_default();
+            if inflow {
+                acc.inflow = acc.inflow.saturating_add(value);
+            } else {
+                acc.outflow = acc.outflow.saturating_add(value);
+            }
+            acc.transfer_count += 1;
+        }
+        let cumulative = db::get_latest_cumulative(conn)?.cumulative_netflow_raw;
+        Ok((by_token, tracked, cumulative))
+    }).await;
+    let (by_token, tracked, cumulative) = match result {
+        Ok(r) => r,
+        Err(e) => return e.into_response(),
+    };
+
+    let fmt = q.format;
+    Json(by_token.into_iter().map(|(token, a)| TokenFlow {
+        cumulative: (token == tracked).then(|| fmt.render(&cumulative)),
+        token: models::checksummed(&token),
+        inflow: fmt.render(&a.inflow.to_string()),
+        outflow: fmt.render(&a.outflow.to_string()),
+        net: fmt.render(&signed_net(a.inflow, a.outflow)),
+        transfer_count: a.transfer_count,
+    }).collect::<Vec<_>>()).into_response()
+}
@@ -0,0 +1,190 @@
+//! In-memory cache of net-flow responses (`/netflow`, `/netflow/range`,
+//! `/netflow/history`), so a crowd of polling clients costs one SQLite read
+//! per distinct query per update rather than one per request.
+//!
+//! Entries are keyed by path and query string (minus `api_key`) and dropped
+//! whenever the indexer in this process publishes a net-flow update. They
+//! also expire after `API_CACHE_TTL_SECS`, which bounds how stale a response
+//! can be when nothing announces the change: a reorg rollback, an indexer in
+//! another process, or the clock moving the velocity window.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{self, Body, Bytes},
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{events::{Event, EventSender, Sequenced}, metrics};
+
+/// Distinct queries kept; past this the cache starts over.
+const MAX_ENTRIES: usize = 1_024;
+
+/// Larger responses (long hourly histories) are served but not kept.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+struct Entry {
+    stored: Instant,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ttl: Duration,
+    /// Bumped on every invalidation; a response computed across one isn't stored
+    generation: AtomicU64,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ResponseCache {
+    /// A cache cleared on every net-flow update from `events`. A zero `ttl`
+    /// disables it.
+    pub fn new(ttl: Duration, events: &EventSender) -> Self {
+        let cache = Self {
+            inner: Arc::new(Inner { ttl, generation: AtomicU64::new(0), entries: Mutex::new(HashMap::new()) }),
+        };
+        if !ttl.is_zero() {
+            tokio::spawn(cache.clone().invalidate_on_updates(events.subscribe()));
+        }
+        cache
+    }
+
+    fn invalidate(&self) {
+        self.inner.generation.fetch_add(1, Ordering::SeqCst);
+        self.inner.entries.lock().expect("cache lock").clear();
+    }
+
+    async fn invalidate_on_updates(self, mut rx: tokio::sync::broadcast::Receiver<Sequenced>) {
+        loop {
+            match rx.recv().await.map(|ev| ev.event) {
+                Ok(Event::Netflow(_)) | Err(RecvError::Lagged(_)) => self.invalidate(),
+                Ok(Event::Transfer(_) | Event::ProvisionalNetflow(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Response> {
+        let entries = self.inner.entries.lock().expect("cache lock");
+        let e = entries.get(key).filter(|e| e.stored.elapsed() < self.inner.ttl)?;
+        let mut res = Body::from(e.body.clone()).into_response();
+        *res.headers_mut() = e.headers.clone();
+        Some(res)
+    }
+
+    fn put(&self, key: String, generation: u64, entry: Entry) {
+        let mut entries = self.inner.entries.lock().expect("cache lock");
+        if self.inner.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.insert(key, entry);
+    }
+}
+
+/// Middleware for cached routes: serve a fresh entry, or run the handler and
+/// keep its `200` response.
+pub async fn cached(State(cache): State<ResponseCache>, req: Request, next: Next) -> Response {
+    if cache.inner.ttl.is_zero() {
+        return next.run(req).await;
+    }
+    let key = cache_key(&req);
+    if let Some(hit) = cache.get(&key) {
+        metrics::API_CACHE_REQUESTS.with_label_values(&["hit"]).inc();
+        return hit;
+    }
+    metrics::API_CACHE_REQUESTS.with_label_values(&["miss"]).inc();
+    let generation = cache.inner.generation.load(Ordering::SeqCst);
+    let res = next.run(req).await;
+    if res.status() != StatusCode::OK {
+        return res;
+    }
+    let (parts, body) = res.into_parts();
+    let body = match body::to_bytes(body, usize::MAX).await {
+        Ok(b) => b,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("reading response: {e}")).into_response(),
+    };
+    if body.len() <= MAX_BODY_BYTES {
+        cache.put(key, generation, Entry { stored: Instant::now(), headers: parts.headers.clone(), body: body.clone() });
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Path plus query, without `api_key` (the key only decides access, which
+/// the auth layer checked already).
+fn cache_key(req: &Request) -> String {
+    let uri = req.uri();
+    let query: Vec<&str> = uri.query().unwrap_or_default()
+        .split('&')
+        .filter(|kv| !kv.is_empty() && !kv.starts_with("api_key="))
+        .collect();
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{events, models::NetflowBlock};
+
+    use super::*;
+
+    fn entry(body: &'static str) -> Entry {
+        Entry { stored: Instant::now(), headers: HeaderMap::new(), body: Bytes::from_static(body.as_bytes()) }
+    }
+
+    #[test]
+    fn api_key_is_not_part_of_the_key() {
+        let req = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+        assert_eq!(cache_key(&req("/netflow?api_key=pk_a&format=scaled")), "/netflow?format=scaled");
+        assert_eq!(cache_key(&req("/netflow?api_key=pk_a")), cache_key(&req("/netflow")));
+        assert_ne!(cache_key(&req("/netflow?format=hex")), cache_key(&req("/netflow")));
+    }
+
+    #[tokio::test]
+    async fn netflow_update_clears_and_blocks_stale_stores() {
+        let events = events::channel();
+        let cache = ResponseCache::new(Duration::from_secs(60), &events);
+        let before = cache.inner.generation.load(Ordering::SeqCst);
+        cache.put("/netflow?".into(), before, entry("old"));
+        assert!(cache.get("/netflow?").is_some());
+
+        let block = NetflowBlock {
+            block_number: 1,
+            ts_unix: 0,
+            inflow: "1".into(),
+            outflow: "0".into(),
+            cumulative: "1".into(),
+            transfer_count: 1,
+            cumulative_token: "0.000000000000000001".into(),
+            price_usd_micro: None,
+            cumulative_usd_micro: None,
+        };
+        events.send(Event::Netflow(block).into()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while cache.get("/netflow?").is_some() {
+                tokio::task::yield_now().await;
+            }
+        }).await.expect("cleared on the update");
+
+        // Computed before the update, finished after: not kept
+        cache.put("/netflow?".into(), before, entry("stale"));
+        assert!(cache.get("/netflow?").is_none());
+    }
+}
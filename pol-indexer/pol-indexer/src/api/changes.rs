@@ -0,0 +1,145 @@
+//! `GET /changes?since_block=N&timeout=30s` — long-polling for clients that
+//! want near-real-time updates without holding a WebSocket or SSE stream.
+//!
+//! Once the indexed-through watermark is past `since_block`, this returns at
+//! once with the net-flow blocks and transfers above it. Until then the
+//! request is held. It wakes on the in-process event feed, and it also checks
+//! the database every second for an indexer running in another process. The
+//! wait ends when a block is indexed or after `?timeout=`: seconds, given as
+//! `30s`, `30` or `1m`. The default is 30s and the maximum 120s. A timeout
+//! returns the same shape with `changed: false`. At most `MAX_BLOCKS`
+//! net-flow blocks are returned per response. Clients pass
+//! `next_since_block` as the next `since_block` and get the rest straight
+//! away.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+
+use super::{internal_error, snapshot, with_watermark, Db};
+use crate::{db, events::EventSender, models::{self, NetflowBlock, TransferRecord, ValueFormat}};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+pub(super) const MAX_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Catches indexers in other processes, which don't publish to this feed.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Net-flow blocks per response; the rest follow from `next_since_block`.
+const MAX_BLOCKS: usize = 500;
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    since_block: u64,
+    timeout: Option<String>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct Changes {
+    since_block: u64,
+    /// `false` when the wait timed out with nothing newer indexed
+    changed: bool,
+    /// Latest indexed block; `0` before any
+    indexed_through_block: u64,
+    /// Pass this as the next `since_block`; below `indexed_through_block` when the response was capped
+    next_since_block: u64,
+    /// Blocks above `since_block` with matched transfers, oldest first
+    netflow: Vec<NetflowBlock>,
+    /// Transfers in those blocks, oldest first
+    transfers: Vec<TransferRecord>,
+}
+
+pub async fn changes(State(conn): State<Db>, State(events): State<EventSender>, Query(q): Query<ChangesQuery>) -> Response {
+    let timeout = match q.timeout.as_deref().map(parse_timeout).transpose() {
+        Ok(t) => t.unwrap_or(DEFAULT_TIMEOUT).min(MAX_TIMEOUT),
+        Err(msg) => return (StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+    let deadline = Instant::now() + timeout;
+    let mut rx = Some(events.subscribe());
+    loop {
+        let found = {
+            let mut conn = conn.lock().await;
+            snapshot(&mut conn, |conn, through| read(conn, q.since_block, through))
+        };
+        let (mut changes, through) = match found {
+            Ok(found) => found,
+            Err(e) => return internal_error(e),
+        };
+        if changes.changed || Instant::now() >= deadline {
+            for b in &mut changes.netflow {
+                b.inflow = q.format.render(&b.inflow);
+                b.outflow = q.format.render(&b.outflow);
+                b.cumulative = q.format.render(&b.cumulative);
+            }
+            for t in &mut changes.transfers {
+                t.render(q.format);
+            }
+            return with_watermark(Json(changes), through);
+        }
+        let wake = (Instant::now() + POLL_INTERVAL).min(deadline);
+        match &mut rx {
+            Some(events) => tokio::select! {
+                r = events.recv() => if let Err(RecvError::Closed) = r { rx = None },
+                _ = tokio::time::sleep_until(wake) => {}
+            },
+            None => tokio::time::sleep_until(wake).await,
+        }
+    }
+}
+
+fn read(conn: &rusqlite::Connection, since_block: u64, through: u64) -> eyre::Result<Changes> {
+    // Imported data without a watermark: bound by the latest stored block
+    let head = if through == u64::MAX {
+        db::stored_block_at_or_before(conn, u64::MAX)?.map_or(0, |(n, _)| n)
+    } else {
+        through
+    };
+    let mut changes = Changes {
+        since_block,
+        changed: head > since_block,
+        indexed_through_block: head,
+        next_since_block: head.max(since_block),
+        netflow: Vec::new(),
+        transfers: Vec::new(),
+    };
+    if !changes.changed {
+        return Ok(changes);
+    }
+    let mut netflow = db::get_netflow_blocks_by_range(conn, since_block + 1, head)?;
+    if netflow.len() > MAX_BLOCKS {
+        netflow.truncate(MAX_BLOCKS);
+        changes.next_since_block = netflow[MAX_BLOCKS - 1].block_number;
+    }
+    changes.transfers = db::get_transfers_by_range(conn, since_block + 1, changes.next_since_block)?;
+    changes.netflow = netflow;
+    Ok(changes)
+}
+
+/// `30s`, `30` (seconds) or a `models::parse_window` span such as `2m`.
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    let secs = s.strip_suffix('s').unwrap_or(s).parse::<u64>().ok()
+        .or_else(|| models::parse_window(s).map(|w| w as u64));
+    secs.map(Duration::from_secs).ok_or_else(|| format!("invalid timeout {s:?}; expected e.g. 30s or 1m"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts_parse_in_seconds_or_minutes() {
+        assert_eq!(parse_timeout("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_timeout("45"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_timeout("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_timeout("soon").is_err());
+    }
+}
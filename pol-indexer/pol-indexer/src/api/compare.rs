@@ -0,0 +1,100 @@
+//! `GET /compare?exchanges=binance&window=7d` — side-by-side inflow, outflow
+//! and net per exchange over a trailing window (`<n>m|h|d|w`, default `7d`).
+//!
+//! An instance indexes a single exchange's address set ([`auth::EXCHANGE`]),
+//! so naming any other exchange is rejected rather than answered with zeros.
+//! Omitting `exchanges` compares every exchange this instance tracks.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{internal_error, range::{signed_net, sum_blocks}, Db};
+use crate::{auth::{self, ApiKey}, db, models::{self, ValueFormat}};
+
+/// Exchanges whose address sets this instance indexes.
+const TRACKED: &[&str] = &[auth::EXCHANGE];
+const DEFAULT_WINDOW: &str = "7d";
+
+#[derive(Deserialize)]
+pub struct CompareQuery {
+    exchanges: Option<String>,
+    window: Option<String>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct Comparison {
+    window: String,
+    from_ts: i64,
+    to_ts: i64,
+    exchanges: Vec<ExchangeFlow>,
+}
+
+#[derive(Serialize)]
+pub struct ExchangeFlow {
+    exchange: String,
+    inflow: String,
+    outflow: String,
+    net: String,
+    transfer_count: u64,
+}
+
+pub async fn compare(
+    State(conn): State<Db>,
+    key: Option<Extension<ApiKey>>,
+    Query(q): Query<CompareQuery>,
+) -> Response {
+    let window = q.window.as_deref().unwrap_or(DEFAULT_WINDOW);
+    let Some(window_secs) = models::parse_window(window) else {
+        return (StatusCode::BAD_REQUEST, format!("invalid window {window:?}; expected e.g. 90m, 24h, 7d, 2w")).into_response();
+    };
+    let exchanges: Vec<String> = match q.exchanges.as_deref() {
+        Some(list) => auth::parse_scope(list).into_iter().filter(|e| e != "*").collect(),
+        None => TRACKED.iter().map(|e| e.to_string()).collect(),
+    };
+    if let Some(unknown) = exchanges.iter().find(|e| !TRACKED.contains(&e.as_str())) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("exchange {unknown:?} is not tracked by this instance (tracked: {})", TRACKED.join(",")),
+        ).into_response();
+    }
+    if let Some(Extension(key)) = &key {
+        if let Some(denied) = exchanges.iter().find(|e| !key.allows_exchange(e)) {
+            return (StatusCode::FORBIDDEN, format!("API key is not scoped to exchange {denied:?}")).into_response();
+        }
+    }
+
+    let to_ts = OffsetDateTime::now_utc().unix_timestamp();
+    let from_ts = to_ts.saturating_sub(window_secs);
+    let rows = {
+        let conn = conn.lock().await;
+        db::get_netflow_blocks_by_time(&conn, from_ts, to_ts)
+    };
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => return internal_error(e),
+    };
+
+    // Every tracked exchange shares this instance's aggregates
+    let (inflow, outflow, transfer_count) = sum_blocks(&rows);
+    let fmt = q.format;
+    Json(Comparison {
+        window: window.to_string(),
+        from_ts,
+        to_ts,
+        exchanges: exchanges.into_iter().map(|exchange| ExchangeFlow {
+            exchange,
+            inflow: fmt.render(&inflow.to_string()),
+            outflow: fmt.render(&outflow.to_string()),
+            net: fmt.render(&signed_net(inflow, outflow)),
+            transfer_count,
+        }).collect(),
+    }).into_response()
+}
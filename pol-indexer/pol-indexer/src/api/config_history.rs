@@ -0,0 +1,48 @@
+//! `GET /config/history` — changes to the tracked address set, newest first,
+//! to explain discontinuities in the net-flow.
+//!
+//! Configured addresses (`BINANCE_ADDRESSES`) are logged when `run`,
+//! `import` or `rebuild` records a different set; deposit addresses when
+//! they are discovered or rolled back with their block. `?address=` keeps
+//! one address's changes; `?limit=` caps the list (default 100, max 1000).
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use super::{internal_error, Db};
+use crate::{db, models};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    address: Option<String>,
+    limit: Option<u32>,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<HistoryQuery>) -> Response {
+    let address = match q.address.as_deref().map(models::parse_address).transpose() {
+        Ok(a) => a.map(|a| models::address_key(&a)),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let rows = {
+        let conn = conn.lock().await;
+        db::list_address_changes(&conn, address.as_deref(), limit)
+    };
+    match rows {
+        Ok(mut rows) => {
+            for r in &mut rows {
+                r.address = models::checksummed(&r.address);
+            }
+            Json(rows).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
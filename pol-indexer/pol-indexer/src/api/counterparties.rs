@@ -0,0 +1,111 @@
+//! `GET /counterparties` — addresses that sent to or received from Binance,
+//! ranked by total volume.
+//!
+//! `?direction=in|out|all` (default `all`) restricts to deposits into or
+//! withdrawals out of Binance; `from_block`/`to_block` bound the range
+//! (inclusive); `?limit=` caps the list (default 50, max 1000). Reverted and
+//! Binance-internal transfers are ignored. Runs on the heavy-query worker.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{range::signed_net, worker::HeavyWorker};
+use crate::{db, models::{self, checksummed, TxStatus, ValueFormat}};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+    #[default]
+    All,
+}
+
+#[derive(Deserialize)]
+pub struct CounterpartiesQuery {
+    #[serde(default)]
+    direction: Direction,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    limit: Option<usize>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct Counterparty {
+    address: String,
+    /// Sent by this address into Binance
+    inflow: String,
+    /// Sent by Binance to this address
+    outflow: String,
+    net: String,
+    transfer_count: u64,
+}
+
+#[derive(Default)]
+struct Acc {
+    inflow: U256,
+    outflow: U256,
+    transfer_count: u64,
+}
+
+pub async fn list(State(worker): State<HeavyWorker>, Query(q): Query<CounterpartiesQuery>) -> Response {
+    let (from_block, to_block) = (q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX));
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let direction = q.direction;
+    let ranked = worker.run(move |conn| {
+        let mut by_address: HashMap<String, Acc> = HashMap::new();
+        for t in db::get_transfers_by_range(conn, from_block, to_block)? {
+            if t.status == Some(TxStatus::Reverted) {
+                continue;
+            }
+            let value = U256::from_dec_str(&t.value).unwrap_or_default();
+            let acc = match (t.direction, direction) {
+                (models::Direction::In, Direction::In | Direction::All) => {
+                    let acc = by_address.entry(t.from).or_default();
+                    acc.inflow = acc.inflow.saturating_add(value);
+                    acc
+                }
+                (models::Direction::Out, Direction::Out | Direction::All) => {
+                    let acc = by_address.entry(t.to).or_default();
+                    acc.outflow = acc.outflow.saturating_add(value);
+                    acc
+                }
+                _ => continue,
+            };
+            acc.transfer_count += 1;
+        }
+        let mut ranked: Vec<_> = by_address.into_iter().collect();
+        ranked.sort_by(|(a_addr, a), (b_addr, b)| {
+            b.inflow.saturating_add(b.outflow)
+                .cmp(&a.inflow.saturating_add(a.outflow))
+                .then_with(|| a_addr.cmp(b_addr))
+        });
+        ranked.truncate(limit);
+        Ok(ranked)
+    }).await;
+    let ranked = match ranked {
+        Ok(r) => r,
+        Err(e) => return e.into_response(),
+    };
+
+    let fmt = q.format;
+    Json(ranked.into_iter().map(|(address, a)| Counterparty {
+        address: checksummed(&address),
+        inflow: fmt.render(&a.inflow.to_string()),
+        outflow: fmt.render(&a.outflow.to_string()),
+        net: fmt.render(&signed_net(a.inflow, a.outflow)),
+        transfer_count: a.transfer_count,
+    }).collect::<Vec<_>>()).into_response()
+}
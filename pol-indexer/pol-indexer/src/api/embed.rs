@@ -0,0 +1,158 @@
+//! Embeddable 24h net-flow badge: `GET /embed/netflow.svg` and `.png`.
+//!
+//! The SVG carries a label, the signed 24h net-flow in POL and a sparkline of
+//! the cumulative. The PNG is the sparkline only (no font rendering).
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use time::OffsetDateTime;
+
+use super::{internal_error, Db};
+use crate::{db, models::POL_DECIMALS};
+
+const WIDTH: u32 = 240;
+const HEIGHT: u32 = 48;
+const SPARK_X: f64 = 132.0;
+const PAD: f64 = 6.0;
+const WINDOW_SECS: i64 = 24 * 3600;
+const CACHE_CONTROL: &str = "public, max-age=60";
+
+struct Badge {
+    /// Signed 24h net-flow in whole tokens
+    net: f64,
+    /// (ts_unix, cumulative) points in raw units
+    points: Vec<(i64, f64)>,
+    from_ts: i64,
+    to_ts: i64,
+}
+
+async fn load(conn: &Db) -> eyre::Result<Badge> {
+    let to_ts = OffsetDateTime::now_utc().unix_timestamp();
+    let from_ts = to_ts - WINDOW_SECS;
+    let rows = {
+        let conn = conn.lock().await;
+        db::get_netflow_blocks_by_time(&conn, from_ts, to_ts)?
+    };
+    let scale = 10f64.powi(POL_DECIMALS as i32);
+    let net = rows.iter()
+        .map(|r| r.inflow.parse::<f64>().unwrap_or(0.0) - r.outflow.parse::<f64>().unwrap_or(0.0))
+        .sum::<f64>() / scale;
+    let points = rows.iter()
+        .map(|r| (r.ts_unix, r.cumulative.parse::<f64>().unwrap_or(0.0)))
+        .collect();
+    Ok(Badge { net, points, from_ts, to_ts })
+}
+
+/// Map points into the sparkline box `[x0, x1] x [PAD, HEIGHT - PAD]` (y grows downwards).
+fn project(b: &Badge, x0: f64, x1: f64) -> Vec<(f64, f64)> {
+    let (min, max) = b.points.iter()
+        .fold((f64::MAX, f64::MIN), |(lo, hi), &(_, v)| (lo.min(v), hi.max(v)));
+    let span_t = (b.to_ts - b.from_ts).max(1) as f64;
+    let span_v = if max > min { max - min } else { 1.0 };
+    let h = HEIGHT as f64 - 2.0 * PAD;
+    let mut out: Vec<(f64, f64)> = b.points.iter()
+        .map(|&(t, v)| {
+            let x = x0 + (t - b.from_ts) as f64 / span_t * (x1 - x0);
+            let y = if max > min { PAD + h - (v - min) / span_v * h } else { PAD + h / 2.0 };
+            (x, y)
+        })
+        .collect();
+    // Hold the last value until "now" (and draw a flat line when there's no data)
+    let last_y = out.last().map_or(PAD + h / 2.0, |p| p.1);
+    if out.is_empty() {
+        out.push((x0, last_y));
+    }
+    out.push((x1, last_y));
+    out
+}
+
+fn color(net: f64) -> (&'static str, [u8; 3]) {
+    if net >= 0.0 { ("#2da44e", [0x2d, 0xa4, 0x4e]) } else { ("#cf222e", [0xcf, 0x22, 0x2e]) }
+}
+
+fn compact(v: f64) -> String {
+    let (abs, sign) = (v.abs(), if v < 0.0 { "-" } else { "+" });
+    let (n, suffix) = match abs {
+        a if a >= 1e9 => (a / 1e9, "B"),
+        a if a >= 1e6 => (a / 1e6, "M"),
+        a if a >= 1e3 => (a / 1e3, "K"),
+        a => (a, ""),
+    };
+    format!("{sign}{n:.2}{suffix}")
+}
+
+pub async fn svg(State(conn): State<Db>) -> Response {
+    let badge = match load(&conn).await {
+        Ok(b) => b,
+        Err(e) => return internal_error(e),
+    };
+    let (stroke, _) = color(badge.net);
+    let path = project(&badge, SPARK_X, WIDTH as f64 - PAD)
+        .iter()
+        .map(|(x, y)| format!("{x:.1},{y:.1}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let body = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}" viewBox="0 0 {WIDTH} {HEIGHT}">
+<rect width="{WIDTH}" height="{HEIGHT}" rx="4" fill="#0d1117"/>
+<text x="8" y="19" font-family="Verdana,sans-serif" font-size="10" fill="#8b949e">POL → Binance 24h</text>
+<text x="8" y="37" font-family="Verdana,sans-serif" font-size="14" font-weight="bold" fill="{stroke}">{net} POL</text>
+<polyline points="{path}" fill="none" stroke="{stroke}" stroke-width="1.5"/>
+</svg>"##,
+        net = compact(badge.net),
+    );
+    (
+        [(header::CONTENT_TYPE, "image/svg+xml"), (header::CACHE_CONTROL, CACHE_CONTROL)],
+        body,
+    ).into_response()
+}
+
+pub async fn png(State(conn): State<Db>) -> Response {
+    let badge = match load(&conn).await {
+        Ok(b) => b,
+        Err(e) => return internal_error(e),
+    };
+    let (_, rgb) = color(badge.net);
+    let (w, h) = (WIDTH as usize, HEIGHT as usize);
+    let mut pixels = [0x0d, 0x11, 0x17, 0xff].repeat(w * h);
+
+    let points = project(&badge, PAD, WIDTH as f64 - PAD);
+    for seg in points.windows(2) {
+        draw_line(&mut pixels, w, h, seg[0], seg[1], rgb);
+    }
+
+    let mut out = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut out, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let written = encoder.write_header().and_then(|mut wr| wr.write_image_data(&pixels));
+        if let Err(e) = written {
+            return internal_error(e.into());
+        }
+    }
+    (
+        [(header::CONTENT_TYPE, "image/png"), (header::CACHE_CONTROL, CACHE_CONTROL)],
+        out,
+    ).into_response()
+}
+
+/// Naive DDA line, 2px thick.
+fn draw_line(px: &mut [u8], w: usize, h: usize, a: (f64, f64), b: (f64, f64), rgb: [u8; 3]) {
+    let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).ceil().max(1.0) as usize;
+    for i in 0..=steps {
+        let t = i as f64 / steps as f64;
+        let x = (a.0 + (b.0 - a.0) * t).round() as isize;
+        let y = (a.1 + (b.1 - a.1) * t).round() as isize;
+        for (dx, dy) in [(0, 0), (0, 1)] {
+            let (x, y) = (x + dx, y + dy);
+            if x >= 0 && y >= 0 && (x as usize) < w && (y as usize) < h {
+                let idx = (y as usize * w + x as usize) * 4;
+                px[idx..idx + 3].copy_from_slice(&rgb);
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! `GET /entities`, `GET /entities/{name}` — flows for entities, groups of
+//! addresses that belong to one party (say several Binance hot wallets),
+//! defined through `PUT /admin/entities/{name}`.
+//!
+//! An entity's `inflow`/`outflow` count only transfers crossing its boundary;
+//! moves between its own addresses are reported as `internal_value` and
+//! `internal_transfers` instead. The per-address detail of `/entities/{name}`
+//! counts every transfer to or from each address, internal ones included.
+//! Optional `?from_block=&to_block=` bounds (inclusive). Non-reverted stored
+//! transfers only. Runs on the heavy-query worker.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{range::signed_net, worker::HeavyWorker};
+use crate::{db, models::{self, TxStatus, ValueFormat}};
+
+#[derive(Deserialize)]
+pub struct EntityQuery {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct EntityList {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    entities: Vec<EntityFlow>,
+}
+
+#[derive(Serialize)]
+pub struct EntityFlow {
+    entity: String,
+    inflow: String,
+    outflow: String,
+    net: String,
+    volume: String,
+    transfer_count: u64,
+    internal_value: String,
+    internal_transfers: u64,
+    /// Members only for the list; member flows for `/entities/{name}`
+    addresses: Vec<AddressFlow>,
+}
+
+#[derive(Serialize)]
+pub struct AddressFlow {
+    address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inflow: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outflow: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    net: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transfer_count: Option<u64>,
+}
+
+#[derive(Default)]
+struct Acc {
+    inflow: U256,
+    outflow: U256,
+    transfer_count: u64,
+}
+
+#[derive(Default)]
+struct EntityAcc {
+    flow: Acc,
+    internal: Acc,
+    /// Member address (lowercase) → its own flows
+    members: BTreeMap<String, Acc>,
+}
+
+pub async fn list(State(worker): State<HeavyWorker>, Query(q): Query<EntityQuery>) -> Response {
+    let (from, to) = (q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX));
+    let result = worker.run(move |conn| {
+        let entities = db::list_entities(conn)?;
+        accumulate(conn, entities, from, to)
+    }).await;
+    let accs = match result {
+        Ok(r) => r,
+        Err(e) => return e.into_response(),
+    };
+    Json(EntityList {
+        from_block: q.from_block,
+        to_block: q.to_block,
+        entities: accs.into_iter().map(|(entity, acc)| render(entity, acc, q.format, false)).collect(),
+    }).into_response()
+}
+
+pub async fn detail(
+    State(worker): State<HeavyWorker>,
+    Path(name): Path<String>,
+    Query(q): Query<EntityQuery>,
+) -> Response {
+    let (from, to) = (q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX));
+    let wanted = name.clone();
+    let result = worker.run(move |conn| {
+        let mut entities = db::list_entities(conn)?;
+        entities.retain(|entity, _| *entity == wanted);
+        accumulate(conn, entities, from, to)
+    }).await;
+    let acc = match result {
+        Ok(mut r) => r.remove(&name),
+        Err(e) => return e.into_response(),
+    };
+    match acc {
+        Some(acc) => Json(render(name, acc, q.format, true)).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("no entity named {name}")).into_response(),
+    }
+}
+
+/// Flows for each of `entities` (entity → lowercase members) over the
+/// stored transfers in `from..=to`.
+fn accumulate(
+    conn: &rusqlite::Connection,
+    entities: BTreeMap<String, Vec<String>>,
+    from: u64,
+    to: u64,
+) -> eyre::Result<BTreeMap<String, EntityAcc>> {
+    let mut owner: HashMap<String, String> = HashMap::new();
+    let mut accs: BTreeMap<String, EntityAcc> = BTreeMap::new();
+    for (entity, members) in entities {
+        let acc = accs.entry(entity.clone()).or_default();
+        for m in members {
+            acc.members.insert(m.clone(), Acc::default());
+            owner.insert(m, entity.clone());
+        }
+    }
+    if owner.is_empty() {
+        return Ok(accs);
+    }
+    for t in db::get_transfers_by_range(conn, from, to)? {
+        let (sender, recipient) = (owner.get(&t.from), owner.get(&t.to));
+        if t.status == Some(TxStatus::Reverted) || (sender.is_none() && recipient.is_none()) {
+            continue;
+        }
+        let value = U256::from_dec_str(&t.value).unwrap_or_default();
+        if let Some(entity) = sender {
+            let acc = accs.get_mut(entity).expect("owner is an entity");
+            let m = acc.members.get_mut(&t.from).expect("owner is a member");
+            m.outflow = m.outflow.saturating_add(value);
+            m.transfer_count += 1;
+            if recipient == Some(entity) {
+                acc.internal.inflow = acc.internal.inflow.saturating_add(value);
+                acc.internal.transfer_count += 1;
+            } else {
+                acc.flow.outflow = acc.flow.outflow.saturating_add(value);
+                acc.flow.transfer_count += 1;
+            }
+        }
+        if let Some(entity) = recipient {
+            let acc = accs.get_mut(entity).expect("owner is an entity");
+            let m = acc.members.get_mut(&t.to).expect("owner is a member");
+            m.inflow = m.inflow.saturating_add(value);
+            m.transfer_count += 1;
+            if sender != Some(entity) {
+                acc.flow.inflow = acc.flow.inflow.saturating_add(value);
+                acc.flow.transfer_count += 1;
+            }
+        }
+    }
+    Ok(accs)
+}
+
+fn render(entity: String, acc: EntityAcc, fmt: ValueFormat, detail: bool) -> EntityFlow {
+    let addresses = acc.members.into_iter().map(|(address, m)| AddressFlow {
+        address: models::checksummed(&address),
+        inflow: detail.then(|| fmt.render(&m.inflow.to_string())),
+        outflow: detail.then(|| fmt.render(&m.outflow.to_string())),
+        net: detail.then(|| fmt.render(&signed_net(m.inflow, m.outflow))),
+        transfer_count: detail.then_some(m.transfer_count),
+    }).collect();
+    EntityFlow {
+        entity,
+        inflow: fmt.render(&acc.flow.inflow.to_string()),
+        outflow: fmt.render(&acc.flow.outflow.to_string()),
+        net: fmt.render(&signed_net(acc.flow.inflow, acc.flow.outflow)),
+        volume: fmt.render(&acc.flow.inflow.saturating_add(acc.flow.outflow).to_string()),
+        transfer_count: acc.flow.transfer_count,
+        internal_value: fmt.render(&acc.internal.inflow.to_string()),
+        internal_transfers: acc.internal.transfer_count,
+        addresses,
+    }
+}
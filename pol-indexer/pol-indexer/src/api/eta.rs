@@ -0,0 +1,94 @@
+//! `GET /blocks/{number}/eta` — when a block is (or was) expected, for lining
+//! the data up with events scheduled by block height (unlocks, burns,
+//! upgrades).
+//!
+//! The average block interval is measured between the latest stored block and
+//! the stored block closest to `?window=` blocks before it (default 43,200,
+//! about a day on Polygon); the estimate extrapolates from the latest stored
+//! block. A block that is already stored gets its actual timestamp instead.
+//! Only blocks with matched transfers are stored, so the window's ends fall
+//! where those are.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::{internal_error, Db};
+use crate::db;
+
+const DEFAULT_WINDOW: u64 = 43_200;
+
+#[derive(Deserialize)]
+pub struct EtaQuery {
+    window: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct BlockEta {
+    block_number: u64,
+    /// `false` when the block is stored and `ts_unix` is its own timestamp
+    estimated: bool,
+    ts_unix: i64,
+    /// `ts_unix` in RFC 3339 (UTC)
+    time: String,
+    /// Seconds from now until `ts_unix`; negative for the past
+    eta_secs: i64,
+    /// Stored block the estimate extrapolates from
+    reference_block: u64,
+    reference_ts_unix: i64,
+    /// Average seconds per block over the sample
+    avg_block_secs: f64,
+    /// Blocks between the sample's ends
+    sample_blocks: u64,
+}
+
+pub async fn eta(State(conn): State<Db>, Path(number): Path<u64>, Query(q): Query<EtaQuery>) -> Response {
+    let window = q.window.unwrap_or(DEFAULT_WINDOW).max(1);
+    let loaded = {
+        let conn = conn.lock().await;
+        (|| {
+            let at = db::stored_block_at_or_before(&conn, number)?;
+            let Some((latest, latest_ts)) = db::stored_block_at_or_before(&conn, u64::MAX)? else {
+                return Ok::<_, eyre::Report>((at, None, None));
+            };
+            let base = match db::stored_block_at_or_before(&conn, latest.saturating_sub(window))? {
+                Some(b) => Some(b),
+                None => db::first_stored_block(&conn)?,
+            };
+            Ok((at, Some((latest, latest_ts)), base))
+        })()
+    };
+    let (at, latest, base) = match loaded {
+        Ok(found) => found,
+        Err(e) => return internal_error(e),
+    };
+    let (Some((ref_n, ref_ts)), Some((base_n, base_ts))) = (latest, base) else {
+        return (StatusCode::NOT_FOUND, "no blocks indexed yet").into_response();
+    };
+    if base_n >= ref_n {
+        return (StatusCode::NOT_FOUND, "not enough indexed blocks to measure the block time yet").into_response();
+    }
+    let avg_block_secs = (ref_ts - base_ts) as f64 / (ref_n - base_n) as f64;
+    let (estimated, ts_unix) = match at {
+        Some((n, ts)) if n == number => (false, ts),
+        _ => (true, ref_ts + ((number as f64 - ref_n as f64) * avg_block_secs).round() as i64),
+    };
+    let time = OffsetDateTime::from_unix_timestamp(ts_unix).ok().and_then(|t| t.format(&Rfc3339).ok()).unwrap_or_default();
+
+    Json(BlockEta {
+        block_number: number,
+        estimated,
+        ts_unix,
+        time,
+        eta_secs: ts_unix - OffsetDateTime::now_utc().unix_timestamp(),
+        reference_block: ref_n,
+        reference_ts_unix: ref_ts,
+        avg_block_secs: (avg_block_secs * 1e3).round() / 1e3,
+        sample_blocks: ref_n - base_n,
+    }).into_response()
+}
@@ -0,0 +1,207 @@
+//! `GET /events` — Server-Sent Events stream of [`Event`]s, each one a JSON
+//! [`Envelope`](crate::events::Envelope) in an SSE event named `transfer`,
+//! `netflow` or (with `FAST_PATH`) `provisional_netflow`. Supports `?format=`
+//! like the other endpoints.
+//!
+//! `?types=` picks the event names, and `?min_value=` (raw units),
+//! `?direction=`, `?address=` and `?token=` narrow the transfers, so a
+//! consumer only gets what it asked for. See [`EventFilter`].
+//!
+//! A logged event's SSE `id` is its `seq`. `?since_seq=N`, or the
+//! `Last-Event-ID` header browsers send when they reconnect, replays the
+//! logged events after `N` from `event_log` and then carries on live, with
+//! nothing lost in between; an event may arrive twice only across a
+//! reconnect. Once a subscriber has seen a `seq`, falling behind the live
+//! feed is also caught up from the log instead of skipped. A `since_seq`
+//! past the last assigned `seq` is refused: it came from another database.
+
+use std::{collections::VecDeque, convert::Infallible};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{sse::{Event as SseEvent, KeepAlive, Sse}, IntoResponse, Response},
+};
+use futures_util::stream;
+use serde::Deserialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio_stream::{Stream, StreamExt};
+
+use super::{internal_error, Db};
+use crate::{db, events::{EventFilter, EventSender, Sequenced}, models::ValueFormat};
+
+/// `event_log` rows read per query while catching up.
+const REPLAY_PAGE: u32 = 500;
+
+#[derive(Deserialize)]
+pub struct EventsQuery {
+    #[serde(default)]
+    format: ValueFormat,
+    types: Option<String>,
+    min_value: Option<String>,
+    direction: Option<String>,
+    address: Option<String>,
+    token: Option<String>,
+    since_seq: Option<u64>,
+}
+
+pub async fn stream(
+    State(conn): State<Db>,
+    State(events): State<EventSender>,
+    headers: HeaderMap,
+    Query(q): Query<EventsQuery>,
+) -> Response {
+    let filter = match EventFilter::parse(q.types.as_deref(), q.min_value.as_deref(), q.direction.as_deref(), q.address.as_deref(), q.token.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let since = match q.since_seq.map(Ok).or_else(|| last_event_id(&headers)).transpose() {
+        Ok(since) => since,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    // Subscribed before the log is read, so nothing logged after it is missed
+    let rx = events.subscribe();
+    if let Some(since) = since {
+        let last = db::last_event_seq(&*conn.lock().await);
+        match last {
+            Ok(last) if since > last => {
+                let msg = format!("since_seq {since} is past the last event logged ({last}); was the database replaced?");
+                return (StatusCode::BAD_REQUEST, msg).into_response();
+            }
+            Ok(_) => {}
+            Err(e) => return internal_error(e),
+        }
+    }
+    let feed = Feed { conn, rx, cursor: since, catch_up: since.is_some(), pending: VecDeque::new() };
+    sse(feed, filter, q.format).into_response()
+}
+
+fn last_event_id(headers: &HeaderMap) -> Option<Result<u64, String>> {
+    let id = headers.get("last-event-id")?;
+    Some(id.to_str().ok().and_then(|id| id.trim().parse().ok()).ok_or_else(|| format!("Last-Event-ID must be a seq, got {id:?}")))
+}
+
+fn sse(feed: Feed, filter: EventFilter, format: ValueFormat) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let items = stream::unfold(feed, |mut feed| async move { feed.next().await.map(|item| (item, feed)) });
+    let stream = items.filter_map(move |item| match item {
+        Item::Event(ev) if !filter.matches(&ev.event) => None,
+        Item::Event(ev) => to_sse(Sequenced { seq: ev.seq, event: ev.event.formatted(format) }),
+        Item::Lagged(n) => {
+            tracing::warn!(skipped = n, "SSE subscriber lagged");
+            Some(SseEvent::default().comment(format!("lagged, skipped {n} events")))
+        }
+    });
+    Sse::new(stream.map(Ok)).keep_alive(KeepAlive::default())
+}
+
+fn to_sse(ev: Sequenced) -> Option<SseEvent> {
+    let sse = SseEvent::default().event(ev.event.kind());
+    let sse = match ev.seq {
+        Some(seq) => sse.id(seq.to_string()),
+        None => sse,
+    };
+    sse.json_data(ev.envelope()).ok()
+}
+
+enum Item {
+    Event(Sequenced),
+    /// Live events skipped before any `seq` was seen
+    Lagged(u64),
+}
+
+/// One subscriber's events in `seq` order: from `event_log` while catching
+/// up, then from the live feed.
+struct Feed {
+    conn: Db,
+    rx: Receiver<Sequenced>,
+    /// Last `seq` delivered
+    cursor: Option<u64>,
+    catch_up: bool,
+    pending: VecDeque<Sequenced>,
+}
+
+impl Feed {
+    /// `None` ends the stream; the client reconnects with `Last-Event-ID`.
+    async fn next(&mut self) -> Option<Item> {
+        loop {
+            if let Some(ev) = self.pending.pop_front() {
+                return Some(Item::Event(ev));
+            }
+            if self.catch_up {
+                let after = self.cursor.unwrap_or(0);
+                let page = db::events_after(&*self.conn.lock().await, after, REPLAY_PAGE);
+                let rows = match page {
+                    Ok(rows) => rows,
+                    Err(e) => {
+                        tracing::warn!(error = %format!("{e:#}"), after, "Reading event_log failed; closing the stream");
+                        return None;
+                    }
+                };
+                self.catch_up = rows.len() == REPLAY_PAGE as usize;
+                for (seq, payload) in rows {
+                    match Sequenced::from_log(seq, &payload) {
+                        Ok(ev) => self.pending.push_back(ev),
+                        Err(e) => tracing::warn!(seq, error = %format!("{e:#}"), "Unreadable event_log entry skipped"),
+                    }
+                    self.cursor = Some(seq);
+                }
+                continue;
+            }
+            match self.rx.recv().await {
+                Ok(ev) => match (ev.seq, self.cursor) {
+                    // Already replayed
+                    (Some(seq), Some(cursor)) if seq <= cursor => {}
+                    // Something in between was missed; it is in the log, and so is `ev`
+                    (Some(seq), Some(cursor)) if seq > cursor + 1 => self.catch_up = true,
+                    _ => {
+                        self.cursor = self.cursor.max(ev.seq);
+                        return Some(Item::Event(ev));
+                    }
+                },
+                Err(RecvError::Lagged(_)) if self.cursor.is_some() => self.catch_up = true,
+                Err(RecvError::Lagged(n)) => return Some(Item::Lagged(n)),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{events::{self, Event}, models::NetflowBlock};
+
+    fn netflow(block_number: u64) -> Event {
+        Event::Netflow(NetflowBlock {
+            block_number, ts_unix: 0, inflow: "1".into(), outflow: "0".into(), cumulative: "1".into(), transfer_count: 1,
+            cumulative_token: "0".into(), price_usd_micro: None, cumulative_usd_micro: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_log_then_follows_live_without_gaps_or_repeats() {
+        let scratch = db::Scratch::new("event-log");
+        let conn = db::init(scratch.path()).unwrap();
+        let logged: Vec<Sequenced> = (1..=3).map(|b| netflow(b).log(&conn).unwrap()).collect();
+        let conn: Db = Arc::new(Mutex::new(conn));
+        let events = events::channel();
+        let mut feed = Feed { conn: conn.clone(), rx: events.subscribe(), cursor: Some(1), catch_up: true, pending: VecDeque::new() };
+        let mut next = async || match feed.next().await {
+            Some(Item::Event(ev)) => ev.seq,
+            _ => panic!("expected an event"),
+        };
+        assert_eq!((next().await, next().await), (Some(2), Some(3)), "replayed after since_seq");
+
+        // Already replayed
+        events.send(logged[2].clone()).unwrap();
+        // 4's live copy is lost; 5 reveals the gap
+        netflow(4).log(&*conn.lock().await).unwrap();
+        events.send(netflow(5).log(&*conn.lock().await).unwrap()).unwrap();
+        events.send(netflow(6).into()).unwrap();
+        assert_eq!([next().await, next().await, next().await], [Some(4), Some(5), None], "unlogged events pass through");
+    }
+}
@@ -0,0 +1,137 @@
+//! `GET /export/transfers.csv` — stored transfers in `[from_block, to_block]`
+//! (inclusive; default: all of them up to the watermark) as CSV, the same
+//! columns as `pol-indexer export --table transfers`, so the file can be fed
+//! to `import`.
+//!
+//! Rows are streamed with chunked transfer encoding as they are read: memory
+//! stays at a few chunks whatever the range. The export takes a heavy-query
+//! worker until the client has read it all, but no timeout applies to it. An
+//! error partway through aborts the response, so a truncated file is never
+//! mistaken for a complete one.
+
+use std::io::{self, Write};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+
+use super::{with_watermark, worker::HeavyWorker};
+use crate::{db, export};
+
+/// Bytes gathered before a chunk is sent
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Chunks buffered ahead of a slow client
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+}
+
+pub async fn transfers_csv(State(heavy): State<HeavyWorker>, Query(q): Query<ExportQuery>) -> Response {
+    let (watermark, read_through) = oneshot::channel();
+    let (chunks, body) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let started = heavy.spawn(move |conn| {
+        let through = db::indexed_through_block(conn)?;
+        let _ = watermark.send(through);
+        let mut out = ChunkWriter { buf: Vec::with_capacity(CHUNK_BYTES), chunks, closed: false };
+        let to = q.to_block.unwrap_or(u64::MAX).min(through);
+        match export::write_transfers_csv(conn, q.from_block.unwrap_or(0), to, &mut out) {
+            Ok(rows) => {
+                tracing::debug!(rows, "Transfer export streamed");
+                Ok(())
+            }
+            Err(_) if out.closed => {
+                tracing::debug!("Transfer export abandoned by the client");
+                Ok(())
+            }
+            Err(e) => {
+                let _ = out.chunks.blocking_send(Err(io::Error::other(e.to_string())));
+                Err(e)
+            }
+        }
+    }).await;
+    if let Err(e) = started {
+        return e.into_response();
+    }
+    let Ok(through) = read_through.await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "export failed before the first row").into_response();
+    };
+    let headers = [
+        (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+        (header::CONTENT_DISPOSITION, "attachment; filename=\"transfers.csv\""),
+    ];
+    with_watermark((headers, Body::from_stream(ReceiverStream::new(body))), through)
+}
+
+/// Hands the CSV to the response body in [`CHUNK_BYTES`] pieces, blocking
+/// while the client is [`CHUNKS_IN_FLIGHT`] chunks behind.
+struct ChunkWriter {
+    buf: Vec<u8>,
+    chunks: mpsc::Sender<Result<Bytes, io::Error>>,
+    /// The response was dropped, i.e. the client went away
+    closed: bool,
+}
+
+impl ChunkWriter {
+    fn send(&mut self) -> io::Result<()> {
+        let chunk = Bytes::from(std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_BYTES)));
+        if self.chunks.blocking_send(Ok(chunk)).is_err() {
+            self.closed = true;
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        Ok(())
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_BYTES {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::{Direction, TxStatus};
+
+    #[tokio::test]
+    async fn streams_the_range_up_to_the_watermark() {
+        let scratch = db::Scratch::new("export-stream");
+        let conn = db::init(scratch.path()).unwrap();
+        for block in 1..=3_000_u64 {
+            let hash = format!("0x{block:064x}");
+            db::insert_transfer(&conn, block, &hash, &hash, 0, "0xt", "0xs", "0xr", "1000", Direction::In, Some(TxStatus::Success)).unwrap();
+        }
+        db::advance_last_indexed_block(&conn, 2_500).unwrap();
+
+        let heavy = HeavyWorker::new(scratch.path(), 1, Duration::from_secs(5));
+        let res = transfers_csv(State(heavy), Query(ExportQuery { from_block: Some(2), to_block: None })).await;
+        assert_eq!(res.headers()[super::super::INDEXED_THROUGH_HEADER], "2500");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert!(body.len() > CHUNK_BYTES, "spans several chunks");
+        let mut rows = csv::Reader::from_reader(&body[..]).into_records().map(|r| r.unwrap());
+        assert_eq!(&rows.next().unwrap()[0], "2");
+        assert_eq!(rows.count(), 2_498);
+    }
+}
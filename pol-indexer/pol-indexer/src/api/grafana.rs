@@ -0,0 +1,100 @@
+//! Grafana simple-json datasource contract (also works with the Infinity plugin).
+//!
+//! Series are built from the `netflow_blocks` aggregates and bucketed to the
+//! panel's `intervalMs`. Values are raw token units as `f64`, which is plenty
+//! of precision for charting.
+
+use std::collections::BTreeMap;
+
+use axum::{extract::State, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{internal_error, Db};
+use crate::{db, models::NetflowBlock};
+
+const METRICS: &[&str] = &["cumulative_netflow", "netflow", "inflow", "outflow", "transfer_count"];
+
+#[derive(Deserialize)]
+pub struct QueryRequest {
+    range: TimeRange,
+    #[serde(rename = "intervalMs", default)]
+    interval_ms: Option<u64>,
+    #[serde(default)]
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct TimeRange {
+    #[serde(with = "time::serde::rfc3339")]
+    from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    to: OffsetDateTime,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    #[serde(default)]
+    target: String,
+}
+
+#[derive(Serialize)]
+pub struct TimeSeries {
+    target: String,
+    datapoints: Vec<(f64, i64)>,
+}
+
+/// `GET /grafana` — datasource "Test connection".
+pub async fn health() -> &'static str {
+    "OK"
+}
+
+/// `POST /grafana/search` — list of queryable metric names.
+pub async fn search() -> Json<&'static [&'static str]> {
+    Json(METRICS)
+}
+
+/// `POST /grafana/query` — one time series per requested target.
+pub async fn query(State(conn): State<Db>, Json(req): Json<QueryRequest>) -> Response {
+    let rows = {
+        let conn = conn.lock().await;
+        match db::get_netflow_blocks_by_time(&conn, req.range.from.unix_timestamp(), req.range.to.unix_timestamp()) {
+            Ok(rows) => rows,
+            Err(e) => return internal_error(e),
+        }
+    };
+    let bucket_secs = (req.interval_ms.unwrap_or(60_000) / 1000).max(1) as i64;
+
+    let series: Vec<TimeSeries> = req.targets.iter()
+        .filter(|t| METRICS.contains(&t.target.as_str()))
+        .map(|t| TimeSeries {
+            target: t.target.clone(),
+            datapoints: bucketize(&rows, &t.target, bucket_secs),
+        })
+        .collect();
+    Json(series).into_response()
+}
+
+fn bucketize(rows: &[NetflowBlock], metric: &str, bucket_secs: i64) -> Vec<(f64, i64)> {
+    let mut buckets: BTreeMap<i64, f64> = BTreeMap::new();
+    for row in rows {
+        let bucket = row.ts_unix - row.ts_unix.rem_euclid(bucket_secs);
+        let inflow = parse_f64(&row.inflow);
+        let outflow = parse_f64(&row.outflow);
+        let entry = buckets.entry(bucket).or_insert(0.0);
+        match metric {
+            // Gauge: last value in the bucket wins
+            "cumulative_netflow" => *entry = parse_f64(&row.cumulative),
+            "netflow" => *entry += inflow - outflow,
+            "inflow" => *entry += inflow,
+            "outflow" => *entry += outflow,
+            "transfer_count" => *entry += row.transfer_count as f64,
+            _ => {}
+        }
+    }
+    buckets.into_iter().map(|(ts, v)| (v, ts * 1000)).collect()
+}
+
+fn parse_f64(dec: &str) -> f64 {
+    dec.parse().unwrap_or(0.0)
+}
@@ -0,0 +1,98 @@
+//! `GET /activity/heatmap?window=30d` — transfer counts and volumes by UTC
+//! hour-of-day and day-of-week over a trailing window (`<n>m|h|d|w`, default
+//! `30d`), to spot an exchange's operational rhythm such as scheduled sweeps.
+//!
+//! Always returns all 168 cells, Monday 00:00 first, zeros included, so
+//! clients can draw the grid directly. `volume` is `inflow + outflow`. Built
+//! from the per-block aggregates. Runs on the heavy-query worker.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::{range::signed_net, worker::HeavyWorker};
+use crate::{db, models::{self, ValueFormat}};
+
+const DEFAULT_WINDOW: &str = "30d";
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Deserialize)]
+pub struct HeatmapQuery {
+    window: Option<String>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct Heatmap {
+    window: String,
+    from_ts: i64,
+    to_ts: i64,
+    cells: Vec<Cell>,
+}
+
+#[derive(Serialize)]
+pub struct Cell {
+    day_of_week: &'static str,
+    /// UTC hour, 0-23
+    hour: u8,
+    transfer_count: u64,
+    inflow: String,
+    outflow: String,
+    net: String,
+    volume: String,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Acc {
+    inflow: U256,
+    outflow: U256,
+    transfer_count: u64,
+}
+
+pub async fn heatmap(State(worker): State<HeavyWorker>, Query(q): Query<HeatmapQuery>) -> Response {
+    let window = q.window.as_deref().unwrap_or(DEFAULT_WINDOW).to_string();
+    let Some(window_secs) = models::parse_window(&window) else {
+        return (StatusCode::BAD_REQUEST, format!("invalid window {window:?}; expected e.g. 24h, 30d, 2w")).into_response();
+    };
+    let to_ts = OffsetDateTime::now_utc().unix_timestamp();
+    let from_ts = to_ts.saturating_sub(window_secs);
+
+    let grid = worker.run(move |conn| {
+        let mut grid = [Acc::default(); 7 * 24];
+        for r in db::get_netflow_blocks_by_time(conn, from_ts, to_ts)? {
+            let Ok(t) = OffsetDateTime::from_unix_timestamp(r.ts_unix) else { continue };
+            let acc = &mut grid[t.weekday().number_days_from_monday() as usize * 24 + t.hour() as usize];
+            acc.inflow = acc.inflow.saturating_add(U256::from_dec_str(&r.inflow).unwrap_or_default());
+            acc.outflow = acc.outflow.saturating_add(U256::from_dec_str(&r.outflow).unwrap_or_default());
+            acc.transfer_count += r.transfer_count;
+        }
+        Ok(grid)
+    }).await;
+    let grid = match grid {
+        Ok(g) => g,
+        Err(e) => return e.into_response(),
+    };
+
+    let fmt = q.format;
+    Json(Heatmap {
+        window,
+        from_ts,
+        to_ts,
+        cells: grid.iter().enumerate().map(|(i, a)| Cell {
+            day_of_week: DAYS[i / 24],
+            hour: (i % 24) as u8,
+            transfer_count: a.transfer_count,
+            inflow: fmt.render(&a.inflow.to_string()),
+            outflow: fmt.render(&a.outflow.to_string()),
+            net: fmt.render(&signed_net(a.inflow, a.outflow)),
+            volume: fmt.render(&a.inflow.saturating_add(a.outflow).to_string()),
+        }).collect(),
+    }).into_response()
+}
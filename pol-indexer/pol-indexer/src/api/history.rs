@@ -0,0 +1,135 @@
+//! `GET /netflow/history` — inflow/outflow/net per time bucket.
+//!
+//! `?interval=hour|day|week` (default `day`) sets the bucket width; buckets
+//! are aligned to the unix epoch (UTC) and only those containing at least one
+//! matched transfer are returned. `from_ts`/`to_ts` bound the range
+//! (inclusive), `?format=` renders amounts. Each bucket lists the annotations
+//! on its transfers. With `TRACK_VOLUME`, each bucket also carries the token's
+//! total transfer volume and Binance's share of it. Blocks above the
+//! indexed-through watermark are left out. Runs on the heavy-query worker.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{range::{exchange_share, signed_net}, with_watermark, worker::HeavyWorker};
+use crate::{db, models::{Annotation, ValueFormat}};
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl Interval {
+    fn secs(self) -> i64 {
+        match self {
+            Interval::Hour => 3_600,
+            Interval::Day => 86_400,
+            Interval::Week => 7 * 86_400,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    #[serde(default)]
+    interval: Interval,
+    from_ts: Option<i64>,
+    to_ts: Option<i64>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct Bucket {
+    /// Unix timestamp the bucket starts at
+    start_ts: i64,
+    inflow: String,
+    outflow: String,
+    net: String,
+    /// Cumulative net-flow after the bucket's last block
+    cumulative: String,
+    transfer_count: u64,
+    blocks: u64,
+    /// Transfer volume across all addresses; `null` for buckets with matched transfers from before `volume_since_block`
+    total_volume: Option<String>,
+    /// `inflow + outflow` as a percentage of `total_volume`
+    exchange_share_pct: Option<f64>,
+    /// Annotated transfers in the bucket
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Default)]
+struct Acc {
+    inflow: U256,
+    outflow: U256,
+    cumulative: String,
+    transfer_count: u64,
+    blocks: u64,
+    first_block: Option<u64>,
+    volume: U256,
+    annotations: Vec<Annotation>,
+}
+
+pub async fn history(State(worker): State<HeavyWorker>, Query(q): Query<HistoryQuery>) -> Response {
+    let width = q.interval.secs();
+    let (from_ts, to_ts) = (q.from_ts.unwrap_or(i64::MIN), q.to_ts.unwrap_or(i64::MAX));
+    let buckets = worker.run(move |conn| {
+        let through = db::indexed_through_block(conn)?;
+        let mut buckets: BTreeMap<i64, Acc> = BTreeMap::new();
+        for r in db::get_netflow_blocks_by_time(conn, from_ts, to_ts)?.into_iter().filter(|r| r.block_number <= through) {
+            let acc = buckets.entry(r.ts_unix.div_euclid(width) * width).or_default();
+            acc.inflow = acc.inflow.saturating_add(U256::from_dec_str(&r.inflow).unwrap_or_default());
+            acc.outflow = acc.outflow.saturating_add(U256::from_dec_str(&r.outflow).unwrap_or_default());
+            acc.cumulative = r.cumulative;
+            acc.transfer_count += r.transfer_count;
+            acc.blocks += 1;
+            acc.first_block = Some(acc.first_block.map_or(r.block_number, |b| b.min(r.block_number)));
+        }
+        let since = db::get_volume_since_block(conn)?;
+        if since.is_some() {
+            for r in db::get_volume_blocks_by_time(conn, from_ts, to_ts)?.into_iter().filter(|r| r.block_number <= through) {
+                if let Some(acc) = buckets.get_mut(&(r.ts_unix.div_euclid(width) * width)) {
+                    acc.volume = acc.volume.saturating_add(U256::from_dec_str(&r.volume).unwrap_or_default());
+                }
+            }
+        }
+        for (ts, a) in db::annotations_by_time(conn, from_ts, to_ts)? {
+            if let Some(acc) = buckets.get_mut(&(ts.div_euclid(width) * width)) {
+                acc.annotations.push(a);
+            }
+        }
+        Ok((buckets, since, through))
+    }).await;
+    let (buckets, since, through) = match buckets {
+        Ok(b) => b,
+        Err(e) => return e.into_response(),
+    };
+
+    let fmt = q.format;
+    with_watermark(Json(buckets.into_iter().map(|(start_ts, a)| {
+        let volume = since.filter(|s| a.first_block.is_some_and(|b| b >= *s)).map(|_| a.volume);
+        Bucket {
+            start_ts,
+            inflow: fmt.render(&a.inflow.to_string()),
+            outflow: fmt.render(&a.outflow.to_string()),
+            net: fmt.render(&signed_net(a.inflow, a.outflow)),
+            cumulative: fmt.render(&a.cumulative),
+            transfer_count: a.transfer_count,
+            blocks: a.blocks,
+            total_volume: volume.map(|v| fmt.render(&v.to_string())),
+            exchange_share_pct: volume.and_then(|v| exchange_share(a.inflow, a.outflow, v)),
+            annotations: a.annotations,
+        }
+    }).collect::<Vec<_>>()), through)
+}
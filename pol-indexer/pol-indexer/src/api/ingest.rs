@@ -0,0 +1,108 @@
+//! Webhook deliveries for `INGEST=webhook`: `POST /ingest/alchemy` (Alchemy
+//! Notify) and `POST /ingest/quicknode` (QuickNode Streams).
+//!
+//! The signature is checked against `INGEST_SIGNING_KEY` (`401` if it doesn't
+//! match), the logs are queued for the indexer and the answer is `202` with
+//! `{"logs": n, "blocks": n}`. A full queue answers `503` so the provider
+//! retries later. Both routes are `404` unless the indexer runs in webhook
+//! mode.
+
+use std::collections::BTreeSet;
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::ingest::{self, Inbox, Provider};
+
+pub async fn alchemy(State(inbox): State<Option<Inbox>>, headers: HeaderMap, body: Bytes) -> Response {
+    deliver(inbox, Provider::Alchemy, &headers, &body)
+}
+
+pub async fn quicknode(State(inbox): State<Option<Inbox>>, headers: HeaderMap, body: Bytes) -> Response {
+    deliver(inbox, Provider::QuickNode, &headers, &body)
+}
+
+fn deliver(inbox: Option<Inbox>, provider: Provider, headers: &HeaderMap, body: &[u8]) -> Response {
+    let Some(inbox) = inbox else {
+        return (StatusCode::NOT_FOUND, "webhook ingestion is off (INGEST=subscription)").into_response();
+    };
+    if !inbox.verify(provider, headers, body) {
+        return (StatusCode::UNAUTHORIZED, "signature does not match INGEST_SIGNING_KEY").into_response();
+    }
+    let logs = match ingest::parse(provider, body) {
+        Ok(logs) => logs,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{e:#}")).into_response(),
+    };
+    let count = logs.len();
+    let blocks = logs.iter().filter_map(|l| l.block_number).collect::<BTreeSet<_>>().len();
+    if count == 0 {
+        return (StatusCode::ACCEPTED, Json(json!({ "logs": 0, "blocks": 0 }))).into_response();
+    }
+    match inbox.sender.try_send(logs) {
+        Ok(()) => (StatusCode::ACCEPTED, Json(json!({ "logs": count, "blocks": blocks }))).into_response(),
+        Err(TrySendError::Full(_)) => (StatusCode::SERVICE_UNAVAILABLE, "ingestion queue is full; retry later").into_response(),
+        Err(TrySendError::Closed(_)) => (StatusCode::SERVICE_UNAVAILABLE, "the indexer has stopped").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    const KEY: &str = "test-signing-key";
+
+    fn delivery(block: u64) -> (HeaderMap, Vec<u8>) {
+        let body = json!({ "event": { "activity": [{ "log": {
+            "address": format!("0x{}", "aa".repeat(20)),
+            "topics": [
+                "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
+                format!("0x{}{}", "00".repeat(12), "11".repeat(20)),
+                format!("0x{}{}", "00".repeat(12), "bb".repeat(20)),
+            ],
+            "data": format!("0x{:064x}", 5),
+            "blockNumber": format!("{block:#x}"),
+            "transactionHash": format!("0x{:064x}", block),
+            "logIndex": "0x0",
+        } }] } }).to_string().into_bytes();
+        let mut mac = Hmac::<Sha256>::new_from_slice(KEY.as_bytes()).unwrap();
+        mac.update(&body);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-alchemy-signature", hex::encode(mac.finalize().into_bytes()).parse().unwrap());
+        (headers, body)
+    }
+
+    #[test]
+    fn full_queue_pushes_back_instead_of_waiting() {
+        let (sender, mut rx) = mpsc::channel(1);
+        let inbox = Inbox { sender, signing_key: KEY.into() };
+
+        let (headers, body) = delivery(100);
+        assert_eq!(deliver(Some(inbox.clone()), Provider::Alchemy, &headers, &body).status(), StatusCode::ACCEPTED);
+        let (headers, body) = delivery(101);
+        assert_eq!(deliver(Some(inbox.clone()), Provider::Alchemy, &headers, &body).status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Once the indexer takes a delivery there is room again
+        assert_eq!(rx.try_recv().unwrap()[0].block_number, Some(100));
+        assert_eq!(deliver(Some(inbox), Provider::Alchemy, &headers, &body).status(), StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn stopped_indexer_is_reported_not_awaited() {
+        let (sender, rx) = mpsc::channel(1);
+        drop(rx);
+        let (headers, body) = delivery(100);
+        let inbox = Inbox { sender, signing_key: KEY.into() };
+        assert_eq!(deliver(Some(inbox), Provider::Alchemy, &headers, &body).status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,587 @@
+use axum::{
+    extract::{DefaultBodyLimit, FromRef, MatchedPath, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use ethers::types::{Address, U256};
+use eyre::Result;
+use rusqlite::Connection;
+use std::{net::SocketAddr, sync::Arc, time::{Duration, Instant}};
+use tokio::sync::Mutex;
+use tower::ServiceBuilder;
+use tower_http::{
+    compression::CompressionLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+use serde::Serialize;
+use crate::{auth, chain, control::Control, db, events::EventSender, ingest::Inbox, metrics, models::{self, NetflowSnapshot, ValueFormat}, supply::{self, SupplyShare}, velocity::{self, ValueCap, Velocity}};
+
+mod admin;
+mod annotations;
+mod balances;
+mod block_stats;
+mod buckets;
+mod cache;
+mod by_tier;
+mod changes;
+mod by_address;
+mod by_token;
+mod compare;
+mod config_history;
+mod counterparties;
+mod embed;
+mod entities;
+mod eta;
+mod events;
+mod export;
+mod grafana;
+mod heatmap;
+mod history;
+mod ingest;
+mod nfts;
+mod range;
+mod reorgs;
+mod search;
+mod status;
+mod supply_events;
+mod total;
+mod transfers;
+mod units;
+mod validate;
+mod worker;
+
+use cache::ResponseCache;
+use worker::HeavyWorker;
+
+/// Shared handle to the API's read connection.
+pub type Db = Arc<Mutex<Connection>>;
+
+/// Response header with the watermark the data was read at (see [`snapshot`]).
+const INDEXED_THROUGH_HEADER: &str = "x-indexed-through-block";
+
+/// Longest query string accepted (414 past it); every endpoint's parameters fit easily.
+const MAX_QUERY_BYTES: usize = 4 * 1024;
+
+/// Body limit of `/ingest/*`: a provider's delivery carries a whole block's logs.
+const INGEST_MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+pub struct ApiConfig {
+    /// Reject requests without a valid, correctly scoped API key
+    pub require_api_key: bool,
+    /// Token served by this instance, checked against key scopes
+    pub pol_token: Address,
+    /// Heavy analytical queries (`/netflow/history`, `/netflow/by-token`, `/netflow/by-tier`, `/counterparties`) allowed to run at once
+    pub heavy_workers: usize,
+    /// How long a heavy query may wait for a worker plus run before it is abandoned
+    pub heavy_timeout: Duration,
+    /// Separate listener for `/admin/*`; when `None` they share the public one
+    pub admin_bind: Option<String>,
+    /// SHA-256 of `ADMIN_TOKEN`; without it, admin routes exist only on `admin_bind`
+    pub admin_token_hash: Option<String>,
+    /// Sliding window for flow velocity on `/netflow` and `/metrics`
+    pub velocity_window_secs: i64,
+    /// Transfers above this (raw units) are left out of the velocity
+    pub velocity_value_cap: Option<U256>,
+    /// Tier tags overriding the defaults (configured = hot, discovered = deposit)
+    pub wallet_tiers: models::WalletTiers,
+    /// Longest a cached net-flow response is served; zero disables the cache
+    pub cache_ttl: Duration,
+    /// Longest a request may take to produce its response (408 after that)
+    pub request_timeout: Duration,
+    /// Largest request body accepted, except on the webhook ingestion routes
+    pub max_body_bytes: usize,
+    /// RPC for `/validate/addresses` bytecode reads; `None` leaves unseen addresses unclassified
+    pub rpc: Option<chain::Source>,
+    /// The indexer in this process, for `/admin/indexer/*`; `None` outside `run`
+    pub control: Option<Arc<Control>>,
+}
+
+impl ApiConfig {
+    fn velocity_cap(&self) -> Option<ValueCap> {
+        self.velocity_value_cap.map(|max| ValueCap { token: models::address_key(&self.pol_token), max })
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    db: Db,
+    db_path: Arc<str>,
+    config: Arc<ApiConfig>,
+    events: EventSender,
+    heavy: HeavyWorker,
+    cache: ResponseCache,
+    ingest: Option<Inbox>,
+    code: Option<validate::CodeReader>,
+}
+
+impl FromRef<AppState> for Db {
+    fn from_ref(state: &AppState) -> Db {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<ApiConfig> {
+    fn from_ref(state: &AppState) -> Arc<ApiConfig> {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for HeavyWorker {
+    fn from_ref(state: &AppState) -> HeavyWorker {
+        state.heavy.clone()
+    }
+}
+
+impl FromRef<AppState> for ResponseCache {
+    fn from_ref(state: &AppState) -> ResponseCache {
+        state.cache.clone()
+    }
+}
+
+impl FromRef<AppState> for EventSender {
+    fn from_ref(state: &AppState) -> EventSender {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<validate::CodeReader> {
+    fn from_ref(state: &AppState) -> Option<validate::CodeReader> {
+        state.code.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<Inbox> {
+    fn from_ref(state: &AppState) -> Option<Inbox> {
+        state.ingest.clone()
+    }
+}
+
+/// Serve the HTTP API. `events` is the indexer's live feed when running in the
+/// same process (`run`); otherwise `/events` simply stays idle. `ingest`
+/// accepts webhook deliveries when the indexer runs with `INGEST=webhook`.
+pub async fn serve(db_path: String, bind: &str, config: ApiConfig, events: EventSender, ingest: Option<Inbox>) -> Result<()> {
+    let state = AppState {
+        db: Arc::new(Mutex::new(Connection::open(&db_path)?)),
+        db_path: db_path.as_str().into(),
+        heavy: HeavyWorker::new(&db_path, config.heavy_workers, config.heavy_timeout),
+        cache: ResponseCache::new(config.cache_ttl, &events),
+        code: config.rpc.clone().map(validate::CodeReader::new),
+        config: Arc::new(config),
+        events,
+        ingest,
+    };
+
+    let admin_bind = state.config.admin_bind.clone();
+    let timeout = || TimeoutLayer::new(state.config.request_timeout);
+    let admin = admin::router(state.clone()).route_layer(timeout());
+
+    let unauthenticated = Router::new()
+        // Signed by the provider instead of an API key
+        .route("/ingest/alchemy", post(ingest::alchemy).layer(DefaultBodyLimit::max(INGEST_MAX_BODY_BYTES)))
+        .route("/ingest/quicknode", post(ingest::quicknode).layer(DefaultBodyLimit::max(INGEST_MAX_BODY_BYTES)));
+    // Unauthenticated so Prometheus can scrape it
+    #[cfg(feature = "metrics")]
+    let unauthenticated = unauthenticated.route("/metrics", get(render_metrics));
+
+    let cached = || middleware::from_fn_with_state(state.cache.clone(), cache::cached);
+    let public = Router::new()
+        .route("/status", get(status::status))
+        .route("/netflow", get(netflow).layer(cached()))
+        .route("/netflow/range", get(range::netflow_range).layer(cached()))
+        .route("/netflow/history", get(history::history).layer(cached()))
+        .route("/netflow/by-token", get(by_token::by_token))
+        .route("/netflow/by-address", get(by_address::by_address))
+        .route("/netflow/by-tier", get(by_tier::by_tier))
+        .route("/netflow/total", get(total::total))
+        .route("/buckets", get(buckets::list))
+        .route("/supply-events", get(supply_events::list))
+        .route("/balances", get(balances::list))
+        .route("/nfts/transfers", get(nfts::transfers))
+        .route("/nfts/inventory", get(nfts::inventory))
+        .route("/counterparties", get(counterparties::list))
+        .route("/compare", get(compare::compare))
+        .route("/activity/heatmap", get(heatmap::heatmap))
+        .route("/entities", get(entities::list))
+        .route("/entities/:name", get(entities::detail))
+        .route("/blocks/:number/eta", get(eta::eta))
+        .route("/reorgs", get(reorgs::list))
+        .route("/stats/blocks", get(block_stats::list))
+        .route("/config/history", get(config_history::list))
+        .route("/transfers", get(transfers::list))
+        .route("/transfers/latest", get(transfers::latest))
+        .route("/export/transfers.csv", get(export::transfers_csv))
+        .route("/transfers/:id/annotations", get(annotations::list).post(annotations::create))
+        .route("/tx/:hash", get(search::tx))
+        .route("/address/:addr/transfers", get(search::address_transfers))
+        .route("/validate/addresses", post(validate::addresses))
+        .route("/events", get(events::stream))
+        // Grafana simple-json / Infinity datasource contract
+        .route("/grafana", get(grafana::health))
+        .route("/grafana/search", post(grafana::search))
+        .route("/grafana/query", post(grafana::query))
+        // Embeddable badges
+        .route("/embed/netflow.svg", get(embed::svg))
+        .route("/embed/netflow.png", get(embed::png))
+        .route_layer(timeout())
+        // Holds the request for up to `?timeout=` before answering
+        .route("/changes", get(changes::changes).layer(TimeoutLayer::new(changes::MAX_TIMEOUT + state.config.request_timeout)))
+        // Inside the key check, which puts the key's default format in reach
+        .layer(middleware::from_fn(units::apply_default))
+        .layer(middleware::from_fn_with_state(state.clone(), require_api_key))
+        .merge(unauthenticated.route_layer(timeout()));
+
+    let Some(admin_bind) = admin_bind else {
+        // Admin routes sit outside the API-key layer: keys never grant admin access
+        let public = if state.config.admin_token_hash.is_some() { public.merge(admin) } else { public };
+        return listen(bind, "HTTP API", with_common_layers(public, state)).await;
+    };
+    tokio::try_join!(
+        listen(bind, "HTTP API", with_common_layers(public, state.clone())),
+        listen(&admin_bind, "Admin API", with_common_layers(admin, state)),
+    )?;
+    Ok(())
+}
+
+fn with_common_layers(router: Router<AppState>, state: AppState) -> Router {
+    let max_body_bytes = state.config.max_body_bytes;
+    router
+        .route_layer(middleware::from_fn(track_latency))
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .layer(middleware::from_fn(limit_query))
+        .layer(
+            // Keeps a caller-supplied x-request-id, otherwise assigns a UUID, and echoes it back
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(request_span))
+                .layer(PropagateRequestIdLayer::x_request_id())
+                // gzip or deflate per Accept-Encoding; SSE, images and tiny bodies are left alone
+                .layer(CompressionLayer::new().gzip(true).deflate(true)),
+        )
+        .with_state(state)
+}
+
+async fn listen(bind: &str, name: &str, app: Router) -> Result<()> {
+    let addr: SocketAddr = bind.parse().map_err(|e| eyre::eyre!("invalid {name} bind address {bind}: {e}"))?;
+    tracing::info!(%addr, "{name} listening");
+    axum::serve(tokio::net::TcpListener::bind(addr).await?, app).await?;
+    Ok(())
+}
+
+/// `?format=decimal|hex|scaled` (or `usd`, see [`units`]) for endpoints that return raw amounts.
+#[derive(serde::Deserialize)]
+struct FormatQuery {
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+struct NetflowResponse {
+    #[serde(flatten)]
+    latest: NetflowSnapshot,
+    velocity: Velocity,
+    /// The cumulative as a share of total supply
+    supply: Option<SupplyShare>,
+}
+
+#[derive(serde::Deserialize)]
+struct NetflowQuery {
+    #[serde(default)]
+    format: ValueFormat,
+    /// The state as it stood after this block instead of the latest
+    as_of_block: Option<u64>,
+}
+
+/// `GET /netflow`. With `?as_of_block=N` the cumulatives are those after
+/// block N (from the checkpoint for a restored database), and the velocity
+/// window ends at its time; the supply share is left out, as only the
+/// latest supply is stored.
+async fn netflow(State(state): State<AppState>, Query(q): Query<NetflowQuery>) -> Response {
+    let window = state.config.velocity_window_secs;
+    let cap = state.config.velocity_cap();
+    let found = {
+        let mut conn = state.db.lock().await;
+        snapshot(&mut conn, |conn, through| match q.as_of_block {
+            None => {
+                let latest = db::get_latest_cumulative(conn)?;
+                let supply = supply::share(conn, &latest.cumulative_netflow_raw, q.format)?;
+                Ok(Ok((latest, velocity::compute(conn, window, cap.as_ref())?, supply)))
+            }
+            Some(block) => match as_of(Some(block), through) {
+                Err(rejected) => Ok(Err(rejected)),
+                Ok(block) => match db::get_cumulative_as_of(conn, block)? {
+                    Some(at) => {
+                        let velocity = velocity::compute_at(conn, window, cap.as_ref(), at.updated_at_unix)?;
+                        Ok(Ok((at, velocity, None)))
+                    }
+                    None => Ok(Err((
+                        StatusCode::NOT_FOUND,
+                        format!("no state as of block {block}: it is before the start block, or before the checkpoint this database was restored from"),
+                    ))),
+                },
+            },
+        })
+    };
+    match found {
+        Ok((Err(rejected), _)) => rejected.into_response(),
+        Ok((Ok((mut latest, mut velocity, supply)), through)) => {
+            latest.cumulative_netflow_raw = q.format.render(&latest.cumulative_netflow_raw);
+            velocity.render(q.format);
+            with_watermark(Json(NetflowResponse { latest, velocity, supply }), through)
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Flow velocity, supply share and the USD cumulative are derived from the database, so they are refreshed per scrape.
+#[cfg(feature = "metrics")]
+async fn render_metrics(State(state): State<AppState>) -> String {
+    let (v, s, p) = {
+        let conn = state.db.lock().await;
+        (velocity::compute(&conn, state.config.velocity_window_secs, state.config.velocity_cap().as_ref()), supply::publish(&conn), crate::prices::publish(&conn))
+    };
+    match v {
+        Ok(v) => velocity::publish(&v),
+        Err(e) => tracing::warn!(error = %e, "Flow velocity unavailable for /metrics"),
+    }
+    if let Err(e) = s {
+        tracing::warn!(error = %e, "Supply share unavailable for /metrics");
+    }
+    if let Err(e) = p {
+        tracing::warn!(error = %e, "USD cumulative unavailable for /metrics");
+    }
+    metrics::render()
+}
+
+/// Refuse overlong query strings before any handler parses them.
+async fn limit_query(req: Request, next: Next) -> Response {
+    if req.uri().query().is_some_and(|q| q.len() > MAX_QUERY_BYTES) {
+        return (StatusCode::URI_TOO_LONG, format!("query string longer than {MAX_QUERY_BYTES} bytes")).into_response();
+    }
+    next.run(req).await
+}
+
+/// Span for every request; anything logged while handling it carries the request id.
+fn request_span(req: &Request) -> tracing::Span {
+    let request_id = req.headers().get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!("http", method = %req.method(), uri = %req.uri(), request_id)
+}
+
+/// Records `http_request_duration_seconds` under the matched route pattern.
+async fn track_latency(req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let res = next.run(req).await;
+    metrics::HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route, res.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    res
+}
+
+/// Resolves the caller's key from `Authorization: Bearer …`, `x-api-key`, or
+/// `?api_key=` (for embeds), and checks it covers this instance's exchange and
+/// token. The key is made available to handlers as an `ApiKey` extension.
+async fn require_api_key(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    if !state.config.require_api_key {
+        return next.run(req).await;
+    }
+    let Some(presented) = presented_key(&req) else {
+        return (StatusCode::UNAUTHORIZED, "missing API key").into_response();
+    };
+    let found = {
+        let conn = state.db.lock().await;
+        db::find_api_key(&conn, &auth::hash_key(&presented))
+    };
+    let key = match found {
+        Ok(Some(key)) => key,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "invalid API key").into_response(),
+        Err(e) => return internal_error(e),
+    };
+    let token = models::address_key(&state.config.pol_token);
+    if !key.allows_exchange(auth::EXCHANGE) || !key.allows_token(&token) {
+        return (StatusCode::FORBIDDEN, "API key is not scoped to this exchange/token").into_response();
+    }
+    req.extensions_mut().insert(key);
+    next.run(req).await
+}
+
+/// `?api_key=`, percent-decoded like every other query parameter
+#[derive(serde::Deserialize)]
+struct KeyQuery {
+    api_key: Option<String>,
+}
+
+fn presented_key(req: &Request) -> Option<String> {
+    let headers = req.headers();
+    if let Some(bearer) = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(bearer.trim().to_string());
+    }
+    if let Some(key) = headers.get("x-api-key").and_then(|v| v.to_str().ok()) {
+        return Some(key.trim().to_string());
+    }
+    Query::<KeyQuery>::try_from_uri(req.uri()).ok()?.0.api_key
+}
+
+/// Run `read` on one snapshot of the database, passing it the
+/// indexed-through watermark of that snapshot (`db::indexed_through_block`).
+/// Reads bounded by it see whole blocks only, however the indexer batches its
+/// writes, and several queries in one response agree with each other.
+fn snapshot<T>(conn: &mut Connection, read: impl FnOnce(&Connection, u64) -> Result<T>) -> Result<(T, u64)> {
+    let tx = conn.transaction()?;
+    let through = db::indexed_through_block(&tx)?;
+    Ok((read(&tx, through)?, through))
+}
+
+/// The block `?as_of_block=` asks for, or the watermark `through` without
+/// one; `400` for a block that isn't indexed yet.
+fn as_of(requested: Option<u64>, through: u64) -> std::result::Result<u64, (StatusCode, String)> {
+    match requested {
+        Some(block) if block > through => Err((
+            StatusCode::BAD_REQUEST,
+            format!("block {block} isn't indexed yet (indexed through {through})"),
+        )),
+        Some(block) => Ok(block),
+        None => Ok(through),
+    }
+}
+
+/// `res` with the `x-indexed-through-block` header, when a block has been indexed.
+fn with_watermark(res: impl IntoResponse, through: u64) -> Response {
+    let mut res = res.into_response();
+    if through != u64::MAX {
+        res.headers_mut().insert(INDEXED_THROUGH_HEADER, through.into());
+    }
+    res
+}
+
+fn internal_error(e: eyre::Report) -> Response {
+    tracing::error!(error = %e, "Request failed");
+    (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_reads_stop_at_the_watermark() {
+        let scratch = db::Scratch::new("watermark");
+        let mut conn = db::init(scratch.path()).unwrap();
+        for block in 1..=3_i64 {
+            conn.execute(
+                "INSERT INTO erc20_transfers (block_number, tx_hash, log_index, token, sender, recipient, value, direction)
+                 VALUES (?1, ?2, 0, 't', 's', 'r', '1', 'in')",
+                rusqlite::params![block, format!("0x{block:064x}")],
+            ).unwrap();
+        }
+        // Block 3's transfers are written, but the block isn't complete yet
+        db::advance_last_indexed_block(&conn, 2).unwrap();
+
+        let (rows, through) = snapshot(&mut conn, |conn, through| db::latest_transfers(conn, None, through, 10)).unwrap();
+        assert_eq!(through, 2);
+        assert_eq!(rows.iter().map(|r| r.block_number).collect::<Vec<_>>(), [2, 1]);
+        let res = with_watermark(Json(rows), through);
+        assert_eq!(res.headers()[INDEXED_THROUGH_HEADER], "2");
+    }
+
+    #[tokio::test]
+    async fn overlong_query_strings_are_refused() {
+        use tower::ServiceExt;
+        let app = Router::new().route("/", get(|| async { "ok" })).layer(middleware::from_fn(limit_query));
+        let get = |uri: String| app.clone().oneshot(Request::builder().uri(uri).body(axum::body::Body::empty()).unwrap());
+        assert_eq!(get("/?limit=10".into()).await.unwrap().status(), StatusCode::OK);
+        let long = format!("/?address={}", "a".repeat(MAX_QUERY_BYTES));
+        assert_eq!(get(long).await.unwrap().status(), StatusCode::URI_TOO_LONG);
+    }
+
+    #[test]
+    fn query_string_keys_are_percent_decoded() {
+        let key = |uri: &str| presented_key(&Request::builder().uri(uri).body(axum::body::Body::empty()).unwrap());
+        assert_eq!(key("/netflow?format=scaled&api_key=pk_%61b%2Fc%3D%26d").as_deref(), Some("pk_ab/c=&d"));
+        assert_eq!(key("/netflow?api_key=pk_abc").as_deref(), Some("pk_abc"));
+        assert_eq!(key("/netflow?format=scaled"), None);
+    }
+
+    #[tokio::test]
+    async fn bad_bind_address_is_an_error() {
+        let err = listen("localhost:http", "HTTP API", Router::new()).await.unwrap_err();
+        assert_eq!(err.to_string(), "invalid HTTP API bind address localhost:http: invalid socket address syntax");
+    }
+
+    #[test]
+    fn as_of_block_reads_the_cumulative_after_that_block() {
+        let conn = db::init(":memory:").unwrap();
+        db::set_start_block(&conn, 10).unwrap();
+        for (block_number, cumulative) in [(12, "5"), (15, "3")] {
+            db::insert_block(&conn, block_number, &format!("0x{block_number:064x}"), 100 + block_number as i64).unwrap();
+            db::insert_netflow_block(&conn, &models::NetflowBlock {
+                block_number, ts_unix: 100 + block_number as i64, inflow: "0".into(), outflow: "0".into(), cumulative: cumulative.into(),
+                transfer_count: 1, cumulative_token: String::new(), price_usd_micro: None, cumulative_usd_micro: None,
+            }).unwrap();
+        }
+        let at = |block| db::get_cumulative_as_of(&conn, block).unwrap().map(|s| (s.cumulative_netflow_raw, s.updated_at_unix));
+        assert_eq!(at(9), None, "before the start block");
+        assert_eq!(at(11), Some(("0".into(), 0)));
+        assert_eq!(at(14), Some(("5".into(), 112)));
+        assert_eq!(at(99), Some(("3".into(), 115)));
+    }
+
+    /// `pol-indexer-client` decodes what the API sends and loses nothing:
+    /// re-encoding its types gives back the server's JSON.
+    #[test]
+    fn client_types_match_the_wire_format() {
+        use crate::events::Event;
+        use pol_indexer_client as client;
+
+        fn round_trip<T: serde::de::DeserializeOwned + Serialize>(server: serde_json::Value) {
+            let decoded: T = serde_json::from_value(server.clone()).unwrap();
+            assert_eq!(serde_json::to_value(decoded).unwrap(), server);
+        }
+
+        let conn = db::init(":memory:").unwrap();
+        let netflow = NetflowResponse {
+            latest: db::get_latest_cumulative(&conn).unwrap(),
+            velocity: velocity::compute(&conn, 3600, None).unwrap(),
+            supply: Some(SupplyShare { total_supply: "1000".into(), block_number: 1, pct: Some(0.5) }),
+        };
+        round_trip::<client::NetflowSnapshot>(serde_json::to_value(netflow).unwrap());
+
+        let transfer = models::TransferRecord {
+            block_number: 1, tx_hash: "0xab".into(), log_index: 2, token: "0x01".into(), from: "0x02".into(), to: "0x03".into(),
+            value: "5".into(), direction: models::Direction::In, status: None, bucket: Some("minted".into()),
+            counterparty_kind: Some(models::CounterpartyKind::Contract), counterparty_label: Some("router".into()),
+        };
+        round_trip::<Vec<client::Transfer>>(serde_json::to_value([&transfer]).unwrap());
+
+        let block = models::NetflowBlock {
+            block_number: 1, ts_unix: 2, inflow: "5".into(), outflow: "0".into(), cumulative: "5".into(), transfer_count: 1,
+            cumulative_token: "0.000000000000000005".into(), price_usd_micro: Some(1), cumulative_usd_micro: None,
+        };
+        let provisional = models::ProvisionalNetflow {
+            block_number: 2, block_hash: None, inflow: "0".into(), outflow: "1".into(), cumulative: "4".into(),
+            transfer_count: 1, received_at_ms: 3,
+        };
+        for event in [Event::Transfer(transfer), Event::Netflow(block), Event::ProvisionalNetflow(provisional)] {
+            let wire = serde_json::to_value(event.envelope()).unwrap();
+            let decoded = client::Event::decode(&wire.to_string()).unwrap().expect("a known event type");
+            let data = match decoded {
+                client::Event::Transfer(t) => serde_json::to_value(t),
+                client::Event::Netflow(b) => serde_json::to_value(b),
+                client::Event::ProvisionalNetflow(p) => serde_json::to_value(p),
+            };
+            assert_eq!(data.unwrap(), wire["data"]);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! NFT transfers of the tracked addresses, recorded with `TRACK_NFTS`.
+//!
+//! `GET /nfts/transfers` lists them newest first: `contract` selects one
+//! collection, `from_block`/`to_block` bound the list and `limit` caps it
+//! (default 100, max 1000).
+//!
+//! `GET /nfts/inventory` nets them per token as of the watermark: received
+//! minus sent since `since_block`, so a token held before then shows as `-1`
+//! once it leaves. Transfers between tracked addresses don't count.
+
+use axum::{
+    extract::{Query, State},
+    response::Response,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{internal_error, range::signed_net, snapshot, with_watermark, Db};
+use crate::{db, models::{NftStandard, NftTransfer}};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct TransfersQuery {
+    contract: Option<String>,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Transfers {
+    /// First block recorded; `None` when NFTs have never been tracked
+    since_block: Option<u64>,
+    transfers: Vec<NftTransfer>,
+}
+
+pub async fn transfers(State(conn): State<Db>, Query(q): Query<TransfersQuery>) -> Response {
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let contract = q.contract.map(|c| c.to_ascii_lowercase());
+    let found = {
+        let mut conn = conn.lock().await;
+        snapshot(&mut conn, |conn, through| {
+            let to = q.to_block.unwrap_or(u64::MAX).min(through);
+            Ok((
+                db::get_nfts_since_block(conn)?,
+                db::list_nft_transfers(conn, contract.as_deref(), q.from_block.unwrap_or(0), to, limit)?,
+            ))
+        })
+    };
+    match found {
+        Ok(((since_block, transfers), through)) => with_watermark(Json(Transfers { since_block, transfers }), through),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InventoryQuery {
+    contract: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Holding {
+    contract: String,
+    standard: NftStandard,
+    token_id: String,
+    received: String,
+    sent: String,
+    /// `received - sent`
+    net: String,
+}
+
+#[derive(Serialize)]
+pub struct Inventory {
+    since_block: Option<u64>,
+    /// Watermark the inventory is as of; `None` before anything is indexed
+    block_number: Option<u64>,
+    tokens: Vec<Holding>,
+}
+
+pub async fn inventory(State(conn): State<Db>, Query(q): Query<InventoryQuery>) -> Response {
+    let contract = q.contract.map(|c| c.to_ascii_lowercase());
+    let found = {
+        let mut conn = conn.lock().await;
+        snapshot(&mut conn, |conn, through| {
+            Ok((db::get_nfts_since_block(conn)?, db::nft_flows(conn, contract.as_deref(), through)?))
+        })
+    };
+    let ((since_block, flows), through) = match found {
+        Ok(found) => found,
+        Err(e) => return internal_error(e),
+    };
+    let tokens = flows.into_iter().map(|((contract, token_id), (standard, received, sent))| Holding {
+        contract,
+        standard,
+        token_id: token_id.to_string(),
+        received: received.to_string(),
+        sent: sent.to_string(),
+        net: signed_net(received, sent),
+    }).collect();
+    with_watermark(Json(Inventory {
+        since_block,
+        block_number: (through != u64::MAX).then_some(through),
+        tokens,
+    }), through)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U256;
+
+    use crate::{db, models::{Direction, NftStandard, NftTransfer}};
+
+    #[test]
+    fn nets_each_token_and_skips_internal_moves() {
+        let scratch = db::Scratch::new("nfts");
+        let conn = db::init(scratch.path()).unwrap();
+        let rows = [
+            (10_u64, "0xc1", "7", "1", Direction::In),
+            (11, "0xc1", "7", "1", Direction::Out),
+            (11, "0xc1", "10", "1", Direction::In),
+            (12, "0xc2", "3", "5", Direction::In),
+            (12, "0xc2", "3", "2", Direction::Internal),
+            (13, "0xc2", "3", "4", Direction::Out),
+        ];
+        for (i, (block_number, contract, token_id, amount, direction)) in rows.into_iter().enumerate() {
+            db::insert_nft_transfer(&conn, &NftTransfer {
+                block_number,
+                tx_hash: format!("0x{i:064x}"),
+                log_index: 0,
+                batch_index: 0,
+                contract: contract.into(),
+                standard: if contract == "0xc1" { NftStandard::Erc721 } else { NftStandard::Erc1155 },
+                token_id: token_id.into(),
+                amount: amount.into(),
+                from: "0xa".into(),
+                to: "0xb".into(),
+                direction,
+                ts_unix: 0,
+            }).unwrap();
+        }
+        let flows = db::nft_flows(&conn, None, 12).unwrap();
+        let net: Vec<_> = flows.iter().map(|((c, id), (_, r, s))| (c.as_str(), id.as_u64(), r.as_u64(), s.as_u64())).collect();
+        assert_eq!(net, [("0xc1", 7, 1, 1), ("0xc1", 10, 1, 0), ("0xc2", 3, 5, 0)], "token ids in numeric order; block 13 is past the watermark");
+        assert_eq!(db::nft_flows(&conn, Some("0xc2"), u64::MAX).unwrap()[&("0xc2".into(), U256::from(3))].2.as_u64(), 4);
+        assert_eq!(db::list_nft_transfers(&conn, Some("0xc1"), 0, u64::MAX, 2).unwrap()[0].block_number, 11);
+    }
+}
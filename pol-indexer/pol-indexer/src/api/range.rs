@@ -0,0 +1,133 @@
+//! `GET /netflow/range` — exact inflow/outflow/net over an arbitrary interval,
+//! summed in `U256` from the per-block aggregates.
+//!
+//! Bounds are inclusive and given either as blocks (`from_block`, `to_block`)
+//! or as unix timestamps (`from_ts`, `to_ts`); omitted bounds are open.
+//! With `TRACK_VOLUME` the response also gives the token's total transfer
+//! volume over the range and the share of it that went through Binance.
+//! Blocks above the indexed-through watermark are left out.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{internal_error, snapshot, with_watermark, Db};
+use crate::{db, models::{NetflowBlock, ValueFormat}, supply::{self, SupplyShare}};
+
+#[derive(Deserialize)]
+pub struct RangeQuery {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    from_ts: Option<i64>,
+    to_ts: Option<i64>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct RangeNetflow {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    from_ts: Option<i64>,
+    to_ts: Option<i64>,
+    /// Raw units, rendered per `?format=`
+    inflow: String,
+    outflow: String,
+    /// `inflow - outflow`, negative when more left Binance than arrived
+    net: String,
+    transfer_count: u64,
+    /// Blocks in the range with at least one matched transfer
+    blocks: u64,
+    /// `net` as a share of total supply
+    supply: Option<SupplyShare>,
+    /// Transfer volume across all addresses; `null` unless every matched transfer is from `volume_since_block` on
+    total_volume: Option<String>,
+    /// `inflow + outflow` as a percentage of `total_volume`
+    exchange_share_pct: Option<f64>,
+}
+
+pub async fn netflow_range(State(conn): State<Db>, Query(q): Query<RangeQuery>) -> Response {
+    let by_block = q.from_block.is_some() || q.to_block.is_some();
+    let by_time = q.from_ts.is_some() || q.to_ts.is_some();
+    if by_block && by_time {
+        return (StatusCode::BAD_REQUEST, "use either from_block/to_block or from_ts/to_ts, not both").into_response();
+    }
+    let found = {
+        let mut conn = conn.lock().await;
+        snapshot(&mut conn, |conn, through| {
+            let mut rows = if by_time {
+                db::get_netflow_blocks_by_time(conn, q.from_ts.unwrap_or(i64::MIN), q.to_ts.unwrap_or(i64::MAX))?
+            } else {
+                db::get_netflow_blocks_by_range(conn, q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX).min(through))?
+            };
+            rows.retain(|r| r.block_number <= through);
+            let (inflow, outflow, transfer_count) = sum_blocks(&rows);
+            let supply = supply::share(conn, &signed_net(inflow, outflow), q.format)?;
+            let since = db::get_volume_since_block(conn)?;
+            let volume = match since {
+                Some(since) if rows.iter().all(|r| r.block_number >= since) => {
+                    let mut volume = if by_time {
+                        db::get_volume_blocks_by_time(conn, q.from_ts.unwrap_or(i64::MIN), q.to_ts.unwrap_or(i64::MAX))?
+                    } else {
+                        db::get_volume_blocks_by_range(conn, q.from_block.unwrap_or(0), q.to_block.unwrap_or(u64::MAX).min(through))?
+                    };
+                    volume.retain(|r| r.block_number <= through);
+                    Some(volume.iter().fold(U256::zero(), |v, r| v.saturating_add(U256::from_dec_str(&r.volume).unwrap_or_default())))
+                }
+                _ => None,
+            };
+            Ok((rows.len(), inflow, outflow, transfer_count, supply, volume))
+        })
+    };
+    let ((blocks, inflow, outflow, transfer_count, supply, volume), through) = match found {
+        Ok(found) => found,
+        Err(e) => return internal_error(e),
+    };
+
+    with_watermark(Json(RangeNetflow {
+        from_block: q.from_block,
+        to_block: q.to_block,
+        from_ts: q.from_ts,
+        to_ts: q.to_ts,
+        inflow: q.format.render(&inflow.to_string()),
+        outflow: q.format.render(&outflow.to_string()),
+        net: q.format.render(&signed_net(inflow, outflow)),
+        transfer_count,
+        blocks: blocks as u64,
+        supply,
+        total_volume: volume.map(|v| q.format.render(&v.to_string())),
+        exchange_share_pct: volume.and_then(|v| exchange_share(inflow, outflow, v)),
+    }), through)
+}
+
+/// Exact `(inflow, outflow, transfer_count)` totals over per-block aggregates.
+pub(super) fn sum_blocks(rows: &[NetflowBlock]) -> (U256, U256, u64) {
+    let mut inflow = U256::zero();
+    let mut outflow = U256::zero();
+    let mut transfer_count = 0;
+    for r in rows {
+        inflow = inflow.saturating_add(U256::from_dec_str(&r.inflow).unwrap_or_default());
+        outflow = outflow.saturating_add(U256::from_dec_str(&r.outflow).unwrap_or_default());
+        transfer_count += r.transfer_count;
+    }
+    (inflow, outflow, transfer_count)
+}
+
+/// Binance's `inflow + outflow` as a percentage of the total `volume`.
+pub(super) fn exchange_share(inflow: U256, outflow: U256, volume: U256) -> Option<f64> {
+    supply::pct_of(&inflow.saturating_add(outflow).to_string(), &volume.to_string())
+}
+
+/// `inflow - outflow` as a decimal string, `-`-prefixed when negative.
+pub(super) fn signed_net(inflow: U256, outflow: U256) -> String {
+    if inflow >= outflow {
+        (inflow - outflow).to_string()
+    } else {
+        format!("-{}", outflow - inflow)
+    }
+}
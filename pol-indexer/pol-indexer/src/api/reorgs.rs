@@ -0,0 +1,50 @@
+//! `GET /reorgs` — audit log of chain reorganisations, newest first.
+//!
+//! Each entry has the fork point, the orphaned block hashes, the transfers
+//! that were dropped and the cumulative before and after the rollback.
+//! `?limit=` caps the list (default 50, max 1000); `?format=` renders amounts.
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use super::{internal_error, Db};
+use crate::{db, models::ValueFormat};
+
+const DEFAULT_LIMIT: u32 = 50;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct ReorgsQuery {
+    #[serde(default)]
+    limit: Option<u32>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<ReorgsQuery>) -> Response {
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let rows = {
+        let conn = conn.lock().await;
+        db::list_reorgs(&conn, limit)
+    };
+    match rows {
+        Ok(mut rows) => {
+            let fmt = q.format;
+            for r in &mut rows {
+                r.removed_inflow = fmt.render(&r.removed_inflow);
+                r.removed_outflow = fmt.render(&r.removed_outflow);
+                r.cumulative_before = fmt.render(&r.cumulative_before);
+                r.cumulative_after = fmt.render(&r.cumulative_after);
+                for t in &mut r.removed_transfers {
+                    t.render(fmt);
+                }
+            }
+            Json(rows).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
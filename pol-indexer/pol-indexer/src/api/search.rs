@@ -0,0 +1,82 @@
+//! Support lookups: was this transaction / address's transfer captured?
+//!
+//! `GET /tx/{hash}` returns every stored transfer logged by the transaction,
+//! in log order, or `404` if none was indexed.
+//!
+//! `GET /address/{addr}/transfers` pages through the transfers an address sent
+//! or received, newest first. `?direction=in|out|all` (default `all`) picks the
+//! side, `?page=` is 1-based and `?limit=` sets the page size (default 100,
+//! max 1000). `?format=` renders values as in `/transfers`.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use super::{internal_error, units, Db, FormatQuery};
+use crate::{db, models::{self, ValueFormat}};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+pub async fn tx(State(conn): State<Db>, Path(hash): Path<String>, Query(q): Query<FormatQuery>) -> Response {
+    let hash = hash.to_ascii_lowercase();
+    let valid = hash.strip_prefix("0x").is_some_and(|h| h.len() == 64 && h.bytes().all(|b| b.is_ascii_hexdigit()));
+    if !valid {
+        return (StatusCode::BAD_REQUEST, format!("invalid transaction hash {hash:?}")).into_response();
+    }
+    let conn = conn.lock().await;
+    match db::transfers_by_tx(&conn, &hash) {
+        Ok(rows) if rows.is_empty() => (StatusCode::NOT_FOUND, format!("no indexed transfers in {hash}")).into_response(),
+        Ok(mut rows) => match units::render_transfers(&conn, &mut rows, q.format) {
+            Ok(()) => Json(rows).into_response(),
+            Err(e) => e.into_response(),
+        },
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AddressQuery {
+    #[serde(default)]
+    direction: Option<String>,
+    #[serde(default)]
+    page: Option<u64>,
+    #[serde(default)]
+    limit: Option<u32>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+pub async fn address_transfers(State(conn): State<Db>, Path(addr): Path<String>, Query(q): Query<AddressQuery>) -> Response {
+    let address = match models::parse_address(&addr) {
+        Ok(a) => models::address_key(&a),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let incoming = match q.direction.as_deref().unwrap_or("all") {
+        "all" => None,
+        "in" => Some(true),
+        "out" => Some(false),
+        other => return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid direction {other:?}; expected in, out or all"),
+        ).into_response(),
+    };
+    let page = match q.page.unwrap_or(1) {
+        0 => return (StatusCode::BAD_REQUEST, "page starts at 1").into_response(),
+        p => p,
+    };
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = (page - 1).saturating_mul(limit as u64);
+    let conn = conn.lock().await;
+    match db::address_transfers(&conn, &address, incoming, offset, limit) {
+        Ok(mut rows) => match units::render_transfers(&conn, &mut rows, q.format) {
+            Ok(()) => Json(rows).into_response(),
+            Err(e) => e.into_response(),
+        },
+        Err(e) => internal_error(e),
+    }
+}
@@ -0,0 +1,53 @@
+//! `GET /status` — where the indexer is: last indexed block, blocks waiting
+//! for a retry, and, while it is catching up, the backfill's progress
+//! (current and target block, blocks/s, ETA), and the health of each RPC
+//! endpoint when several are configured.
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use super::{internal_error, Db};
+use crate::{chain::health::{self, EndpointHealth}, db, progress::{self, CatchUp}};
+
+#[derive(Serialize)]
+pub struct Status {
+    start_block: Option<u64>,
+    last_indexed_block: Option<u64>,
+    failed_blocks: usize,
+    catching_up: bool,
+    /// Backfill progress of the indexer in this process; `null` when it is
+    /// following the head (or runs in another process)
+    catch_up: Option<CatchUp>,
+    /// Scores of the indexer's RPC endpoints in this process, preferred one
+    /// flagged; empty with a single endpoint
+    rpc_endpoints: Vec<EndpointHealth>,
+}
+
+pub async fn status(State(conn): State<Db>) -> Response {
+    let loaded = {
+        let conn = conn.lock().await;
+        (|| Ok::<_, eyre::Report>((
+            db::get_start_block(&conn)?,
+            db::get_last_indexed_block(&conn)?,
+            db::list_failed_blocks(&conn)?.len(),
+        )))()
+    };
+    match loaded {
+        Ok((start_block, last_indexed_block, failed_blocks)) => {
+            let catch_up = progress::current();
+            Json(Status {
+                start_block,
+                last_indexed_block,
+                failed_blocks,
+                catching_up: catch_up.is_some(),
+                catch_up,
+                rpc_endpoints: health::current(),
+            }).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
@@ -0,0 +1,74 @@
+//! `GET /supply-events` — mints and burns of the token recorded with
+//! `TRACK_SUPPLY_EVENTS`, newest first, with the running totals.
+//!
+//! `from_block`/`to_block` bound the list and `limit` caps it (default 100,
+//! max 1000). The totals are as of `to_block` (default: the watermark) and
+//! count from `since_block`; `?format=` renders every amount.
+
+use axum::{
+    extract::{Query, State},
+    response::Response,
+    Json,
+};
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::{internal_error, range::signed_net, snapshot, with_watermark, Db};
+use crate::{db, models::{SupplyEvent, ValueFormat}};
+
+const DEFAULT_LIMIT: u32 = 100;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct SupplyEventsQuery {
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    limit: Option<u32>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+#[derive(Serialize)]
+pub struct SupplyEvents {
+    /// First block recorded; `None` when supply events have never been tracked
+    since_block: Option<u64>,
+    minted_total: String,
+    burned_total: String,
+    /// `minted_total - burned_total`; negative when more was burned
+    net_minted: String,
+    events: Vec<SupplyEvent>,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<SupplyEventsQuery>) -> Response {
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let found = {
+        let mut conn = conn.lock().await;
+        snapshot(&mut conn, |conn, through| {
+            let to = q.to_block.unwrap_or(u64::MAX).min(through);
+            Ok((
+                db::get_supply_events_since_block(conn)?,
+                db::supply_totals_at(conn, to)?,
+                db::list_supply_events(conn, q.from_block.unwrap_or(0), to, limit)?,
+            ))
+        })
+    };
+    let ((since_block, totals, mut events), through) = match found {
+        Ok(found) => found,
+        Err(e) => return internal_error(e),
+    };
+    let (minted, burned) = totals.unwrap_or_else(|| ("0".into(), "0".into()));
+    let parse = |v: &str| U256::from_dec_str(v).unwrap_or_default();
+    let fmt = q.format;
+    for e in &mut events {
+        e.value = fmt.render(&e.value);
+        e.minted_total = fmt.render(&e.minted_total);
+        e.burned_total = fmt.render(&e.burned_total);
+    }
+    with_watermark(Json(SupplyEvents {
+        since_block,
+        net_minted: fmt.render(&signed_net(parse(&minted), parse(&burned))),
+        minted_total: fmt.render(&minted),
+        burned_total: fmt.render(&burned),
+        events,
+    }), through)
+}
@@ -0,0 +1,61 @@
+//! `GET /netflow/total` — combined POL net-flow: the ERC-20 cumulative plus
+//! native (gas token) flows recorded with `TRACK_NATIVE`.
+//!
+//! The native part only covers blocks from `native_since_block` on and can be
+//! negative, so `total` is signed; `?format=` renders every amount.
+
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::U256;
+use serde::Serialize;
+
+use super::{internal_error, range::signed_net, Db, FormatQuery};
+use crate::{aggregates::{self, Delta}, db};
+
+#[derive(Serialize)]
+pub struct TotalNetflow {
+    /// Last block reflected in the ERC-20 cumulative
+    block_number: u64,
+    erc20_cumulative: String,
+    /// `None` when native POL has never been tracked on this database
+    native_since_block: Option<u64>,
+    native_inflow: String,
+    native_outflow: String,
+    native_net: String,
+    /// `erc20_cumulative + native_net`
+    total: String,
+}
+
+pub async fn total(State(conn): State<Db>, Query(q): Query<FormatQuery>) -> Response {
+    let loaded = {
+        let conn = conn.lock().await;
+        (|| Ok::<_, eyre::Report>((
+            db::get_latest_cumulative(&conn)?,
+            db::latest_native_netflow(&conn)?,
+            db::get_native_since_block(&conn)?,
+        )))()
+    };
+    let (erc20, native, native_since_block) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => return internal_error(e),
+    };
+
+    let parse = |v: &str| U256::from_dec_str(v).unwrap_or_default();
+    let erc20_cumulative = aggregates::parse_cumulative(&erc20.cumulative_netflow_raw);
+    let (native_in, native_out) = native
+        .map(|n| (parse(&n.cumulative_inflow), parse(&n.cumulative_outflow)))
+        .unwrap_or_default();
+    let fmt = q.format;
+    Json(TotalNetflow {
+        block_number: erc20.block_number,
+        erc20_cumulative: fmt.render(&erc20.cumulative_netflow_raw),
+        native_since_block,
+        native_inflow: fmt.render(&native_in.to_string()),
+        native_outflow: fmt.render(&native_out.to_string()),
+        native_net: fmt.render(&signed_net(native_in, native_out)),
+        total: fmt.render(&Delta::new(native_in, native_out).apply(erc20_cumulative).to_string()),
+    }).into_response()
+}
@@ -0,0 +1,83 @@
+//! `GET /transfers` — recent stored transfers, newest first.
+//!
+//! `GET /transfers/latest` is the index-backed fast path: the newest `?limit=`
+//! rows (default 20, max 1000), optionally only those involving `?address=`.
+//!
+//! `?status=success|reverted|unknown|all` (default `all`) filters on the
+//! receipt status; `?limit=` caps the page (default 100, max 1000);
+//! `?format=` renders values (see [`ValueFormat`]; `usd` prices each transfer
+//! at its block, see [`super::units`]). Addresses are EIP-55
+//! checksummed. Both stop at the indexed-through watermark.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use super::{internal_error, snapshot, units, with_watermark, Db};
+use crate::{db, models::{self, TxStatus, ValueFormat}};
+
+const DEFAULT_LIMIT: u32 = 100;
+const DEFAULT_LATEST_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 1000;
+
+#[derive(Deserialize)]
+pub struct TransfersQuery {
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    limit: Option<u32>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+pub async fn list(State(conn): State<Db>, Query(q): Query<TransfersQuery>) -> Response {
+    let status = match q.status.as_deref().unwrap_or("all") {
+        "all" => None,
+        "unknown" => Some(None),
+        "success" => Some(Some(TxStatus::Success)),
+        "reverted" => Some(Some(TxStatus::Reverted)),
+        other => return (
+            StatusCode::BAD_REQUEST,
+            format!("invalid status {other:?}; expected success, reverted, unknown or all"),
+        ).into_response(),
+    };
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let mut conn = conn.lock().await;
+    match snapshot(&mut conn, |conn, through| db::list_transfers(conn, status, through, limit)) {
+        Ok((mut rows, through)) => match units::render_transfers(&conn, &mut rows, q.format) {
+            Ok(()) => with_watermark(Json(rows), through),
+            Err(e) => e.into_response(),
+        },
+        Err(e) => internal_error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LatestQuery {
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    limit: Option<u32>,
+    #[serde(default)]
+    format: ValueFormat,
+}
+
+pub async fn latest(State(conn): State<Db>, Query(q): Query<LatestQuery>) -> Response {
+    let address = match q.address.as_deref().map(models::parse_address).transpose() {
+        Ok(a) => a.map(|a| models::address_key(&a)),
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let limit = q.limit.unwrap_or(DEFAULT_LATEST_LIMIT).min(MAX_LIMIT);
+    let mut conn = conn.lock().await;
+    match snapshot(&mut conn, |conn, through| db::latest_transfers(conn, address.as_deref(), through, limit)) {
+        Ok((mut rows, through)) => match units::render_transfers(&conn, &mut rows, q.format) {
+            Ok(()) => with_watermark(Json(rows), through),
+            Err(e) => e.into_response(),
+        },
+        Err(e) => internal_error(e),
+    }
+}
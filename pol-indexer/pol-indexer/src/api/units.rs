@@ -0,0 +1,141 @@
+//! Units of account: an API key's `default_format` stands in for `?format=`
+//! on requests that don't pass one, so each consumer gets raw, hex, whole-token
+//! or USD values without changing its queries.
+//!
+//! `format=usd` values each transfer at the price stored with its block
+//! (`PRICE_FEED_ADDRESS`), so only the per-transfer routes in [`USD_ROUTES`]
+//! offer it. Asked for explicitly elsewhere it is a `400`; as a key's default
+//! those routes get whole tokens instead.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use rusqlite::Connection;
+
+use super::internal_error;
+use crate::{auth::ApiKey, db, models::{TransferRecord, ValueFormat}, prices};
+
+/// Routes whose values each belong to one block, and so can be priced.
+pub const USD_ROUTES: &[&str] = &["/transfers", "/transfers/latest", "/tx/:hash", "/address/:addr/transfers"];
+
+pub async fn apply_default(mut req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()).unwrap_or_default();
+    let priced = USD_ROUTES.contains(&route.as_str());
+    match requested(req.uri()) {
+        Some("usd") if !priced => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("format=usd is only offered on {}", USD_ROUTES.join(", ")),
+            ).into_response();
+        }
+        Some(_) => {}
+        None => {
+            let default = req.extensions().get::<ApiKey>().and_then(|k| k.default_format);
+            let format = match default {
+                Some(ValueFormat::Usd) if !priced => Some(ValueFormat::Scaled),
+                other => other,
+            };
+            if let Some(format) = format {
+                match with_format(req.uri(), format) {
+                    Ok(uri) => *req.uri_mut() = uri,
+                    Err(e) => return internal_error(e),
+                }
+            }
+        }
+    }
+    next.run(req).await
+}
+
+fn requested(uri: &Uri) -> Option<&str> {
+    uri.query()?.split('&').find_map(|kv| kv.strip_prefix("format="))
+}
+
+fn with_format(uri: &Uri, format: ValueFormat) -> eyre::Result<Uri> {
+    let query = match uri.query() {
+        Some(q) if !q.is_empty() => format!("{q}&format={}", format.as_str()),
+        _ => format!("format={}", format.as_str()),
+    };
+    Ok(format!("{}?{query}", uri.path()).parse()?)
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// A row's block has no stored price: a `400` rather than a made-up value
+    Unpriced(u64),
+    Db(eyre::Report),
+}
+
+impl IntoResponse for RenderError {
+    fn into_response(self) -> Response {
+        match self {
+            RenderError::Unpriced(block) => (
+                StatusCode::BAD_REQUEST,
+                format!("no stored price for block {block} (PRICE_FEED_ADDRESS wasn't set when it was indexed); use format=scaled"),
+            ).into_response(),
+            RenderError::Db(e) => internal_error(e),
+        }
+    }
+}
+
+/// Render `rows` in `format`, looking up each block's price for `usd`.
+pub fn render_transfers(conn: &Connection, rows: &mut [TransferRecord], format: ValueFormat) -> Result<(), RenderError> {
+    if format != ValueFormat::Usd {
+        for r in rows {
+            r.render(format);
+        }
+        return Ok(());
+    }
+    let mut prices: HashMap<u64, Option<i64>> = HashMap::new();
+    for r in rows {
+        let price = match prices.entry(r.block_number) {
+            Entry::Occupied(e) => *e.get(),
+            Entry::Vacant(e) => *e.insert(db::block_price(conn, r.block_number).map_err(RenderError::Db)?),
+        };
+        let price = price.ok_or(RenderError::Unpriced(r.block_number))?;
+        let raw = std::mem::take(&mut r.value);
+        r.render(ValueFormat::Decimal);
+        r.value = prices::render_usd(&raw, price);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Direction;
+
+    #[test]
+    fn appends_the_default_format() {
+        let uri: Uri = "/transfers?limit=5".parse().unwrap();
+        assert_eq!(requested(&uri), None);
+        assert_eq!(with_format(&uri, ValueFormat::Usd).unwrap(), "/transfers?limit=5&format=usd");
+        assert_eq!(with_format(&"/netflow".parse().unwrap(), ValueFormat::Hex).unwrap(), "/netflow?format=hex");
+        assert_eq!(requested(&"/tx/0x1?api_key=k&format=usd".parse().unwrap()), Some("usd"));
+    }
+
+    #[test]
+    fn prices_each_transfer_at_its_block() {
+        let scratch = db::Scratch::new("units-usd");
+        let conn = db::init(scratch.path()).unwrap();
+        for (block, price) in [(1_u64, Some(500_000_i64)), (2, None)] {
+            conn.execute(
+                "INSERT INTO netflow_blocks (block_number, ts_unix, inflow, outflow, cumulative, transfer_count, price_usd_micro) VALUES (?1, 0, '0', '0', '0', 1, ?2)",
+                rusqlite::params![block, price],
+            ).unwrap();
+            let hash = format!("0x{block:064x}");
+            db::insert_transfer(&conn, block, &hash, &hash, 0, "0xt", "0xs", "0xr", "3000000000000000000", Direction::In, None).unwrap();
+        }
+        let mut rows = db::transfers_by_tx(&conn, &format!("0x{:064x}", 1)).unwrap();
+        render_transfers(&conn, &mut rows, ValueFormat::Usd).unwrap();
+        assert_eq!(rows[0].value, "1.500000");
+
+        let mut unpriced = db::transfers_by_tx(&conn, &format!("0x{:064x}", 2)).unwrap();
+        let err = render_transfers(&conn, &mut unpriced, ValueFormat::Usd).unwrap_err();
+        assert!(matches!(err, RenderError::Unpriced(2)));
+    }
+}
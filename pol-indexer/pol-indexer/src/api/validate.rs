@@ -0,0 +1,188 @@
+//! `POST /validate/addresses` — check a list of addresses before adding them
+//! to the tracked set: body `["0x…", …]`, at most [`MAX_ADDRESSES`].
+//!
+//! Each entry comes back in input order, checksummed (EIP-55) when valid. A
+//! mixed-case input with a wrong checksum is invalid, as wallets treat it.
+//! `tracked` says whether the address is already configured or discovered,
+//! `entity` names the entity it belongs to, and `kind` is `eoa` or
+//! `contract`. `kind` comes from the counterparty cache, else from
+//! `eth_getCode` when the API runs inside `run`; `null` when neither is
+//! available.
+
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::{types::Address, utils::to_checksum};
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use super::{internal_error, Db};
+use crate::{chain::{self, ChainClient}, contracts::{self, ContractLabels}, db, models::{self, CounterpartyKind}};
+
+pub const MAX_ADDRESSES: usize = 1000;
+
+/// `eth_getCode` reads in flight at once.
+const CODE_READS: usize = 8;
+
+/// RPC connection for bytecode reads, opened by the first request that needs it.
+#[derive(Clone)]
+pub struct CodeReader {
+    source: chain::Source,
+    client: Arc<OnceCell<Box<dyn ChainClient>>>,
+}
+
+impl CodeReader {
+    pub fn new(source: chain::Source) -> Self {
+        CodeReader { source, client: Arc::new(OnceCell::new()) }
+    }
+
+    async fn kind(&self, address: Address) -> eyre::Result<(CounterpartyKind, Option<String>)> {
+        let client = self.client.get_or_try_init(|| chain::connect(&self.source)).await?;
+        let c = contracts::classify(&client.code_at(address).await?, &ContractLabels::default());
+        let kind = if c.code_hash.is_some() { CounterpartyKind::Contract } else { CounterpartyKind::Eoa };
+        Ok((kind, c.label))
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Validated {
+    input: String,
+    valid: bool,
+    /// Checksummed
+    address: Option<String>,
+    error: Option<String>,
+    /// Same address as an earlier entry
+    duplicate: bool,
+    /// `configured` or `deposit` (discovered)
+    tracked: Option<&'static str>,
+    entity: Option<String>,
+    kind: Option<CounterpartyKind>,
+    label: Option<String>,
+}
+
+pub async fn addresses(
+    State(conn): State<Db>,
+    State(reader): State<Option<CodeReader>>,
+    Json(inputs): Json<Vec<String>>,
+) -> Response {
+    if inputs.len() > MAX_ADDRESSES {
+        return (StatusCode::BAD_REQUEST, format!("at most {MAX_ADDRESSES} addresses per request")).into_response();
+    }
+    let parsed: Vec<Result<Address, String>> = inputs.iter().map(|s| normalize(s)).collect();
+    let keys: Vec<String> = parsed.iter().flatten().map(models::address_key).collect();
+
+    let known = {
+        let conn = conn.lock().await;
+        known(&conn, &keys)
+    };
+    let (tracked, entities, mut kinds) = match known {
+        Ok(known) => known,
+        Err(e) => return internal_error(e),
+    };
+
+    if let Some(reader) = &reader {
+        let unknown: Vec<Address> = parsed.iter().flatten().copied()
+            .filter(|a| !kinds.contains_key(&models::address_key(a)))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let read: Vec<_> = stream::iter(unknown)
+            .map(|a| async move { (a, reader.kind(a).await) })
+            .buffer_unordered(CODE_READS)
+            .collect()
+            .await;
+        for (address, result) in read {
+            match result {
+                Ok(kind) => { kinds.insert(models::address_key(&address), kind); }
+                Err(e) => tracing::warn!(?address, error = %format!("{e:#}"), "eth_getCode failed; kind left unknown"),
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let out: Vec<Validated> = inputs.into_iter().zip(parsed).map(|(input, parsed)| match parsed {
+        Ok(address) => {
+            let key = models::address_key(&address);
+            let kind = kinds.get(&key);
+            Validated {
+                input,
+                valid: true,
+                address: Some(to_checksum(&address, None)),
+                duplicate: !seen.insert(address),
+                tracked: tracked.get(&key).copied(),
+                entity: entities.get(&key).cloned(),
+                kind: kind.map(|k| k.0),
+                label: kind.and_then(|k| k.1.clone()),
+                ..Default::default()
+            }
+        }
+        Err(error) => Validated { input, error: Some(error), ..Default::default() },
+    }).collect();
+    Json(out).into_response()
+}
+
+/// A `0x`-prefixed (or bare) hex address. All-lowercase and all-uppercase
+/// inputs carry no checksum; mixed case must match EIP-55.
+fn normalize(input: &str) -> Result<Address, String> {
+    let hex = input.trim();
+    let hex = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X")).unwrap_or(hex);
+    if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("not a 20-byte hex address".into());
+    }
+    let address: Address = hex.parse().map_err(|_| "not a 20-byte hex address".to_string())?;
+    let mixed = hex.bytes().any(|b| b.is_ascii_lowercase()) && hex.bytes().any(|b| b.is_ascii_uppercase());
+    if mixed && to_checksum(&address, None)[2..] != *hex {
+        return Err(format!("checksum mismatch; expected {}", to_checksum(&address, None)));
+    }
+    Ok(address)
+}
+
+type Known = (HashMap<String, &'static str>, HashMap<String, String>, HashMap<String, (CounterpartyKind, Option<String>)>);
+
+/// What the database already says about `keys`: tracked status, entity
+/// membership and cached classifications.
+fn known(conn: &rusqlite::Connection, keys: &[String]) -> eyre::Result<Known> {
+    let mut tracked = HashMap::new();
+    let configured = db::get_dataset_binance_addresses(conn)?.unwrap_or_default();
+    for addr in configured.split(',').filter(|a| !a.is_empty()) {
+        tracked.insert(addr.to_string(), "configured");
+    }
+    for addr in db::list_deposit_addresses(conn)? {
+        tracked.entry(addr).or_insert("deposit");
+    }
+    let mut entities = HashMap::new();
+    for (entity, members) in db::list_entities(conn)? {
+        for member in members {
+            entities.insert(member, entity.clone());
+        }
+    }
+    let mut kinds = HashMap::new();
+    for key in keys {
+        if let Some(kind) = db::get_contract(conn, key)? {
+            kinds.insert(key.clone(), kind);
+        }
+    }
+    Ok((tracked, entities, kinds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_and_checks_eip55() {
+        let hot = "0xF977814e90dA44bFA03b6295A0616a897441aceC";
+        assert_eq!(to_checksum(&normalize(hot).unwrap(), None), hot);
+        assert_eq!(normalize(&hot.to_lowercase()), normalize(hot));
+        assert_eq!(normalize(&hot[2..]), normalize(hot), "0x is optional");
+        assert!(normalize("0xf977814e90da44bfa03b6295a0616a897441acEC").unwrap_err().starts_with("checksum mismatch"));
+        assert!(normalize("0x1234").is_err());
+        assert!(normalize("binance").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Bounded worker for expensive analytical queries.
+//!
+//! Each job gets its own read-only SQLite connection and runs on the blocking
+//! thread pool, so it neither stalls the async runtime nor holds the shared
+//! API connection. At most `workers` jobs run at once; a job that cannot start,
+//! or finish, within `timeout` is abandoned and its query interrupted. A job
+//! reads one snapshot of the database, as [`super::snapshot`] does.
+//! Streamed responses ([`HeavyWorker::spawn`]) hold a worker as long as the
+//! client keeps reading, with only the wait for it bounded.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use rusqlite::{Connection, OpenFlags};
+use tokio::{sync::{OwnedSemaphorePermit, Semaphore}, time::Instant};
+
+use super::internal_error;
+
+#[derive(Clone)]
+pub struct HeavyWorker {
+    db_path: Arc<str>,
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+pub enum HeavyError {
+    /// Every worker stayed busy for the whole timeout
+    Busy,
+    /// The query itself ran past the timeout and was interrupted
+    TimedOut,
+    Failed(eyre::Report),
+}
+
+impl IntoResponse for HeavyError {
+    fn into_response(self) -> Response {
+        match self {
+            HeavyError::Busy => (StatusCode::SERVICE_UNAVAILABLE, "too many heavy queries in flight; retry later").into_response(),
+            HeavyError::TimedOut => (StatusCode::GATEWAY_TIMEOUT, "query exceeded the heavy-query timeout").into_response(),
+            HeavyError::Failed(e) => internal_error(e),
+        }
+    }
+}
+
+impl HeavyWorker {
+    pub fn new(db_path: &str, workers: usize, timeout: Duration) -> Self {
+        HeavyWorker {
+            db_path: db_path.into(),
+            permits: Arc::new(Semaphore::new(workers.max(1))),
+            timeout,
+        }
+    }
+
+    /// Run `job` against a fresh read-only connection, within the worker limit and timeout.
+    pub async fn run<T, F>(&self, job: F) -> Result<T, HeavyError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> eyre::Result<T> + Send + 'static,
+    {
+        let deadline = Instant::now() + self.timeout;
+        let (permit, conn) = self.acquire(deadline).await?;
+        let interrupt = conn.get_interrupt_handle();
+        let task = tokio::task::spawn_blocking(move || {
+            // Held until the query returns, even if the caller has given up on it
+            let _permit = permit;
+            let tx = conn.unchecked_transaction()?;
+            job(&tx)
+        });
+
+        match tokio::time::timeout_at(deadline, task).await {
+            Ok(Ok(result)) => result.map_err(HeavyError::Failed),
+            Ok(Err(join)) => Err(HeavyError::Failed(eyre::eyre!("heavy query panicked: {join}"))),
+            Err(_) => {
+                interrupt.interrupt();
+                tracing::warn!(timeout = ?self.timeout, "Heavy query timed out; interrupted");
+                Err(HeavyError::TimedOut)
+            }
+        }
+    }
+
+    /// Start `job` once a worker is free (`Busy` after waiting the timeout),
+    /// and let it run to the end, however long that takes. For streamed
+    /// responses, whose job sends its output on as it goes.
+    pub async fn spawn<F>(&self, job: F) -> Result<(), HeavyError>
+    where
+        F: FnOnce(&Connection) -> eyre::Result<()> + Send + 'static,
+    {
+        let (permit, conn) = self.acquire(Instant::now() + self.timeout).await?;
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let result = conn.unchecked_transaction().map_err(eyre::Report::from).and_then(|tx| job(&tx));
+            if let Err(e) = result {
+                tracing::warn!(error = %format!("{e:#}"), "Streamed heavy query failed");
+            }
+        });
+        Ok(())
+    }
+
+    async fn acquire(&self, deadline: Instant) -> Result<(OwnedSemaphorePermit, Connection), HeavyError> {
+        let permit = match tokio::time::timeout_at(deadline, self.permits.clone().acquire_owned()).await {
+            Ok(permit) => permit.expect("semaphore is never closed"),
+            Err(_) => return Err(HeavyError::Busy),
+        };
+        let conn = Connection::open_with_flags(
+            &*self.db_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        ).map_err(|e| HeavyError::Failed(e.into()))?;
+        Ok((permit, conn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Scratch;
+
+    fn scratch(name: &str) -> Scratch {
+        let db = Scratch::new(name);
+        Connection::open(db.path()).unwrap().execute_batch("CREATE TABLE t (x INTEGER);").unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn abandoned_job_keeps_its_worker_until_it_returns() {
+        let db = scratch("worker-busy");
+        let heavy = HeavyWorker::new(db.path(), 1, Duration::from_millis(300));
+        let (started_tx, started) = std::sync::mpsc::channel();
+        let (release, release_rx) = std::sync::mpsc::channel::<()>();
+
+        let first = tokio::spawn({
+            let heavy = heavy.clone();
+            async move {
+                heavy.run(move |_| {
+                    started_tx.send(()).unwrap();
+                    release_rx.recv().ok();
+                    Ok(())
+                }).await
+            }
+        });
+        tokio::task::spawn_blocking(move || started.recv()).await.unwrap().unwrap();
+
+        assert!(matches!(heavy.run(|_| Ok(())).await, Err(HeavyError::Busy)));
+        assert!(matches!(first.await.unwrap(), Err(HeavyError::TimedOut)));
+        // The caller gave up, but the job is still running on the only worker
+        assert!(matches!(heavy.run(|_| Ok(())).await, Err(HeavyError::Busy)));
+
+        release.send(()).unwrap();
+        assert!(matches!(heavy.run(|_| Ok(1)).await, Ok(1)));
+    }
+
+    #[tokio::test]
+    async fn runaway_query_is_interrupted_and_frees_its_worker() {
+        let db = scratch("worker-interrupt");
+        let heavy = HeavyWorker::new(db.path(), 1, Duration::from_millis(300));
+
+        let endless = heavy.run(|conn| {
+            let n: i64 = conn.query_row(
+                "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT COUNT(*) FROM c",
+                [],
+                |r| r.get(0),
+            )?;
+            Ok(n)
+        });
+        assert!(matches!(endless.await, Err(HeavyError::TimedOut)));
+
+        let counted = heavy.run(|conn| Ok(conn.query_row("SELECT COUNT(*) FROM t", [], |r| r.get::<_, i64>(0))?));
+        assert!(matches!(counted.await, Ok(0)));
+    }
+}
@@ -0,0 +1,60 @@
+//! API keys with per-exchange / per-token visibility scopes.
+//!
+//! Only the SHA-256 of a key is stored; the plaintext is printed once by
+//! `keys create`. A scope list of `*` grants everything.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::models::ValueFormat;
+
+/// The exchange whose address set this instance tracks.
+pub const EXCHANGE: &str = "binance";
+
+const KEY_PREFIX: &str = "pk_";
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub exchanges: Vec<String>,
+    /// Lowercase 0x-prefixed token addresses
+    pub tokens: Vec<String>,
+    pub created_at_unix: i64,
+    pub revoked_at_unix: Option<i64>,
+    /// `?format=` for requests that don't pass one
+    pub default_format: Option<ValueFormat>,
+}
+
+impl ApiKey {
+    pub fn allows_exchange(&self, exchange: &str) -> bool {
+        scope_allows(&self.exchanges, exchange)
+    }
+
+    pub fn allows_token(&self, token: &str) -> bool {
+        scope_allows(&self.tokens, token)
+    }
+}
+
+fn scope_allows(scope: &[String], item: &str) -> bool {
+    scope.iter().any(|s| s == "*" || s.eq_ignore_ascii_case(item))
+}
+
+/// Parse a comma-separated scope list; empty means "all".
+pub fn parse_scope(csv: &str) -> Vec<String> {
+    let items: Vec<String> = csv.split(',')
+        .map(|s| s.trim().to_ascii_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if items.is_empty() { vec!["*".to_string()] } else { items }
+}
+
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{KEY_PREFIX}{}", hex::encode(bytes))
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
@@ -0,0 +1,129 @@
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{Address as AlloyAddress, B256},
+    consensus::Transaction as _,
+    network::TransactionResponse as _,
+    providers::{DynProvider, Provider, ProviderBuilder, WsConnect},
+    rpc::types::{Filter, Header, Log, TransactionRequest},
+};
+use async_trait::async_trait;
+use ethers::types::{Address, H256, U256};
+use eyre::Result;
+use futures_util::{stream::BoxStream, StreamExt};
+
+use super::{BlockHeader, ChainClient, LogFilter, NativeTx, RawLog};
+
+pub struct AlloyClient {
+    provider: DynProvider,
+}
+
+impl AlloyClient {
+    pub async fn connect(rpc_url: &str) -> Result<Self> {
+        let provider = ProviderBuilder::new().connect_ws(WsConnect::new(rpc_url)).await?;
+        Ok(Self { provider: provider.erased() })
+    }
+}
+
+fn h256(b: B256) -> H256 {
+    H256(b.0)
+}
+
+fn b256(h: H256) -> B256 {
+    B256::from(h.0)
+}
+
+fn header(h: &Header) -> BlockHeader {
+    BlockHeader { number: h.number, hash: h256(h.hash), parent_hash: h256(h.parent_hash), timestamp: h.timestamp }
+}
+
+fn raw_log(l: Log) -> RawLog {
+    RawLog {
+        address: Address::from(l.inner.address.0 .0),
+        topics: l.inner.data.topics().iter().copied().map(h256).collect(),
+        data: l.inner.data.data.to_vec(),
+        block_number: l.block_number,
+        block_hash: l.block_hash.map(h256),
+        transaction_hash: l.transaction_hash.map(h256),
+        log_index: l.log_index,
+    }
+}
+
+#[async_trait]
+impl ChainClient for AlloyClient {
+    async fn chain_id(&self) -> Result<u64> {
+        Ok(self.provider.get_chain_id().await?)
+    }
+
+    async fn block_number(&self) -> Result<u64> {
+        Ok(self.provider.get_block_number().await?)
+    }
+
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+        let sub = self.provider.subscribe_blocks().await?;
+        Ok(sub.into_stream().map(|h| header(&h)).boxed())
+    }
+
+    async fn get_logs(&self, f: &LogFilter) -> Result<Vec<RawLog>> {
+        let mut filter = Filter::new().event_signature(b256(f.topic0));
+        if let Some(address) = f.address {
+            filter = filter.address(AlloyAddress::from(address.0));
+        }
+        filter = match f.block_hash {
+            Some(hash) => filter.at_block_hash(b256(hash)),
+            None => filter.from_block(f.from_block).to_block(f.to_block),
+        };
+        if let Some(t) = &f.topic1 {
+            filter = filter.topic1(t.iter().copied().map(b256).collect::<Vec<_>>());
+        }
+        if let Some(t) = &f.topic2 {
+            filter = filter.topic2(t.iter().copied().map(b256).collect::<Vec<_>>());
+        }
+        if let Some(t) = &f.topic3 {
+            filter = filter.topic3(t.iter().copied().map(b256).collect::<Vec<_>>());
+        }
+        let logs = self.provider.get_logs(&filter).await?;
+        Ok(logs.into_iter().map(raw_log).collect())
+    }
+
+    async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>> {
+        let block = self.provider.get_block_by_number(BlockNumberOrTag::Number(number)).await?;
+        Ok(block.map(|b| header(&b.header)))
+    }
+
+    async fn block_transactions(&self, number: u64) -> Result<Vec<NativeTx>> {
+        let block = self.provider.get_block_by_number(BlockNumberOrTag::Number(number)).full().await?;
+        let Some(block) = block else { return Ok(Vec::new()) };
+        Ok(block.transactions.into_transactions()
+            .map(|tx| NativeTx {
+                hash: h256(tx.tx_hash()),
+                from: Address::from(tx.from().0 .0),
+                to: tx.to().map(|a| Address::from(a.0 .0)),
+                value: U256::from_little_endian(&tx.value().to_le_bytes::<32>()),
+            })
+            .collect())
+    }
+
+    async fn call(&self, to: Address, data: Vec<u8>, block: Option<u64>) -> Result<Vec<u8>> {
+        let tx = TransactionRequest::default()
+            .to(AlloyAddress::from(to.0))
+            .input(data.into());
+        let at = block.map_or(BlockNumberOrTag::Latest, BlockNumberOrTag::Number);
+        Ok(self.provider.call(tx).block(at.into()).await?.to_vec())
+    }
+
+    async fn storage_at(&self, address: Address, slot: H256) -> Result<H256> {
+        let value = self.provider
+            .get_storage_at(AlloyAddress::from(address.0), b256(slot).into())
+            .await?;
+        Ok(H256(value.to_be_bytes()))
+    }
+
+    async fn code_at(&self, address: Address) -> Result<Vec<u8>> {
+        Ok(self.provider.get_code_at(AlloyAddress::from(address.0)).await?.to_vec())
+    }
+
+    async fn transaction_status(&self, tx_hash: H256) -> Result<Option<bool>> {
+        let receipt = self.provider.get_transaction_receipt(b256(tx_hash)).await?;
+        Ok(receipt.map(|r| r.status()))
+    }
+}
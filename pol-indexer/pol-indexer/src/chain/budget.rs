@@ -0,0 +1,199 @@
+//! RPC budget: a [`ChainClient`] wrapper that paces requests and caps how
+//! many are in flight, so a long backfill stays inside a paid plan's quota.
+//!
+//! When the provider rate-limits a request (JSON-RPC code 429, "too many
+//! requests", "rate limit", or Alchemy's compute-units error) the call is retried after a backoff and the
+//! request rate is halved. It creeps back up by a quarter after each quiet
+//! `RECOVER_AFTER`, never above `RPC_MAX_RPS`. Without a configured rate the
+//! first 429 starts pacing at half the rate observed just before it.
+
+use std::{
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use ethers::types::{Address, H256};
+use eyre::Result;
+use futures_util::stream::BoxStream;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use super::{BlockHeader, ChainClient, LogFilter, NativeTx, RawLog};
+use crate::metrics;
+
+/// Attempts per call before a rate-limit error is returned to the caller.
+const MAX_ATTEMPTS: u32 = 6;
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const RECOVER_AFTER: Duration = Duration::from_secs(30);
+/// Pacing never drops below this.
+const MIN_RPS: f64 = 0.5;
+/// A recovered rate above this (with no configured maximum) stops pacing.
+const UNPACED_RPS: f64 = 1_000.0;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RpcBudget {
+    /// Requests per second; `None` paces only after the provider pushes back
+    pub max_rps: Option<f64>,
+    /// Requests in flight at once; `None` = unlimited
+    pub max_concurrency: Option<usize>,
+}
+
+struct Pace {
+    /// Current rate; `None` = unpaced
+    rps: Option<f64>,
+    next_slot: Instant,
+    last_limited: Instant,
+    /// Requests started in the current one-second window, for the observed rate
+    window_start: Instant,
+    window_count: u32,
+    last_window_count: u32,
+}
+
+pub struct Budgeted {
+    inner: Box<dyn ChainClient>,
+    max_rps: Option<f64>,
+    in_flight: Option<Semaphore>,
+    pace: Mutex<Pace>,
+}
+
+pub fn wrap(inner: Box<dyn ChainClient>, budget: RpcBudget) -> Box<dyn ChainClient> {
+    let now = Instant::now();
+    publish(budget.max_rps);
+    Box::new(Budgeted {
+        inner,
+        max_rps: budget.max_rps,
+        in_flight: budget.max_concurrency.map(Semaphore::new),
+        pace: Mutex::new(Pace {
+            rps: budget.max_rps,
+            next_slot: now,
+            last_limited: now,
+            window_start: now,
+            window_count: 0,
+            last_window_count: 0,
+        }),
+    })
+}
+
+impl Budgeted {
+    async fn run<T, F, Fut>(&self, call: F) -> Result<T>
+    where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let _permit = match &self.in_flight {
+            Some(s) => Some(s.acquire().await.expect("RPC semaphore is never closed")),
+            None => None,
+        };
+        let mut attempt = 1;
+        loop {
+            let wait = self.reserve_slot();
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            match call().await {
+                Err(e) if is_rate_limited(&e) && attempt < MAX_ATTEMPTS => {
+                    let backoff = Duration::from_secs(1 << (attempt - 1)).min(MAX_BACKOFF);
+                    let rps = self.slow_down();
+                    metrics::RPC_RATE_LIMITED.inc();
+                    warn!(attempt, backoff_secs = backoff.as_secs(), rps = format!("{rps:.1}"), error = %e, "Provider rate limit; slowing down");
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// Claim the next request slot; returns how long to wait for it.
+    fn reserve_slot(&self) -> Duration {
+        let mut p = self.pace.lock().expect("pace lock");
+        let now = Instant::now();
+        if now.duration_since(p.window_start) >= Duration::from_secs(1) {
+            p.last_window_count = p.window_count;
+            p.window_start = now;
+            p.window_count = 0;
+        }
+        p.window_count += 1;
+        if let Some(rps) = p.rps {
+            if now.duration_since(p.last_limited) >= RECOVER_AFTER {
+                p.last_limited = now;
+                let recovered = rps * 1.25;
+                p.rps = match self.max_rps {
+                    Some(max) => Some(recovered.min(max)),
+                    None => (recovered < UNPACED_RPS).then_some(recovered),
+                };
+                publish(p.rps);
+            }
+        }
+        let Some(rps) = p.rps else { return Duration::ZERO };
+        let slot = p.next_slot.max(now);
+        p.next_slot = slot + Duration::from_secs_f64(1.0 / rps);
+        slot - now
+    }
+
+    /// Halve the rate after a 429; returns the new rate.
+    fn slow_down(&self) -> f64 {
+        let mut p = self.pace.lock().expect("pace lock");
+        let current = p.rps.unwrap_or(p.last_window_count.max(p.window_count) as f64);
+        let rps = (current / 2.0).max(MIN_RPS);
+        p.rps = Some(rps);
+        p.last_limited = Instant::now();
+        publish(p.rps);
+        rps
+    }
+}
+
+fn publish(rps: Option<f64>) {
+    metrics::RPC_PACED_RPS.set(rps.unwrap_or(0.0));
+}
+
+fn is_rate_limited(e: &eyre::Report) -> bool {
+    let msg = format!("{e:#}").to_ascii_lowercase();
+    ["code: 429", "too many requests", "rate limit", "compute units per second"].iter().any(|m| msg.contains(m))
+}
+
+#[async_trait]
+impl ChainClient for Budgeted {
+    async fn chain_id(&self) -> Result<u64> {
+        self.run(|| self.inner.chain_id()).await
+    }
+
+    async fn block_number(&self) -> Result<u64> {
+        self.run(|| self.inner.block_number()).await
+    }
+
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+        self.run(|| self.inner.subscribe_blocks()).await
+    }
+
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>> {
+        self.run(|| self.inner.get_logs(filter)).await
+    }
+
+    async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>> {
+        self.run(|| self.inner.get_block(number)).await
+    }
+
+    async fn block_transactions(&self, number: u64) -> Result<Vec<NativeTx>> {
+        self.run(|| self.inner.block_transactions(number)).await
+    }
+
+    async fn call(&self, to: Address, data: Vec<u8>, block: Option<u64>) -> Result<Vec<u8>> {
+        self.run(|| self.inner.call(to, data.clone(), block)).await
+    }
+
+    async fn storage_at(&self, address: Address, slot: H256) -> Result<H256> {
+        self.run(|| self.inner.storage_at(address, slot)).await
+    }
+
+    async fn code_at(&self, address: Address) -> Result<Vec<u8>> {
+        self.run(|| self.inner.code_at(address)).await
+    }
+
+    async fn transaction_status(&self, tx_hash: H256) -> Result<Option<bool>> {
+        self.run(|| self.inner.transaction_status(tx_hash)).await
+    }
+}
@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use ethers::{
+    providers::{Middleware, Provider, StreamExt, Ws},
+    types::{Address, Block, BlockId, BlockNumber, Bytes, Filter, Log, TransactionRequest, H256},
+};
+use eyre::Result;
+use futures_util::stream::BoxStream;
+
+use super::{BlockHeader, ChainClient, LogFilter, NativeTx, RawLog};
+
+pub struct EthersClient {
+    provider: Provider<Ws>,
+}
+
+impl EthersClient {
+    pub async fn connect(rpc_url: &str) -> Result<Self> {
+        let ws = Ws::connect(rpc_url).await?;
+        Ok(Self { provider: Provider::new(ws) })
+    }
+}
+
+fn header(b: &Block<H256>) -> Option<BlockHeader> {
+    Some(BlockHeader {
+        number: b.number?.as_u64(),
+        hash: b.hash.unwrap_or_default(),
+        parent_hash: b.parent_hash,
+        timestamp: b.timestamp.as_u64(),
+    })
+}
+
+fn raw_log(l: Log) -> RawLog {
+    RawLog {
+        address: l.address,
+        topics: l.topics,
+        data: l.data.to_vec(),
+        block_number: l.block_number.map(|n| n.as_u64()),
+        block_hash: l.block_hash,
+        transaction_hash: l.transaction_hash,
+        log_index: l.log_index.map(|i| i.as_u64()),
+    }
+}
+
+#[async_trait]
+impl ChainClient for EthersClient {
+    async fn chain_id(&self) -> Result<u64> {
+        Ok(self.provider.get_chainid().await?.as_u64())
+    }
+
+    async fn block_number(&self) -> Result<u64> {
+        Ok(self.provider.get_block_number().await?.as_u64())
+    }
+
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+        let stream = self.provider.subscribe_blocks().await?;
+        Ok(stream.filter_map(|b| async move { header(&b) }).boxed())
+    }
+
+    async fn get_logs(&self, f: &LogFilter) -> Result<Vec<RawLog>> {
+        let mut filter = Filter::new().topic0(f.topic0);
+        if let Some(address) = f.address {
+            filter = filter.address(address);
+        }
+        filter = match f.block_hash {
+            Some(hash) => filter.at_block_hash(hash),
+            None => filter.from_block(f.from_block).to_block(f.to_block),
+        };
+        if let Some(t) = &f.topic1 {
+            filter = filter.topic1(t.clone());
+        }
+        if let Some(t) = &f.topic2 {
+            filter = filter.topic2(t.clone());
+        }
+        if let Some(t) = &f.topic3 {
+            filter = filter.topic3(t.clone());
+        }
+        let logs = self.provider.get_logs(&filter).await?;
+        Ok(logs.into_iter().map(raw_log).collect())
+    }
+
+    async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>> {
+        let block = self.provider.get_block(BlockId::Number(BlockNumber::Number(number.into()))).await?;
+        Ok(block.as_ref().and_then(header))
+    }
+
+    async fn block_transactions(&self, number: u64) -> Result<Vec<NativeTx>> {
+        let block = self.provider.get_block_with_txs(BlockId::Number(BlockNumber::Number(number.into()))).await?;
+        Ok(block.map(|b| b.transactions).unwrap_or_default().into_iter()
+            .map(|tx| NativeTx { hash: tx.hash, from: tx.from, to: tx.to, value: tx.value })
+            .collect())
+    }
+
+    async fn call(&self, to: Address, data: Vec<u8>, block: Option<u64>) -> Result<Vec<u8>> {
+        let tx = TransactionRequest::new().to(to).data(Bytes::from(data));
+        let at = block.map(|n| BlockId::Number(BlockNumber::Number(n.into())));
+        Ok(self.provider.call(&tx.into(), at).await?.to_vec())
+    }
+
+    async fn storage_at(&self, address: Address, slot: H256) -> Result<H256> {
+        Ok(self.provider.get_storage_at(address, slot, None).await?)
+    }
+
+    async fn code_at(&self, address: Address) -> Result<Vec<u8>> {
+        Ok(self.provider.get_code(address, None).await?.to_vec())
+    }
+
+    async fn transaction_status(&self, tx_hash: H256) -> Result<Option<bool>> {
+        let receipt = self.provider.get_transaction_receipt(tx_hash).await?;
+        Ok(receipt.and_then(|r| r.status).map(|s| s.as_u64() == 1))
+    }
+}
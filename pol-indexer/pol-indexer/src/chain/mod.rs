@@ -0,0 +1,192 @@
+//! RPC access behind a small trait so the indexer doesn't depend on a specific
+//! client library. ethers is the default; an alloy implementation is available
+//! with the `alloy` cargo feature (`RPC_CLIENT=alloy`).
+//!
+//! The types here use the `primitive-types` hashes/addresses the rest of the
+//! crate already works with. They serialize so RPC traffic can be recorded
+//! and replayed (see [`replay`]).
+
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use ethers::types::{Address, H256, U256};
+use eyre::Result;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "alloy")]
+mod alloy;
+mod budget;
+mod ethers_client;
+pub mod health;
+pub mod replay;
+
+pub use budget::RpcBudget;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub timestamp: u64,
+}
+
+/// `eth_getLogs` filter. Topic positions given as a list match any of the values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFilter {
+    /// `None`: any contract
+    pub address: Option<Address>,
+    pub topic0: H256,
+    pub topic1: Option<Vec<H256>>,
+    pub topic2: Option<Vec<H256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic3: Option<Vec<H256>>,
+    pub from_block: u64,
+    pub to_block: u64,
+    /// Query this block by hash (EIP-234) instead of the range, so the logs
+    /// are those of exactly that block even if a reorg has replaced it at its
+    /// height. `from_block`/`to_block` must then both be its number.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<H256>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    #[serde(with = "hex_data")]
+    pub data: Vec<u8>,
+    pub block_number: Option<u64>,
+    /// Absent from recordings made before it was kept
+    #[serde(default)]
+    pub block_hash: Option<H256>,
+    pub transaction_hash: Option<H256>,
+    pub log_index: Option<u64>,
+}
+
+/// A transaction's top-level native (POL) value transfer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NativeTx {
+    pub hash: H256,
+    pub from: Address,
+    /// `None` for contract creations
+    pub to: Option<Address>,
+    pub value: U256,
+}
+
+#[async_trait]
+pub trait ChainClient: Send + Sync {
+    async fn chain_id(&self) -> Result<u64>;
+    async fn block_number(&self) -> Result<u64>;
+    /// New-heads subscription; ends when the connection drops.
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>>;
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>>;
+    async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>>;
+    /// Every transaction in block `number` (empty if the block isn't available).
+    async fn block_transactions(&self, number: u64) -> Result<Vec<NativeTx>>;
+    /// `eth_call` at `block`, or the latest block when `None`.
+    async fn call(&self, to: Address, data: Vec<u8>, block: Option<u64>) -> Result<Vec<u8>>;
+    /// Storage slot of `address` at the latest block.
+    async fn storage_at(&self, address: Address, slot: H256) -> Result<H256>;
+    /// Runtime bytecode of `address` at the latest block; empty for an account without code.
+    async fn code_at(&self, address: Address) -> Result<Vec<u8>>;
+    /// Receipt status: `Some(true)` succeeded, `Some(false)` reverted, `None`
+    /// when the receipt isn't available (yet) or carries no status.
+    async fn transaction_status(&self, tx_hash: H256) -> Result<Option<bool>>;
+}
+
+/// Stands in for a node where none is configured and the work shouldn't need
+/// one (`rebuild` from the raw log archive); every request fails.
+pub struct Offline;
+
+#[async_trait]
+impl ChainClient for Offline {
+    async fn chain_id(&self) -> Result<u64> {
+        Err(offline())
+    }
+    async fn block_number(&self) -> Result<u64> {
+        Err(offline())
+    }
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+        Err(offline())
+    }
+    async fn get_logs(&self, _filter: &LogFilter) -> Result<Vec<RawLog>> {
+        Err(offline())
+    }
+    async fn get_block(&self, _number: u64) -> Result<Option<BlockHeader>> {
+        Err(offline())
+    }
+    async fn block_transactions(&self, _number: u64) -> Result<Vec<NativeTx>> {
+        Err(offline())
+    }
+    async fn call(&self, _to: Address, _data: Vec<u8>, _block: Option<u64>) -> Result<Vec<u8>> {
+        Err(offline())
+    }
+    async fn storage_at(&self, _address: Address, _slot: H256) -> Result<H256> {
+        Err(offline())
+    }
+    async fn code_at(&self, _address: Address) -> Result<Vec<u8>> {
+        Err(offline())
+    }
+    async fn transaction_status(&self, _tx_hash: H256) -> Result<Option<bool>> {
+        Err(offline())
+    }
+}
+
+fn offline() -> eyre::Report {
+    eyre::eyre!("no RPC configured (set RPC_URL)")
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum ClientKind {
+    #[default]
+    Ethers,
+    Alloy,
+}
+
+/// Where chain data comes from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Several URLs are scored and failed over between (see [`health`])
+    Rpc { urls: Vec<String>, client: ClientKind },
+    /// Responses recorded by `pol-indexer record` (`REPLAY_FILE`)
+    Replay(PathBuf),
+}
+
+/// [`connect`], with every request going through `budget`.
+pub async fn connect_budgeted(source: &Source, budget: RpcBudget) -> Result<Box<dyn ChainClient>> {
+    Ok(budget::wrap(connect(source).await?, budget))
+}
+
+pub async fn connect(source: &Source) -> Result<Box<dyn ChainClient>> {
+    match source {
+        Source::Rpc { urls, client } if urls.len() > 1 => health::connect(urls, *client).await,
+        Source::Rpc { urls, client } => connect_url(urls.first().map_or("", String::as_str), *client).await,
+        Source::Replay(path) => Ok(Box::new(replay::Replay::open(path)?)),
+    }
+}
+
+async fn connect_url(rpc_url: &str, kind: ClientKind) -> Result<Box<dyn ChainClient>> {
+    match kind {
+        ClientKind::Ethers => Ok(Box::new(ethers_client::EthersClient::connect(rpc_url).await?)),
+        #[cfg(feature = "alloy")]
+        ClientKind::Alloy => Ok(Box::new(alloy::AlloyClient::connect(rpc_url).await?)),
+        #[cfg(not(feature = "alloy"))]
+        ClientKind::Alloy => Err(eyre::eyre!("RPC_CLIENT=alloy requires building with `--features alloy`")),
+    }
+}
+
+/// Byte strings as 0x-prefixed hex, as on the wire.
+mod hex_data {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        format!("0x{}", hex::encode(data)).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+        hex::decode(s.trim_start_matches("0x")).map_err(serde::de::Error::custom)
+    }
+}
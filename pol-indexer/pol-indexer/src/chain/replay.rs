@@ -0,0 +1,268 @@
+//! Deterministic record/replay of RPC traffic, for regression tests and bug
+//! reproduction without a live node.
+//!
+//! `pol-indexer record` runs the normal pipeline over a block range through a
+//! [`Recorder`], which writes every request and its response (or error) to an
+//! NDJSON file. `REPLAY_FILE` swaps the node for a [`Replay`] that answers the
+//! same requests from that file, so the pipeline makes the same decisions.
+//!
+//! Each line is a versioned envelope like the event feed's:
+//! `{"v":1,"type":"response","data":{…}}`, after one `meta` line describing
+//! the recording. A request seen more than once is answered in recorded order,
+//! then with its last answer. Requests that weren't recorded fail.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use ethers::types::{Address, H256};
+use eyre::{eyre, Result, WrapErr};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{hex_data, BlockHeader, ChainClient, LogFilter, NativeTx, RawLog};
+use crate::models::AccountingMode;
+
+/// Recording format version; bumped on incompatible changes.
+pub const RECORDING_VERSION: u32 = 1;
+
+/// What a recording covers; replays must be configured the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub token: Address,
+    pub binance_addrs: Vec<Address>,
+    pub accounting_mode: AccountingMode,
+    pub track_native: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+enum Request {
+    ChainId,
+    BlockNumber,
+    GetLogs(LogFilter),
+    GetBlock(u64),
+    BlockTransactions(u64),
+    Call {
+        to: Address,
+        #[serde(with = "hex_data")]
+        data: Vec<u8>,
+        block: Option<u64>,
+    },
+    StorageAt { address: Address, slot: H256 },
+    CodeAt(Address),
+    TransactionStatus(H256),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Response {
+    request: Request,
+    #[serde(flatten)]
+    outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Result(Value),
+    Error(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum Entry {
+    Meta(Meta),
+    Response(Response),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    v: u32,
+    #[serde(flatten)]
+    entry: Entry,
+}
+
+/// Records every response from `inner`. The head is capped at the end of the
+/// range and there are no new heads, so `run` stops once the range is indexed.
+pub struct Recorder {
+    inner: Box<dyn ChainClient>,
+    to_block: u64,
+    out: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(inner: Box<dyn ChainClient>, path: &Path, meta: Meta) -> Result<Self> {
+        let file = File::create(path).wrap_err_with(|| format!("creating {}", path.display()))?;
+        let to_block = meta.to_block;
+        let recorder = Self { inner, to_block, out: Mutex::new(LineWriter::new(file)) };
+        recorder.write(Entry::Meta(meta))?;
+        Ok(recorder)
+    }
+
+    fn write(&self, entry: Entry) -> Result<()> {
+        let line = serde_json::to_string(&Envelope { v: RECORDING_VERSION, entry })?;
+        writeln!(self.out.lock().expect("recording lock"), "{line}")?;
+        Ok(())
+    }
+
+    fn record<T: Serialize>(&self, request: Request, result: Result<T>) -> Result<T> {
+        let outcome = match &result {
+            Ok(v) => Outcome::Result(serde_json::to_value(v)?),
+            Err(e) => Outcome::Error(format!("{e:#}")),
+        };
+        self.write(Entry::Response(Response { request, outcome }))?;
+        result
+    }
+}
+
+#[async_trait]
+impl ChainClient for Recorder {
+    async fn chain_id(&self) -> Result<u64> {
+        let r = self.inner.chain_id().await;
+        self.record(Request::ChainId, r)
+    }
+
+    async fn block_number(&self) -> Result<u64> {
+        let head = self.inner.block_number().await.map(|n| n.min(self.to_block));
+        self.record(Request::BlockNumber, head)
+    }
+
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+        Ok(stream::empty().boxed())
+    }
+
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>> {
+        let r = self.inner.get_logs(filter).await;
+        self.record(Request::GetLogs(filter.clone()), r)
+    }
+
+    async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>> {
+        let r = self.inner.get_block(number).await;
+        self.record(Request::GetBlock(number), r)
+    }
+
+    async fn block_transactions(&self, number: u64) -> Result<Vec<NativeTx>> {
+        let r = self.inner.block_transactions(number).await;
+        self.record(Request::BlockTransactions(number), r)
+    }
+
+    async fn call(&self, to: Address, data: Vec<u8>, block: Option<u64>) -> Result<Vec<u8>> {
+        let r = self.inner.call(to, data.clone(), block).await.map(ethers::types::Bytes::from);
+        self.record(Request::Call { to, data, block }, r).map(|b| b.to_vec())
+    }
+
+    async fn storage_at(&self, address: Address, slot: H256) -> Result<H256> {
+        let r = self.inner.storage_at(address, slot).await;
+        self.record(Request::StorageAt { address, slot }, r)
+    }
+
+    async fn code_at(&self, address: Address) -> Result<Vec<u8>> {
+        let r = self.inner.code_at(address).await.map(ethers::types::Bytes::from);
+        self.record(Request::CodeAt(address), r).map(|b| b.to_vec())
+    }
+
+    async fn transaction_status(&self, tx_hash: H256) -> Result<Option<bool>> {
+        let r = self.inner.transaction_status(tx_hash).await;
+        self.record(Request::TransactionStatus(tx_hash), r)
+    }
+}
+
+/// Answers requests from a recording instead of a node.
+pub struct Replay {
+    responses: Mutex<HashMap<String, VecDeque<Outcome>>>,
+}
+
+/// The `meta` line of a recording.
+pub fn read_meta(path: &Path) -> Result<Meta> {
+    let file = File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+    let first = BufReader::new(file).lines().next().ok_or_else(|| eyre!("{} is empty", path.display()))??;
+    match decode(&first)? {
+        Entry::Meta(meta) => Ok(meta),
+        Entry::Response(_) => Err(eyre!("{} doesn't start with a meta line", path.display())),
+    }
+}
+
+fn decode(line: &str) -> Result<Entry> {
+    let env: Envelope = serde_json::from_str(line)?;
+    if env.v != RECORDING_VERSION {
+        return Err(eyre!("unsupported recording version {} (this build reads v{RECORDING_VERSION})", env.v));
+    }
+    Ok(env.entry)
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).wrap_err_with(|| format!("opening {}", path.display()))?;
+        let mut responses: HashMap<String, VecDeque<Outcome>> = HashMap::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let entry = decode(&line?).wrap_err_with(|| format!("{} line {}", path.display(), i + 1))?;
+            if let Entry::Response(r) = entry {
+                responses.entry(serde_json::to_string(&r.request)?).or_default().push_back(r.outcome);
+            }
+        }
+        tracing::info!(path = %path.display(), requests = responses.len(), "Replaying recorded RPC responses");
+        Ok(Self { responses: Mutex::new(responses) })
+    }
+
+    fn answer<T: DeserializeOwned>(&self, request: Request) -> Result<T> {
+        let key = serde_json::to_string(&request)?;
+        let mut responses = self.responses.lock().expect("replay lock");
+        let queue = responses.get_mut(&key).ok_or_else(|| eyre!("not in the recording: {key}"))?;
+        let outcome = if queue.len() > 1 { queue.pop_front() } else { queue.front().cloned() };
+        match outcome.expect("queues are never empty") {
+            Outcome::Result(v) => Ok(serde_json::from_value(v)?),
+            Outcome::Error(e) => Err(eyre!(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl ChainClient for Replay {
+    async fn chain_id(&self) -> Result<u64> {
+        self.answer(Request::ChainId)
+    }
+
+    async fn block_number(&self) -> Result<u64> {
+        self.answer(Request::BlockNumber)
+    }
+
+    async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+        Ok(stream::empty().boxed())
+    }
+
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>> {
+        self.answer(Request::GetLogs(filter.clone()))
+    }
+
+    async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>> {
+        self.answer(Request::GetBlock(number))
+    }
+
+    async fn block_transactions(&self, number: u64) -> Result<Vec<NativeTx>> {
+        self.answer(Request::BlockTransactions(number))
+    }
+
+    async fn call(&self, to: Address, data: Vec<u8>, block: Option<u64>) -> Result<Vec<u8>> {
+        self.answer::<ethers::types::Bytes>(Request::Call { to, data, block }).map(|b| b.to_vec())
+    }
+
+    async fn storage_at(&self, address: Address, slot: H256) -> Result<H256> {
+        self.answer(Request::StorageAt { address, slot })
+    }
+
+    async fn code_at(&self, address: Address) -> Result<Vec<u8>> {
+        self.answer::<ethers::types::Bytes>(Request::CodeAt(address)).map(|b| b.to_vec())
+    }
+
+    async fn transaction_status(&self, tx_hash: H256) -> Result<Option<bool>> {
+        self.answer(Request::TransactionStatus(tx_hash))
+    }
+}
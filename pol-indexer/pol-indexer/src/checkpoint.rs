@@ -0,0 +1,322 @@
+//! Signed checkpoints of the cumulative state, so a new node can start from a
+//! trusted peer's numbers instead of backfilling from the start block.
+//!
+//! A checkpoint holds, as of one indexed block:
+//! - the dataset configuration and accounting epoch
+//! - the cumulatives
+//! - discovered deposit addresses
+//! - `rows_sha256`, a hash chain over the counted transfers behind the
+//!   cumulative
+//!
+//! The chain starts at 32 zero bytes. For every block with counted
+//! transfers, it becomes the SHA-256 of the previous value followed by that
+//! block's rows, in log order. A node restored from a checkpoint carries the
+//! chain on, so its later checkpoints hash the same as those of a node that
+//! backfilled everything, and either can audit the other with `verify`.
+//!
+//! The file is signed with a secp256k1 key (EIP-191 personal-message
+//! signature over the checkpoint's JSON). Verifying or restoring accepts only
+//! the signer addresses it is given.
+
+use std::path::Path;
+
+use ethers::{
+    signers::{LocalWallet, Signer},
+    types::{Address, Signature},
+    utils::hash_message,
+};
+use eyre::{eyre, Result, WrapErr};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::{db, models::{self, AccountingMode}};
+
+/// Checkpoint format version; bumped on incompatible changes.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub v: u32,
+    pub chain_id: Option<u64>,
+    pub token: String,
+    /// Sorted, lowercase, comma-separated, as recorded by `dataset`
+    pub binance_addresses: String,
+    pub accounting_mode: AccountingMode,
+    pub start_block: u64,
+    /// Every block up to this one is reflected
+    pub block_number: u64,
+    /// Raw units
+    pub cumulative: String,
+    pub cumulative_usd_micro: Option<i64>,
+    /// Timestamp of the newest stored block up to `block_number`
+    pub updated_at_unix: i64,
+    pub rows_sha256: String,
+    pub deposit_addresses: Vec<DepositAddress>,
+    pub created_at_unix: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepositAddress {
+    pub address: String,
+    pub hot_wallet: String,
+    pub first_seen_block: u64,
+    pub sweep_tx_hash: String,
+}
+
+/// The file's contents.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedCheckpoint {
+    pub checkpoint: Checkpoint,
+    pub signer: Address,
+    pub signature: String,
+}
+
+/// Checkpoint the state as of `block` (default: the last indexed block) and
+/// sign it with `signing_key` (hex private key).
+pub fn create(conn: &Connection, out: &Path, block: Option<u64>, signing_key: &str) -> Result<SignedCheckpoint> {
+    let wallet: LocalWallet = signing_key.trim().parse().map_err(|e| eyre!("CHECKPOINT_SIGNING_KEY: {e}"))?;
+    let last = db::get_last_indexed_block(conn)?.ok_or_else(|| eyre!("nothing indexed yet"))?;
+    let block_number = block.unwrap_or(last);
+    if block_number > last {
+        return Err(eyre!("block {block_number} is past the last indexed block {last}"));
+    }
+    let base = db::get_checkpoint_base(conn)?;
+    if let Some(base) = base.as_ref().filter(|b| b.block_number > block_number) {
+        return Err(eyre!("this database starts from a checkpoint at block {}; pick a later block", base.block_number));
+    }
+    let start_block = db::get_start_block(conn)?.ok_or_else(|| eyre!("no accounting start block recorded"))?;
+    let (_, cumulative, cumulative_usd_micro) = db::cumulative_at(conn, block_number)?.unwrap_or((0, "0".into(), None));
+    let checkpoint = Checkpoint {
+        v: CHECKPOINT_VERSION,
+        chain_id: db::get_chain_id(conn)?,
+        token: db::get_dataset_token(conn)?.ok_or_else(|| eyre!("no dataset configuration recorded"))?,
+        binance_addresses: db::get_dataset_binance_addresses(conn)?.unwrap_or_default(),
+        accounting_mode: db::get_accounting_mode(conn)?.unwrap_or_default(),
+        start_block,
+        block_number,
+        cumulative,
+        cumulative_usd_micro,
+        updated_at_unix: match db::stored_block_at_or_before(conn, block_number)? {
+            Some((_, ts)) => ts,
+            None => db::get_latest_cumulative(conn)?.updated_at_unix,
+        },
+        rows_sha256: rows_sha256(conn, base.as_ref(), block_number)?,
+        deposit_addresses: db::deposit_address_rows(conn, block_number)?
+            .into_iter()
+            .map(|(address, hot_wallet, first_seen_block, sweep_tx_hash)| DepositAddress { address, hot_wallet, first_seen_block, sweep_tx_hash })
+            .collect(),
+        created_at_unix: OffsetDateTime::now_utc().unix_timestamp(),
+    };
+    let signature = wallet.sign_hash(hash_message(payload(&checkpoint)?))?;
+    let signed = SignedCheckpoint { checkpoint, signer: wallet.address(), signature: format!("0x{signature}") };
+    std::fs::write(out, serde_json::to_vec_pretty(&signed)?).wrap_err_with(|| format!("writing {}", out.display()))?;
+    info!(path = %out.display(), block = block_number, signer = %models::checksummed(&models::address_key(&signed.signer)), "Checkpoint created");
+    Ok(signed)
+}
+
+/// Read `path` and check it is signed by one of `trusted`.
+pub fn load(path: &Path, trusted: &[Address]) -> Result<Checkpoint> {
+    let text = std::fs::read_to_string(path).wrap_err_with(|| format!("reading {}", path.display()))?;
+    let signed: SignedCheckpoint = serde_json::from_str(&text).wrap_err("invalid checkpoint file")?;
+    if signed.checkpoint.v != CHECKPOINT_VERSION {
+        return Err(eyre!("unsupported checkpoint version {} (this build reads v{CHECKPOINT_VERSION})", signed.checkpoint.v));
+    }
+    let signature: Signature = signed.signature.parse().map_err(|e| eyre!("invalid checkpoint signature: {e}"))?;
+    let recovered = signature.recover(hash_message(payload(&signed.checkpoint)?))
+        .map_err(|e| eyre!("invalid checkpoint signature: {e}"))?;
+    if recovered != signed.signer {
+        return Err(eyre!("checkpoint signature does not match its signer {:?}", signed.signer));
+    }
+    if !trusted.contains(&recovered) {
+        return Err(eyre!("checkpoint is signed by {recovered:?}, which is not in CHECKPOINT_TRUSTED_SIGNERS"));
+    }
+    Ok(signed.checkpoint)
+}
+
+/// What `checkpoint verify` checked.
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub block_number: u64,
+    pub cumulative: String,
+    /// `false` when this database doesn't cover the block, so only the
+    /// signature was checked
+    pub compared: bool,
+}
+
+/// Compare a checkpoint with this database where it covers the block.
+pub fn verify(conn: &Connection, checkpoint: &Checkpoint) -> Result<Verification> {
+    let mut verification = Verification {
+        block_number: checkpoint.block_number,
+        cumulative: checkpoint.cumulative.clone(),
+        compared: false,
+    };
+    if db::get_last_indexed_block(conn)?.is_none_or(|last| last < checkpoint.block_number) {
+        warn!(block = checkpoint.block_number, "This database hasn't indexed the checkpoint's block; only the signature was checked");
+        return Ok(verification);
+    }
+    let base = db::get_checkpoint_base(conn)?;
+    if base.as_ref().is_some_and(|b| b.block_number > checkpoint.block_number) {
+        warn!(block = checkpoint.block_number, "This database starts from a later checkpoint; only the signature was checked");
+        return Ok(verification);
+    }
+    let mut problems = configuration_conflicts(conn, checkpoint)?;
+    let (_, cumulative, _) = db::cumulative_at(conn, checkpoint.block_number)?.unwrap_or((0, "0".into(), None));
+    if cumulative != checkpoint.cumulative {
+        problems.push(format!("cumulative: database has {cumulative}, checkpoint {}", checkpoint.cumulative));
+    }
+    let rows = rows_sha256(conn, base.as_ref(), checkpoint.block_number)?;
+    if rows != checkpoint.rows_sha256 {
+        problems.push(format!("rows_sha256: database has {rows}, checkpoint {}", checkpoint.rows_sha256));
+    }
+    if !problems.is_empty() {
+        return Err(eyre!("checkpoint disagrees with this database at block {}:\n  {}", checkpoint.block_number, problems.join("\n  ")));
+    }
+    info!(block = checkpoint.block_number, cumulative = %checkpoint.cumulative, "Checkpoint matches this database");
+    verification.compared = true;
+    Ok(verification)
+}
+
+/// Seed an empty database with `checkpoint`; `run` continues from the block
+/// after it.
+pub fn restore(conn: &mut Connection, checkpoint: &Checkpoint) -> Result<()> {
+    if db::get_last_indexed_block(conn)?.is_some() || db::get_start_block(conn)?.is_some() {
+        return Err(eyre!("checkpoint restore needs an empty database; this one has been indexed already"));
+    }
+    let tx = conn.transaction()?;
+    db::set_dataset_token(&tx, &checkpoint.token)?;
+    db::set_dataset_binance_addresses(&tx, &checkpoint.binance_addresses)?;
+    if let Some(id) = checkpoint.chain_id {
+        db::set_chain_id(&tx, id)?;
+    }
+    db::set_start_block(&tx, checkpoint.start_block)?;
+    db::set_accounting_mode(&tx, checkpoint.accounting_mode)?;
+    for d in &checkpoint.deposit_addresses {
+        db::insert_deposit_address(&tx, &d.address, &d.hot_wallet, d.first_seen_block, &d.sweep_tx_hash)?;
+    }
+    db::set_checkpoint_base(&tx, &db::CheckpointBase {
+        block_number: checkpoint.block_number,
+        cumulative: checkpoint.cumulative.clone(),
+        cumulative_usd_micro: checkpoint.cumulative_usd_micro,
+        rows_sha256: checkpoint.rows_sha256.clone(),
+    })?;
+    db::update_cumulative(&tx, checkpoint.block_number, &checkpoint.cumulative, checkpoint.cumulative_usd_micro, checkpoint.updated_at_unix)?;
+    db::set_last_indexed_block(&tx, checkpoint.block_number)?;
+    tx.commit()?;
+    info!(block = checkpoint.block_number, cumulative = %checkpoint.cumulative, "Restored from checkpoint; `run` continues from the next block");
+    Ok(())
+}
+
+fn configuration_conflicts(conn: &Connection, checkpoint: &Checkpoint) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut compare = |setting: &str, stored: Option<String>, theirs: String| {
+        if let Some(stored) = stored.filter(|s| *s != theirs) {
+            problems.push(format!("{setting}: database has {stored}, checkpoint {theirs}"));
+        }
+    };
+    compare("token", db::get_dataset_token(conn)?, checkpoint.token.clone());
+    compare("binance_addresses", db::get_dataset_binance_addresses(conn)?, checkpoint.binance_addresses.clone());
+    compare("start_block", db::get_start_block(conn)?.map(|b| b.to_string()), checkpoint.start_block.to_string());
+    compare(
+        "accounting_mode",
+        db::get_accounting_mode(conn)?.map(|m| m.as_str().to_string()),
+        checkpoint.accounting_mode.as_str().to_string(),
+    );
+    Ok(problems)
+}
+
+/// The signed bytes.
+fn payload(checkpoint: &Checkpoint) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(checkpoint)?)
+}
+
+/// The hash chain (see the module docs) through `through`, continuing from
+/// `base` when the database was restored from a checkpoint. Transfers are
+/// counted as `aggregates::rebuild` counts them.
+fn rows_sha256(conn: &Connection, base: Option<&db::CheckpointBase>, through: u64) -> Result<String> {
+    let mut chain = [0u8; 32];
+    if let Some(base) = base {
+        hex::decode_to_slice(&base.rows_sha256, &mut chain).map_err(|e| eyre!("stored checkpoint rows_sha256: {e}"))?;
+    }
+    let mut stmt = conn.prepare(
+        "SELECT t.block_number, t.tx_hash, t.log_index, t.sender, t.recipient, t.value, t.direction
+         FROM erc20_transfers t LEFT JOIN blocks b ON b.block_number = t.block_number
+         WHERE t.block_number > ?1 AND t.block_number <= ?2
+           AND (t.status IS NULL OR t.status != 0)
+           AND (t.block_hash = '' OR COALESCE(b.block_hash, '') IN ('', t.block_hash))
+         ORDER BY t.block_number, t.log_index"
+    )?;
+    let after = base.map_or(-1, |b| b.block_number as i64);
+    let mut rows = stmt.query(rusqlite::params![after, through.min(i64::MAX as u64) as i64])?;
+    let mut block: Option<(i64, Sha256)> = None;
+    while let Some(row) = rows.next()? {
+        let number: i64 = row.get(0)?;
+        if block.as_ref().is_some_and(|(n, _)| *n != number) {
+            chain = block.take().expect("checked").1.finalize().into();
+        }
+        let (_, hasher) = block.get_or_insert_with(|| {
+            let mut h = Sha256::new();
+            h.update(chain);
+            (number, h)
+        });
+        hasher.update(format!(
+            "{number}:{}:{}:{}:{}:{}:{}\n",
+            row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, String>(3)?,
+            row.get::<_, String>(4)?, row.get::<_, String>(5)?, row.get::<_, String>(6)?,
+        ));
+    }
+    if let Some((_, hasher)) = block {
+        chain = hasher.finalize().into();
+    }
+    Ok(hex::encode(chain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aggregates, models::{Clock, Direction}};
+
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn indexed(conn: &Connection, blocks: std::ops::RangeInclusive<u64>) {
+        for n in blocks {
+            db::insert_transfer(conn, n, "", &format!("0x{n:064x}"), 0, "t", "s", "r", &(n * 10).to_string(), Direction::In, None).unwrap();
+        }
+        aggregates::rebuild(conn, Clock::Block).unwrap();
+    }
+
+    #[test]
+    fn restored_node_hashes_like_a_full_one() {
+        let dir = std::env::temp_dir().join(format!("pol-indexer-checkpoint-{}.json", std::process::id()));
+        let full = db::init(":memory:").unwrap();
+        db::set_dataset_token(&full, "t").unwrap();
+        db::set_start_block(&full, 1).unwrap();
+        indexed(&full, 1..=3);
+        db::set_last_indexed_block(&full, 3).unwrap();
+        let signed = create(&full, &dir, None, KEY).unwrap();
+
+        // Another key isn't trusted; the signer is
+        assert!(load(&dir, &[Address::zero()]).is_err());
+        let checkpoint = load(&dir, &[signed.signer]).unwrap();
+        assert_eq!(checkpoint.cumulative, "60");
+        assert!(verify(&full, &checkpoint).unwrap().compared);
+
+        let mut restored = db::init(":memory:").unwrap();
+        restore(&mut restored, &checkpoint).unwrap();
+        assert!(restore(&mut restored, &checkpoint).is_err());
+        assert_eq!(db::get_latest_cumulative(&restored).unwrap().cumulative_netflow_raw, "60");
+
+        // Both index block 4; a rebuild keeps the restored base
+        for conn in [&full, &restored] {
+            indexed(conn, 4..=4);
+            db::set_last_indexed_block(conn, 4).unwrap();
+        }
+        assert_eq!(db::get_latest_cumulative(&restored).unwrap().cumulative_netflow_raw, "100");
+        let base = db::get_checkpoint_base(&restored).unwrap();
+        assert_eq!(rows_sha256(&restored, base.as_ref(), 4).unwrap(), rows_sha256(&full, None, 4).unwrap());
+
+        let _ = std::fs::remove_file(dir);
+    }
+}
@@ -0,0 +1,89 @@
+//! Classification of transfer counterparties by their bytecode
+//! (`TAG_CONTRACTS`). Each address on the other side of an inflow or outflow
+//! is read with `eth_getCode` the first time it appears and cached in
+//! `contracts`: no code is an EOA, anything else a contract identified by the
+//! keccak256 of its runtime code. Code hashes listed in `CONTRACT_LABELS`
+//! name the contract (DEX routers, bridges…); EIP-1167 minimal proxies and
+//! EIP-7702 delegations are recognised by their fixed layout.
+
+use std::collections::HashMap;
+
+use ethers::{types::{Address, H256}, utils::keccak256};
+
+/// `CONTRACT_LABELS`, e.g. `0x<code hash>=QuickSwap router,0x<code hash>=PoS bridge`.
+#[derive(Debug, Clone, Default)]
+pub struct ContractLabels(pub HashMap<H256, String>);
+
+impl std::str::FromStr for ContractLabels {
+    type Err = String;
+
+    fn from_str(csv: &str) -> std::result::Result<Self, String> {
+        let mut out = HashMap::new();
+        for part in csv.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (hash, label) = part.split_once('=').ok_or_else(|| format!("expected codehash=label, got {part:?}"))?;
+            let hash: H256 = hash.trim().parse().map_err(|e| format!("code hash {hash:?}: {e}"))?;
+            let label = label.trim();
+            if label.is_empty() {
+                return Err(format!("empty label for {hash:?}"));
+            }
+            out.insert(hash, label.to_string());
+        }
+        Ok(ContractLabels(out))
+    }
+}
+
+/// What `eth_getCode` says an address is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    /// `None` for an address without code of its own
+    pub code_hash: Option<H256>,
+    pub label: Option<String>,
+}
+
+// EIP-1167 runtime code around the 20-byte implementation address
+const MINIMAL_PROXY_PREFIX: [u8; 10] = [0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x36, 0x3d, 0x73];
+const MINIMAL_PROXY_SUFFIX: [u8; 15] = [0x5a, 0xf4, 0x3d, 0x82, 0x80, 0x3e, 0x90, 0x3d, 0x91, 0x60, 0x2b, 0x57, 0xfd, 0x5b, 0xf3];
+
+// EIP-7702 delegation designator, followed by the delegate's address
+const DELEGATION_PREFIX: [u8; 3] = [0xef, 0x01, 0x00];
+
+pub fn classify(code: &[u8], labels: &ContractLabels) -> Classification {
+    if code.is_empty() {
+        return Classification { code_hash: None, label: None };
+    }
+    // A delegated EOA still signs its own transactions
+    if code.len() == 23 && code.starts_with(&DELEGATION_PREFIX) {
+        let delegate = Address::from_slice(&code[3..]);
+        return Classification { code_hash: None, label: Some(format!("EIP-7702 delegation to {delegate:?}")) };
+    }
+    let code_hash = H256(keccak256(code));
+    let label = labels.0.get(&code_hash).cloned().or_else(|| {
+        let proxied = code.len() == 45 && code.starts_with(&MINIMAL_PROXY_PREFIX) && code.ends_with(&MINIMAL_PROXY_SUFFIX);
+        proxied.then(|| format!("EIP-1167 proxy of {:?}", Address::from_slice(&code[10..30])))
+    });
+    Classification { code_hash: Some(code_hash), label }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_code() {
+        let router = vec![0x60, 0x80, 0x60, 0x40, 0x52];
+        let labels: ContractLabels = format!("{:?}=QuickSwap router", H256(keccak256(&router))).parse().unwrap();
+
+        assert_eq!(classify(&[], &labels), Classification { code_hash: None, label: None });
+        assert_eq!(classify(&router, &labels).label.as_deref(), Some("QuickSwap router"));
+        let other = classify(&[0x60, 0x00], &labels);
+        assert!(other.code_hash.is_some() && other.label.is_none());
+
+        let target = Address::repeat_byte(0xab);
+        let proxy = [&MINIMAL_PROXY_PREFIX[..], target.as_bytes(), &MINIMAL_PROXY_SUFFIX[..]].concat();
+        assert_eq!(classify(&proxy, &labels).label, Some(format!("EIP-1167 proxy of {target:?}")));
+        let delegated = classify(&[&DELEGATION_PREFIX[..], target.as_bytes()].concat(), &labels);
+        assert_eq!(delegated.code_hash, None, "a delegated EOA is still an EOA");
+
+        assert!("0x12=x".parse::<ContractLabels>().is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! Holding the indexer at a block, for debugging the pipeline on specific
+//! blocks: `STOP_AT_BLOCK` holds it before the first block above N, and the
+//! admin API steps it one block at a time, pauses it or lets it run on.
+//!
+//! The hold applies to new blocks, gaps and backfill. Blocks in
+//! `failed_blocks` aren't retried while it is held.
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct State {
+    /// Held before any block above this one
+    pub stop_at_block: Option<u64>,
+    /// Held before the next block, whatever it is
+    pub paused: bool,
+    /// Blocks still let through past the hold
+    pub steps: u32,
+    /// Block the indexer is waiting to index; `None` while it runs
+    pub held_at: Option<u64>,
+}
+
+impl State {
+    fn holds(&self, block: u64) -> bool {
+        self.paused || self.stop_at_block.is_some_and(|stop| block > stop)
+    }
+}
+
+pub struct Control {
+    state: watch::Sender<State>,
+}
+
+impl Control {
+    pub fn new(stop_at_block: Option<u64>) -> Self {
+        Control { state: watch::Sender::new(State { stop_at_block, ..Default::default() }) }
+    }
+
+    pub fn state(&self) -> State {
+        *self.state.borrow()
+    }
+
+    /// Paused, or waiting at `STOP_AT_BLOCK`.
+    pub fn is_held(&self) -> bool {
+        let s = self.state.borrow();
+        s.paused || s.held_at.is_some()
+    }
+
+    /// Hold before the next block.
+    pub fn pause(&self) -> State {
+        self.state.send_modify(|s| s.paused = true);
+        self.state()
+    }
+
+    /// Index exactly one more block, then hold again.
+    pub fn step(&self) -> State {
+        self.state.send_modify(|s| {
+            s.paused = true;
+            s.steps += 1;
+        });
+        self.state()
+    }
+
+    /// Lift the pause and `STOP_AT_BLOCK`.
+    pub fn resume(&self) -> State {
+        self.state.send_modify(|s| *s = State { held_at: s.held_at, ..Default::default() });
+        self.state()
+    }
+
+    /// Wait until `block` may be indexed, and return the last block that may
+    /// be indexed after it without asking again: `block` itself when it was
+    /// let through by a step.
+    pub async fn wait_through(&self, block: u64) -> u64 {
+        let mut changes = self.state.subscribe();
+        let mut logged = false;
+        loop {
+            let mut through = None;
+            self.state.send_if_modified(|s| {
+                let was = s.held_at;
+                if !s.holds(block) {
+                    through = Some(s.stop_at_block.unwrap_or(u64::MAX));
+                    s.held_at = None;
+                } else if s.steps > 0 {
+                    s.steps -= 1;
+                    through = Some(block);
+                    s.held_at = None;
+                } else {
+                    s.held_at = Some(block);
+                }
+                s.held_at != was
+            });
+            if let Some(through) = through {
+                if logged {
+                    info!(block, "Indexer released");
+                }
+                return through;
+            }
+            if !logged {
+                info!(block, "Indexer held before this block; step or resume it with /admin/indexer");
+                logged = true;
+            }
+            // The sender lives as long as `self`
+            let _ = changes.changed().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stops_after_the_block_and_steps_one_at_a_time() {
+        let control = Arc::new(Control::new(Some(10)));
+        assert_eq!(control.wait_through(9).await, 10, "runs up to STOP_AT_BLOCK");
+
+        let held = tokio::spawn({
+            let control = control.clone();
+            async move { control.wait_through(11).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(control.state().held_at, Some(11));
+        assert!(control.is_held());
+        control.step();
+        assert_eq!(timeout(Duration::from_secs(5), held).await.unwrap().unwrap(), 11, "one block only");
+        assert_eq!(control.state().held_at, None);
+
+        control.step();
+        control.step();
+        assert_eq!((control.wait_through(12).await, control.wait_through(13).await), (12, 13));
+        assert!(timeout(Duration::from_millis(50), control.wait_through(14)).await.is_err(), "steps used up");
+
+        control.resume();
+        assert_eq!(control.wait_through(14).await, u64::MAX);
+        assert!(!control.is_held());
+        control.pause();
+        assert!(timeout(Duration::from_millis(50), control.wait_through(2)).await.is_err(), "a pause holds whatever block is next");
+    }
+}
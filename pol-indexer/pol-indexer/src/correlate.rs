@@ -0,0 +1,216 @@
+//! `pol-indexer correlate`: line the stored net-flow up against an external
+//! series (price, funding rate, open interest…) and measure how they move
+//! together, for studying whether flows lead price.
+//!
+//! The series is a CSV with a header row: a timestamp column first (unix
+//! seconds or milliseconds, or RFC 3339), then one or more numeric columns.
+//! Both sides are bucketed by `--interval`; the series keeps the last value
+//! in each bucket, and buckets without transfers count as zero flow. For each
+//! column and each lag in `-max_lag..=max_lag` the Pearson correlation of the
+//! bucket's net flow (whole tokens) is computed against the column's level
+//! and against its change from the previous bucket, `lag` buckets later: a
+//! positive lag pairs flow with what the series did afterwards.
+
+use std::{collections::BTreeMap, fs::File, io::BufWriter, path::Path};
+
+use clap::ValueEnum;
+use eyre::{eyre, Result};
+use rusqlite::Connection;
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{db, models::ValueFormat};
+
+/// Below this a timestamp is taken as seconds, above it as milliseconds.
+const MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Interval {
+    Hour,
+    Day,
+    Week,
+}
+
+impl Interval {
+    fn secs(self) -> i64 {
+        match self {
+            Interval::Hour => 3_600,
+            Interval::Day => 86_400,
+            Interval::Week => 7 * 86_400,
+        }
+    }
+}
+
+/// One column against net flow at one lag.
+#[derive(Serialize)]
+pub struct Correlation {
+    pub column: String,
+    /// Buckets the series is shifted by; positive = flow first
+    pub lag: i64,
+    /// Buckets with both values
+    pub pairs: usize,
+    /// Pearson r of net flow against the column's value (`null` under 3 pairs or no variance)
+    pub level: Option<f64>,
+    /// Pearson r of net flow against the column's change from the previous bucket
+    pub change: Option<f64>,
+}
+
+/// An imported series, bucketed: the last value of each column per bucket.
+struct Series {
+    columns: Vec<String>,
+    buckets: BTreeMap<i64, Vec<Option<f64>>>,
+}
+
+#[derive(Default)]
+struct FlowBucket {
+    inflow: f64,
+    outflow: f64,
+    cumulative: f64,
+    transfer_count: u64,
+}
+
+pub fn run(conn: &Connection, series: &Path, interval: Interval, max_lag: u32, merged: Option<&Path>) -> Result<Vec<Correlation>> {
+    let width = interval.secs();
+    let series = read_series(series, width)?;
+    let flows = flow_buckets(conn, width)?;
+    if flows.is_empty() {
+        return Err(eyre!("no net-flow stored yet; run the indexer first"));
+    }
+    if let Some(path) = merged {
+        write_merged(path, &flows, &series)?;
+    }
+
+    let max_lag = i64::from(max_lag);
+    let mut out = Vec::new();
+    for (col, column) in series.columns.iter().enumerate() {
+        let value = |ts: i64| series.buckets.get(&ts).and_then(|row| row[col]);
+        for lag in -max_lag..=max_lag {
+            let (mut level, mut change) = (Vec::new(), Vec::new());
+            for (&ts, f) in &flows {
+                let net = f.inflow - f.outflow;
+                let at = ts + lag * width;
+                if let Some(v) = value(at) {
+                    level.push((net, v));
+                    if let Some(prev) = value(at - width) {
+                        change.push((net, v - prev));
+                    }
+                }
+            }
+            out.push(Correlation { column: column.clone(), lag, pairs: level.len(), level: pearson(&level), change: pearson(&change) });
+        }
+    }
+    Ok(out)
+}
+
+fn read_series(path: &Path, width: i64) -> Result<Series> {
+    let mut reader = csv::Reader::from_path(path).map_err(|e| eyre!("{}: {e}", path.display()))?;
+    let headers = reader.headers()?.clone();
+    if headers.len() < 2 {
+        return Err(eyre!("{}: expected a timestamp column and at least one value column", path.display()));
+    }
+    let columns: Vec<String> = headers.iter().skip(1).map(str::to_string).collect();
+    let mut buckets: BTreeMap<i64, Vec<Option<f64>>> = BTreeMap::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let line = i + 2;
+        let ts = parse_ts(record.get(0).unwrap_or_default()).map_err(|e| eyre!("{}:{line}: {e}", path.display()))?;
+        let row = buckets.entry(ts.div_euclid(width) * width).or_insert_with(|| vec![None; columns.len()]);
+        for (cell, slot) in record.iter().skip(1).zip(row.iter_mut()) {
+            let cell = cell.trim();
+            if cell.is_empty() {
+                continue;
+            }
+            let v: f64 = cell.parse().map_err(|_| eyre!("{}:{line}: {cell:?} is not a number", path.display()))?;
+            // Rows are taken in file order; the last one in a bucket wins
+            *slot = Some(v);
+        }
+    }
+    Ok(Series { columns, buckets })
+}
+
+fn parse_ts(s: &str) -> Result<i64> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<i64>() {
+        return Ok(if n.abs() >= MILLIS_THRESHOLD { n.div_euclid(1000) } else { n });
+    }
+    OffsetDateTime::parse(s, &Rfc3339)
+        .map(OffsetDateTime::unix_timestamp)
+        .map_err(|_| eyre!("{s:?} is neither a unix timestamp nor RFC 3339"))
+}
+
+/// Every bucket from the first stored net-flow block to the latest indexed
+/// block, zero where nothing moved.
+fn flow_buckets(conn: &Connection, width: i64) -> Result<BTreeMap<i64, FlowBucket>> {
+    let rows = db::get_netflow_blocks_by_time(conn, i64::MIN, i64::MAX)?;
+    let Some(first) = rows.first() else { return Ok(BTreeMap::new()) };
+    let bucket = |ts: i64| ts.div_euclid(width) * width;
+    let last = db::latest_block_ts(conn)?.unwrap_or(first.ts_unix).max(rows[rows.len() - 1].ts_unix);
+
+    let mut out = BTreeMap::new();
+    let mut cumulative = 0.0;
+    let mut rows = rows.iter().peekable();
+    let mut ts = bucket(first.ts_unix);
+    while ts <= bucket(last) {
+        let mut b = FlowBucket { cumulative, ..Default::default() };
+        while let Some(r) = rows.next_if(|r| bucket(r.ts_unix) == ts) {
+            b.inflow += whole(&r.inflow);
+            b.outflow += whole(&r.outflow);
+            b.cumulative = whole(&r.cumulative);
+            b.transfer_count += r.transfer_count;
+        }
+        cumulative = b.cumulative;
+        out.insert(ts, b);
+        ts += width;
+    }
+    Ok(out)
+}
+
+/// Raw decimal units → whole tokens.
+fn whole(raw: &str) -> f64 {
+    ValueFormat::Scaled.render(raw).parse().unwrap_or(0.0)
+}
+
+/// The joined series, one row per flow bucket; series cells are empty where
+/// it has no value.
+fn write_merged(path: &Path, flows: &BTreeMap<i64, FlowBucket>, series: &Series) -> Result<()> {
+    let mut w = csv::Writer::from_writer(BufWriter::new(File::create(path)?));
+    let mut header = vec!["start_ts", "inflow", "outflow", "net", "cumulative", "transfer_count"];
+    header.extend(series.columns.iter().map(String::as_str));
+    w.write_record(&header)?;
+    for (ts, f) in flows {
+        let mut row = vec![
+            ts.to_string(),
+            f.inflow.to_string(),
+            f.outflow.to_string(),
+            (f.inflow - f.outflow).to_string(),
+            f.cumulative.to_string(),
+            f.transfer_count.to_string(),
+        ];
+        match series.buckets.get(ts) {
+            Some(values) => row.extend(values.iter().map(|v| v.map(|v| v.to_string()).unwrap_or_default())),
+            None => row.extend(series.columns.iter().map(|_| String::new())),
+        }
+        w.write_record(&row)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Pearson correlation coefficient to 4 places; `None` with fewer than 3
+/// pairs or a constant side.
+fn pearson(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 3 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let (mx, my) = pairs.iter().fold((0.0, 0.0), |(x, y), (a, b)| (x + a, y + b));
+    let (mx, my) = (mx / n, my / n);
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        let (dx, dy) = (x - mx, y - my);
+        sxy += dx * dy;
+        sxx += dx * dx;
+        syy += dy * dy;
+    }
+    (sxx > 0.0 && syy > 0.0).then(|| (sxy / (sxx * syy).sqrt() * 1e4).round() / 1e4)
+}
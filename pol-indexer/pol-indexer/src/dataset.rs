@@ -0,0 +1,161 @@
+//! The configuration a database was built with: token, hot wallets and chain.
+//!
+//! It is written to `state` the first time and checked on every later start,
+//! so one file never mixes data indexed under different settings. `--force`
+//! accepts the new settings and overwrites the record; `rebuild` adopts a new
+//! address set once it has re-filtered the stored blocks. The start block and
+//! accounting mode are checked by the indexer as before and can't be forced,
+//! because the cumulative is defined by them.
+//!
+//! Each change to the address set is also logged in `address_set_history`
+//! (`GET /config/history`), with the command that made it.
+
+use ethers::types::Address;
+use eyre::{eyre, Result};
+use rusqlite::Connection;
+use tracing::{info, warn};
+
+use crate::{db, models};
+
+pub struct Dataset<'a> {
+    pub token: Address,
+    pub binance_addrs: &'a [Address],
+    /// `None` where no RPC is involved (imports)
+    pub chain_id: Option<u64>,
+    /// The command recording it, for the address set history
+    pub source: &'a str,
+}
+
+/// Record `current` on first use; afterwards refuse a different one unless `force`.
+pub fn check(conn: &Connection, current: &Dataset, force: bool) -> Result<()> {
+    let conflicts: Vec<String> = conflicts(conn, current)?.into_iter().map(|(_, c)| c).collect();
+    if !conflicts.is_empty() {
+        if !force {
+            return Err(eyre!(
+                "Database was built with a different configuration; refusing to mix datasets (use --force to adopt the new one):\n  {}",
+                conflicts.join("\n  ")
+            ));
+        }
+        for c in &conflicts {
+            warn!(conflict = %c, "--force: overwriting the recorded configuration");
+        }
+    }
+    record(conn, current)
+}
+
+/// For `rebuild`, which re-filters stored blocks against a new address set:
+/// the token and chain must match; the addresses are [`record`]ed once it's done.
+pub fn check_rebuild(conn: &Connection, current: &Dataset) -> Result<()> {
+    let (addresses, others): (Vec<_>, Vec<_>) = conflicts(conn, current)?.into_iter().partition(|(setting, _)| *setting == BINANCE_ADDRESSES);
+    if !others.is_empty() {
+        let others: Vec<String> = others.into_iter().map(|(_, c)| c).collect();
+        return Err(eyre!("rebuild only changes the address set; the database differs in:\n  {}", others.join("\n  ")));
+    }
+    for (_, c) in &addresses {
+        info!(change = %c, "Adopting the new address set");
+    }
+    Ok(())
+}
+
+const BINANCE_ADDRESSES: &str = "BINANCE_ADDRESSES";
+
+/// `(setting, description)` for each recorded setting `current` differs from.
+fn conflicts(conn: &Connection, current: &Dataset) -> Result<Vec<(&'static str, String)>> {
+    let mut conflicts = Vec::new();
+    let mut compare = |setting: &'static str, stored: Option<String>, configured: &str| {
+        if let Some(stored) = stored.filter(|s| s != configured) {
+            conflicts.push((setting, format!("{setting}: database has {stored}, configured {configured}")));
+        }
+    };
+    compare("POL_TOKEN_ADDRESS", db::get_dataset_token(conn)?, &models::address_key(&current.token));
+    compare(BINANCE_ADDRESSES, db::get_dataset_binance_addresses(conn)?, &address_list(current));
+    if let Some(id) = current.chain_id {
+        compare("chain id", db::get_chain_id(conn)?.map(|c| c.to_string()), &id.to_string());
+    }
+    Ok(conflicts)
+}
+
+/// Sorted and deduplicated, so order in the configuration doesn't matter.
+fn address_keys(current: &Dataset) -> Vec<String> {
+    let mut addrs: Vec<String> = current.binance_addrs.iter().map(models::address_key).collect();
+    addrs.sort();
+    addrs.dedup();
+    addrs
+}
+
+fn address_list(current: &Dataset) -> String {
+    address_keys(current).join(",")
+}
+
+const CONFIGURED: &str = "configured";
+
+/// Log each configured address added or removed since the set the history
+/// ends with. A database older than the history first gets the set it had recorded.
+fn record_history(conn: &Connection, current: &Dataset) -> Result<()> {
+    let block = db::get_last_indexed_block(conn)?;
+    let actor = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok();
+    let mut previous = db::history_address_set(conn, CONFIGURED)?;
+    if previous.is_empty() {
+        if let Some(stored) = db::get_dataset_binance_addresses(conn)?.filter(|s| !s.is_empty()) {
+            previous = stored.split(',').map(str::to_string).collect();
+            for addr in &previous {
+                db::insert_address_change(conn, addr, "added", CONFIGURED, "recorded before history", None, block)?;
+            }
+        }
+    }
+    let initial = previous.is_empty();
+    let configured = address_keys(current);
+    let added = configured.iter().filter(|a| !previous.contains(a)).map(|a| (a, "added"));
+    let removed = previous.iter().filter(|a| !configured.contains(a)).map(|a| (a, "removed"));
+    for (addr, change) in added.chain(removed) {
+        if !initial {
+            info!(address = %addr, change, source = current.source, "Address set changed");
+        }
+        db::insert_address_change(conn, addr, change, CONFIGURED, current.source, actor.as_deref(), block)?;
+    }
+    Ok(())
+}
+
+pub fn record(conn: &Connection, current: &Dataset) -> Result<()> {
+    let token = models::address_key(&current.token);
+    let first = db::get_dataset_token(conn)?.is_none();
+    record_history(conn, current)?;
+    db::set_dataset_token(conn, &token)?;
+    db::set_dataset_binance_addresses(conn, &address_list(current))?;
+    if let Some(id) = current.chain_id {
+        db::set_chain_id(conn, id)?;
+    }
+    if first {
+        info!(token = %token, chain_id = ?current.chain_id, "Recorded dataset configuration");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_set_changes_are_logged() {
+        let conn = db::init(":memory:").unwrap();
+        let (a, b, c) = (Address::from_low_u64_be(1), Address::from_low_u64_be(2), Address::from_low_u64_be(3));
+        fn dataset<'a>(binance_addrs: &'a [Address], source: &'a str) -> Dataset<'a> {
+            Dataset { token: Address::zero(), binance_addrs, chain_id: None, source }
+        }
+        record(&conn, &dataset(&[a, b], "run")).unwrap();
+        record(&conn, &dataset(&[b, a], "run")).unwrap();
+        record(&conn, &dataset(&[b, c], "rebuild --since-block 5")).unwrap();
+
+        let changes: Vec<_> = db::list_address_changes(&conn, None, 10).unwrap().into_iter()
+            .map(|c| (c.address, c.change, c.source))
+            .collect();
+        let key = |addr| models::address_key(&addr);
+        assert_eq!(changes, [
+            (key(a), "removed".into(), "rebuild --since-block 5".into()),
+            (key(c), "added".into(), "rebuild --since-block 5".into()),
+            (key(b), "added".into(), "run".into()),
+            (key(a), "added".into(), "run".into()),
+        ]);
+        assert_eq!(db::history_address_set(&conn, CONFIGURED).unwrap(), [key(b), key(c)]);
+    }
+}
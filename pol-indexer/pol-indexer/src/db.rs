@@ -1,15 +1,27 @@
-use eyre::Result;
-use rusqlite::{Connection, params};
+use std::{borrow::Cow, collections::{BTreeMap, HashMap}};
+
+use ethers::types::U256;
+use eyre::{Result, eyre};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rusqlite::{Connection, OptionalExtension, params};
 use time::OffsetDateTime;
 
-use crate::models::NetflowSnapshot;
+use crate::auth::ApiKey;
+use crate::chain::RawLog;
+use crate::models::{AccountingMode, AddressChange, Annotation, BalanceBaseline, BlockStats, CounterpartyKind, Direction, NativeNetflowBlock, NativeTransferRecord, NetflowBlock, NetflowSnapshot, NftStandard, NftTransfer, QueuedAlert, ReorgRecord, SessionSummary, SupplyEvent, SupplyEventKind, TransferRecord, TxStatus, ValueFormat};
+use crate::prices;
 
 pub const SCHEMA_SQL: &str = r#"
 PRAGMA journal_mode=WAL;
+-- chain_id: the chain a row belongs to (state's chain_id, 0 before it is known).
+-- It comes last in each key so lookups by block number or transaction still use it
 CREATE TABLE IF NOT EXISTS blocks (
-    block_number INTEGER PRIMARY KEY,
+    block_number INTEGER NOT NULL,
     block_hash TEXT NOT NULL,
-    ts_unix INTEGER NOT NULL
+    ts_unix INTEGER NOT NULL,
+    chain_id INTEGER NOT NULL DEFAULT 0,
+    ts_interpolated INTEGER NOT NULL DEFAULT 0, -- 1: the header had no timestamp; ts_unix is estimated from neighbouring blocks
+    PRIMARY KEY (block_number, chain_id)
 );
 
 CREATE TABLE IF NOT EXISTS erc20_transfers (
@@ -21,18 +33,276 @@ CREATE TABLE IF NOT EXISTS erc20_transfers (
     sender TEXT NOT NULL,
     recipient TEXT NOT NULL,
     value TEXT NOT NULL, -- U256 as decimal string
-    is_binance_in BOOLEAN NOT NULL,
-    is_binance_out BOOLEAN NOT NULL,
-    UNIQUE(tx_hash, log_index)
+    direction TEXT NOT NULL CHECK (direction IN ('in', 'out', 'internal', 'none')), -- relative to the tracked addresses
+    status INTEGER, -- receipt status: 1 success, 0 reverted, NULL unknown
+    block_hash TEXT NOT NULL DEFAULT '', -- block the log was read from; '' when unknown (some imports)
+    chain_id INTEGER NOT NULL DEFAULT 0,
+    bucket TEXT, -- set by an accounting rule; such transfers are outside the net-flow
+    -- A transaction re-included after a reorg keeps its log index but not its block
+    UNIQUE(tx_hash, log_index, block_hash, chain_id)
 );
+CREATE INDEX IF NOT EXISTS idx_transfers_block ON erc20_transfers(block_number, log_index);
+CREATE INDEX IF NOT EXISTS idx_transfers_sender ON erc20_transfers(sender);
+CREATE INDEX IF NOT EXISTS idx_transfers_recipient ON erc20_transfers(recipient);
+CREATE INDEX IF NOT EXISTS idx_transfers_bucket ON erc20_transfers(bucket, block_number) WHERE bucket IS NOT NULL;
 
 -- Stores the running cumulative netflow value as a raw integer string (no decimals scaling)
 CREATE TABLE IF NOT EXISTS cumulative_netflow (
-    id INTEGER PRIMARY KEY CHECK (id = 1),
+    chain_id INTEGER PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    value TEXT NOT NULL, -- U256 decimal string
+    updated_at_unix INTEGER NOT NULL,
+    value_token TEXT, -- the same in whole tokens
+    value_usd_micro INTEGER -- USD cumulative (PRICE_FEED_ADDRESS), micro-USD; NULL before the first price
+);
+
+-- Per-block aggregates for blocks with at least one matched transfer
+CREATE TABLE IF NOT EXISTS netflow_blocks (
+    block_number INTEGER PRIMARY KEY,
+    ts_unix INTEGER NOT NULL,
+    inflow TEXT NOT NULL, -- U256 decimal string
+    outflow TEXT NOT NULL, -- U256 decimal string
+    cumulative TEXT NOT NULL, -- cumulative netflow after this block
+    transfer_count INTEGER NOT NULL,
+    cumulative_token TEXT, -- the same in whole tokens
+    price_usd_micro INTEGER, -- token price at this block, micro-USD; NULL without a price feed
+    cumulative_usd_micro INTEGER -- USD cumulative after this block, micro-USD
+);
+CREATE INDEX IF NOT EXISTS idx_netflow_blocks_ts ON netflow_blocks(ts_unix);
+
+-- Transfer volume of the token per block across all addresses, for context on
+-- the net-flow (TRACK_VOLUME); blocks without token transfers have no row
+CREATE TABLE IF NOT EXISTS volume_blocks (
+    block_number INTEGER PRIMARY KEY,
+    ts_unix INTEGER NOT NULL,
+    volume TEXT NOT NULL, -- U256 decimal string, sum of every Transfer value
+    transfer_count INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_volume_blocks_ts ON volume_blocks(ts_unix);
+
+-- Mints (Transfers from the zero address) and burns (to it) of the token,
+-- whoever the other side is (TRACK_SUPPLY_EVENTS)
+CREATE TABLE IF NOT EXISTS supply_events (
+    block_number INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('mint', 'burn')),
+    account TEXT NOT NULL, -- lowercase; recipient of a mint, sender of a burn
+    value TEXT NOT NULL, -- U256 decimal string
+    ts_unix INTEGER NOT NULL,
+    minted_total TEXT NOT NULL, -- every mint from supply_events_since_block through this one
+    burned_total TEXT NOT NULL,
+    PRIMARY KEY (block_number, log_index)
+);
+
+-- API keys (SHA-256 of the key; scopes are comma-separated, '*' = all)
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    exchanges TEXT NOT NULL,
+    tokens TEXT NOT NULL,
+    created_at_unix INTEGER NOT NULL,
+    revoked_at_unix INTEGER,
+    default_format TEXT -- ?format= applied when a request has none; NULL: decimal
+);
+
+-- EIP-1967 implementation behind the token proxy; each row holds until the next from_block
+CREATE TABLE IF NOT EXISTS token_implementations (
+    token TEXT NOT NULL,
+    from_block INTEGER NOT NULL, -- head when the implementation was first observed
+    implementation TEXT NOT NULL,
+    observed_at_unix INTEGER NOT NULL,
+    PRIMARY KEY (token, from_block)
+);
+
+-- Deposit addresses discovered by sweeping into a hot wallet (deposit-netting mode)
+CREATE TABLE IF NOT EXISTS deposit_addresses (
+    address TEXT PRIMARY KEY,
+    hot_wallet TEXT NOT NULL, -- first hot wallet it swept to
+    first_seen_block INTEGER NOT NULL,
+    sweep_tx_hash TEXT NOT NULL
+);
+
+-- Every change to the tracked address set, to explain discontinuities in the net-flow
+CREATE TABLE IF NOT EXISTS address_set_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    changed_at_unix INTEGER NOT NULL,
+    address TEXT NOT NULL, -- lowercase
+    change TEXT NOT NULL CHECK (change IN ('added', 'removed')),
+    kind TEXT NOT NULL CHECK (kind IN ('configured', 'deposit')),
+    source TEXT NOT NULL, -- command or process that made the change
+    actor TEXT, -- OS user, when known
+    block_number INTEGER -- last indexed block when recorded; for deposit addresses, the sweep's block
+);
+CREATE INDEX IF NOT EXISTS idx_address_set_history_address ON address_set_history(address);
+
+-- Detected chain reorganisations and the rollback applied for each
+CREATE TABLE IF NOT EXISTS reorgs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    detected_at_unix INTEGER NOT NULL,
+    new_head INTEGER NOT NULL, -- head that revealed the fork
+    new_head_hash TEXT NOT NULL,
+    fork_block INTEGER NOT NULL, -- last block shared by both branches; everything above was rolled back
+    depth INTEGER NOT NULL, -- blocks rolled back (last indexed - fork_block)
+    orphaned_hashes TEXT NOT NULL, -- JSON array of stored block hashes that were dropped
+    removed_transfers TEXT NOT NULL, -- JSON array of the dropped transfer rows
+    removed_inflow TEXT NOT NULL, -- U256 decimal string
+    removed_outflow TEXT NOT NULL, -- U256 decimal string
+    cumulative_before TEXT NOT NULL,
+    cumulative_after TEXT NOT NULL -- after the rollback, before the new branch was indexed
+);
+
+-- Native POL (gas token) value transfers to/from tracked addresses; top-level
+-- transaction values only, recorded when run with TRACK_NATIVE
+CREATE TABLE IF NOT EXISTS native_transfers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
     block_number INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL UNIQUE,
+    sender TEXT NOT NULL,
+    recipient TEXT NOT NULL,
     value TEXT NOT NULL, -- U256 decimal string
-    updated_at_unix INTEGER NOT NULL
+    direction TEXT NOT NULL CHECK (direction IN ('in', 'out', 'internal', 'none')),
+    status INTEGER -- receipt status, as in erc20_transfers
+);
+
+-- Per-block native aggregates with running totals (kept apart, since the net can go negative)
+CREATE TABLE IF NOT EXISTS native_netflow_blocks (
+    block_number INTEGER PRIMARY KEY,
+    ts_unix INTEGER NOT NULL,
+    inflow TEXT NOT NULL,
+    outflow TEXT NOT NULL,
+    cumulative_inflow TEXT NOT NULL,
+    cumulative_outflow TEXT NOT NULL,
+    transfer_count INTEGER NOT NULL
+);
+
+-- Blocks that failed to index; skipped over and retried in the background
+CREATE TABLE IF NOT EXISTS failed_blocks (
+    block_number INTEGER PRIMARY KEY,
+    error TEXT NOT NULL, -- most recent failure
+    attempts INTEGER NOT NULL,
+    first_failed_at_unix INTEGER NOT NULL,
+    last_failed_at_unix INTEGER NOT NULL
+);
+
+-- One row per `run` session, written on exit
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at_unix INTEGER NOT NULL,
+    ended_at_unix INTEGER NOT NULL,
+    exit_reason TEXT NOT NULL, -- signal, stream_ended or the error
+    blocks_processed INTEGER NOT NULL,
+    transfers_indexed INTEGER NOT NULL, -- newly stored rows
+    reorgs INTEGER NOT NULL,
+    first_block INTEGER, -- range of blocks processed; NULL when none were
+    last_block INTEGER,
+    first_block_ts_unix INTEGER,
+    last_block_ts_unix INTEGER,
+    final_cumulative TEXT NOT NULL
+);
+
+-- Analyst notes on transfers, keyed like erc20_transfers so they survive a
+-- rollback or rebuild that re-inserts the row
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tx_hash TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    note TEXT NOT NULL,
+    tags TEXT NOT NULL, -- comma-separated, lowercase
+    created_at_unix INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_annotations_transfer ON annotations(tx_hash, log_index);
+
+-- Alert deliveries, one row per alert and sink; deleted once delivered
+CREATE TABLE IF NOT EXISTS alert_queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sink TEXT NOT NULL, -- discord or telegram
+    alert TEXT NOT NULL, -- JSON
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at_unix INTEGER NOT NULL,
+    last_error TEXT,
+    created_at_unix INTEGER NOT NULL,
+    dead_at_unix INTEGER -- retries exhausted (dead letter); NULL while pending
+);
+CREATE INDEX IF NOT EXISTS idx_alert_queue_due ON alert_queue(dead_at_unix, next_attempt_at_unix);
+
+-- Every Transfer log of the token, tracked or not, per block (ARCHIVE_RAW_LOGS)
+CREATE TABLE IF NOT EXISTS raw_logs (
+    block_number INTEGER PRIMARY KEY,
+    block_hash TEXT NOT NULL,
+    ts_unix INTEGER NOT NULL,
+    log_count INTEGER NOT NULL,
+    logs BLOB NOT NULL -- gzip'd JSON array of logs as eth_getLogs returns them
+);
+
+-- Addresses grouped into one logical entity (e.g. several Binance hot
+-- wallets); an address belongs to at most one
+CREATE TABLE IF NOT EXISTS entities (
+    address TEXT PRIMARY KEY, -- lowercase
+    entity TEXT NOT NULL,
+    added_at_unix INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_entities_entity ON entities(entity);
+
+-- Transfer counterparties classified by their bytecode (TAG_CONTRACTS),
+-- checked once when first seen
+CREATE TABLE IF NOT EXISTS contracts (
+    address TEXT PRIMARY KEY, -- lowercase
+    code_hash TEXT, -- keccak256 of the runtime bytecode; NULL: no code (an EOA)
+    label TEXT, -- known contract the bytecode matched
+    first_seen_block INTEGER NOT NULL
+);
+
+-- Token balance of each tracked address before the first block indexed for
+-- it; baseline plus the stored transfers after it is the implied balance
+CREATE TABLE IF NOT EXISTS balance_baselines (
+    address TEXT PRIMARY KEY, -- lowercase
+    block_number INTEGER NOT NULL, -- balance as of the end of this block
+    balance TEXT NOT NULL,
+    recorded_at_unix INTEGER NOT NULL
+);
+
+-- Work each block took to index, for capacity planning; rewritten when a
+-- block is indexed again
+CREATE TABLE IF NOT EXISTS block_stats (
+    block_number INTEGER PRIMARY KEY,
+    logs_fetched INTEGER NOT NULL,
+    transfers_matched INTEGER NOT NULL,
+    processing_ms INTEGER NOT NULL,
+    rpc_ms INTEGER NOT NULL, -- part of processing_ms waiting on the RPC
+    indexed_at_unix INTEGER NOT NULL
+);
+
+-- Every event published on the feed except `provisional_netflow`, in order;
+-- `seq` is what SSE consumers resume from. Append-only: a reorg leaves the
+-- orphaned block's events here, and the replacement block's follow them
+CREATE TABLE IF NOT EXISTS event_log (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT, -- never reused
+    block_number INTEGER NOT NULL,
+    kind TEXT NOT NULL, -- `transfer` or `netflow`
+    payload TEXT NOT NULL, -- the event as JSON, amounts in raw units
+    created_at_unix INTEGER NOT NULL
+);
+
+-- ERC-721/1155 transfers to or from a tracked address, of any collection
+-- (TRACK_NFTS); an ERC-1155 batch is one row per token id
+CREATE TABLE IF NOT EXISTS nft_transfers (
+    block_number INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    batch_index INTEGER NOT NULL, -- position in a TransferBatch; 0 otherwise
+    contract TEXT NOT NULL, -- lowercase
+    standard TEXT NOT NULL, -- `erc721` or `erc1155`
+    token_id TEXT NOT NULL, -- decimal
+    amount TEXT NOT NULL, -- decimal; 1 for erc721
+    sender TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    direction TEXT NOT NULL,
+    ts_unix INTEGER NOT NULL,
+    PRIMARY KEY (tx_hash, log_index, batch_index)
 );
+CREATE INDEX IF NOT EXISTS idx_nft_transfers_block ON nft_transfers(block_number);
 
 -- Bookkeeping
 CREATE TABLE IF NOT EXISTS state (
@@ -41,79 +311,2097 @@ CREATE TABLE IF NOT EXISTS state (
 );
 "#;
 
+/// Version of `SCHEMA_SQL`; recorded in `state` as `schema_version`.
+pub const SCHEMA_VERSION: u32 = 28;
+
+/// Columns added after a table was first released: `(since_version, table, column, declaration)`.
+/// `SCHEMA_SQL` already includes them for new databases; older files get an `ALTER TABLE`.
+const ADDED_COLUMNS: &[(u32, &str, &str, &str)] = &[
+    (2, "erc20_transfers", "status", "INTEGER"),
+    (11, "cumulative_netflow", "value_token", "TEXT"),
+    (11, "cumulative_netflow", "value_usd_micro", "INTEGER"),
+    (11, "netflow_blocks", "cumulative_token", "TEXT"),
+    (11, "netflow_blocks", "price_usd_micro", "INTEGER"),
+    (11, "netflow_blocks", "cumulative_usd_micro", "INTEGER"),
+    (19, "blocks", "ts_interpolated", "INTEGER NOT NULL DEFAULT 0"),
+    (20, "erc20_transfers", "bucket", "TEXT"),
+    (24, "api_keys", "default_format", "TEXT"),
+];
+
+/// Tables whose constraints changed after release, which SQLite can only apply
+/// by copying into a fresh table: `(since_version, table, added_column, value_for_old_rows)`.
+/// The rebuild runs when any of a table's `added_column`s is missing, fills
+/// each missing one, drops columns the embedded definition no longer has and
+/// recreates the table's indexes.
+const REBUILT_TABLES: &[(u32, &str, &str, &str)] = &[
+    (
+        12, "erc20_transfers", "block_hash",
+        "COALESCE((SELECT b.block_hash FROM blocks b WHERE b.block_number = erc20_transfers.block_number), '')",
+    ),
+    (17, "erc20_transfers", "direction", DIRECTION_FROM_FLAGS),
+    (17, "native_transfers", "direction", DIRECTION_FROM_FLAGS),
+    (18, "blocks", "chain_id", CURRENT_CHAIN),
+    (18, "erc20_transfers", "chain_id", CURRENT_CHAIN),
+    (18, "cumulative_netflow", "chain_id", CURRENT_CHAIN),
+];
+
+/// Chain id the rows of this database belong to: the one recorded in `state`
+/// (see `dataset`), or `0` before it is known (imports). A database holds one
+/// chain for now, and [`set_chain_id`] moves every row to a newly recorded
+/// id. The column is a placeholder so several chains can later share a file:
+/// rows are written with it, but only the `cumulative_netflow` row is looked
+/// up by it. Every other read, update and delete ignores it, which is only
+/// right while a file holds one chain.
+macro_rules! current_chain {
+    () => { "COALESCE((SELECT CAST(value AS INTEGER) FROM state WHERE key = 'chain_id'), 0)" };
+}
+const CURRENT_CHAIN: &str = current_chain!();
+
+/// Tables keyed by `chain_id`.
+const CHAIN_TABLES: [&str; 3] = ["blocks", "erc20_transfers", "cumulative_netflow"];
+
+/// `direction` for rows written with the `is_binance_in` / `is_binance_out`
+/// flags it replaced.
+const DIRECTION_FROM_FLAGS: &str =
+    "CASE WHEN is_binance_in AND is_binance_out THEN 'internal' WHEN is_binance_in THEN 'in' \
+     WHEN is_binance_out THEN 'out' ELSE 'none' END";
+
 pub fn init(db_path: &str) -> Result<Connection> {
-    let conn = Connection::open(db_path)?;
-    conn.execute_batch(SCHEMA_SQL)?;
+    let mut conn = Connection::open(db_path)?;
+    // Only takes effect on a file with no tables yet; lets maintenance run incremental vacuum
+    conn.execute_batch("PRAGMA auto_vacuum=INCREMENTAL;")?;
+    conn.execute_batch("PRAGMA journal_mode=WAL;")?;
+    let pending = pending_migrations(&conn)?;
+    if pending.iter().any(|m| m.alters_existing) {
+        tracing::info!(from = ?stored_schema_version(&conn)?, to = SCHEMA_VERSION, "Migrating schema");
+    }
+    apply_migrations(&mut conn, &pending)?;
 
-    // Initialize cumulative to zero if missing
+    // Initialize cumulative to zero if missing; updated_at 0 = never updated
     let exists: Option<i64> = conn.query_row(
-        "SELECT id FROM cumulative_netflow WHERE id=1",
+        concat!("SELECT 1 FROM cumulative_netflow WHERE chain_id=", current_chain!()),
         [],
         |row| row.get(0)
     ).optional()?;
     if exists.is_none() {
         conn.execute(
-            "INSERT INTO cumulative_netflow (id, block_number, value, updated_at_unix) VALUES (1, 0, '0', ?)",
-            params![OffsetDateTime::now_utc().unix_timestamp()],
+            concat!("INSERT INTO cumulative_netflow (chain_id, block_number, value, updated_at_unix) VALUES (", current_chain!(), ", 0, '0', 0)"),
+            [],
         )?;
     }
     Ok(conn)
 }
 
+/// Whether `e` is SQLite reporting a condition that clears up by itself:
+/// another writer holding the database (`SQLITE_BUSY`, `SQLITE_LOCKED`) or
+/// a full disk (`SQLITE_FULL`). Worth retrying rather than giving up.
+pub fn is_transient(e: &eyre::Report) -> bool {
+    use rusqlite::ErrorCode::{DatabaseBusy, DatabaseLocked, DiskFull};
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<rusqlite::Error>())
+        .any(|e| matches!(e.sqlite_error_code(), Some(DatabaseBusy | DatabaseLocked | DiskFull)))
+}
+
+/// One statement needed to bring a database up to `SCHEMA_VERSION`.
+pub struct Migration {
+    pub sql: String,
+    /// Changes an existing table (as opposed to creating a missing object)
+    pub alters_existing: bool,
+}
+
+/// `schema_version` recorded in the database; `None` for files written before
+/// it was tracked (or that aren't indexer databases at all).
+pub fn stored_schema_version(conn: &Connection) -> Result<Option<u32>> {
+    if !object_exists(conn, "state")? {
+        return Ok(None);
+    }
+    get_state(conn, "schema_version")?
+        .map(|v| v.parse().map_err(|_| eyre!("Invalid schema_version: {v}")))
+        .transpose()
+}
+
+/// Statements that would move this database to the embedded schema, in order:
+/// missing tables, table rebuilds, added columns, missing indexes, then the
+/// version stamp. Empty when the database is already current.
+pub fn pending_migrations(conn: &Connection) -> Result<Vec<Migration>> {
+    let objects = schema_objects();
+    let mut tables = Vec::new();
+    let mut indexes = Vec::new();
+    for (kind, name, sql) in &objects {
+        if object_exists(conn, name)? {
+            continue;
+        }
+        let m = Migration { sql: sql.clone(), alters_existing: false };
+        if *kind == "TABLE" { tables.push(m) } else { indexes.push(m) }
+    }
+
+    let mut rebuilt: Vec<&str> = Vec::new();
+    let mut alters = Vec::new();
+    for (_, table, _, _) in REBUILT_TABLES {
+        if rebuilt.contains(table) || !object_exists(conn, table)? {
+            continue;
+        }
+        let mut fills = Vec::new();
+        for (_, t, column, fill) in REBUILT_TABLES.iter().filter(|(_, t, _, _)| t == table) {
+            if !has_column(conn, t, column)? {
+                fills.push((*column, *fill));
+            }
+        }
+        if !fills.is_empty() {
+            alters.push(rebuild_table(conn, &objects, table, &fills)?);
+            rebuilt.push(table);
+        }
+    }
+    for (_, table, column, decl) in ADDED_COLUMNS {
+        // Tables about to be created or rebuilt already include every column
+        if object_exists(conn, table)? && !rebuilt.contains(table) && !has_column(conn, table, column)? {
+            alters.push(Migration {
+                sql: format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"),
+                alters_existing: true,
+            });
+        }
+    }
+
+    let mut out: Vec<Migration> = tables.into_iter().chain(alters).chain(indexes).collect();
+    if !out.is_empty() || stored_schema_version(conn)? != Some(SCHEMA_VERSION) {
+        out.push(Migration {
+            sql: format!(
+                "INSERT INTO state (key, value) VALUES ('schema_version', '{SCHEMA_VERSION}') \
+                 ON CONFLICT(key) DO UPDATE SET value=excluded.value"
+            ),
+            alters_existing: false,
+        });
+    }
+    Ok(out)
+}
+
+/// Run `pending` in a single transaction.
+pub fn apply_migrations(conn: &mut Connection, pending: &[Migration]) -> Result<()> {
+    let tx = conn.transaction()?;
+    for m in pending {
+        tx.execute_batch(&m.sql)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Copy `table` into a new table with the embedded definition, keeping ids and
+/// every existing column it still has and filling each of `fills`
+/// (`(column, expression)`), then put it in place of the old one and recreate
+/// its indexes, which the drop removed.
+fn rebuild_table(
+    conn: &Connection,
+    objects: &[(&'static str, String, String)],
+    table: &str,
+    fills: &[(&str, &str)],
+) -> Result<Migration> {
+    let create = objects.iter()
+        .find(|(kind, name, _)| *kind == "TABLE" && name == table)
+        .map(|(_, _, sql)| sql)
+        .ok_or_else(|| eyre!("{table} is not in the embedded schema"))?;
+    let staging = format!("{table}_rebuild");
+    let embedded = {
+        let scratch = Connection::open_in_memory()?;
+        scratch.execute_batch(create)?;
+        column_names(&scratch, table)?
+    };
+    let kept: Vec<String> = column_names(conn, table)?.into_iter().filter(|c| embedded.contains(c)).collect();
+    let columns = kept.iter().map(String::as_str).chain(fills.iter().map(|(c, _)| *c)).collect::<Vec<_>>().join(", ");
+    let values = kept.iter().map(String::as_str).chain(fills.iter().map(|(_, f)| *f)).collect::<Vec<_>>().join(", ");
+
+    let mut sql = vec![
+        create.replacen(&format!("IF NOT EXISTS {table}"), &staging, 1),
+        format!("INSERT INTO {staging} ({columns}) SELECT {values} FROM {table}"),
+        format!("DROP TABLE {table}"),
+        format!("ALTER TABLE {staging} RENAME TO {table}"),
+    ];
+    let on_table = format!(" ON {table}(");
+    sql.extend(objects.iter()
+        .filter(|(kind, _, stmt)| *kind == "INDEX" && stmt.contains(&on_table))
+        .map(|(_, _, stmt)| stmt.clone()));
+    Ok(Migration { sql: sql.join(";\n"), alters_existing: true })
+}
+
+/// `(TABLE|INDEX, name, statement)` for each `CREATE … IF NOT EXISTS` in `SCHEMA_SQL`.
+fn schema_objects() -> Vec<(&'static str, String, String)> {
+    let stripped: String = SCHEMA_SQL.lines()
+        .map(|l| l.split("--").next().unwrap_or_default().trim_end())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    stripped.split(';')
+        .map(str::trim)
+        .filter_map(|stmt| {
+            let words: Vec<&str> = stmt.split_whitespace().collect();
+            let kind = match words.get(1) {
+                Some(&"TABLE") => "TABLE",
+                Some(&"INDEX") | Some(&"UNIQUE") => "INDEX",
+                _ => return None, // PRAGMA
+            };
+            let pos = words.iter().position(|w| *w == "EXISTS")?;
+            let name = words.get(pos + 1)?.trim_end_matches('(').to_string();
+            Some((kind, name, stmt.to_string()))
+        })
+        .collect()
+}
+
+fn object_exists(conn: &Connection, name: &str) -> Result<bool> {
+    let found: Option<i64> = conn.query_row(
+        "SELECT 1 FROM sqlite_master WHERE name=?",
+        params![name],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(found.is_some())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    Ok(column_names(conn, table)?.iter().any(|n| n == column))
+}
+
+fn column_names(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(names)
+}
+
 pub fn insert_block(conn: &Connection, number: u64, hash: &str, ts_unix: i64) -> Result<()> {
     conn.execute(
-        "INSERT OR IGNORE INTO blocks (block_number, block_hash, ts_unix) VALUES (?, ?, ?)",
-        params![number as i64, hash, ts_unix],
+        concat!("INSERT OR IGNORE INTO blocks (block_number, block_hash, ts_unix, chain_id) VALUES (?, ?, ?, ", current_chain!(), ")"),
+        params![block_value(number)?, hash, ts_unix],
     )?;
     Ok(())
 }
 
+/// Addresses are stored lowercase regardless of the case passed in (see
+/// [`crate::models::address_key`]).
+///
+/// Returns false for a duplicate: the same `(tx_hash, log_index)` read from
+/// the same block. The same log from another block, i.e. a transaction
+/// re-included after a reorg, is a new row. A block hash that isn't known
+/// (`""`, as in an import without one) matches only at the same height: such
+/// a stored row is this log, and takes `block_hash`.
+#[allow(clippy::too_many_arguments)]
 pub fn insert_transfer(
     conn: &Connection,
     block_number: u64,
+    block_hash: &str,
     tx_hash: &str,
     log_index: u64,
     token: &str,
     sender: &str,
     recipient: &str,
     value_dec: &str,
-    is_binance_in: bool,
-    is_binance_out: bool,
-) -> Result<()> {
-    conn.execute(
-        "INSERT OR IGNORE INTO erc20_transfers (block_number, tx_hash, log_index, token, sender, recipient, value, is_binance_in, is_binance_out)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    direction: Direction,
+    status: Option<TxStatus>,
+) -> Result<bool> {
+    // Cached: this runs once per transfer, millions of times in a backfill
+    let known = conn.prepare_cached(
+        "SELECT 1 FROM erc20_transfers
+         WHERE tx_hash=?1 AND log_index=?2 AND (block_hash=?3 OR (?3='' AND block_number=?4))"
+    )?.query_row(params![tx_hash, log_index as i64, block_hash, block_value(block_number)?], |_| Ok(())).optional()?;
+    if known.is_some() {
+        return Ok(false);
+    }
+    if !block_hash.is_empty() {
+        let claimed = conn.prepare_cached(
+            "UPDATE erc20_transfers SET block_hash=?3 WHERE tx_hash=?1 AND log_index=?2 AND block_hash='' AND block_number=?4"
+        )?.execute(params![tx_hash, log_index as i64, block_hash, block_value(block_number)?])?;
+        if claimed > 0 {
+            return Ok(false);
+        }
+    }
+    let inserted = conn.prepare_cached(
+        concat!(
+            "INSERT OR IGNORE INTO erc20_transfers (block_number, tx_hash, log_index, token, sender, recipient, value, direction, status, block_hash, chain_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ", current_chain!(), ")"
+        )
+    )?.execute(
         params![
-            block_number as i64,
+            block_value(block_number)?,
             tx_hash,
             log_index as i64,
-            token,
-            sender,
-            recipient,
+            lowercase(token),
+            lowercase(sender),
+            lowercase(recipient),
             value_dec,
-            is_binance_in as i64,
-            is_binance_out as i64
+            direction.as_str(),
+            status.map(TxStatus::as_i64),
+            block_hash
         ],
     )?;
+    Ok(inserted == 1)
+}
+
+/// `s` lowercased, copying only when it isn't already (stored keys normally are).
+fn lowercase(s: &str) -> Cow<'_, str> {
+    if s.bytes().any(|b| b.is_ascii_uppercase()) { Cow::Owned(s.to_ascii_lowercase()) } else { Cow::Borrowed(s) }
+}
+
+/// A block number (or chain id) to store. SQLite integers are signed 64-bit,
+/// so numbers past `i64::MAX` are refused instead of wrapping negative.
+fn block_value(n: u64) -> Result<i64> {
+    i64::try_from(n).map_err(|_| eyre!("block number {n} is past the largest the database can store ({})", i64::MAX))
+}
+
+/// A block number to compare against: numbers past `i64::MAX` are above every
+/// stored one, so they clamp.
+fn block_bound(n: u64) -> i64 {
+    n.min(i64::MAX as u64) as i64
+}
+
+/// Columns read by [`transfer_from_row`], with the counterparty's
+/// classification from `contracts` (the sender of an inflow, the recipient
+/// of an outflow).
+const TRANSFER_SELECT: &str =
+    "SELECT block_number, tx_hash, log_index, token, sender, recipient, value, direction, status, bucket,
+            CASE WHEN c.address IS NULL THEN NULL WHEN c.code_hash IS NULL THEN 'eoa' ELSE 'contract' END, c.label
+     FROM erc20_transfers
+     LEFT JOIN contracts c ON c.address = CASE direction WHEN 'in' THEN sender WHEN 'out' THEN recipient END";
+
+/// The newest `limit` transfers at or below block `through`. `status` of
+/// `None` returns every row, `Some(None)` only rows whose receipt status is
+/// unknown.
+pub fn list_transfers(conn: &Connection, status: Option<Option<TxStatus>>, through: u64, limit: u32) -> Result<Vec<TransferRecord>> {
+    let filter = match status {
+        None => "",
+        Some(None) => "AND status IS NULL",
+        Some(Some(TxStatus::Success)) => "AND status = 1",
+        Some(Some(TxStatus::Reverted)) => "AND status = 0",
+    };
+    let mut stmt = conn.prepare(&format!(
+        "{TRANSFER_SELECT} WHERE block_number <= ?2 {filter} ORDER BY block_number DESC, log_index DESC LIMIT ?1"
+    ))?;
+    let rows = stmt.query_map(params![limit, block_bound(through)], transfer_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// The newest `limit` transfers at or below block `through`, optionally only
+/// those sent or received by `address` (lowercase). Served from the block /
+/// sender / recipient indexes.
+pub fn latest_transfers(conn: &Connection, address: Option<&str>, through: u64, limit: u32) -> Result<Vec<TransferRecord>> {
+    let filter = if address.is_some() { "AND (sender = ?3 OR recipient = ?3)" } else { "" };
+    let mut stmt = conn.prepare(&format!(
+        "{TRANSFER_SELECT} WHERE block_number <= ?2 {filter} ORDER BY block_number DESC, log_index DESC LIMIT ?1"
+    ))?;
+    let through = block_bound(through);
+    let rows = match address {
+        Some(a) => stmt.query_map(params![limit, through, a], transfer_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?,
+        None => stmt.query_map(params![limit, through], transfer_from_row)?.collect::<rusqlite::Result<Vec<_>>>()?,
+    };
+    Ok(rows)
+}
+
+/// Every transfer logged by transaction `tx_hash` (lowercase), in log order.
+pub fn transfers_by_tx(conn: &Connection, tx_hash: &str) -> Result<Vec<TransferRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "{TRANSFER_SELECT} WHERE tx_hash = ?1 ORDER BY log_index"
+    ))?;
+    let rows = stmt.query_map(params![tx_hash], transfer_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// One page of transfers sent (`Some(false)` = outgoing only) or received
+/// (`Some(true)`) by `address` (lowercase), or both for `None`; newest first.
+pub fn address_transfers(conn: &Connection, address: &str, incoming: Option<bool>, offset: u64, limit: u32) -> Result<Vec<TransferRecord>> {
+    let filter = match incoming {
+        None => "sender = ?1 OR recipient = ?1",
+        Some(true) => "recipient = ?1",
+        Some(false) => "sender = ?1",
+    };
+    let mut stmt = conn.prepare(&format!(
+        "{TRANSFER_SELECT} WHERE {filter} ORDER BY block_number DESC, log_index DESC LIMIT ?2 OFFSET ?3"
+    ))?;
+    let rows = stmt.query_map(params![address, limit, offset.min(i64::MAX as u64) as i64], transfer_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Transfers with `from_block <= block_number <= to_block`, in chain order.
+pub fn get_transfers_by_range(conn: &Connection, from_block: u64, to_block: u64) -> Result<Vec<TransferRecord>> {
+    let mut stmt = conn.prepare(&format!(
+        "{TRANSFER_SELECT} WHERE block_number >= ?1 AND block_number <= ?2 ORDER BY block_number, log_index"
+    ))?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block)], transfer_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// [`get_transfers_by_range`] one row at a time, for exports too large to hold.
+pub fn for_each_transfer(conn: &Connection, from_block: u64, to_block: u64, mut f: impl FnMut(TransferRecord) -> Result<()>) -> Result<()> {
+    let mut stmt = conn.prepare(&format!(
+        "{TRANSFER_SELECT} WHERE block_number >= ?1 AND block_number <= ?2 ORDER BY block_number, log_index"
+    ))?;
+    let mut rows = stmt.query(params![block_bound(from_block), block_bound(to_block)])?;
+    while let Some(row) = rows.next()? {
+        f(transfer_from_row(row)?)?;
+    }
     Ok(())
 }
 
-pub fn update_cumulative(conn: &Connection, block_number: u64, new_value_dec: &str) -> Result<()> {
+fn transfer_from_row(row: &rusqlite::Row) -> rusqlite::Result<TransferRecord> {
+    Ok(TransferRecord{
+        block_number: row.get::<_, i64>(0)? as u64,
+        tx_hash: row.get(1)?,
+        log_index: row.get::<_, i64>(2)? as u64,
+        token: row.get(3)?,
+        from: row.get(4)?,
+        to: row.get(5)?,
+        value: row.get(6)?,
+        direction: direction_column(row, 7)?,
+        status: row.get::<_, Option<i64>>(8)?.map(TxStatus::from_i64),
+        bucket: row.get(9)?,
+        counterparty_kind: row.get::<_, Option<String>>(10)?.as_deref().and_then(CounterpartyKind::parse),
+        counterparty_label: row.get(11)?,
+    })
+}
+
+fn direction_column(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<Direction> {
+    let s: String = row.get(idx)?;
+    Direction::parse(&s).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, format!("invalid direction {s:?}").into())
+    })
+}
+
+/// `updated_at_unix` only moves forward: an earlier stamp (host clock skew, a
+/// rollback, a rebuild from older data) leaves the stored one in place.
+pub fn update_cumulative(conn: &Connection, block_number: u64, new_value_dec: &str, usd_micro: Option<i64>, updated_at_unix: i64) -> Result<()> {
     conn.execute(
-        "UPDATE cumulative_netflow SET block_number=?, value=?, updated_at_unix=? WHERE id=1",
-        params![block_number as i64, new_value_dec, OffsetDateTime::now_utc().unix_timestamp()],
+        concat!(
+            "UPDATE cumulative_netflow SET block_number=?1, value=?2, updated_at_unix=MAX(updated_at_unix, ?3),
+             value_token=?4, value_usd_micro=?5 WHERE chain_id=", current_chain!()
+        ),
+        params![block_value(block_number)?, new_value_dec, updated_at_unix, ValueFormat::Scaled.render(new_value_dec), usd_micro],
     )?;
     Ok(())
 }
 
+/// Flag the stored block's timestamp as estimated, see [`interpolated_ts`].
+pub fn mark_ts_interpolated(conn: &Connection, number: u64) -> Result<()> {
+    conn.execute("UPDATE blocks SET ts_interpolated=1 WHERE block_number=?", params![block_value(number)?])?;
+    Ok(())
+}
+
+/// Polygon PoS's block time, roughly; for estimates past the last known block.
+const ASSUMED_BLOCK_SECS: i64 = 2;
+
+/// An estimate of block `number`'s timestamp from the nearest stored blocks
+/// with a real one: linear between those on either side, or
+/// `ASSUMED_BLOCK_SECS` per block away from the one on a single side. `None`
+/// with no such block stored.
+pub fn interpolated_ts(conn: &Connection, number: u64) -> Result<Option<i64>> {
+    let n = block_bound(number);
+    let neighbour = |sql: &str| conn.query_row(sql, params![n], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))).optional();
+    let below = neighbour(
+        "SELECT block_number, ts_unix FROM blocks WHERE block_number < ? AND ts_interpolated = 0 AND ts_unix > 0
+         ORDER BY block_number DESC LIMIT 1",
+    )?;
+    let above = neighbour(
+        "SELECT block_number, ts_unix FROM blocks WHERE block_number > ? AND ts_interpolated = 0 AND ts_unix > 0
+         ORDER BY block_number LIMIT 1",
+    )?;
+    Ok(match (below, above) {
+        (Some((b, b_ts)), Some((a, a_ts))) => Some(b_ts + (a_ts - b_ts) * (n - b) / (a - b)),
+        (Some((b, b_ts)), None) => Some(b_ts + ASSUMED_BLOCK_SECS * (n - b)),
+        (None, Some((a, a_ts))) => Some(a_ts - ASSUMED_BLOCK_SECS * (a - n)),
+        (None, None) => None,
+    })
+}
+
+/// Timestamp of the newest stored block.
+pub fn latest_block_ts(conn: &Connection) -> Result<Option<i64>> {
+    let ts = conn.query_row(
+        "SELECT ts_unix FROM blocks ORDER BY block_number DESC LIMIT 1",
+        [],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(ts)
+}
+
+/// `(block_number, ts_unix)` of the closest stored block at or below `number`.
+pub fn stored_block_at_or_before(conn: &Connection, number: u64) -> Result<Option<(u64, i64)>> {
+    let row = conn.query_row(
+        "SELECT block_number, ts_unix FROM blocks WHERE block_number <= ? ORDER BY block_number DESC LIMIT 1",
+        params![block_bound(number)],
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+    ).optional()?;
+    Ok(row)
+}
+
+pub fn first_stored_block(conn: &Connection) -> Result<Option<(u64, i64)>> {
+    let row = conn.query_row(
+        "SELECT block_number, ts_unix FROM blocks ORDER BY block_number LIMIT 1",
+        [],
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+    ).optional()?;
+    Ok(row)
+}
+
+/// Timestamp of the closest stored block below `number`.
+pub fn previous_block_ts(conn: &Connection, number: u64) -> Result<Option<i64>> {
+    let ts = conn.query_row(
+        "SELECT ts_unix FROM blocks WHERE block_number < ? ORDER BY block_number DESC LIMIT 1",
+        params![block_bound(number)],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(ts)
+}
+
 pub fn get_latest_cumulative(conn: &Connection) -> Result<NetflowSnapshot> {
-    let mut stmt = conn.prepare("SELECT block_number, value, updated_at_unix FROM cumulative_netflow WHERE id=1")?;
+    let mut stmt = conn.prepare(concat!(
+        "SELECT block_number, value, updated_at_unix, (SELECT value FROM state WHERE key='start_block'), value_token, value_usd_micro,
+                (SELECT MIN(block_number) FROM netflow_blocks WHERE price_usd_micro IS NOT NULL)
+         FROM cumulative_netflow WHERE chain_id=", current_chain!()
+    ))?;
     let row = stmt.query_row([], |row| {
+        let raw: String = row.get(1)?;
         Ok(NetflowSnapshot{
             block_number: row.get::<_, i64>(0)? as u64,
-            cumulative_netflow_raw: row.get::<_, String>(1)?,
+            cumulative_token: row.get::<_, Option<String>>(4)?.unwrap_or_else(|| ValueFormat::Scaled.render(&raw)),
+            cumulative_netflow_raw: raw,
             updated_at_unix: row.get::<_, i64>(2)?,
+            start_block: row.get::<_, Option<String>>(3)?.and_then(|v| v.parse().ok()),
+            cumulative_usd: row.get::<_, Option<i64>>(5)?.map(prices::format_usd),
+            usd_since_block: row.get::<_, Option<i64>>(6)?.map(|b| b as u64),
         })
     })?;
     Ok(row)
 }
+
+/// The USD cumulative in micro-USD; `None` until a price has been recorded.
+pub fn get_cumulative_usd_micro(conn: &Connection) -> Result<Option<i64>> {
+    Ok(conn.query_row(concat!("SELECT value_usd_micro FROM cumulative_netflow WHERE chain_id=", current_chain!()), [], |row| row.get(0))?)
+}
+
+pub fn get_block_hash(conn: &Connection, number: u64) -> Result<Option<String>> {
+    let hash = conn.query_row(
+        "SELECT block_hash FROM blocks WHERE block_number=?",
+        params![block_bound(number)],
+        |row| row.get(0),
+    ).optional()?;
+    Ok(hash)
+}
+
+/// Up to `limit` stored `(block_number, block_hash)` at or below `at_most`, newest first.
+pub fn recent_block_hashes(conn: &Connection, at_most: u64, limit: u32) -> Result<Vec<(u64, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, block_hash FROM blocks WHERE block_number <= ?1 ORDER BY block_number DESC LIMIT ?2"
+    )?;
+    let rows = stmt.query_map(params![block_bound(at_most), limit], |row| {
+        Ok((row.get::<_, i64>(0)? as u64, row.get(1)?))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// `(block_number, cumulative, USD cumulative)` of the last aggregate row at
+/// or below `block`, i.e. the cumulatives as they stood after that block. A
+/// database restored from a checkpoint has no rows up to it; the
+/// checkpoint's values stand in.
+pub fn cumulative_at(conn: &Connection, block: u64) -> Result<Option<(u64, String, Option<i64>)>> {
+    let row = conn.query_row(
+        "SELECT block_number, cumulative, cumulative_usd_micro FROM netflow_blocks WHERE block_number <= ? ORDER BY block_number DESC LIMIT 1",
+        params![block_bound(block)],
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?, row.get(2)?)),
+    ).optional()?;
+    if row.is_some() {
+        return Ok(row);
+    }
+    Ok(get_checkpoint_base(conn)?
+        .filter(|base| base.block_number <= block)
+        .map(|base| (base.block_number, base.cumulative, base.cumulative_usd_micro)))
+}
+
+/// The cumulatives as they stood after `block`, shaped like
+/// [`get_latest_cumulative`] with `block_number` = `block` and the time of
+/// the last stored block at or before it. `None` when the database holds no
+/// state for it: before the start block, or before the checkpoint it was
+/// restored from.
+pub fn get_cumulative_as_of(conn: &Connection, block: u64) -> Result<Option<NetflowSnapshot>> {
+    let start_block = get_start_block(conn)?;
+    let base = get_checkpoint_base(conn)?;
+    if start_block.is_some_and(|s| block < s) || base.as_ref().is_some_and(|b| block < b.block_number) {
+        return Ok(None);
+    }
+    let (raw, usd_micro) = match cumulative_at(conn, block)? {
+        Some((_, raw, usd_micro)) => (raw, usd_micro),
+        None => ("0".to_string(), None),
+    };
+    let updated_at_unix = conn.query_row(
+        "SELECT ts_unix FROM blocks WHERE block_number <= ? ORDER BY block_number DESC LIMIT 1",
+        params![block_bound(block)],
+        |row| row.get(0),
+    ).optional()?.unwrap_or(0);
+    let usd_since_block: Option<i64> = conn.query_row(
+        "SELECT MIN(block_number) FROM netflow_blocks WHERE price_usd_micro IS NOT NULL AND block_number <= ?",
+        params![block_bound(block)],
+        |row| row.get(0),
+    )?;
+    Ok(Some(NetflowSnapshot {
+        block_number: block,
+        cumulative_token: ValueFormat::Scaled.render(&raw),
+        cumulative_netflow_raw: raw,
+        cumulative_usd: usd_micro.map(prices::format_usd),
+        usd_since_block: usd_since_block.map(|b| b as u64),
+        updated_at_unix,
+        start_block,
+    }))
+}
+
+/// Drop every block, transfer, aggregate and discovered deposit address above `block`.
+pub fn delete_after(conn: &Connection, block: u64) -> Result<()> {
+    let block = block_bound(block);
+    conn.execute("DELETE FROM erc20_transfers WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM netflow_blocks WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM blocks WHERE block_number > ?", params![block])?;
+    conn.execute(
+        "INSERT INTO address_set_history (changed_at_unix, address, change, kind, source, block_number)
+         SELECT ?1, address, 'removed', 'deposit', 'rollback', first_seen_block FROM deposit_addresses WHERE first_seen_block > ?2",
+        params![OffsetDateTime::now_utc().unix_timestamp(), block],
+    )?;
+    conn.execute("DELETE FROM deposit_addresses WHERE first_seen_block > ?", params![block])?;
+    conn.execute("DELETE FROM failed_blocks WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM native_transfers WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM native_netflow_blocks WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM raw_logs WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM volume_blocks WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM supply_events WHERE block_number > ?", params![block])?;
+    conn.execute("DELETE FROM nft_transfers WHERE block_number > ?", params![block])?;
+    Ok(())
+}
+
+/// Record (or count another) failure to index `number`.
+pub fn record_failed_block(conn: &Connection, number: u64, error: &str) -> Result<()> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    conn.execute(
+        "INSERT INTO failed_blocks (block_number, error, attempts, first_failed_at_unix, last_failed_at_unix)
+         VALUES (?1, ?2, 1, ?3, ?3)
+         ON CONFLICT(block_number) DO UPDATE SET error=excluded.error, attempts=attempts+1, last_failed_at_unix=excluded.last_failed_at_unix",
+        params![block_value(number)?, error, now],
+    )?;
+    Ok(())
+}
+
+pub fn clear_failed_block(conn: &Connection, number: u64) -> Result<()> {
+    conn.execute("DELETE FROM failed_blocks WHERE block_number=?", params![block_bound(number)])?;
+    Ok(())
+}
+
+/// Failed blocks awaiting retry, oldest first.
+pub fn list_failed_blocks(conn: &Connection) -> Result<Vec<u64>> {
+    let mut stmt = conn.prepare("SELECT block_number FROM failed_blocks ORDER BY block_number")?;
+    let rows = stmt.query_map([], |row| Ok(row.get::<_, i64>(0)? as u64))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn insert_reorg(conn: &Connection, r: &ReorgRecord) -> Result<()> {
+    conn.execute(
+        "INSERT INTO reorgs (detected_at_unix, new_head, new_head_hash, fork_block, depth, orphaned_hashes,
+                             removed_transfers, removed_inflow, removed_outflow, cumulative_before, cumulative_after)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            r.detected_at_unix,
+            block_value(r.new_head)?,
+            r.new_head_hash,
+            block_value(r.fork_block)?,
+            r.depth as i64,
+            serde_json::to_string(&r.orphaned_hashes)?,
+            serde_json::to_string(&r.removed_transfers)?,
+            r.removed_inflow,
+            r.removed_outflow,
+            r.cumulative_before,
+            r.cumulative_after,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn insert_session(conn: &Connection, s: &SessionSummary) -> Result<()> {
+    conn.execute(
+        "INSERT INTO sessions (started_at_unix, ended_at_unix, exit_reason, blocks_processed, transfers_indexed, reorgs,
+                               first_block, last_block, first_block_ts_unix, last_block_ts_unix, final_cumulative)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            s.started_at_unix,
+            s.ended_at_unix,
+            s.exit_reason,
+            s.blocks_processed as i64,
+            s.transfers_indexed as i64,
+            s.reorgs as i64,
+            s.first_block.map(block_value).transpose()?,
+            s.last_block.map(block_value).transpose()?,
+            s.first_block_ts_unix,
+            s.last_block_ts_unix,
+            s.final_cumulative,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn insert_block_stats(conn: &Connection, s: &BlockStats) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO block_stats (block_number, logs_fetched, transfers_matched, processing_ms, rpc_ms, indexed_at_unix)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+    )?.execute(params![
+        block_bound(s.block_number),
+        s.logs_fetched as i64,
+        s.transfers_matched as i64,
+        s.processing_ms.min(i64::MAX as u64) as i64,
+        s.rpc_ms.min(i64::MAX as u64) as i64,
+        s.indexed_at_unix,
+    ])?;
+    Ok(())
+}
+
+/// The newest `limit` `block_stats` rows with `from_block <= block_number <= to_block`, newest first.
+pub fn list_block_stats(conn: &Connection, from_block: u64, to_block: u64, limit: u32) -> Result<Vec<BlockStats>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, logs_fetched, transfers_matched, processing_ms, rpc_ms, indexed_at_unix
+         FROM block_stats WHERE block_number >= ?1 AND block_number <= ?2 ORDER BY block_number DESC LIMIT ?3"
+    )?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block), limit], |r| Ok(BlockStats {
+        block_number: r.get::<_, i64>(0)? as u64,
+        logs_fetched: r.get::<_, i64>(1)? as u64,
+        transfers_matched: r.get::<_, i64>(2)? as u64,
+        processing_ms: r.get::<_, i64>(3)? as u64,
+        rpc_ms: r.get::<_, i64>(4)? as u64,
+        indexed_at_unix: r.get(5)?,
+    }))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Append an event to `event_log`, returning its `seq`.
+pub fn append_event(conn: &Connection, block_number: u64, kind: &str, payload: &str) -> Result<u64> {
+    conn.prepare_cached(
+        "INSERT INTO event_log (block_number, kind, payload, created_at_unix) VALUES (?1, ?2, ?3, ?4)"
+    )?.execute(params![block_bound(block_number), kind, payload, OffsetDateTime::now_utc().unix_timestamp()])?;
+    Ok(conn.last_insert_rowid() as u64)
+}
+
+/// Up to `limit` logged events with `seq > after_seq`, oldest first: `(seq, payload)`.
+pub fn events_after(conn: &Connection, after_seq: u64, limit: u32) -> Result<Vec<(u64, String)>> {
+    let mut stmt = conn.prepare_cached("SELECT seq, payload FROM event_log WHERE seq > ?1 ORDER BY seq LIMIT ?2")?;
+    let rows = stmt.query_map(params![after_seq.min(i64::MAX as u64) as i64, limit], |r| {
+        Ok((r.get::<_, i64>(0)? as u64, r.get(1)?))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Highest `seq` assigned so far; `0` before any event.
+pub fn last_event_seq(conn: &Connection) -> Result<u64> {
+    let seq: Option<i64> = conn.query_row("SELECT MAX(seq) FROM event_log", [], |r| r.get(0))?;
+    Ok(seq.unwrap_or(0) as u64)
+}
+
+/// Most recent reorgs first.
+pub fn list_reorgs(conn: &Connection, limit: u32) -> Result<Vec<ReorgRecord>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, detected_at_unix, new_head, new_head_hash, fork_block, depth, orphaned_hashes,
+                removed_transfers, removed_inflow, removed_outflow, cumulative_before, cumulative_after
+         FROM reorgs ORDER BY id DESC LIMIT ?1"
+    )?;
+    let mut rows = stmt.query(params![limit])?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        out.push(ReorgRecord {
+            id: row.get(0)?,
+            detected_at_unix: row.get(1)?,
+            new_head: row.get::<_, i64>(2)? as u64,
+            new_head_hash: row.get(3)?,
+            fork_block: row.get::<_, i64>(4)? as u64,
+            depth: row.get::<_, i64>(5)? as u64,
+            orphaned_hashes: serde_json::from_str(&row.get::<_, String>(6)?)?,
+            removed_transfers: serde_json::from_str(&row.get::<_, String>(7)?)?,
+            removed_inflow: row.get(8)?,
+            removed_outflow: row.get(9)?,
+            cumulative_before: row.get(10)?,
+            cumulative_after: row.get(11)?,
+        });
+    }
+    Ok(out)
+}
+
+pub fn get_state(conn: &Connection, key: &str) -> Result<Option<String>> {
+    let value = conn.query_row(
+        "SELECT value FROM state WHERE key=?",
+        params![key],
+        |row| row.get(0)
+    ).optional()?;
+    Ok(value)
+}
+
+pub fn set_state(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO state (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value=excluded.value",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn get_state_u64(conn: &Connection, key: &str) -> Result<Option<u64>> {
+    get_state(conn, key)?
+        .map(|v| v.parse::<u64>().map_err(|_| eyre!("Invalid state value for {key}: {v}")))
+        .transpose()
+}
+
+/// First block of the accounting epoch; the cumulative is "net-flow since this block".
+pub fn get_start_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "start_block")
+}
+
+pub fn set_start_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "start_block", &block.to_string())
+}
+
+/// First block whose native POL transfers were recorded (`TRACK_NATIVE`).
+pub fn get_native_since_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "native_since_block")
+}
+
+pub fn set_native_since_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "native_since_block", &block.to_string())
+}
+
+/// First block `raw_logs` covers; every block from here to the last indexed
+/// one is either archived or had no token transfers.
+pub fn get_raw_logs_since_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "raw_logs_since_block")
+}
+
+pub fn set_raw_logs_since_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "raw_logs_since_block", &block.to_string())
+}
+
+/// First block whose total transfer volume was recorded (`TRACK_VOLUME`).
+pub fn get_volume_since_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "volume_since_block")
+}
+
+pub fn set_volume_since_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "volume_since_block", &block.to_string())
+}
+
+/// First block whose mints and burns were recorded (`TRACK_SUPPLY_EVENTS`).
+pub fn get_nfts_since_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "nfts_since_block")
+}
+
+pub fn set_nfts_since_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "nfts_since_block", &block.to_string())
+}
+
+pub fn get_supply_events_since_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "supply_events_since_block")
+}
+
+pub fn set_supply_events_since_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "supply_events_since_block", &block.to_string())
+}
+
+/// Highest block whose transfers are fully reflected in the cumulative.
+pub fn get_last_indexed_block(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "last_indexed_block")
+}
+
+/// The readers' watermark: every block up to it is complete, transfers and
+/// aggregates alike, and nothing above it is. Writers advance
+/// `last_indexed_block` in the same transaction as a block's data, so it is
+/// that value; a reader that takes it and its data from one snapshot, and
+/// stays at or below it, never sees a block in part. `u64::MAX` (no bound)
+/// before anything is indexed, for imported data.
+pub fn indexed_through_block(conn: &Connection) -> Result<u64> {
+    Ok(get_last_indexed_block(conn)?.unwrap_or(u64::MAX))
+}
+
+pub fn set_last_indexed_block(conn: &Connection, block: u64) -> Result<()> {
+    set_state(conn, "last_indexed_block", &block.to_string())
+}
+
+/// Like `set_last_indexed_block`, but never moves it backwards (retried blocks
+/// are older than the tip).
+pub fn advance_last_indexed_block(conn: &Connection, block: u64) -> Result<()> {
+    if get_last_indexed_block(conn)?.is_none_or(|last| block > last) {
+        set_last_indexed_block(conn, block)?;
+    }
+    Ok(())
+}
+
+/// The state a database was bootstrapped from (see `checkpoint`): the
+/// cumulatives after `block_number`, which has no transfer rows of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointBase {
+    pub block_number: u64,
+    pub cumulative: String,
+    pub cumulative_usd_micro: Option<i64>,
+    /// Hex SHA-256 chain over the counted transfers up to `block_number`
+    pub rows_sha256: String,
+}
+
+pub fn get_checkpoint_base(conn: &Connection) -> Result<Option<CheckpointBase>> {
+    let Some(block_number) = get_state_u64(conn, "checkpoint_block")? else { return Ok(None) };
+    Ok(Some(CheckpointBase {
+        block_number,
+        cumulative: get_state(conn, "checkpoint_cumulative")?.unwrap_or_else(|| "0".into()),
+        cumulative_usd_micro: get_state(conn, "checkpoint_cumulative_usd_micro")?.and_then(|v| v.parse().ok()),
+        rows_sha256: get_state(conn, "checkpoint_rows_sha256")?.unwrap_or_default(),
+    }))
+}
+
+pub fn set_checkpoint_base(conn: &Connection, base: &CheckpointBase) -> Result<()> {
+    set_state(conn, "checkpoint_block", &base.block_number.to_string())?;
+    set_state(conn, "checkpoint_cumulative", &base.cumulative)?;
+    if let Some(usd) = base.cumulative_usd_micro {
+        set_state(conn, "checkpoint_cumulative_usd_micro", &usd.to_string())?;
+    }
+    set_state(conn, "checkpoint_rows_sha256", &base.rows_sha256)
+}
+
+/// Token the database was built for (lowercase), see `dataset`.
+pub fn get_dataset_token(conn: &Connection) -> Result<Option<String>> {
+    get_state(conn, "token")
+}
+
+pub fn set_dataset_token(conn: &Connection, token: &str) -> Result<()> {
+    set_state(conn, "token", token)
+}
+
+/// Configured hot wallets: sorted, lowercase, comma-separated.
+pub fn get_dataset_binance_addresses(conn: &Connection) -> Result<Option<String>> {
+    get_state(conn, "binance_addresses")
+}
+
+pub fn set_dataset_binance_addresses(conn: &Connection, addrs: &str) -> Result<()> {
+    set_state(conn, "binance_addresses", addrs)
+}
+
+pub fn get_chain_id(conn: &Connection) -> Result<Option<u64>> {
+    get_state_u64(conn, "chain_id")
+}
+
+/// Record the chain and move the rows of the [`CHAIN_TABLES`] to it: those
+/// written before it was known, or every row when `--force` adopts another
+/// chain.
+pub fn set_chain_id(conn: &Connection, chain_id: u64) -> Result<()> {
+    if get_chain_id(conn)? == Some(chain_id) {
+        return Ok(());
+    }
+    let id = block_value(chain_id)?;
+    for table in CHAIN_TABLES {
+        conn.execute(&format!("UPDATE {table} SET chain_id=?1 WHERE chain_id<>?1"), params![id])?;
+    }
+    set_state(conn, "chain_id", &chain_id.to_string())
+}
+
+/// Latest `totalSupply()` read (raw units) and the block it was read at.
+pub fn get_total_supply(conn: &Connection) -> Result<Option<(String, u64)>> {
+    let Some(supply) = get_state(conn, "total_supply")? else { return Ok(None) };
+    Ok(Some((supply, get_state_u64(conn, "total_supply_block")?.unwrap_or(0))))
+}
+
+pub fn set_total_supply(conn: &Connection, supply: &str, block: u64) -> Result<()> {
+    set_state(conn, "total_supply", supply)?;
+    set_state(conn, "total_supply_block", &block.to_string())
+}
+
+/// Accounting mode the cumulative was built with.
+pub fn get_accounting_mode(conn: &Connection) -> Result<Option<AccountingMode>> {
+    get_state(conn, "accounting_mode")?
+        .map(|v| AccountingMode::parse(&v))
+        .transpose()
+}
+
+pub fn set_accounting_mode(conn: &Connection, mode: AccountingMode) -> Result<()> {
+    set_state(conn, "accounting_mode", mode.as_str())
+}
+
+/// Returns false if the address was already known.
+pub fn insert_deposit_address(conn: &Connection, address: &str, hot_wallet: &str, block: u64, sweep_tx_hash: &str) -> Result<bool> {
+    let n = conn.execute(
+        "INSERT OR IGNORE INTO deposit_addresses (address, hot_wallet, first_seen_block, sweep_tx_hash) VALUES (?1, ?2, ?3, ?4)",
+        params![address.to_ascii_lowercase(), hot_wallet.to_ascii_lowercase(), block_value(block)?, sweep_tx_hash],
+    )?;
+    if n == 1 {
+        insert_address_change(conn, address, "added", "deposit", "deposit discovery", None, Some(block))?;
+    }
+    Ok(n == 1)
+}
+
+pub fn insert_address_change(
+    conn: &Connection,
+    address: &str,
+    change: &str,
+    kind: &str,
+    source: &str,
+    actor: Option<&str>,
+    block_number: Option<u64>,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO address_set_history (changed_at_unix, address, change, kind, source, actor, block_number)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            OffsetDateTime::now_utc().unix_timestamp(), address.to_ascii_lowercase(), change, kind, source, actor,
+            block_number.map(block_value).transpose()?,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Addresses of `kind` whose latest recorded change added them, sorted.
+pub fn history_address_set(conn: &Connection, kind: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT address FROM address_set_history h WHERE kind = ?1 AND change = 'added'
+           AND id = (SELECT MAX(id) FROM address_set_history WHERE kind = ?1 AND address = h.address)
+         ORDER BY address"
+    )?;
+    let rows = stmt.query_map(params![kind], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Newest first, optionally only the changes to `address` (lowercase).
+pub fn list_address_changes(conn: &Connection, address: Option<&str>, limit: u32) -> Result<Vec<AddressChange>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, changed_at_unix, address, change, kind, source, actor, block_number FROM address_set_history
+         WHERE ?1 IS NULL OR address = ?1 ORDER BY id DESC LIMIT ?2"
+    )?;
+    let rows = stmt.query_map(params![address, limit], |row| {
+        Ok(AddressChange {
+            id: row.get(0)?,
+            changed_at_unix: row.get(1)?,
+            address: row.get(2)?,
+            change: row.get(3)?,
+            kind: row.get(4)?,
+            source: row.get(5)?,
+            actor: row.get(6)?,
+            block_number: row.get::<_, Option<i64>>(7)?.map(|b| b as u64),
+        })
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn list_deposit_addresses(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT address FROM deposit_addresses ORDER BY first_seen_block")?;
+    let rows = stmt.query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// `(address, hot_wallet, first_seen_block, sweep_tx_hash)` of the deposit
+/// addresses discovered up to `through`.
+pub fn deposit_address_rows(conn: &Connection, through: u64) -> Result<Vec<(String, String, u64, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT address, hot_wallet, first_seen_block, sweep_tx_hash FROM deposit_addresses
+         WHERE first_seen_block <= ? ORDER BY first_seen_block, address"
+    )?;
+    let rows = stmt.query_map(params![block_bound(through)], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64, row.get(3)?))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn insert_netflow_block(conn: &Connection, row: &NetflowBlock) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO netflow_blocks (block_number, ts_unix, inflow, outflow, cumulative, transfer_count,
+                                                cumulative_token, price_usd_micro, cumulative_usd_micro)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            block_value(row.block_number)?,
+            row.ts_unix,
+            row.inflow,
+            row.outflow,
+            row.cumulative,
+            row.transfer_count as i64,
+            row.cumulative_token,
+            row.price_usd_micro,
+            row.cumulative_usd_micro
+        ],
+    )?;
+    Ok(())
+}
+
+/// Returns false when the transaction was already recorded.
+pub fn insert_native_transfer(conn: &Connection, t: &NativeTransferRecord) -> Result<bool> {
+    let n = conn.execute(
+        "INSERT OR IGNORE INTO native_transfers (block_number, tx_hash, sender, recipient, value, direction, status)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            block_value(t.block_number)?,
+            t.tx_hash,
+            t.from,
+            t.to,
+            t.value,
+            t.direction.as_str(),
+            t.status.map(TxStatus::as_i64),
+        ],
+    )?;
+    Ok(n > 0)
+}
+
+/// Counted (not reverted) native transfers, oldest first, with their block timestamps.
+pub fn counted_native_transfers(conn: &Connection) -> Result<Vec<(NativeTransferRecord, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.block_number, t.tx_hash, t.sender, t.recipient, t.value, t.direction, t.status,
+                COALESCE(b.ts_unix, 0)
+         FROM native_transfers t LEFT JOIN blocks b ON b.block_number = t.block_number
+         WHERE t.status IS NULL OR t.status != 0
+         ORDER BY t.block_number, t.id"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((
+        NativeTransferRecord {
+            block_number: row.get::<_, i64>(0)? as u64,
+            tx_hash: row.get(1)?,
+            from: row.get(2)?,
+            to: row.get(3)?,
+            value: row.get(4)?,
+            direction: direction_column(row, 5)?,
+            status: row.get::<_, Option<i64>>(6)?.map(TxStatus::from_i64),
+        },
+        row.get(7)?,
+    )))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn insert_native_netflow_block(conn: &Connection, row: &NativeNetflowBlock) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO native_netflow_blocks
+             (block_number, ts_unix, inflow, outflow, cumulative_inflow, cumulative_outflow, transfer_count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            block_value(row.block_number)?,
+            row.ts_unix,
+            row.inflow,
+            row.outflow,
+            row.cumulative_inflow,
+            row.cumulative_outflow,
+            row.transfer_count as i64,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Native aggregates of the highest block with native flows.
+pub fn latest_native_netflow(conn: &Connection) -> Result<Option<NativeNetflowBlock>> {
+    Ok(conn.query_row(
+        "SELECT block_number, ts_unix, inflow, outflow, cumulative_inflow, cumulative_outflow, transfer_count
+         FROM native_netflow_blocks ORDER BY block_number DESC LIMIT 1",
+        [],
+        |row| Ok(NativeNetflowBlock {
+            block_number: row.get::<_, i64>(0)? as u64,
+            ts_unix: row.get(1)?,
+            inflow: row.get(2)?,
+            outflow: row.get(3)?,
+            cumulative_inflow: row.get(4)?,
+            cumulative_outflow: row.get(5)?,
+            transfer_count: row.get::<_, i64>(6)? as u64,
+        }),
+    ).optional()?)
+}
+
+pub fn clear_native_netflow_blocks(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM native_netflow_blocks", [])?;
+    Ok(())
+}
+
+/// Aggregate rows with `from_ts <= ts_unix <= to_ts`, oldest first.
+pub fn get_netflow_blocks_by_time(conn: &Connection, from_ts: i64, to_ts: i64) -> Result<Vec<NetflowBlock>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {NETFLOW_BLOCK_COLUMNS} FROM netflow_blocks
+         WHERE ts_unix >= ?1 AND ts_unix <= ?2 ORDER BY block_number"
+    ))?;
+    let rows = stmt.query_map(params![from_ts, to_ts], netflow_block_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Inflow, outflow and count of the transfers of `token` (lowercase) above
+/// `cap` that the aggregates timestamped `[from_ts, to_ts]` counted: in or
+/// out, not reverted, outside any rule bucket.
+pub fn transfers_above(conn: &Connection, token: &str, cap: U256, from_ts: i64, to_ts: i64) -> Result<(U256, U256, u64)> {
+    let cap_dec = cap.to_string();
+    // Values have no leading zeros, so only ones at least as long as the cap can exceed it
+    let mut stmt = conn.prepare_cached(
+        "SELECT t.value, t.direction FROM erc20_transfers t JOIN netflow_blocks n ON n.block_number = t.block_number
+         WHERE n.ts_unix >= ?1 AND n.ts_unix <= ?2 AND t.token = ?3 AND length(t.value) >= ?4
+           AND t.direction IN ('in', 'out') AND t.bucket IS NULL AND t.status IS NOT 0"
+    )?;
+    let mut rows = stmt.query(params![from_ts, to_ts, token, cap_dec.len() as i64])?;
+    let (mut inflow, mut outflow, mut count) = (U256::zero(), U256::zero(), 0);
+    while let Some(row) = rows.next()? {
+        let value = U256::from_dec_str(&row.get::<_, String>(0)?).map_err(|e| eyre!("stored transfer value: {e}"))?;
+        if value <= cap {
+            continue;
+        }
+        count += 1;
+        if row.get::<_, String>(1)? == "in" {
+            inflow = inflow.saturating_add(value);
+        } else {
+            outflow = outflow.saturating_add(value);
+        }
+    }
+    Ok((inflow, outflow, count))
+}
+
+/// Aggregate rows with `from_block <= block_number <= to_block`, oldest first.
+pub fn get_netflow_blocks_by_range(conn: &Connection, from_block: u64, to_block: u64) -> Result<Vec<NetflowBlock>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {NETFLOW_BLOCK_COLUMNS} FROM netflow_blocks
+         WHERE block_number >= ?1 AND block_number <= ?2 ORDER BY block_number"
+    ))?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block)], netflow_block_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn clear_netflow_blocks(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM netflow_blocks", [])?;
+    Ok(())
+}
+
+/// Stored per-block prices (micro-USD), for rebuilding the USD cumulative.
+pub fn netflow_block_prices(conn: &Connection) -> Result<HashMap<u64, i64>> {
+    let mut stmt = conn.prepare("SELECT block_number, price_usd_micro FROM netflow_blocks WHERE price_usd_micro IS NOT NULL")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(rows)
+}
+
+/// One `volume_blocks` row.
+pub struct VolumeBlock {
+    pub block_number: u64,
+    pub ts_unix: i64,
+    pub volume: String,
+    pub transfer_count: u64,
+}
+
+pub fn insert_volume_block(conn: &Connection, row: &VolumeBlock) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO volume_blocks (block_number, ts_unix, volume, transfer_count) VALUES (?1, ?2, ?3, ?4)",
+        params![block_value(row.block_number)?, row.ts_unix, row.volume, row.transfer_count as i64],
+    )?;
+    Ok(())
+}
+
+pub fn get_volume_blocks_by_time(conn: &Connection, from_ts: i64, to_ts: i64) -> Result<Vec<VolumeBlock>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, ts_unix, volume, transfer_count FROM volume_blocks
+         WHERE ts_unix >= ?1 AND ts_unix <= ?2 ORDER BY block_number"
+    )?;
+    let rows = stmt.query_map(params![from_ts, to_ts], volume_block_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn get_volume_blocks_by_range(conn: &Connection, from_block: u64, to_block: u64) -> Result<Vec<VolumeBlock>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, ts_unix, volume, transfer_count FROM volume_blocks
+         WHERE block_number >= ?1 AND block_number <= ?2 ORDER BY block_number"
+    )?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block)], volume_block_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+fn volume_block_from_row(row: &rusqlite::Row) -> rusqlite::Result<VolumeBlock> {
+    Ok(VolumeBlock {
+        block_number: row.get::<_, i64>(0)? as u64,
+        ts_unix: row.get(1)?,
+        volume: row.get(2)?,
+        transfer_count: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+pub fn insert_supply_event(conn: &Connection, e: &SupplyEvent) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO supply_events (block_number, log_index, tx_hash, kind, account, value, ts_unix, minted_total, burned_total)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            block_value(e.block_number)?, e.log_index as i64, e.tx_hash, e.kind.as_str(), e.account, e.value,
+            e.ts_unix, e.minted_total, e.burned_total,
+        ],
+    )?;
+    Ok(())
+}
+
+/// `(minted_total, burned_total)` after the last supply event through block `through`.
+pub fn supply_totals_at(conn: &Connection, through: u64) -> Result<Option<(String, String)>> {
+    Ok(conn.query_row(
+        "SELECT minted_total, burned_total FROM supply_events WHERE block_number <= ?
+         ORDER BY block_number DESC, log_index DESC LIMIT 1",
+        params![block_bound(through)],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?)
+}
+
+/// Supply events with `from_block <= block_number <= to_block`, newest first.
+pub fn list_supply_events(conn: &Connection, from_block: u64, to_block: u64, limit: u32) -> Result<Vec<SupplyEvent>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, log_index, tx_hash, kind, account, value, ts_unix, minted_total, burned_total FROM supply_events
+         WHERE block_number >= ?1 AND block_number <= ?2 ORDER BY block_number DESC, log_index DESC LIMIT ?3"
+    )?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block), limit], |row| {
+        Ok(SupplyEvent {
+            block_number: row.get::<_, i64>(0)? as u64,
+            log_index: row.get::<_, i64>(1)? as u64,
+            tx_hash: row.get(2)?,
+            kind: if row.get::<_, String>(3)? == "mint" { SupplyEventKind::Mint } else { SupplyEventKind::Burn },
+            account: row.get(4)?,
+            value: row.get(5)?,
+            ts_unix: row.get(6)?,
+            minted_total: row.get(7)?,
+            burned_total: row.get(8)?,
+        })
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+pub fn insert_nft_transfer(conn: &Connection, t: &NftTransfer) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO nft_transfers
+         (block_number, tx_hash, log_index, batch_index, contract, standard, token_id, amount, sender, recipient, direction, ts_unix)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            block_value(t.block_number)?, t.tx_hash, t.log_index as i64, t.batch_index as i64, t.contract, t.standard.as_str(),
+            t.token_id, t.amount, t.from, t.to, t.direction.as_str(), t.ts_unix,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Stored NFT transfers in `[from_block, to_block]`, newest first, optionally
+/// of one collection (lowercase).
+pub fn list_nft_transfers(conn: &Connection, contract: Option<&str>, from_block: u64, to_block: u64, limit: u32) -> Result<Vec<NftTransfer>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, tx_hash, log_index, batch_index, contract, standard, token_id, amount, sender, recipient, direction, ts_unix
+         FROM nft_transfers WHERE (?1 IS NULL OR contract = ?1) AND block_number >= ?2 AND block_number <= ?3
+         ORDER BY block_number DESC, log_index DESC, batch_index DESC LIMIT ?4"
+    )?;
+    let rows = stmt.query_map(params![contract, block_bound(from_block), block_bound(to_block), limit], |row| {
+        let standard: String = row.get(5)?;
+        Ok(NftTransfer {
+            block_number: row.get::<_, i64>(0)? as u64,
+            tx_hash: row.get(1)?,
+            log_index: row.get::<_, i64>(2)? as u64,
+            batch_index: row.get::<_, i64>(3)? as u64,
+            contract: row.get(4)?,
+            standard: NftStandard::parse(&standard).ok_or_else(|| {
+                rusqlite::Error::FromSqlConversionFailure(5, rusqlite::types::Type::Text, format!("invalid standard {standard:?}").into())
+            })?,
+            token_id: row.get(6)?,
+            amount: row.get(7)?,
+            from: row.get(8)?,
+            to: row.get(9)?,
+            direction: direction_column(row, 10)?,
+            ts_unix: row.get(11)?,
+        })
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// `(standard, received, sent)` by `(contract, token id)`.
+pub type NftFlows = BTreeMap<(String, U256), (NftStandard, U256, U256)>;
+
+/// Amount of each token received and sent by the tracked addresses in the
+/// stored NFT transfers through block `through`, keyed by `(contract,
+/// token id)` in token id order; transfers between tracked addresses move
+/// nothing.
+pub fn nft_flows(conn: &Connection, contract: Option<&str>, through: u64) -> Result<NftFlows> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT contract, standard, token_id, amount, direction FROM nft_transfers
+         WHERE (?1 IS NULL OR contract = ?1) AND block_number <= ?2 AND direction IN ('in', 'out')"
+    )?;
+    let mut rows = stmt.query(params![contract, block_bound(through)])?;
+    let mut out = BTreeMap::new();
+    while let Some(row) = rows.next()? {
+        let number = |i: usize| -> Result<U256> {
+            U256::from_dec_str(&row.get::<_, String>(i)?).map_err(|e| eyre!("stored NFT transfer: {e}"))
+        };
+        let standard: String = row.get(1)?;
+        let standard = NftStandard::parse(&standard).ok_or_else(|| eyre!("stored NFT transfer: invalid standard {standard:?}"))?;
+        let (amount, inbound) = (number(3)?, row.get::<_, String>(4)? == "in");
+        let entry = out.entry((row.get(0)?, number(2)?)).or_insert((standard, U256::zero(), U256::zero()));
+        if inbound {
+            entry.1 = entry.1.saturating_add(amount);
+        } else {
+            entry.2 = entry.2.saturating_add(amount);
+        }
+    }
+    Ok(out)
+}
+
+const NETFLOW_BLOCK_COLUMNS: &str =
+    "block_number, ts_unix, inflow, outflow, cumulative, transfer_count, cumulative_token, price_usd_micro, cumulative_usd_micro";
+
+fn netflow_block_from_row(row: &rusqlite::Row) -> rusqlite::Result<NetflowBlock> {
+    let cumulative: String = row.get(4)?;
+    Ok(NetflowBlock{
+        block_number: row.get::<_, i64>(0)? as u64,
+        ts_unix: row.get(1)?,
+        inflow: row.get(2)?,
+        outflow: row.get(3)?,
+        cumulative_token: row.get::<_, Option<String>>(6)?.unwrap_or_else(|| ValueFormat::Scaled.render(&cumulative)),
+        cumulative,
+        transfer_count: row.get::<_, i64>(5)? as u64,
+        price_usd_micro: row.get(7)?,
+        cumulative_usd_micro: row.get(8)?,
+    })
+}
+
+/// Most recently recorded implementation for `token`, with the block it was first seen at.
+pub fn get_token_implementation(conn: &Connection, token: &str) -> Result<Option<(u64, String)>> {
+    let row = conn.query_row(
+        "SELECT from_block, implementation FROM token_implementations WHERE token=? ORDER BY from_block DESC LIMIT 1",
+        params![token.to_ascii_lowercase()],
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+    ).optional()?;
+    Ok(row)
+}
+
+pub fn insert_token_implementation(conn: &Connection, token: &str, from_block: u64, implementation: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO token_implementations (token, from_block, implementation, observed_at_unix) VALUES (?1, ?2, ?3, ?4)",
+        params![token.to_ascii_lowercase(), block_value(from_block)?, implementation.to_ascii_lowercase(), OffsetDateTime::now_utc().unix_timestamp()],
+    )?;
+    Ok(())
+}
+
+pub fn insert_api_key(
+    conn: &Connection, name: &str, key_hash: &str, exchanges: &[String], tokens: &[String], default_format: Option<ValueFormat>,
+) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO api_keys (name, key_hash, exchanges, tokens, created_at_unix, default_format) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![name, key_hash, exchanges.join(","), tokens.join(","), OffsetDateTime::now_utc().unix_timestamp(), default_format.map(ValueFormat::as_str)],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Set (or with `None` clear) an active key's default `?format=`; false if no active key has this id.
+pub fn set_api_key_format(conn: &Connection, id: i64, default_format: Option<ValueFormat>) -> Result<bool> {
+    let n = conn.execute(
+        "UPDATE api_keys SET default_format=?2 WHERE id=?1 AND revoked_at_unix IS NULL",
+        params![id, default_format.map(ValueFormat::as_str)],
+    )?;
+    Ok(n == 1)
+}
+
+pub fn list_api_keys(conn: &Connection) -> Result<Vec<ApiKey>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, name, exchanges, tokens, created_at_unix, revoked_at_unix, default_format FROM api_keys ORDER BY id"
+    )?;
+    let rows = stmt.query_map([], api_key_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Active (non-revoked) key by hash.
+pub fn find_api_key(conn: &Connection, key_hash: &str) -> Result<Option<ApiKey>> {
+    let key = conn.query_row(
+        "SELECT id, name, exchanges, tokens, created_at_unix, revoked_at_unix, default_format FROM api_keys
+         WHERE key_hash=? AND revoked_at_unix IS NULL",
+        params![key_hash],
+        api_key_from_row,
+    ).optional()?;
+    Ok(key)
+}
+
+/// Returns false if no active key has this id.
+pub fn revoke_api_key(conn: &Connection, id: i64) -> Result<bool> {
+    let n = conn.execute(
+        "UPDATE api_keys SET revoked_at_unix=? WHERE id=? AND revoked_at_unix IS NULL",
+        params![OffsetDateTime::now_utc().unix_timestamp(), id],
+    )?;
+    Ok(n == 1)
+}
+
+fn api_key_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApiKey> {
+    let split = |s: String| s.split(',').map(str::to_string).collect();
+    Ok(ApiKey{
+        id: row.get(0)?,
+        name: row.get(1)?,
+        exchanges: split(row.get(2)?),
+        tokens: split(row.get(3)?),
+        created_at_unix: row.get(4)?,
+        revoked_at_unix: row.get(5)?,
+        default_format: row.get::<_, Option<String>>(6)?.as_deref().and_then(ValueFormat::parse),
+    })
+}
+
+/// Whether the transfer is stored from block `block_hash`, or with `None`
+/// from any block (an annotation id names the log, not the block).
+pub fn transfer_exists(conn: &Connection, tx_hash: &str, log_index: u64, block_hash: Option<&str>) -> Result<bool> {
+    let found = conn.query_row(
+        "SELECT 1 FROM erc20_transfers WHERE tx_hash=?1 AND log_index=?2 AND (?3 IS NULL OR block_hash=?3)",
+        params![tx_hash, log_index as i64, block_hash],
+        |_| Ok(()),
+    ).optional()?;
+    Ok(found.is_some())
+}
+
+/// Re-attribute the transfer stored from block `block_hash` after the tracked
+/// address set changed; false if its direction was already right.
+pub fn set_transfer_direction(conn: &Connection, tx_hash: &str, log_index: u64, block_hash: &str, direction: Direction) -> Result<bool> {
+    let n = conn.execute(
+        "UPDATE erc20_transfers SET direction=?4 WHERE tx_hash=?1 AND log_index=?2 AND block_hash=?3 AND direction!=?4",
+        params![tx_hash, log_index as i64, block_hash, direction.as_str()],
+    )?;
+    Ok(n > 0)
+}
+
+/// Set (or with `None` clear) the accounting-rule bucket of the transfer
+/// stored from block `block_hash`; false if it already had that bucket.
+pub fn set_transfer_bucket(conn: &Connection, tx_hash: &str, log_index: u64, block_hash: &str, bucket: Option<&str>) -> Result<bool> {
+    let n = conn.execute(
+        "UPDATE erc20_transfers SET bucket=?4 WHERE tx_hash=?1 AND log_index=?2 AND block_hash=?3 AND bucket IS NOT ?4",
+        params![tx_hash, log_index as i64, block_hash, bucket],
+    )?;
+    Ok(n > 0)
+}
+
+/// `(bucket, value, block_number)` of the bucketed transfers through block
+/// `through`, reverted ones excluded, by bucket.
+pub fn bucketed_transfers(conn: &Connection, through: u64) -> Result<Vec<(String, String, u64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT bucket, value, block_number FROM erc20_transfers
+         WHERE bucket IS NOT NULL AND block_number <= ?1 AND (status IS NULL OR status != 0)
+         ORDER BY bucket, block_number"
+    )?;
+    let rows = stmt.query_map(params![block_bound(through)], |r| Ok((r.get(0)?, r.get(1)?, r.get::<_, i64>(2)? as u64)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Token price stored with block `block`'s aggregates, micro-USD.
+pub fn block_price(conn: &Connection, block: u64) -> Result<Option<i64>> {
+    let mut stmt = conn.prepare_cached("SELECT price_usd_micro FROM netflow_blocks WHERE block_number = ?1")?;
+    Ok(stmt.query_row(params![block_bound(block)], |r| r.get(0)).optional()?.flatten())
+}
+
+/// Whether `address` (lowercase) is already in `contracts`, and if so its
+/// `(kind, label)`.
+pub fn get_contract(conn: &Connection, address: &str) -> Result<Option<(CounterpartyKind, Option<String>)>> {
+    let mut stmt = conn.prepare_cached("SELECT code_hash IS NOT NULL, label FROM contracts WHERE address = ?1")?;
+    let row = stmt.query_row(params![address], |r| {
+        let kind = if r.get::<_, bool>(0)? { CounterpartyKind::Contract } else { CounterpartyKind::Eoa };
+        Ok((kind, r.get(1)?))
+    }).optional()?;
+    Ok(row)
+}
+
+pub fn insert_contract(conn: &Connection, address: &str, code_hash: Option<&str>, label: Option<&str>, first_seen_block: u64) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO contracts (address, code_hash, label, first_seen_block) VALUES (?1, ?2, ?3, ?4)",
+        params![address, code_hash, label, first_seen_block as i64],
+    )?;
+    Ok(())
+}
+
+pub fn has_balance_baseline(conn: &Connection, address: &str) -> Result<bool> {
+    Ok(conn.query_row("SELECT 1 FROM balance_baselines WHERE address = ?1", params![address], |_| Ok(())).optional()?.is_some())
+}
+
+pub fn insert_balance_baseline(conn: &Connection, b: &BalanceBaseline) -> Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO balance_baselines (address, block_number, balance, recorded_at_unix) VALUES (?1, ?2, ?3, ?4)",
+        params![b.address, block_bound(b.block_number), b.balance, b.recorded_at_unix],
+    )?;
+    Ok(())
+}
+
+pub fn list_balance_baselines(conn: &Connection) -> Result<Vec<BalanceBaseline>> {
+    let mut stmt = conn.prepare("SELECT address, block_number, balance, recorded_at_unix FROM balance_baselines ORDER BY address")?;
+    let rows = stmt.query_map([], |r| Ok(BalanceBaseline {
+        address: r.get(0)?,
+        block_number: r.get::<_, i64>(1)? as u64,
+        balance: r.get(2)?,
+        recorded_at_unix: r.get(3)?,
+    }))?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Token received and sent by `address` (lowercase) in the stored transfers
+/// with `after_block < block_number <= through`, whatever their direction.
+/// Reverted transfers moved nothing and are left out.
+pub fn address_flows(conn: &Connection, address: &str, after_block: u64, through: u64) -> Result<(U256, U256)> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT recipient = ?1, sender = ?1, value FROM erc20_transfers
+         WHERE (sender = ?1 OR recipient = ?1) AND block_number > ?2 AND block_number <= ?3 AND status IS NOT 0"
+    )?;
+    let mut rows = stmt.query(params![address, block_bound(after_block), block_bound(through)])?;
+    let (mut received, mut sent) = (U256::zero(), U256::zero());
+    while let Some(row) = rows.next()? {
+        let value = U256::from_dec_str(&row.get::<_, String>(2)?).map_err(|e| eyre!("stored transfer value: {e}"))?;
+        if row.get(0)? {
+            received = received.saturating_add(value);
+        }
+        if row.get(1)? {
+            sent = sent.saturating_add(value);
+        }
+    }
+    Ok((received, sent))
+}
+
+/// Counted flows per tracked address through block `through`: the recipient
+/// of each inflow and the sender of each outflow, over the canonical
+/// non-reverted transfers `aggregates::rebuild` counts. By address (lowercase):
+/// `(inflow, outflow, transfer_count)`.
+pub fn address_netflows(conn: &Connection, through: u64) -> Result<BTreeMap<String, (U256, U256, u64)>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT CASE t.direction WHEN 'in' THEN t.recipient ELSE t.sender END, t.direction = 'in', t.value
+         FROM erc20_transfers t LEFT JOIN blocks b ON b.block_number = t.block_number
+         WHERE t.direction IN ('in', 'out') AND t.block_number <= ?1 AND t.status IS NOT 0
+           AND (t.block_hash = '' OR COALESCE(b.block_hash, '') IN ('', t.block_hash))"
+    )?;
+    let mut rows = stmt.query(params![block_bound(through)])?;
+    let mut flows: BTreeMap<String, (U256, U256, u64)> = BTreeMap::new();
+    while let Some(row) = rows.next()? {
+        let value = U256::from_dec_str(&row.get::<_, String>(2)?).map_err(|e| eyre!("stored transfer value: {e}"))?;
+        let entry = flows.entry(row.get(0)?).or_default();
+        if row.get(1)? {
+            entry.0 = entry.0.saturating_add(value);
+        } else {
+            entry.1 = entry.1.saturating_add(value);
+        }
+        entry.2 += 1;
+    }
+    Ok(flows)
+}
+
+/// Label every cached contract whose code hash (lowercase) is `code_hash`;
+/// returns how many changed.
+pub fn label_contracts(conn: &Connection, code_hash: &str, label: &str) -> Result<usize> {
+    Ok(conn.execute(
+        "UPDATE contracts SET label = ?2 WHERE code_hash = ?1 AND label IS NOT ?2",
+        params![code_hash, label],
+    )?)
+}
+
+/// `(tx_hash, log_index, block_hash)` of the stored transfers in
+/// `[from_block, to_block]`: the keys of every stored copy.
+pub fn transfer_keys_by_range(conn: &Connection, from_block: u64, to_block: u64) -> Result<Vec<(String, u64, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT tx_hash, log_index, block_hash FROM erc20_transfers WHERE block_number >= ?1 AND block_number <= ?2"
+    )?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block)], |r| {
+        Ok((r.get(0)?, r.get::<_, i64>(1)? as u64, r.get(2)?))
+    })?.collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Drop the transfer stored from block `block_hash`, once it no longer
+/// involves a tracked address.
+pub fn delete_transfer(conn: &Connection, tx_hash: &str, log_index: u64, block_hash: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM erc20_transfers WHERE tx_hash=?1 AND log_index=?2 AND block_hash=?3",
+        params![tx_hash, log_index as i64, block_hash],
+    )?;
+    Ok(())
+}
+
+pub fn insert_annotation(conn: &Connection, tx_hash: &str, log_index: u64, note: &str, tags: &[String]) -> Result<Annotation> {
+    let created_at_unix = OffsetDateTime::now_utc().unix_timestamp();
+    conn.execute(
+        "INSERT INTO annotations (tx_hash, log_index, note, tags, created_at_unix) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![tx_hash, log_index as i64, note, tags.join(","), created_at_unix],
+    )?;
+    Ok(Annotation {
+        id: conn.last_insert_rowid(),
+        tx_hash: tx_hash.to_string(),
+        log_index,
+        note: note.to_string(),
+        tags: tags.to_vec(),
+        created_at_unix,
+    })
+}
+
+const ANNOTATION_COLUMNS: &str = "a.id, a.tx_hash, a.log_index, a.note, a.tags, a.created_at_unix";
+
+fn annotation_from_row(row: &rusqlite::Row) -> rusqlite::Result<Annotation> {
+    let tags: String = row.get(4)?;
+    Ok(Annotation {
+        id: row.get(0)?,
+        tx_hash: row.get(1)?,
+        log_index: row.get::<_, i64>(2)? as u64,
+        note: row.get(3)?,
+        tags: tags.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect(),
+        created_at_unix: row.get(5)?,
+    })
+}
+
+/// Annotations of one transfer, oldest first.
+pub fn annotations_for_transfer(conn: &Connection, tx_hash: &str, log_index: u64) -> Result<Vec<Annotation>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ANNOTATION_COLUMNS} FROM annotations a WHERE a.tx_hash=?1 AND a.log_index=?2 ORDER BY a.id"
+    ))?;
+    let rows = stmt.query_map(params![tx_hash, log_index as i64], annotation_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Annotations of stored transfers in `[from_block, to_block]`, oldest first.
+pub fn annotations_by_range(conn: &Connection, from_block: u64, to_block: u64) -> Result<Vec<Annotation>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ANNOTATION_COLUMNS} FROM annotations a
+         JOIN erc20_transfers t ON t.tx_hash = a.tx_hash AND t.log_index = a.log_index
+         WHERE t.block_number >= ?1 AND t.block_number <= ?2 ORDER BY a.id"
+    ))?;
+    let rows = stmt.query_map(params![block_bound(from_block), block_bound(to_block)], annotation_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Annotations of stored transfers whose block time is in `[from_ts, to_ts]`,
+/// with that time, oldest block first.
+pub fn annotations_by_time(conn: &Connection, from_ts: i64, to_ts: i64) -> Result<Vec<(i64, Annotation)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {ANNOTATION_COLUMNS}, b.ts_unix FROM annotations a
+         JOIN erc20_transfers t ON t.tx_hash = a.tx_hash AND t.log_index = a.log_index
+         JOIN blocks b ON b.block_number = t.block_number
+         WHERE b.ts_unix >= ?1 AND b.ts_unix <= ?2 ORDER BY b.ts_unix, a.id"
+    ))?;
+    let rows = stmt.query_map(params![from_ts, to_ts], |row| Ok((row.get(6)?, annotation_from_row(row)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+const QUEUED_ALERT_COLUMNS: &str = "id, sink, alert, attempts, next_attempt_at_unix, last_error, created_at_unix, dead_at_unix";
+
+fn queued_alert_from_row(row: &rusqlite::Row) -> rusqlite::Result<QueuedAlert> {
+    let alert: String = row.get(2)?;
+    Ok(QueuedAlert {
+        id: row.get(0)?,
+        sink: row.get(1)?,
+        alert: serde_json::from_str(&alert).unwrap_or(serde_json::Value::String(alert)),
+        attempts: row.get(3)?,
+        next_attempt_at_unix: row.get(4)?,
+        last_error: row.get(5)?,
+        created_at_unix: row.get(6)?,
+        dead_at_unix: row.get(7)?,
+    })
+}
+
+/// Queue `alert` (JSON) for `sink`, due now.
+pub fn enqueue_alert(conn: &Connection, sink: &str, alert: &str, now: i64) -> Result<()> {
+    conn.execute(
+        "INSERT INTO alert_queue (sink, alert, next_attempt_at_unix, created_at_unix) VALUES (?1, ?2, ?3, ?3)",
+        params![sink, alert, now],
+    )?;
+    Ok(())
+}
+
+/// Pending deliveries due at `now`, oldest first.
+pub fn due_alerts(conn: &Connection, now: i64, limit: u32) -> Result<Vec<QueuedAlert>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {QUEUED_ALERT_COLUMNS} FROM alert_queue
+         WHERE dead_at_unix IS NULL AND next_attempt_at_unix <= ?1 ORDER BY id LIMIT ?2"
+    ))?;
+    let rows = stmt.query_map(params![now, limit], queued_alert_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// When the next pending delivery is due.
+pub fn next_alert_due(conn: &Connection) -> Result<Option<i64>> {
+    Ok(conn.query_row(
+        "SELECT MIN(next_attempt_at_unix) FROM alert_queue WHERE dead_at_unix IS NULL",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+pub fn delete_queued_alert(conn: &Connection, id: i64) -> Result<()> {
+    conn.execute("DELETE FROM alert_queue WHERE id=?", params![id])?;
+    Ok(())
+}
+
+/// Count a failed attempt; retry at `next_attempt` or, when `None`, dead-letter it.
+pub fn fail_queued_alert(conn: &Connection, id: i64, error: &str, next_attempt: Option<i64>, now: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE alert_queue SET attempts=attempts+1, last_error=?2,
+           next_attempt_at_unix=COALESCE(?3, next_attempt_at_unix),
+           dead_at_unix=CASE WHEN ?3 IS NULL THEN ?4 END
+         WHERE id=?1",
+        params![id, error, next_attempt, now],
+    )?;
+    Ok(())
+}
+
+/// `(pending, dead)` row counts.
+pub fn alert_queue_counts(conn: &Connection) -> Result<(u64, u64)> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*) FILTER (WHERE dead_at_unix IS NULL), COUNT(*) FILTER (WHERE dead_at_unix IS NOT NULL) FROM alert_queue",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?)
+}
+
+/// Dead letters, newest first.
+pub fn dead_alerts(conn: &Connection, limit: u32) -> Result<Vec<QueuedAlert>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {QUEUED_ALERT_COLUMNS} FROM alert_queue WHERE dead_at_unix IS NOT NULL ORDER BY id DESC LIMIT ?1"
+    ))?;
+    let rows = stmt.query_map(params![limit], queued_alert_from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows)
+}
+
+/// Put a dead letter back in the queue with a fresh set of attempts; false if
+/// `id` isn't a dead letter.
+pub fn requeue_alert(conn: &Connection, id: i64, now: i64) -> Result<bool> {
+    let n = conn.execute(
+        "UPDATE alert_queue SET dead_at_unix=NULL, attempts=0, next_attempt_at_unix=?2 WHERE id=?1 AND dead_at_unix IS NOT NULL",
+        params![id, now],
+    )?;
+    Ok(n > 0)
+}
+
+/// A database file of its own for a test, removed (with its WAL) on drop.
+#[cfg(test)]
+pub struct Scratch(pub std::path::PathBuf);
+
+#[cfg(test)]
+impl Scratch {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("pol-indexer-{name}-{}.sqlite", std::process::id()));
+        let scratch = Scratch(path);
+        scratch.remove();
+        scratch
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().expect("temp dir is UTF-8")
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", self.path()));
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// One `raw_logs` row, decompressed.
+pub struct ArchivedBlock {
+    pub number: u64,
+    pub block_hash: String,
+    pub ts_unix: i64,
+    pub logs: Vec<RawLog>,
+}
+
+/// Archive a block's token logs (all of them, not only tracked transfers).
+pub fn insert_raw_logs(conn: &Connection, number: u64, block_hash: &str, ts_unix: i64, logs: &[RawLog]) -> Result<()> {
+    let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+    serde_json::to_writer(&mut gz, logs)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO raw_logs (block_number, block_hash, ts_unix, log_count, logs) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![block_value(number)?, block_hash, ts_unix, logs.len() as i64, gz.finish()?],
+    )?;
+    Ok(())
+}
+
+/// Archived blocks in `[from, to]`, in order.
+pub fn archived_logs(conn: &Connection, from: u64, to: u64) -> Result<Vec<ArchivedBlock>> {
+    let mut stmt = conn.prepare(
+        "SELECT block_number, block_hash, ts_unix, logs FROM raw_logs WHERE block_number BETWEEN ?1 AND ?2 ORDER BY block_number"
+    )?;
+    let mut rows = stmt.query(params![block_bound(from), block_bound(to)])?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        let number = row.get::<_, i64>(0)? as u64;
+        let blob: Vec<u8> = row.get(3)?;
+        let logs = serde_json::from_reader(GzDecoder::new(blob.as_slice()))
+            .map_err(|e| eyre!("raw_logs for block {number}: {e}"))?;
+        out.push(ArchivedBlock { number, block_hash: row.get(1)?, ts_unix: row.get(2)?, logs });
+    }
+    Ok(out)
+}
+
+/// Make `addresses` (lowercase) the members of `entity`, replacing its
+/// previous ones. An address in another entity moves to this one.
+pub fn set_entity(conn: &Connection, entity: &str, addresses: &[String]) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM entities WHERE entity = ?1", params![entity])?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    for address in addresses {
+        tx.execute(
+            "INSERT OR REPLACE INTO entities (address, entity, added_at_unix) VALUES (?1, ?2, ?3)",
+            params![address, entity, now],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Returns false if there was no such entity.
+pub fn delete_entity(conn: &Connection, entity: &str) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM entities WHERE entity = ?1", params![entity])? > 0)
+}
+
+/// Entity → member addresses (lowercase), both sorted.
+pub fn list_entities(conn: &Connection) -> Result<std::collections::BTreeMap<String, Vec<String>>> {
+    let mut stmt = conn.prepare("SELECT entity, address FROM entities ORDER BY entity, address")?;
+    let mut out: std::collections::BTreeMap<String, Vec<String>> = std::collections::BTreeMap::new();
+    for row in stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))? {
+        let (entity, address) = row?;
+        out.entry(entity).or_default().push(address);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `blocks`, `erc20_transfers` and `cumulative_netflow` as of schema
+    /// version 17, before `chain_id`.
+    const V17_CHAIN_TABLES: &str = "
+        CREATE TABLE state (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+        CREATE TABLE blocks (block_number INTEGER PRIMARY KEY, block_hash TEXT NOT NULL, ts_unix INTEGER NOT NULL);
+        CREATE TABLE erc20_transfers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, block_number INTEGER NOT NULL, tx_hash TEXT NOT NULL,
+            log_index INTEGER NOT NULL, token TEXT NOT NULL, sender TEXT NOT NULL, recipient TEXT NOT NULL,
+            value TEXT NOT NULL, direction TEXT NOT NULL, status INTEGER, block_hash TEXT NOT NULL DEFAULT '',
+            UNIQUE(tx_hash, log_index, block_hash)
+        );
+        CREATE TABLE cumulative_netflow (
+            id INTEGER PRIMARY KEY CHECK (id = 1), block_number INTEGER NOT NULL, value TEXT NOT NULL,
+            updated_at_unix INTEGER NOT NULL, value_token TEXT, value_usd_micro INTEGER
+        );
+    ";
+
+    #[test]
+    fn v18_rebuild_fills_in_the_recorded_chain_id() {
+        let scratch = Scratch::new("v18-chain-id");
+        {
+            let conn = Connection::open(scratch.path()).unwrap();
+            conn.execute_batch(V17_CHAIN_TABLES).unwrap();
+            conn.execute_batch("
+                INSERT INTO state VALUES ('schema_version', '17'), ('chain_id', '137');
+                INSERT INTO blocks VALUES (10, '0xb10', 1700000000), (11, '0xb11', 1700000002);
+                INSERT INTO erc20_transfers (block_number, tx_hash, log_index, token, sender, recipient, value, direction, status, block_hash)
+                VALUES (10, '0x01', 0, '0xt', '0xa', '0xhot', '5', 'in', 1, '0xb10'),
+                       (11, '0x02', 0, '0xt', '0xhot', '0xb', '3', 'out', NULL, '0xb11');
+                INSERT INTO cumulative_netflow (id, block_number, value, updated_at_unix) VALUES (1, 11, '2', 1700000002);
+            ").unwrap();
+        }
+
+        let conn = init(scratch.path()).unwrap();
+        for (table, rows) in CHAIN_TABLES.into_iter().zip([2, 2, 1]) {
+            let chains: Vec<i64> = conn.prepare(&format!("SELECT chain_id FROM {table}")).unwrap()
+                .query_map([], |r| r.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+            assert_eq!(chains, vec![137; rows], "{table}");
+        }
+        // Still the row the cumulative is read from, not a fresh zero one
+        let latest = get_latest_cumulative(&conn).unwrap();
+        assert_eq!((latest.block_number, latest.cumulative_netflow_raw.as_str()), (11, "2"));
+        assert_eq!(get_block_hash(&conn, 11).unwrap().as_deref(), Some("0xb11"));
+        assert_eq!(stored_schema_version(&conn).unwrap(), Some(SCHEMA_VERSION));
+        assert!(pending_migrations(&conn).unwrap().is_empty());
+    }
+
+    /// The transfer tables as of schema version 16, with the flags `direction` replaced.
+    const V16_TRANSFER_TABLES: &str = "
+        CREATE TABLE state (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+        CREATE TABLE erc20_transfers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, block_number INTEGER NOT NULL, tx_hash TEXT NOT NULL,
+            log_index INTEGER NOT NULL, token TEXT NOT NULL, sender TEXT NOT NULL, recipient TEXT NOT NULL,
+            value TEXT NOT NULL, is_binance_in BOOLEAN NOT NULL, is_binance_out BOOLEAN NOT NULL, status INTEGER,
+            block_hash TEXT NOT NULL DEFAULT '', UNIQUE(tx_hash, log_index, block_hash)
+        );
+        CREATE TABLE native_transfers (
+            id INTEGER PRIMARY KEY AUTOINCREMENT, block_number INTEGER NOT NULL, tx_hash TEXT NOT NULL UNIQUE,
+            sender TEXT NOT NULL, recipient TEXT NOT NULL, value TEXT NOT NULL,
+            is_binance_in INTEGER NOT NULL, is_binance_out INTEGER NOT NULL, status INTEGER
+        );
+    ";
+
+    #[test]
+    fn v17_rebuild_turns_the_flags_into_a_direction() {
+        let scratch = Scratch::new("v17-direction");
+        // (is_binance_in, is_binance_out) → direction
+        let flags = [(1, 1, "internal"), (1, 0, "in"), (0, 1, "out"), (0, 0, "none")];
+        {
+            let conn = Connection::open(scratch.path()).unwrap();
+            conn.execute_batch(V16_TRANSFER_TABLES).unwrap();
+            conn.execute("INSERT INTO state VALUES ('schema_version', '16')", []).unwrap();
+            for (i, (is_in, is_out, _)) in flags.iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO erc20_transfers (block_number, tx_hash, log_index, token, sender, recipient, value, is_binance_in, is_binance_out)
+                     VALUES (10, ?1, 0, '0xt', '0xa', '0xb', '1', ?2, ?3)",
+                    params![hash(i as u64), is_in, is_out],
+                ).unwrap();
+                conn.execute(
+                    "INSERT INTO native_transfers (block_number, tx_hash, sender, recipient, value, is_binance_in, is_binance_out)
+                     VALUES (10, ?1, '0xa', '0xb', '1', ?2, ?3)",
+                    params![hash(i as u64), is_in, is_out],
+                ).unwrap();
+            }
+        }
+
+        let conn = init(scratch.path()).unwrap();
+        let expected: Vec<(String, String)> = flags.iter().enumerate().map(|(i, (_, _, d))| (hash(i as u64), d.to_string())).collect();
+        for table in ["erc20_transfers", "native_transfers"] {
+            let rows: Vec<(String, String)> = conn.prepare(&format!("SELECT tx_hash, direction FROM {table} ORDER BY tx_hash")).unwrap()
+                .query_map([], |r| Ok((r.get(0)?, r.get(1)?))).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+            assert_eq!(rows, expected, "{table}: every row kept, with its direction");
+            assert!(!has_column(&conn, table, "is_binance_in").unwrap() && !has_column(&conn, table, "is_binance_out").unwrap());
+        }
+        assert!(pending_migrations(&conn).unwrap().is_empty());
+    }
+
+    fn hash(n: u64) -> String {
+        format!("0x{n:064x}")
+    }
+
+    fn copies(conn: &Connection, tx_hash: &str) -> Vec<(String, String)> {
+        conn.prepare("SELECT block_hash, direction FROM erc20_transfers WHERE tx_hash = ?1 ORDER BY block_hash").unwrap()
+            .query_map([tx_hash], |r| Ok((r.get(0)?, r.get(1)?))).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn reincluded_transfer_is_kept_per_block_hash() {
+        let scratch = Scratch::new("reincluded");
+        let conn = init(scratch.path()).unwrap();
+        let (tx, orphaned, canonical) = (hash(1), hash(0xa), hash(0xb));
+        let insert = |block, block_hash: &str| {
+            insert_transfer(&conn, block, block_hash, &tx, 0, "0xt", "0xa", "0xhot", "5", Direction::In, None).unwrap()
+        };
+        assert!(insert(10, &orphaned));
+        assert!(insert(11, &canonical), "the re-included copy is a new row");
+        assert!(!insert(11, &canonical));
+        assert!(!insert(10, &orphaned));
+
+        assert!(transfer_exists(&conn, &tx, 0, Some(&canonical)).unwrap());
+        assert!(!transfer_exists(&conn, &tx, 0, Some(&hash(0xc))).unwrap());
+        assert!(set_transfer_direction(&conn, &tx, 0, &canonical, Direction::Out).unwrap());
+        assert!(set_transfer_bucket(&conn, &tx, 0, &canonical, Some("otc")).unwrap());
+        assert_eq!(copies(&conn, &tx), [(orphaned.clone(), "in".to_string()), (canonical.clone(), "out".to_string())]);
+
+        delete_transfer(&conn, &tx, 0, &orphaned).unwrap();
+        assert_eq!(copies(&conn, &tx), [(canonical, "out".to_string())]);
+    }
+
+    #[test]
+    fn unknown_block_hash_matches_only_at_the_same_height() {
+        let scratch = Scratch::new("unknown-hash");
+        let conn = init(scratch.path()).unwrap();
+        let tx = hash(1);
+        let insert = |block, block_hash: &str| {
+            insert_transfer(&conn, block, block_hash, &tx, 0, "0xt", "0xa", "0xhot", "5", Direction::In, None).unwrap()
+        };
+        assert!(insert(10, ""));
+        // Re-included at another height, the log doesn't hide behind the import
+        assert!(insert(12, &hash(0xc)));
+        // Read from the chain at its height, the imported row is the same log and takes the hash
+        assert!(!insert(10, &hash(0xa)));
+        assert!(!insert(10, ""));
+        assert_eq!(copies(&conn, &tx), [(hash(0xa), "in".to_string()), (hash(0xc), "in".to_string())]);
+    }
+}
@@ -0,0 +1,171 @@
+//! Cross-check of the RPC provider against an independent reference endpoint
+//! (`REFERENCE_RPC_URL`), for providers that go stale or follow a fork while
+//! still answering requests.
+//!
+//! Every `REFERENCE_CHECK_SECS` both heads are read. A gap of more than
+//! `REFERENCE_MAX_DRIFT_BLOCKS` either way raises [`Alert::ProviderDrift`].
+//! The two blocks [`FORK_CHECK_DEPTH`] below the lower head are then compared
+//! by hash, and a mismatch raises [`Alert::ProviderForked`]. Each alerts once
+//! per episode and re-arms when the endpoints agree again.
+
+use std::time::Duration;
+
+use ethers::types::H256;
+use eyre::Result;
+use tracing::{debug, info, warn};
+
+use crate::{
+    alerts::{Alert, Alerter},
+    chain::{self, ChainClient},
+    metrics,
+};
+
+/// Blocks below the lower head compared by hash; nearer the tip an ordinary
+/// reorg can leave the two endpoints briefly on different blocks.
+pub const FORK_CHECK_DEPTH: u64 = 32;
+
+#[derive(Debug, Clone)]
+pub struct ReferenceConfig {
+    pub source: chain::Source,
+    /// Host of the endpoint, for logs and alerts (URLs often carry an API key)
+    pub label: String,
+    pub max_drift: u64,
+    pub every: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub provider_head: u64,
+    pub reference_head: u64,
+    pub fork: Option<Fork>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fork {
+    pub block_number: u64,
+    pub provider_hash: H256,
+    pub reference_hash: H256,
+}
+
+/// Compare `provider` against the reference every `config.every`, forever.
+/// The reference is reconnected after a failed comparison.
+pub async fn watch(provider: &dyn ChainClient, alerts: Option<&Alerter>, config: ReferenceConfig) {
+    info!(reference = %config.label, max_drift = config.max_drift, "Comparing the RPC provider with a reference endpoint");
+    let mut reference: Option<Box<dyn ChainClient>> = None;
+    let mut tracker = Tracker::default();
+    let mut tick = tokio::time::interval(config.every);
+    loop {
+        tick.tick().await;
+        let client = match &reference {
+            Some(client) => client,
+            None => match chain::connect(&config.source).await {
+                Ok(client) => reference.insert(client),
+                Err(e) => {
+                    warn!(reference = %config.label, error = %format!("{e:#}"), "Could not connect to the reference endpoint");
+                    continue;
+                }
+            },
+        };
+        match compare(provider, client.as_ref()).await {
+            Ok(comparison) => {
+                for alert in tracker.observe(&comparison, config.max_drift, &config.label) {
+                    if let Some(alerts) = alerts {
+                        alerts.send(alert);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!(reference = %config.label, error = %format!("{e:#}"), "Reference comparison failed");
+                reference = None;
+            }
+        }
+    }
+}
+
+/// Both heads, and the hashes at [`FORK_CHECK_DEPTH`] below the lower one.
+pub async fn compare(provider: &dyn ChainClient, reference: &dyn ChainClient) -> Result<Comparison> {
+    let (provider_head, reference_head) = tokio::try_join!(provider.block_number(), reference.block_number())?;
+    let at = provider_head.min(reference_head).saturating_sub(FORK_CHECK_DEPTH);
+    let (ours, theirs) = tokio::try_join!(provider.get_block(at), reference.get_block(at))?;
+    let fork = match (ours, theirs) {
+        (Some(ours), Some(theirs)) if ours.hash != theirs.hash => Some(Fork {
+            block_number: at,
+            provider_hash: ours.hash,
+            reference_hash: theirs.hash,
+        }),
+        _ => None,
+    };
+    Ok(Comparison { provider_head, reference_head, fork })
+}
+
+/// Which divergences are ongoing, so each alerts once.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    drifting: bool,
+    forked: bool,
+}
+
+impl Tracker {
+    /// Update the metrics and log; returns the alerts for divergences that just began.
+    pub fn observe(&mut self, c: &Comparison, max_drift: u64, reference: &str) -> Vec<Alert> {
+        let drift = c.provider_head as i64 - c.reference_head as i64;
+        metrics::REFERENCE_HEAD_DRIFT.set(drift);
+        metrics::REFERENCE_FORKED.set(i64::from(c.fork.is_some()));
+        debug!(provider_head = c.provider_head, reference_head = c.reference_head, drift, "Compared heads with the reference");
+
+        let mut alerts = Vec::new();
+        let drifting = drift.unsigned_abs() > max_drift;
+        if drifting && !self.drifting {
+            warn!(provider_head = c.provider_head, reference_head = c.reference_head, reference, "RPC provider drifted from the reference endpoint");
+            alerts.push(Alert::ProviderDrift {
+                provider_head: c.provider_head,
+                reference_head: c.reference_head,
+                reference: reference.to_string(),
+            });
+        } else if !drifting && self.drifting {
+            info!(provider_head = c.provider_head, reference_head = c.reference_head, reference, "RPC provider back in line with the reference endpoint");
+        }
+        self.drifting = drifting;
+
+        match (&c.fork, self.forked) {
+            (Some(fork), false) => {
+                warn!(block = fork.block_number, provider_hash = ?fork.provider_hash, reference_hash = ?fork.reference_hash, reference, "RPC provider and reference endpoint are on different forks");
+                alerts.push(Alert::ProviderForked {
+                    block_number: fork.block_number,
+                    provider_hash: fork.provider_hash,
+                    reference_hash: fork.reference_hash,
+                    reference: reference.to_string(),
+                });
+            }
+            (None, true) => info!(reference, "RPC provider and reference endpoint agree on block hashes again"),
+            _ => {}
+        }
+        self.forked = c.fork.is_some();
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_once_per_divergence() {
+        let mut tracker = Tracker::default();
+        let heads = |provider_head, reference_head| Comparison { provider_head, reference_head, fork: None };
+
+        assert!(tracker.observe(&heads(100, 110), 20, "ref").is_empty());
+        let alerts = tracker.observe(&heads(100, 121), 20, "ref");
+        assert!(matches!(alerts[..], [Alert::ProviderDrift { provider_head: 100, reference_head: 121, .. }]));
+        assert!(tracker.observe(&heads(100, 150), 20, "ref").is_empty(), "still the same episode");
+        assert!(tracker.observe(&heads(150, 151), 20, "ref").is_empty());
+        assert_eq!(tracker.observe(&heads(200, 150), 20, "ref").len(), 1, "ahead counts too, once re-armed");
+
+        let fork = Fork { block_number: 168, provider_hash: H256::repeat_byte(1), reference_hash: H256::repeat_byte(2) };
+        let forked = Comparison { fork: Some(fork), ..heads(200, 200) };
+        assert!(matches!(tracker.observe(&forked, 20, "ref")[..], [Alert::ProviderForked { block_number: 168, .. }]));
+        assert!(tracker.observe(&forked, 20, "ref").is_empty());
+        assert!(tracker.observe(&heads(201, 201), 20, "ref").is_empty());
+        assert_eq!(tracker.observe(&forked, 20, "ref").len(), 1);
+    }
+}
@@ -0,0 +1,309 @@
+//! Dual-write: mirror every SQLite change into Postgres for a trial period
+//! before cutting over, checking the two stores agree along the way.
+//!
+//! SQLite stays the store of record. Triggers on every table append the
+//! primary key of each inserted, updated or deleted row to `dual_write_log`,
+//! in the same transaction as the change, and a background task replays the
+//! log: the current SQLite row is upserted into Postgres, or deleted there if
+//! it is gone. A Postgres outage therefore only delays the mirror; nothing is
+//! lost. The first start copies the existing rows (as `migrate` does).
+//!
+//! Every `DUAL_WRITE_CHECK_SECS` the task compares row counts, the cumulative
+//! and the last indexed block at a moment when the log is drained. Once
+//! `DUAL_WRITE_FOR` has passed and a check finds the stores in parity, the
+//! triggers are dropped and Postgres can take over. Unsetting `DUAL_WRITE_URL`
+//! stops dual-writing early.
+//!
+//! The mirror needs the `postgres` feature; [`disable`] doesn't, so a build
+//! without it still removes the triggers a previous trial left behind.
+
+#[cfg(feature = "postgres")]
+use std::{collections::HashSet, time::{Duration, Instant}};
+
+use eyre::Result;
+use rusqlite::Connection;
+#[cfg(feature = "postgres")]
+use rusqlite::{params_from_iter, types::Value, OptionalExtension};
+#[cfg(feature = "postgres")]
+use time::OffsetDateTime;
+#[cfg(feature = "postgres")]
+use tokio_postgres::{types::ToSql, Client};
+use tracing::info;
+#[cfg(feature = "postgres")]
+use tracing::{error, warn};
+
+use crate::metrics;
+#[cfg(feature = "postgres")]
+use crate::{db, migrate::{self, Table}};
+
+pub const LOG_TABLE: &str = "dual_write_log";
+#[cfg(feature = "postgres")]
+const TRIGGER_PREFIX: &str = "dual_write_";
+const STARTED_KEY: &str = "dual_write_started_at";
+#[cfg(feature = "postgres")]
+const DRAIN_BATCH: usize = 500;
+#[cfg(feature = "postgres")]
+const POLL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "postgres")]
+pub struct DualWriteConfig {
+    pub url: String,
+    /// Seconds to keep mirroring before a passing check ends dual-writing
+    pub period_secs: i64,
+    pub check_every: Duration,
+}
+
+#[cfg(feature = "postgres")]
+/// Run the mirror on its own thread: SQLite connections can't be held across
+/// awaits on the shared runtime.
+pub fn spawn(db_path: String, config: DualWriteConfig) {
+    std::thread::Builder::new()
+        .name("dual-write".into())
+        .spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("dual-write runtime");
+            if let Err(e) = rt.block_on(run(&db_path, &config)) {
+                error!(?e, "Dual-write stopped; SQLite changes are still logged and will be mirrored after a restart");
+            }
+        })
+        .expect("spawn dual-write thread");
+}
+
+#[cfg(feature = "postgres")]
+async fn run(db_path: &str, config: &DualWriteConfig) -> Result<()> {
+    let conn = Connection::open(db_path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    let client = migrate::connect(&config.url).await?;
+
+    let tables = migrate::list_tables(&conn)?;
+    for table in &tables {
+        client.batch_execute(&migrate::create_table_sql(table)).await?;
+    }
+    if enable(&conn, &tables)? {
+        info!(tables = tables.len(), "Dual-write enabled; copying existing rows to Postgres");
+        for table in &tables {
+            migrate::copy_table(&conn, &client, table, &table.upsert_clause()).await?;
+        }
+    }
+    let started_at: i64 = match db::get_state(&conn, STARTED_KEY)?.and_then(|s| s.parse().ok()) {
+        Some(t) => t,
+        None => {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            db::set_state(&conn, STARTED_KEY, &now.to_string())?;
+            now
+        }
+    };
+    let ends_at = started_at + config.period_secs;
+    info!(ends_at, "Dual-writing to Postgres");
+
+    let mut last_check: Option<Instant> = None;
+    loop {
+        let drained = drain(&conn, &client, &tables).await?;
+        if drained < DRAIN_BATCH && last_check.is_none_or(|t| t.elapsed() >= config.check_every) {
+            if let Some(divergent) = check(&conn, &client, &tables).await? {
+                last_check = Some(Instant::now());
+                metrics::DUAL_WRITE_IN_PARITY.set(divergent.is_empty() as i64);
+                let period_over = OffsetDateTime::now_utc().unix_timestamp() >= ends_at;
+                match (divergent.is_empty(), period_over) {
+                    (true, true) => {
+                        disable(&conn)?;
+                        client.execute("DELETE FROM state WHERE key = $1", &[&STARTED_KEY]).await?;
+                        info!("Dual-write period over with SQLite and Postgres in parity; safe to cut over to Postgres");
+                        return Ok(());
+                    }
+                    (true, false) => info!("Dual-write check: SQLite and Postgres in parity"),
+                    (false, _) => warn!(
+                        divergent = %divergent.join(", "),
+                        period_over,
+                        "Dual-write check: Postgres diverges from SQLite; still mirroring"
+                    ),
+                }
+            }
+        }
+        if drained < DRAIN_BATCH {
+            tokio::time::sleep(POLL).await;
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+/// Create the change log and triggers; `true` if they didn't exist yet.
+fn enable(conn: &Connection, tables: &[Table]) -> Result<bool> {
+    let existing: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name=?1",
+        [LOG_TABLE],
+        |r| r.get(0),
+    )?;
+    let mut sql = format!(
+        "CREATE TABLE IF NOT EXISTS {LOG_TABLE} (id INTEGER PRIMARY KEY AUTOINCREMENT, tbl TEXT NOT NULL, pk TEXT NOT NULL);\n"
+    );
+    for table in tables.iter().filter(|t| !t.primary_key.is_empty()) {
+        let key = |row: &str| {
+            let cols: Vec<String> = table.primary_key.iter().map(|c| format!("{row}.\"{c}\"")).collect();
+            format!("json_array({})", cols.join(", "))
+        };
+        let log = |row: &str| format!("INSERT INTO {LOG_TABLE} (tbl, pk) VALUES ('{}', {});", table.name, key(row));
+        let t = &table.name;
+        sql += &format!(
+            "CREATE TRIGGER IF NOT EXISTS {TRIGGER_PREFIX}{t}_ins AFTER INSERT ON \"{t}\" BEGIN {} END;\n\
+             CREATE TRIGGER IF NOT EXISTS {TRIGGER_PREFIX}{t}_upd AFTER UPDATE ON \"{t}\" BEGIN {} {} END;\n\
+             CREATE TRIGGER IF NOT EXISTS {TRIGGER_PREFIX}{t}_del AFTER DELETE ON \"{t}\" BEGIN {} END;\n",
+            log("NEW"),
+            log("OLD"),
+            log("NEW"),
+            log("OLD"),
+        );
+    }
+    conn.execute_batch(&format!("BEGIN IMMEDIATE;\n{sql}COMMIT;"))?;
+    Ok(existing == 0)
+}
+
+/// Drop the triggers and change log, if present.
+pub fn disable(conn: &Connection) -> Result<()> {
+    let triggers: Vec<String> = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type='trigger' AND name LIKE 'dual\\_write\\_%' ESCAPE '\\'")?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    let logged: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name=?1",
+        [LOG_TABLE],
+        |r| r.get(0),
+    )?;
+    if triggers.is_empty() && !logged {
+        return Ok(());
+    }
+    let mut sql = String::from("BEGIN IMMEDIATE;\n");
+    for t in &triggers {
+        sql += &format!("DROP TRIGGER IF EXISTS \"{t}\";\n");
+    }
+    sql += &format!("DROP TABLE IF EXISTS {LOG_TABLE};\nDELETE FROM state WHERE key = '{STARTED_KEY}';\nCOMMIT;");
+    conn.execute_batch(&sql)?;
+    metrics::DUAL_WRITE_PENDING.set(0);
+    info!(triggers = triggers.len(), "Dual-write disabled");
+    Ok(())
+}
+
+#[cfg(feature = "postgres")]
+/// Replay up to `DRAIN_BATCH` logged changes into Postgres; returns how many.
+async fn drain(conn: &Connection, client: &Client, tables: &[Table]) -> Result<usize> {
+    let entries: Vec<(i64, String, String)> = conn
+        .prepare(&format!("SELECT id, tbl, pk FROM {LOG_TABLE} ORDER BY id LIMIT ?1"))?
+        .query_map([DRAIN_BATCH as i64], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let Some(&(last_id, _, _)) = entries.last() else {
+        metrics::DUAL_WRITE_PENDING.set(0);
+        return Ok(0);
+    };
+
+    // Only the row's current state matters, so each key is mirrored once per batch
+    let mut seen = HashSet::new();
+    for (_, tbl, pk) in &entries {
+        if !seen.insert((tbl, pk)) {
+            continue;
+        }
+        let Some(table) = tables.iter().find(|t| t.name == *tbl) else { continue };
+        let key: Vec<Value> = serde_json::from_str::<Vec<serde_json::Value>>(pk)?
+            .into_iter()
+            .map(|v| match v {
+                serde_json::Value::Number(n) => n.as_i64().map(Value::Integer).unwrap_or(Value::Real(n.as_f64().unwrap_or_default())),
+                serde_json::Value::String(s) => Value::Text(s),
+                _ => Value::Null,
+            })
+            .collect();
+        let matches: Vec<String> = table.primary_key.iter().enumerate()
+            .map(|(i, c)| format!("\"{c}\" = ?{}", i + 1))
+            .collect();
+        let row: Option<Vec<Value>> = conn
+            .query_row(
+                &format!("SELECT {} FROM \"{}\" WHERE {}", table.column_list(), table.name, matches.join(" AND ")),
+                params_from_iter(&key),
+                |r| (0..table.columns.len()).map(|i| r.get::<_, Value>(i)).collect(),
+            )
+            .optional()?;
+
+        match row {
+            Some(values) => {
+                let params: Vec<Box<dyn ToSql + Sync>> = values.iter().zip(&table.columns)
+                    .map(|(v, c)| migrate::to_pg(v, c.pg_type))
+                    .collect();
+                let placeholders: Vec<String> = (1..=params.len()).map(|i| format!("${i}")).collect();
+                let upsert = format!(
+                    "INSERT INTO \"{}\" ({}) VALUES ({}) {}",
+                    table.name,
+                    table.column_list(),
+                    placeholders.join(", "),
+                    table.upsert_clause()
+                );
+                let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+                client.execute(&upsert, &refs).await?;
+            }
+            None => {
+                let params: Vec<Box<dyn ToSql + Sync>> = key.iter().zip(&table.primary_key)
+                    .map(|(v, name)| {
+                        let pg_type = table.columns.iter().find(|c| c.name == *name).map_or("TEXT", |c| c.pg_type);
+                        migrate::to_pg(v, pg_type)
+                    })
+                    .collect();
+                let matches: Vec<String> = table.primary_key.iter().enumerate()
+                    .map(|(i, c)| format!("\"{c}\" = ${}", i + 1))
+                    .collect();
+                let delete = format!("DELETE FROM \"{}\" WHERE {}", table.name, matches.join(" AND "));
+                let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+                client.execute(&delete, &refs).await?;
+            }
+        }
+    }
+
+    conn.execute(&format!("DELETE FROM {LOG_TABLE} WHERE id <= ?1"), [last_id])?;
+    let pending: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {LOG_TABLE}"), [], |r| r.get(0))?;
+    metrics::DUAL_WRITE_PENDING.set(pending);
+    Ok(entries.len())
+}
+
+#[cfg(feature = "postgres")]
+/// Divergences between the stores, or `None` if changes arrived before the
+/// comparison could be made against a drained log.
+async fn check(conn: &Connection, client: &Client, tables: &[Table]) -> Result<Option<Vec<String>>> {
+    // A read transaction pins the SQLite side while Postgres is queried
+    conn.execute_batch("BEGIN")?;
+    let result = compare(conn, client, tables).await;
+    conn.execute_batch("COMMIT")?;
+    result
+}
+
+#[cfg(feature = "postgres")]
+async fn compare(conn: &Connection, client: &Client, tables: &[Table]) -> Result<Option<Vec<String>>> {
+    let pending: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {LOG_TABLE}"), [], |r| r.get(0))?;
+    if pending > 0 {
+        return Ok(None);
+    }
+    let mut divergent: Vec<String> = migrate::row_counts(conn, client, tables).await?
+        .into_iter()
+        .filter(|(_, src, dst)| src != dst)
+        .map(|(table, src, dst)| format!("{table} rows: sqlite={src} postgres={dst}"))
+        .collect();
+
+    // One row per chain, and a database holds one chain
+    let cumulative: Option<String> = conn
+        .query_row("SELECT value FROM cumulative_netflow ORDER BY chain_id LIMIT 1", [], |r| r.get(0))
+        .optional()?;
+    let pg_cumulative: Option<String> = client
+        .query_opt("SELECT value FROM cumulative_netflow ORDER BY chain_id LIMIT 1", &[])
+        .await?
+        .map(|r| r.get(0));
+    if cumulative != pg_cumulative {
+        divergent.push(format!("cumulative: sqlite={cumulative:?} postgres={pg_cumulative:?}"));
+    }
+
+    let head = db::get_state(conn, "last_indexed_block")?;
+    let pg_head: Option<String> = client
+        .query_opt("SELECT value FROM state WHERE key = 'last_indexed_block'", &[])
+        .await?
+        .map(|r| r.get(0));
+    if head != pg_head {
+        divergent.push(format!("last indexed block: sqlite={head:?} postgres={pg_head:?}"));
+    }
+    Ok(Some(divergent))
+}
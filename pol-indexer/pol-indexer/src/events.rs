@@ -0,0 +1,239 @@
+//! In-process feed of indexer updates, consumed by the API's `GET /events`.
+//!
+//! Events are published after the block's transaction commits, so
+//! subscribers never see data that was rolled back. The exception is
+//! `provisional_netflow` (`FAST_PATH`), sent before any of it is checked or
+//! stored; its `netflow` follows, or not, if the block fails or is orphaned.
+//! Slow subscribers that fall more than `CAPACITY` events behind skip ahead.
+//!
+//! Whatever the transport, an event leaves the process as an [`Envelope`]:
+//! `{"v":2,"seq":17,"type":"transfer","data":{…}}`. Consumers need one
+//! parser, and `v` lets them reject payloads from a newer, incompatible
+//! version.
+//!
+//! Stored events are also appended to `event_log` in the block's
+//! transaction, which assigns `seq`: increasing, never reused, and durable,
+//! so a consumer that reconnects can ask for everything after the last `seq`
+//! it saw. Provisional events aren't logged and carry no `seq`.
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use ethers::types::U256;
+
+use rusqlite::Connection;
+
+use crate::db;
+use crate::models::{self, Direction, NetflowBlock, ProvisionalNetflow, TransferRecord, ValueFormat};
+
+const CAPACITY: usize = 1024;
+
+/// Envelope version; bumped when an event's `data` changes incompatibly.
+pub const EVENT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    /// A matched transfer was stored
+    Transfer(TransferRecord),
+    /// A block with matched transfers moved the aggregates
+    Netflow(NetflowBlock),
+    /// A new block's logs have matched transfers; not yet confirmed or stored
+    ProvisionalNetflow(ProvisionalNetflow),
+}
+
+/// Versioned wire format shared by every event transport.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub v: u32,
+    /// Position in `event_log`; absent on provisional events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Envelope {
+    /// Parse an envelope, refusing versions this build doesn't understand.
+    pub fn decode(json: &str) -> eyre::Result<Event> {
+        let env: Envelope = serde_json::from_str(json)?;
+        if env.v != EVENT_VERSION {
+            return Err(eyre::eyre!("unsupported event version {} (this build reads v{EVENT_VERSION})", env.v));
+        }
+        Ok(env.event)
+    }
+}
+
+impl Event {
+    pub fn envelope(self) -> Envelope {
+        Envelope { v: EVENT_VERSION, seq: None, event: self }
+    }
+
+    /// Append to `event_log`; call inside the transaction that stores the
+    /// event's rows, and publish the result once it commits.
+    pub fn log(self, conn: &Connection) -> eyre::Result<Sequenced> {
+        let block_number = match &self {
+            Event::Transfer(t) => t.block_number,
+            Event::Netflow(b) => b.block_number,
+            Event::ProvisionalNetflow(p) => p.block_number,
+        };
+        let seq = db::append_event(conn, block_number, self.kind(), &serde_json::to_string(&self)?)?;
+        Ok(Sequenced { seq: Some(seq), event: self })
+    }
+
+    /// Copy as served to clients: amounts in `format`, addresses checksummed.
+    pub fn formatted(&self, format: ValueFormat) -> Event {
+        let mut ev = self.clone();
+        match &mut ev {
+            Event::Transfer(t) => t.render(format),
+            Event::Netflow(b) => {
+                b.inflow = format.render(&b.inflow);
+                b.outflow = format.render(&b.outflow);
+                b.cumulative = format.render(&b.cumulative);
+            }
+            Event::ProvisionalNetflow(p) => {
+                p.inflow = format.render(&p.inflow);
+                p.outflow = format.render(&p.outflow);
+                p.cumulative = format.render(&p.cumulative);
+            }
+        }
+        ev
+    }
+
+    /// SSE event name
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Transfer(_) => "transfer",
+            Event::Netflow(_) => "netflow",
+            Event::ProvisionalNetflow(_) => "provisional_netflow",
+        }
+    }
+}
+
+/// What the feed carries: an event and its `event_log` position.
+#[derive(Debug, Clone)]
+pub struct Sequenced {
+    pub seq: Option<u64>,
+    pub event: Event,
+}
+
+impl Sequenced {
+    /// An `event_log` row, as returned by [`db::events_after`].
+    pub fn from_log(seq: u64, payload: &str) -> eyre::Result<Self> {
+        Ok(Sequenced { seq: Some(seq), event: serde_json::from_str(payload)? })
+    }
+
+    pub fn envelope(self) -> Envelope {
+        Envelope { v: EVENT_VERSION, seq: self.seq, event: self.event }
+    }
+}
+
+impl From<Event> for Sequenced {
+    /// An unlogged event
+    fn from(event: Event) -> Self {
+        Sequenced { seq: None, event }
+    }
+}
+
+pub type EventSender = broadcast::Sender<Sequenced>;
+
+pub fn channel() -> EventSender {
+    broadcast::channel(CAPACITY).0
+}
+
+/// What a subscriber asked to receive. `kinds` applies to every event; the
+/// other fields narrow `transfer` events only. `None` lets everything through.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    /// Event names, as in [`Event::kind`]
+    pub kinds: Option<Vec<String>>,
+    /// Raw units
+    pub min_value: Option<U256>,
+    pub directions: Option<Vec<Direction>>,
+    /// Either side of the transfer (lowercase)
+    pub address: Option<String>,
+    /// Token contract (lowercase)
+    pub token: Option<String>,
+}
+
+impl EventFilter {
+    /// From the `?types=`, `?min_value=`, `?direction=`, `?address=` and
+    /// `?token=` parameters; lists are comma-separated.
+    pub fn parse(
+        types: Option<&str>, min_value: Option<&str>, direction: Option<&str>, address: Option<&str>, token: Option<&str>,
+    ) -> Result<Self, String> {
+        const KINDS: [&str; 3] = ["transfer", "netflow", "provisional_netflow"];
+        let list = |csv: &str| csv.split(',').map(str::trim).filter(|p| !p.is_empty()).map(str::to_string).collect::<Vec<_>>();
+        let kinds = types.map(list);
+        if let Some(unknown) = kinds.iter().flatten().find(|k| !KINDS.contains(&k.as_str())) {
+            return Err(format!("unknown event type {unknown:?}; expected {}", KINDS.join(", ")));
+        }
+        let min_value = min_value
+            .map(|v| U256::from_dec_str(v.trim()).map_err(|_| format!("min_value must be a decimal amount in raw units, got {v:?}")))
+            .transpose()?;
+        let directions = direction
+            .map(|csv| list(csv).iter()
+                .map(|d| Direction::parse(d).ok_or_else(|| format!("unknown direction {d:?}; expected in, out, internal or none")))
+                .collect::<Result<Vec<_>, _>>())
+            .transpose()?;
+        let key = |s: &str| models::parse_address(s.trim()).map(|a| models::address_key(&a)).map_err(|e| e.to_string());
+        Ok(EventFilter {
+            kinds,
+            min_value,
+            directions,
+            address: address.map(key).transpose()?,
+            token: token.map(key).transpose()?,
+        })
+    }
+
+    pub fn matches(&self, ev: &Event) -> bool {
+        if self.kinds.as_ref().is_some_and(|kinds| !kinds.iter().any(|k| k == ev.kind())) {
+            return false;
+        }
+        let Event::Transfer(t) = ev else { return true };
+        self.min_value.is_none_or(|min| U256::from_dec_str(&t.value).is_ok_and(|v| v >= min))
+            && self.directions.as_ref().is_none_or(|ds| ds.contains(&t.direction))
+            && self.address.as_ref().is_none_or(|a| *a == t.from || *a == t.to)
+            && self.token.as_ref().is_none_or(|token| *token == t.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_transfers_by_value_direction_and_address() {
+        let hot = "0xf977814e90da44bfa03b6295a0616a897441acec";
+        let transfer = |value: &str, direction| Event::Transfer(TransferRecord {
+            block_number: 1,
+            tx_hash: "0x1".into(),
+            log_index: 0,
+            token: "0x455e53cbb86018ac2b8092fdcd39d8444affc3f6".into(),
+            from: "0x0000000000000000000000000000000000000001".into(),
+            to: hot.into(),
+            value: value.into(),
+            direction,
+            status: None,
+            bucket: None,
+            counterparty_kind: None,
+            counterparty_label: None,
+        });
+        let filter = EventFilter::parse(None, Some("1000"), Some("in,out"), Some("0xF977814e90dA44bFA03b6295A0616a897441aceC"), None).unwrap();
+        assert!(filter.matches(&transfer("1000", Direction::In)));
+        assert!(!filter.matches(&transfer("999", Direction::In)));
+        assert!(!filter.matches(&transfer("5000", Direction::Internal)));
+
+        let only_transfers = EventFilter::parse(Some("transfer"), None, None, None, Some("0x0000000000000000000000000000000000000002")).unwrap();
+        assert!(!only_transfers.matches(&transfer("1", Direction::In)), "another token");
+        let netflow = Event::Netflow(NetflowBlock {
+            block_number: 1, ts_unix: 0, inflow: "0".into(), outflow: "0".into(), cumulative: "0".into(), transfer_count: 0,
+            cumulative_token: "0".into(), price_usd_micro: None, cumulative_usd_micro: None,
+        });
+        assert!(!only_transfers.matches(&netflow));
+        assert!(filter.matches(&netflow), "transfer filters leave other events alone");
+
+        assert!(EventFilter::parse(Some("blocks"), None, None, None, None).is_err());
+        assert!(EventFilter::parse(None, Some("1.5"), None, None, None).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+//! `pol-indexer export`: dump stored transfers or per-block net-flow as CSV/JSON.
+//!
+//! Every amount is written in all three encodings (`<col>`, `<col>_hex`,
+//! `<col>_scaled`) so one file suits any downstream consumer. Transfer exports
+//! carry their annotations and can be fed back into `import`.
+//!
+//! Net-flow can also be written as OpenMetrics samples timestamped with each
+//! block's time, for backfilling a Prometheus-compatible store.
+//!
+//! The counterparty graph sums the stored, non-reverted transfers into one
+//! directed edge per sender → recipient pair, as a CSV/JSON edge list or as
+//! GraphML (nodes marked `tracked` for the exchange side), for clustering in
+//! graph tools.
+
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use clap::ValueEnum;
+use ethers::types::U256;
+use eyre::{eyre, Result};
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::{db, models::{self, Annotation, Direction, NetflowBlock, TransferRecord, TxStatus, ValueFormat}, prices};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    /// Timestamped samples in whole POL (`--table netflow` only)
+    Openmetrics,
+    /// GraphML document (`--table graph` only)
+    Graphml,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ExportTable {
+    /// Rows of `erc20_transfers`
+    Transfers,
+    /// Rows of `netflow_blocks`
+    Netflow,
+    /// Sender → recipient edges with summed value
+    Graph,
+}
+
+#[derive(Serialize)]
+struct TransferRow {
+    block_number: u64,
+    tx_hash: String,
+    log_index: u64,
+    token: String,
+    from: String,
+    to: String,
+    value: String,
+    value_hex: String,
+    value_scaled: String,
+    direction: Direction,
+    status: Option<TxStatus>,
+    bucket: Option<String>,
+    /// Annotation notes, `; `-separated
+    notes: String,
+    /// Annotation tags, comma-separated
+    tags: String,
+}
+
+impl TransferRow {
+    fn new(t: TransferRecord, annotations: &[Annotation]) -> Self {
+        let mut tags: Vec<&str> = annotations.iter().flat_map(|a| a.tags.iter().map(String::as_str)).collect();
+        tags.sort_unstable();
+        tags.dedup();
+        TransferRow {
+            notes: annotations.iter().map(|a| a.note.as_str()).collect::<Vec<_>>().join("; "),
+            tags: tags.join(","),
+            value_hex: ValueFormat::Hex.render(&t.value),
+            value_scaled: ValueFormat::Scaled.render(&t.value),
+            block_number: t.block_number,
+            tx_hash: t.tx_hash,
+            log_index: t.log_index,
+            token: t.token,
+            from: t.from,
+            to: t.to,
+            value: t.value,
+            direction: t.direction,
+            status: t.status,
+            bucket: t.bucket,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NetflowRow {
+    block_number: u64,
+    ts_unix: i64,
+    inflow: String,
+    inflow_hex: String,
+    inflow_scaled: String,
+    outflow: String,
+    outflow_hex: String,
+    outflow_scaled: String,
+    cumulative: String,
+    cumulative_hex: String,
+    cumulative_scaled: String,
+    transfer_count: u64,
+    /// USD per whole token at the block; empty without a price feed
+    price_usd: Option<String>,
+    cumulative_usd: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EdgeRow {
+    source: String,
+    target: String,
+    value: String,
+    value_hex: String,
+    value_scaled: String,
+    transfer_count: u64,
+    first_block: u64,
+    last_block: u64,
+}
+
+#[derive(Default)]
+struct Edge {
+    value: U256,
+    transfer_count: u64,
+    first_block: u64,
+    last_block: u64,
+}
+
+impl From<NetflowBlock> for NetflowRow {
+    fn from(b: NetflowBlock) -> Self {
+        NetflowRow {
+            block_number: b.block_number,
+            ts_unix: b.ts_unix,
+            inflow_hex: ValueFormat::Hex.render(&b.inflow),
+            inflow_scaled: ValueFormat::Scaled.render(&b.inflow),
+            inflow: b.inflow,
+            outflow_hex: ValueFormat::Hex.render(&b.outflow),
+            outflow_scaled: ValueFormat::Scaled.render(&b.outflow),
+            outflow: b.outflow,
+            cumulative_hex: ValueFormat::Hex.render(&b.cumulative),
+            cumulative_scaled: b.cumulative_token,
+            cumulative: b.cumulative,
+            transfer_count: b.transfer_count,
+            price_usd: b.price_usd_micro.map(prices::format_usd),
+            cumulative_usd: b.cumulative_usd_micro.map(prices::format_usd),
+        }
+    }
+}
+
+/// What `export` prints once the rows are written.
+#[derive(Serialize)]
+pub struct ExportSummary {
+    pub rows: usize,
+}
+
+/// Write `table` rows in `[from_block, to_block]` to `out` (stdout if `None`).
+/// Returns the number of rows written.
+pub fn run(
+    conn: &Connection,
+    table: ExportTable,
+    format: ExportFormat,
+    from_block: u64,
+    to_block: u64,
+    out: Option<&Path>,
+) -> Result<usize> {
+    match (table, format) {
+        (ExportTable::Transfers | ExportTable::Graph, ExportFormat::Openmetrics) => {
+            return Err(eyre!("--format openmetrics only applies to --table netflow"));
+        }
+        (ExportTable::Transfers | ExportTable::Netflow, ExportFormat::Graphml) => {
+            return Err(eyre!("--format graphml only applies to --table graph"));
+        }
+        _ => {}
+    }
+    let mut writer: Box<dyn Write> = match out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let n = match table {
+        ExportTable::Transfers if matches!(format, ExportFormat::Csv) => write_transfers_csv(conn, from_block, to_block, &mut writer)?,
+        ExportTable::Transfers => {
+            let mut annotations: HashMap<(String, u64), Vec<Annotation>> = HashMap::new();
+            for a in db::annotations_by_range(conn, from_block, to_block)? {
+                annotations.entry((a.tx_hash.clone(), a.log_index)).or_default().push(a);
+            }
+            let rows: Vec<TransferRow> = db::get_transfers_by_range(conn, from_block, to_block)?
+                .into_iter()
+                .map(|t| {
+                    let notes = annotations.remove(&(t.tx_hash.clone(), t.log_index)).unwrap_or_default();
+                    TransferRow::new(t, &notes)
+                })
+                .collect();
+            write_rows(&rows, format, &mut writer)?
+        }
+        ExportTable::Netflow if matches!(format, ExportFormat::Openmetrics) => {
+            write_openmetrics(&db::get_netflow_blocks_by_range(conn, from_block, to_block)?, &mut writer)?
+        }
+        ExportTable::Netflow => {
+            let rows: Vec<NetflowRow> = db::get_netflow_blocks_by_range(conn, from_block, to_block)?
+                .into_iter().map(Into::into).collect();
+            write_rows(&rows, format, &mut writer)?
+        }
+        ExportTable::Graph => {
+            let (edges, tracked) = counterparty_graph(db::get_transfers_by_range(conn, from_block, to_block)?);
+            if matches!(format, ExportFormat::Graphml) {
+                write_graphml(&edges, &tracked, &mut writer)?
+            } else {
+                let rows: Vec<EdgeRow> = edges.into_iter().map(|((source, target), e)| EdgeRow {
+                    source: models::checksummed(&source),
+                    target: models::checksummed(&target),
+                    value: e.value.to_string(),
+                    value_hex: ValueFormat::Hex.render(&e.value.to_string()),
+                    value_scaled: models::scaled(e.value),
+                    transfer_count: e.transfer_count,
+                    first_block: e.first_block,
+                    last_block: e.last_block,
+                }).collect();
+                write_rows(&rows, format, &mut writer)?
+            }
+        }
+    };
+    writer.flush()?;
+    Ok(n)
+}
+
+/// Transfers in `[from_block, to_block]` as CSV, read and written one row at
+/// a time (`export --table transfers`, `GET /export/transfers.csv`). Returns
+/// the number of rows written.
+pub fn write_transfers_csv(conn: &Connection, from_block: u64, to_block: u64, out: &mut dyn Write) -> Result<usize> {
+    let mut annotations: HashMap<(String, u64), Vec<Annotation>> = HashMap::new();
+    for a in db::annotations_by_range(conn, from_block, to_block)? {
+        annotations.entry((a.tx_hash.clone(), a.log_index)).or_default().push(a);
+    }
+    let mut w = csv::Writer::from_writer(out);
+    let mut n = 0;
+    db::for_each_transfer(conn, from_block, to_block, |t| {
+        let notes = annotations.remove(&(t.tx_hash.clone(), t.log_index)).unwrap_or_default();
+        w.serialize(TransferRow::new(t, &notes))?;
+        n += 1;
+        Ok(())
+    })?;
+    w.flush()?;
+    Ok(n)
+}
+
+fn write_rows<T: Serialize>(rows: &[T], format: ExportFormat, out: &mut dyn Write) -> Result<usize> {
+    match format {
+        ExportFormat::Openmetrics | ExportFormat::Graphml => unreachable!("rejected for row tables before writing"),
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, rows)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            let mut w = csv::Writer::from_writer(out);
+            for row in rows {
+                w.serialize(row)?;
+            }
+            w.flush()?;
+        }
+    }
+    Ok(rows.len())
+}
+
+/// Net-flow at one timestamp.
+struct Sample {
+    ts_unix: i64,
+    inflow: U256,
+    outflow: U256,
+    cumulative: String,
+    transfer_count: u64,
+}
+
+/// One gauge family per column, each series in block order. Blocks sharing a
+/// timestamp are merged into one sample, since a store rejects two different
+/// values for one series at the same time.
+fn write_openmetrics(blocks: &[NetflowBlock], out: &mut dyn Write) -> Result<usize> {
+    let mut samples: Vec<Sample> = Vec::new();
+    for b in blocks {
+        let inflow = U256::from_dec_str(&b.inflow).unwrap_or_default();
+        let outflow = U256::from_dec_str(&b.outflow).unwrap_or_default();
+        match samples.last_mut() {
+            Some(s) if s.ts_unix == b.ts_unix => {
+                s.inflow = s.inflow.saturating_add(inflow);
+                s.outflow = s.outflow.saturating_add(outflow);
+                s.cumulative.clone_from(&b.cumulative);
+                s.transfer_count += b.transfer_count;
+            }
+            _ => samples.push(Sample {
+                ts_unix: b.ts_unix,
+                inflow,
+                outflow,
+                cumulative: b.cumulative.clone(),
+                transfer_count: b.transfer_count,
+            }),
+        }
+    }
+
+    type Render = fn(&Sample) -> String;
+    let families: [(&str, &str, Render); 4] = [
+        ("netflow_block_inflow", "POL received by Binance addresses in the block, whole POL", |s| models::scaled(s.inflow)),
+        ("netflow_block_outflow", "POL sent from Binance addresses in the block, whole POL", |s| models::scaled(s.outflow)),
+        ("netflow_cumulative", "Cumulative net inflow after the block, whole POL", |s| ValueFormat::Scaled.render(&s.cumulative)),
+        ("netflow_block_transfers", "Matched transfers in the block", |s| s.transfer_count.to_string()),
+    ];
+    for (name, help, value) in families {
+        writeln!(out, "# TYPE {name} gauge")?;
+        writeln!(out, "# HELP {name} {help}")?;
+        for s in &samples {
+            writeln!(out, "{name} {} {}", value(s), s.ts_unix)?;
+        }
+    }
+    writeln!(out, "# EOF")?;
+    Ok(blocks.len())
+}
+
+/// Edges keyed by (sender, recipient), lowercase, and the addresses on the
+/// tracked side of at least one of the transfers.
+fn counterparty_graph(transfers: Vec<TransferRecord>) -> (BTreeMap<(String, String), Edge>, BTreeSet<String>) {
+    let mut edges: BTreeMap<(String, String), Edge> = BTreeMap::new();
+    let mut tracked = BTreeSet::new();
+    for t in transfers {
+        if t.status == Some(TxStatus::Reverted) {
+            continue;
+        }
+        if matches!(t.direction, Direction::Out | Direction::Internal) {
+            tracked.insert(t.from.clone());
+        }
+        if matches!(t.direction, Direction::In | Direction::Internal) {
+            tracked.insert(t.to.clone());
+        }
+        let value = U256::from_dec_str(&t.value).unwrap_or_default();
+        let e = edges.entry((t.from, t.to)).or_default();
+        if e.transfer_count == 0 {
+            e.first_block = t.block_number;
+        }
+        e.value = e.value.saturating_add(value);
+        e.transfer_count += 1;
+        e.last_block = t.block_number;
+    }
+    (edges, tracked)
+}
+
+/// Directed GraphML. `value` is kept in raw units as a string (it overflows a
+/// `long`); `value_pol` is the same amount in whole POL for weighting.
+fn write_graphml(edges: &BTreeMap<(String, String), Edge>, tracked: &BTreeSet<String>, out: &mut dyn Write) -> Result<usize> {
+    let nodes: BTreeSet<&String> = edges.keys().flat_map(|(s, t)| [s, t]).collect();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(out, r#"  <key id="tracked" for="node" attr.name="tracked" attr.type="boolean"/>"#)?;
+    writeln!(out, r#"  <key id="value" for="edge" attr.name="value" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="value_pol" for="edge" attr.name="value_pol" attr.type="double"/>"#)?;
+    writeln!(out, r#"  <key id="transfer_count" for="edge" attr.name="transfer_count" attr.type="long"/>"#)?;
+    writeln!(out, r#"  <key id="first_block" for="edge" attr.name="first_block" attr.type="long"/>"#)?;
+    writeln!(out, r#"  <key id="last_block" for="edge" attr.name="last_block" attr.type="long"/>"#)?;
+    writeln!(out, r#"  <graph id="counterparties" edgedefault="directed">"#)?;
+    for n in &nodes {
+        writeln!(
+            out,
+            r#"    <node id="{}"><data key="tracked">{}</data></node>"#,
+            models::checksummed(n),
+            tracked.contains(*n),
+        )?;
+    }
+    for ((source, target), e) in edges {
+        writeln!(
+            out,
+            r#"    <edge source="{}" target="{}"><data key="value">{}</data><data key="value_pol">{}</data><data key="transfer_count">{}</data><data key="first_block">{}</data><data key="last_block">{}</data></edge>"#,
+            models::checksummed(source),
+            models::checksummed(target),
+            e.value,
+            models::scaled(e.value),
+            e.transfer_count,
+            e.first_block,
+            e.last_block,
+        )?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    Ok(edges.len())
+}
@@ -0,0 +1,159 @@
+//! Import of externally sourced transfer records (Dune, Etherscan, …).
+//!
+//! Records are validated against the configured token and Binance set, inserted
+//! with the same `(tx_hash, log_index, block_hash)` dedup as the live indexer
+//! (a record without `block_hash` matches that log in any block), and the
+//! aggregates are rebuilt afterwards. The whole file is imported in a single
+//! transaction: one invalid record aborts the import.
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use clap::ValueEnum;
+use eyre::{Result, WrapErr, eyre};
+use ethers::types::{Address, H256, U256};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{aggregates, db, models::{address_key, parse_address, AccountingMode, Clock, Direction}, rules::Rules};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ImportFormat {
+    Csv,
+    Json,
+}
+
+/// One transfer. Column names follow `erc20_transfers`, with `from`/`to` accepted as aliases.
+#[derive(Deserialize)]
+struct ImportRecord {
+    block_number: u64,
+    tx_hash: String,
+    log_index: u64,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(alias = "from")]
+    sender: String,
+    #[serde(alias = "to")]
+    recipient: String,
+    /// Raw units, decimal or 0x-hex
+    value: String,
+    /// Block timestamp; stored in `blocks` when the block isn't known yet
+    #[serde(default, alias = "timestamp")]
+    ts_unix: Option<i64>,
+    #[serde(default)]
+    block_hash: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub read: u64,
+    pub inserted: u64,
+    pub duplicates: u64,
+    /// Neither side is a tracked Binance address
+    pub unrelated: u64,
+}
+
+pub fn run(
+    conn: &mut Connection,
+    path: &Path,
+    format: ImportFormat,
+    pol_token: Address,
+    binance_addrs: &[Address],
+    rules: &Rules,
+    clock: Clock,
+) -> Result<ImportSummary> {
+    let records = read_records(path, format)?;
+    let start_block = db::get_start_block(conn)?;
+    // Classify like the live indexer: discovered deposit addresses count as Binance
+    let mut binance_addrs = binance_addrs.to_vec();
+    if db::get_accounting_mode(conn)? == Some(AccountingMode::DepositNetting) {
+        for addr in db::list_deposit_addresses(conn)? {
+            binance_addrs.push(parse_address(&addr)?);
+        }
+    }
+
+    let tx = conn.transaction()?;
+    let mut summary = ImportSummary::default();
+    for (i, rec) in records.into_iter().enumerate() {
+        summary.read += 1;
+        let rec_no = i + 1;
+
+        let token = match &rec.token {
+            Some(t) => parse_address(t).wrap_err_with(|| format!("record {rec_no}"))?,
+            None => pol_token,
+        };
+        if token != pol_token {
+            return Err(eyre!("record {rec_no}: token {token:?} is not the configured POL token"));
+        }
+        if let Some(start) = start_block {
+            if rec.block_number < start {
+                return Err(eyre!("record {rec_no}: block {} is before the accounting start block {start}", rec.block_number));
+            }
+        }
+        let tx_hash: H256 = rec.tx_hash.parse()
+            .map_err(|_| eyre!("record {rec_no}: invalid tx hash {}", rec.tx_hash))?;
+        let from = parse_address(&rec.sender).wrap_err_with(|| format!("record {rec_no}"))?;
+        let to = parse_address(&rec.recipient).wrap_err_with(|| format!("record {rec_no}"))?;
+        let value = parse_value(&rec.value)
+            .ok_or_else(|| eyre!("record {rec_no}: invalid value {}", rec.value))?;
+
+        let from_is_binance = binance_addrs.contains(&from);
+        let to_is_binance = binance_addrs.contains(&to);
+        if !from_is_binance && !to_is_binance {
+            summary.unrelated += 1;
+            continue;
+        }
+
+        let (direction, bucket) = rules.apply(&from, &to, value, Direction::of(from_is_binance, to_is_binance));
+        let tx_hash = format!("{:?}", tx_hash);
+        if let Some(ts) = rec.ts_unix {
+            db::insert_block(&tx, rec.block_number, rec.block_hash.as_deref().unwrap_or(""), ts)?;
+        }
+        let block_hash = rec.block_hash.as_deref().unwrap_or("").to_ascii_lowercase();
+        let inserted = db::insert_transfer(
+            &tx,
+            rec.block_number,
+            &block_hash,
+            &tx_hash,
+            rec.log_index,
+            &address_key(&token),
+            &address_key(&from),
+            &address_key(&to),
+            &value.to_string(),
+            direction,
+            None,
+        )?;
+        if inserted && bucket.is_some() {
+            db::set_transfer_bucket(&tx, &tx_hash, rec.log_index, &block_hash, bucket)?;
+        }
+        if inserted { summary.inserted += 1; } else { summary.duplicates += 1; }
+    }
+
+    if summary.inserted > 0 {
+        aggregates::rebuild(&tx, clock)?;
+    }
+    tx.commit()?;
+    info!(?summary, "Import finished");
+    Ok(summary)
+}
+
+fn read_records(path: &Path, format: ImportFormat) -> Result<Vec<ImportRecord>> {
+    let file = BufReader::new(File::open(path)?);
+    let records = match format {
+        ImportFormat::Csv => csv::Reader::from_reader(file)
+            .deserialize()
+            .enumerate()
+            .map(|(i, r)| r.wrap_err_with(|| format!("record {}", i + 1)))
+            .collect::<Result<Vec<_>>>()?,
+        ImportFormat::Json => serde_json::from_reader(file)?,
+    };
+    Ok(records)
+}
+
+fn parse_value(s: &str) -> Option<U256> {
+    let s = s.trim();
+    match s.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_dec_str(s).ok(),
+    }
+}
@@ -1,144 +1,1900 @@
-use std::sync::Arc;
+use std::{collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque}, path::PathBuf, sync::{Arc, RwLock}, time::{Duration, Instant}};
 
-use alloy_primitives::bytes;
 use eyre::{Result, eyre};
-use ethers::{
-    providers::{Provider, Ws, StreamExt},
-    types::{Filter, H160, H256, U256, BlockId, BlockNumber, Log, Address, H64},
-};
+use ethers::types::{H160, H256, U256, Address};
+use futures_util::{stream::BoxStream, Stream, StreamExt};
 use rusqlite::Connection;
+use serde::Serialize;
 use tokio::sync::Mutex;
-use tracing::{info, warn, error};
+use tracing::{debug, info, warn};
 
-use crate::db;
-use crate::models::Erc20Transfer;
+use time::OffsetDateTime;
+
+use crate::alerts::{Alert, Alerter};
+use crate::chain::{self, replay, BlockHeader, ChainClient, LogFilter, RawLog, Source};
+use crate::{aggregates, contracts::{self, Classification, ContractLabels}, dataset::{self, Dataset}, db, drift, ingest, metrics, models, prices::{self, PriceFeed}, progress, nft, rules::Rules, subgraph::Subgraph, supply};
+use crate::control::Control;
+use crate::events::{Event, EventSender};
+use crate::models::{AccountingMode, AddressStr, BalanceBaseline, BlockStats, Clock, CounterpartyKind, DecimalStr, Direction, Erc20Transfer, HashStr, NativeNetflowBlock, NativeTransferRecord, NetflowBlock, NftTransfer, ProvisionalNetflow, ReorgRecord, SessionSummary, SupplyEventKind, TransferRecord, TxStatus, ValueFormat, POL_DECIMALS};
+
+/// Blocks older than this (i.e. backfilled history) don't raise alerts.
+const ALERT_MAX_AGE_SECS: i64 = 15 * 60;
+
+/// Block span per eth_getLogs call during backfill; conservative enough for
+/// providers that cap the range.
+const BACKFILL_CHUNK: u64 = 500;
+
+/// Block span per subgraph query during backfill; it pages by row, not range.
+const SUBGRAPH_CHUNK: u64 = 100_000;
+
+/// Addresses per topic OR-list in one eth_getLogs call.
+const TOPICS_PER_QUERY: usize = 500;
+
+/// Default `RPC_MAX_LOGS`: the common provider cap on one eth_getLogs response.
+pub const DEFAULT_MAX_LOGS: usize = 10_000;
+
+/// Default `HEAD_TIMEOUT_SECS`. Polygon produces a block every ~2s, so a
+/// minute without one means the subscription is gone, not the chain.
+pub const DEFAULT_HEAD_TIMEOUT_SECS: u64 = 60;
+
+/// Pause between attempts to reconnect after a stalled subscription.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Reads of a block header before its timestamp is estimated instead.
+const HEADER_ATTEMPTS: u32 = 3;
+
+/// Pause before the second header read; doubled before each later one.
+const HEADER_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Default `DB_RETRY_BUFFER_HEADS`: about half an hour of Polygon heads.
+pub const DEFAULT_DB_RETRY_BUFFER: usize = 1_000;
+
+/// Wait before writing again after the database was busy, locked or full;
+/// doubled after each failure up to `DB_RETRY_MAX_DELAY`.
+const DB_RETRY_DELAY: Duration = Duration::from_secs(1);
+const DB_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Stored blocks compared against the canonical chain when looking for a fork
+/// point; a reorg deeper than this stops the indexer.
+const MAX_REORG_DEPTH: u32 = 256;
 
 // keccak256("Transfer(address,address,uint256)")
-const TRANSFER_TOPIC: H256 = H256([
+pub const TRANSFER_TOPIC: H256 = H256([
     0xdd, 0xf2, 0x52, 0xad, 0x1b, 0xe2, 0xc8, 0x9b,
     0x69, 0xc2, 0xb0, 0x68, 0xfc, 0x37, 0x8d, 0xaa,
     0x95, 0x2b, 0xa7, 0xf1, 0x63, 0xc4, 0xa1, 0x16,
     0x28, 0xf5, 0x5a, 0x4d, 0xf8, 0x3e, 0x34, 0x34
 ]);
 
-pub async fn run(
-    rpc_url: String,
+// keccak256("eip1967.proxy.implementation") - 1
+const IMPLEMENTATION_SLOT: H256 = H256([
+    0x36, 0x08, 0x94, 0xa1, 0x3b, 0xa1, 0xa3, 0x21,
+    0x06, 0x67, 0xc8, 0x28, 0x49, 0x2d, 0xb9, 0x8d,
+    0xca, 0x3e, 0x20, 0x76, 0xcc, 0x37, 0x35, 0xa9,
+    0x20, 0xa3, 0xca, 0x50, 0x5d, 0x38, 0x2b, 0xbc
+]);
+
+pub struct IndexerConfig {
+    pub source: chain::Source,
+    pub pol_token: Address,
+    pub binance_addrs: Vec<Address>,
+    pub start_block: Option<u64>,
+    pub mode: AccountingMode,
+    /// How often to re-read the token's proxy implementation; `None` = startup only
+    pub proxy_check_interval: Option<Duration>,
+    /// How often to re-read the token's `totalSupply()`; `None` = startup only
+    pub supply_refresh_interval: Option<Duration>,
+    /// Resubscribe when no new head arrives for this long; `None` = never
+    pub head_timeout: Option<Duration>,
+    /// Independent endpoint the provider's head is compared with
+    pub reference: Option<drift::ReferenceConfig>,
+    /// Chainlink aggregator pricing the token in USD, for the USD cumulative
+    pub price_feed: Option<Address>,
+    pub clock: Clock,
+    /// Pause between passes over `failed_blocks`
+    pub failed_block_retry: Duration,
+    /// New heads held in memory while the database rejects writes as busy, locked or full
+    pub db_retry_buffer: usize,
+    /// Also record native POL value transfers (every block's transactions are read)
+    pub track_native: bool,
+    pub rpc_budget: chain::RpcBudget,
+    /// Results at which an eth_getLogs response is assumed truncated and the query split
+    pub max_logs_per_query: usize,
+    /// Write every RPC response to this file (`pol-indexer record`)
+    pub record_to: Option<(PathBuf, replay::Meta)>,
+    /// Take new blocks from webhook deliveries instead of a head subscription
+    pub ingest: Option<ingest::Receiver>,
+    /// Read backfilled transfers from this subgraph instead of eth_getLogs
+    pub subgraph: Option<Subgraph>,
+    /// Keep every Transfer log of the token in `raw_logs`, not only tracked ones
+    pub archive_raw_logs: bool,
+    /// Also record each block's total transfer volume across all addresses
+    pub track_volume: bool,
+    /// Also record mints and burns of the token, whoever receives or sends them
+    pub track_supply_events: bool,
+    /// Also record ERC-721/1155 transfers of any collection to or from the tracked addresses
+    pub track_nfts: bool,
+    /// Publish each new block's net-flow from its logs before reading its header and receipts
+    pub fast_path: bool,
+    /// Classify transfer counterparties by their bytecode, naming these code hashes
+    pub tag_contracts: Option<ContractLabels>,
+    /// Adopt this token/address set/chain even if the database was built with another
+    pub force_config: bool,
+    pub rules: Rules,
+    /// `STOP_AT_BLOCK` and the admin API's pause/step/resume
+    pub control: Arc<Control>,
+}
+
+pub async fn run(mut config: IndexerConfig, conn: Connection, alerts: Option<Alerter>, events: EventSender) -> Result<()> {
+    let started_at_unix = OffsetDateTime::now_utc().unix_timestamp();
+    let mut provider = chain::connect_budgeted(&config.source, config.rpc_budget).await?;
+    if let Some((path, meta)) = &config.record_to {
+        provider = Box::new(replay::Recorder::create(provider, path, meta.clone())?);
+    }
+
+    let dataset = Dataset {
+        token: config.pol_token,
+        binance_addrs: &config.binance_addrs,
+        chain_id: Some(provider.chain_id().await?),
+        source: if config.force_config { "run --force" } else { "run" },
+    };
+    dataset::check(&conn, &dataset, config.force_config)?;
+    check_token_decimals(provider.as_ref(), config.pol_token).await;
+    let price_feed = match config.price_feed {
+        Some(address) => Some(PriceFeed::connect(provider.as_ref(), address).await?),
+        None => None,
+    };
+
+    let head = provider.block_number().await?;
+    let start_block = resolve_start_block(&conn, config.start_block, head)?;
+    let last_indexed = db::get_last_indexed_block(&conn)?;
+    let mode = resolve_accounting_mode(&conn, config.mode)?;
+    let deposits = match mode {
+        AccountingMode::HotWallet => HashSet::new(),
+        AccountingMode::DepositNetting => db::list_deposit_addresses(&conn)?
+            .iter()
+            .map(|a| models::parse_address(a))
+            .collect::<Result<_>>()?,
+    };
+
+    if let Some(labels) = &config.tag_contracts {
+        for (code_hash, label) in &labels.0 {
+            let relabelled = db::label_contracts(&conn, &format!("{code_hash:?}"), label)?;
+            if relabelled > 0 {
+                info!(%label, contracts = relabelled, "Labelled cached contracts");
+            }
+        }
+    }
+
+    if config.archive_raw_logs && config.subgraph.take().is_some() {
+        warn!("ARCHIVE_RAW_LOGS needs every token log, which the subgraph doesn't serve; SUBGRAPH_URL is ignored");
+    }
+    if config.track_volume && config.subgraph.take().is_some() {
+        warn!("TRACK_VOLUME needs every token log, which the subgraph doesn't serve; SUBGRAPH_URL is ignored");
+    }
+    if config.track_supply_events && config.subgraph.take().is_some() {
+        warn!("TRACK_SUPPLY_EVENTS needs mints and burns to any address, which the subgraph doesn't serve; SUBGRAPH_URL is ignored");
+    }
+    if config.track_nfts && config.subgraph.take().is_some() {
+        warn!("TRACK_NFTS needs transfers of other contracts, which the subgraph doesn't serve; SUBGRAPH_URL is ignored");
+    }
+
+    let indexer = Arc::new(Indexer {
+        binance_topics: config.binance_addrs.iter().map(|a| H256::from(*a)).collect(),
+        provider,
+        pol_token: config.pol_token,
+        binance_addrs: config.binance_addrs,
+        mode,
+        deposits: RwLock::new(deposits),
+        start_block,
+        clock: config.clock,
+        track_native: config.track_native,
+        max_logs: config.max_logs_per_query.max(1),
+        price_feed,
+        subgraph: config.subgraph.take(),
+        archive_raw_logs: config.archive_raw_logs,
+        track_volume: config.track_volume,
+        track_supply_events: config.track_supply_events,
+        track_nfts: config.track_nfts,
+        fast_path: config.fast_path,
+        contract_labels: config.tag_contracts,
+        rules: config.rules,
+        control: config.control,
+        conn: Arc::new(Mutex::new(conn)),
+        alerts,
+        events,
+        session: std::sync::Mutex::new(SessionSummary { started_at_unix, ..Default::default() }),
+    });
+    indexer.record_baselines(last_indexed).await?;
+    tokio::spawn(indexer.clone().retry_failed_blocks(config.failed_block_retry));
+    if let Some(reference) = config.reference.take() {
+        let indexer = indexer.clone();
+        tokio::spawn(async move { drift::watch(indexer.provider.as_ref(), indexer.alerts.as_ref(), reference).await });
+    }
+
+    // Stopping mid-block is safe: the block's uncommitted transaction rolls back
+    let result = tokio::select! {
+        r = follow(&indexer, &config.source, config.ingest.as_mut(), config.head_timeout, config.proxy_check_interval, config.supply_refresh_interval, config.db_retry_buffer, head, last_indexed) => r.map(|()| "stream_ended"),
+        () = shutdown_signal() => Ok("signal"),
+    };
+    let reason = match &result {
+        Ok(reason) => reason.to_string(),
+        Err(e) => format!("error: {e:#}"),
+    };
+    if let Err(e) = indexer.finish_session(reason).await {
+        warn!(?e, "Could not record the session summary");
+    }
+    result.map(|_| ())
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let term = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => { s.recv().await; }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let term = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = term => {}
+    }
+    info!("Shutdown signal received");
+}
+
+/// Backfill to `head`, then index new heads until the subscription ends.
+/// A subscription silent for `head_timeout` is replaced on a new connection.
+/// With `ingest`, new blocks come from webhook deliveries instead, and
+/// silence is normal.
+///
+/// While the database rejects writes as busy, locked or full, up to
+/// `db_retry_buffer` new heads wait in memory and the write is retried with
+/// backoff; see [`Outage`].
+#[allow(clippy::too_many_arguments)]
+async fn follow(
+    indexer: &Indexer,
+    source: &Source,
+    mut ingest: Option<&mut ingest::Receiver>,
+    head_timeout: Option<Duration>,
+    proxy_check_interval: Option<Duration>,
+    supply_refresh_interval: Option<Duration>,
+    db_retry_buffer: usize,
+    head: u64,
+    last_indexed: Option<u64>,
+) -> Result<()> {
+    let start_block = indexer.start_block;
+    if let Err(e) = indexer.check_implementation(head).await {
+        warn!(?e, "Proxy implementation check failed");
+    }
+    let mut last_proxy_check = Instant::now();
+    indexer.refresh_supply(head).await;
+    let mut last_supply_read = Instant::now();
+
+    // Catch up from the accounting epoch (or where we left off) to the current head
+    let from = last_indexed.map_or(start_block, |b| (b + 1).max(start_block));
+    if indexer.track_native {
+        let conn = indexer.conn.lock().await;
+        if db::get_native_since_block(&conn)?.is_none() {
+            db::set_native_since_block(&conn, from)?;
+        }
+    }
+    if indexer.archive_raw_logs {
+        let conn = indexer.conn.lock().await;
+        if db::get_raw_logs_since_block(&conn)?.is_none() {
+            db::set_raw_logs_since_block(&conn, from)?;
+        }
+    }
+    if indexer.track_volume {
+        let conn = indexer.conn.lock().await;
+        if db::get_volume_since_block(&conn)?.is_none() {
+            db::set_volume_since_block(&conn, from)?;
+        }
+    }
+    if indexer.track_supply_events {
+        let conn = indexer.conn.lock().await;
+        if db::get_supply_events_since_block(&conn)?.is_none() {
+            db::set_supply_events_since_block(&conn, from)?;
+        }
+    }
+    if indexer.track_nfts {
+        let conn = indexer.conn.lock().await;
+        if db::get_nfts_since_block(&conn)?.is_none() {
+            db::set_nfts_since_block(&conn, from)?;
+        }
+    }
+    let mut outage = Outage::default();
+    if from <= head {
+        loop {
+            // Resumes after whatever an attempt cut short did write
+            let attempt = async {
+                let last = db::get_last_indexed_block(&*indexer.conn.lock().await)?;
+                let from = last.map_or(from, |b| (b + 1).max(from));
+                if from <= head {
+                    indexer.backfill(from, head).await?;
+                }
+                Ok(())
+            };
+            match attempt.await {
+                Ok(()) => break,
+                Err(e) if db::is_transient(&e) => tokio::time::sleep(outage.failed(indexer, head, &e)).await,
+                Err(e) => return Err(e),
+            }
+        }
+        outage.recovered();
+    }
+
+    let head_timeout = if ingest.is_some() {
+        info!(start_block, "Indexer started. Waiting for webhook deliveries…");
+        None
+    } else {
+        info!(start_block, "Indexer started. Subscribing to new heads…");
+        head_timeout
+    };
+
+    // A resubscription after a stall gets a fresh connection of its own
+    let mut heads: Option<Box<dyn ChainClient>> = None;
+    let mut buffered = VecDeque::new();
+    loop {
+        let client = heads.as_deref().unwrap_or(indexer.provider.as_ref());
+        let mut stream = match ingest.as_deref_mut() {
+            Some(rx) => indexer.delivered_blocks(rx).boxed(),
+            None => client.subscribe_blocks().await?.map(|h| (h, None)).boxed(),
+        };
+        let stalled = loop {
+            let next = match head_timeout {
+                Some(limit) => match tokio::time::timeout(limit, stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => break true,
+                },
+                None => stream.next().await,
+            };
+            let Some((header, logs)) = next else { break false };
+            let mut number = header.number;
+            info!(block = number, hash = ?header.hash, "New block");
+
+            buffered.push_back((header, logs));
+            let mut open = true;
+            while let Some((header, logs)) = buffered.pop_front() {
+                match handle_head(indexer, header.clone(), logs.clone()).await {
+                    Ok(()) => {}
+                    Err(e) if db::is_transient(&e) => {
+                        let wait = outage.failed(indexer, header.number, &e);
+                        buffered.push_front((header, logs));
+                        open = buffer_heads(&mut stream, &mut buffered, db_retry_buffer, wait, open).await;
+                        number = buffered.back().map_or(number, |(h, _)| h.number);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            outage.recovered();
+            if !open {
+                break false;
+            }
+
+            if proxy_check_interval.is_some_and(|every| last_proxy_check.elapsed() >= every) {
+                if let Err(e) = indexer.check_implementation(number).await {
+                    warn!(?e, "Proxy implementation check failed");
+                }
+                last_proxy_check = Instant::now();
+            }
+            if supply_refresh_interval.is_some_and(|every| last_supply_read.elapsed() >= every) {
+                indexer.refresh_supply(number).await;
+                last_supply_read = Instant::now();
+            }
+        };
+        drop(stream);
+        if !stalled {
+            return Ok(());
+        }
+        metrics::HEAD_SUBSCRIPTION_STALLS.inc();
+        warn!(secs = head_timeout.map(|t| t.as_secs()), "No new heads; assuming a half-open subscription and reconnecting");
+        heads = Some(reconnect(source).await);
+    }
+}
+
+/// One new head: roll back if it orphans our tip, fill any gap below it,
+/// then index it. Safe to repeat after a failure, since what is already
+/// stored is read back first.
+async fn handle_head(indexer: &Indexer, header: BlockHeader, logs: Option<Vec<RawLog>>) -> Result<()> {
+    let number = header.number;
+    if number < indexer.start_block {
+        return Ok(());
+    }
+    let last = { db::get_last_indexed_block(&*indexer.conn.lock().await)? };
+    if let Some(mut last) = last {
+        if indexer.tip_orphaned(&header, last).await? {
+            let fork = indexer.find_fork_point(number.min(last + 1).saturating_sub(1)).await?;
+            last = indexer.roll_back(&header, fork).await?;
+        } else if number <= last {
+            if let Some(logs) = logs {
+                indexer.late_delivery(number, &logs).await?;
+            }
+            return Ok(());
+        }
+        // Missed heads (e.g. blocks produced while we were backfilling)
+        if number > last + 1 {
+            indexer.backfill(last + 1, number - 1).await?;
+        }
+    }
+    indexer.control.wait_through(number).await;
+    indexer.index_or_record(number, Some(header), logs).await?;
+    Ok(())
+}
+
+/// Sleep `wait`, meanwhile moving new heads from `stream` into `buffered`.
+/// Past `cap` the newest head replaces the last one kept: the blocks in
+/// between are backfilled once it is indexed. Returns whether the stream is
+/// still open.
+async fn buffer_heads(
+    stream: &mut BoxStream<'_, (BlockHeader, Option<Vec<RawLog>>)>,
+    buffered: &mut VecDeque<(BlockHeader, Option<Vec<RawLog>>)>,
+    cap: usize,
+    wait: Duration,
+    mut open: bool,
+) -> bool {
+    let sleep = tokio::time::sleep(wait);
+    tokio::pin!(sleep);
+    loop {
+        tokio::select! {
+            () = &mut sleep => return open,
+            next = stream.next(), if open => match next {
+                Some(head) => {
+                    if buffered.len() >= cap.max(1) {
+                        buffered.pop_back();
+                    }
+                    buffered.push_back(head);
+                    metrics::DB_BUFFERED_HEADS.set(buffered.len() as i64);
+                }
+                None => open = false,
+            },
+        }
+    }
+}
+
+/// A spell of database writes failing as busy, locked or disk full. The
+/// first failure alerts; the indexer keeps retrying rather than exiting.
+#[derive(Default)]
+struct Outage {
+    failures: u32,
+    since: Option<Instant>,
+}
+
+impl Outage {
+    /// Wait before the next attempt to write up to `block`.
+    fn failed(&mut self, indexer: &Indexer, block: u64, e: &eyre::Report) -> Duration {
+        self.failures += 1;
+        let wait = DB_RETRY_DELAY.saturating_mul(1 << (self.failures - 1).min(6)).min(DB_RETRY_MAX_DELAY);
+        let error = format!("{e:#}");
+        if self.since.is_none() {
+            self.since = Some(Instant::now());
+            metrics::DB_WRITES_STALLED.set(1);
+            warn!(block, %error, "Database unavailable; holding new heads in memory and retrying");
+            if let Some(alerts) = &indexer.alerts {
+                alerts.send(Alert::DatabaseUnavailable { block_number: block, error });
+            }
+        } else {
+            warn!(block, attempts = self.failures, retry_in_secs = wait.as_secs(), %error, "Database still unavailable");
+        }
+        wait
+    }
+
+    fn recovered(&mut self) {
+        if let Some(since) = self.since.take() {
+            info!(attempts = self.failures, secs = since.elapsed().as_secs(), "Database writable again; resuming");
+            metrics::DB_WRITES_STALLED.set(0);
+            metrics::DB_BUFFERED_HEADS.set(0);
+        }
+        self.failures = 0;
+    }
+}
+
+/// Connect again, retrying every `RECONNECT_DELAY` until the node answers.
+async fn reconnect(source: &Source) -> Box<dyn ChainClient> {
+    loop {
+        match chain::connect(source).await {
+            Ok(client) => return client,
+            Err(e) => {
+                warn!(?e, retry_secs = RECONNECT_DELAY.as_secs(), "Reconnect failed");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+pub struct RebuildConfig {
+    /// Only needed where `raw_logs` doesn't cover the blocks
+    pub source: Option<chain::Source>,
+    pub pol_token: Address,
+    /// The new address set
+    pub binance_addrs: Vec<Address>,
+    pub since_block: u64,
+    pub clock: Clock,
+    pub max_logs_per_query: usize,
+    /// Accounting rules to re-apply to every transfer in the range
+    pub rules: Rules,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RebuildSummary {
+    pub from_block: u64,
+    pub to_block: u64,
+    /// Transfers of the new addresses that weren't stored
+    pub added: u64,
+    /// Stored transfers whose in/out attribution changed
+    pub updated: u64,
+    /// Stored transfers no longer involving a tracked address
+    pub removed: u64,
+    /// Read from `raw_logs` rather than the RPC
+    pub from_archive: bool,
+}
+
+/// `pol-indexer rebuild`: re-read the transfers of `[since_block, last indexed]`
+/// for the configured address set, patch `erc20_transfers` to match and
+/// recompute the aggregates, then adopt the set. Deposit addresses already
+/// discovered stay tracked. Run it with the indexer stopped.
+///
+/// Where `raw_logs` covers the whole range the logs come from there, with no
+/// RPC access; transfers added that way have an unknown receipt status.
+pub async fn rebuild(config: RebuildConfig, conn: Connection) -> Result<RebuildSummary> {
+    let (Some(start_block), Some(last_indexed)) = (db::get_start_block(&conn)?, db::get_last_indexed_block(&conn)?) else {
+        return Err(eyre!("nothing indexed yet; there is nothing to rebuild"));
+    };
+    let from = config.since_block.max(start_block);
+    if from > last_indexed {
+        return Err(eyre!("--since-block {} is past the last indexed block {last_indexed}", config.since_block));
+    }
+    // A failed block was never archived
+    let archived = db::get_raw_logs_since_block(&conn)?.is_some_and(|since| since <= from)
+        && !db::list_failed_blocks(&conn)?.iter().any(|n| (from..=last_indexed).contains(n));
+    let provider = match (&config.source, archived) {
+        (_, true) => Box::new(chain::Offline) as Box<dyn ChainClient>,
+        (Some(source), false) => chain::connect(source).await?,
+        (None, false) => return Err(match db::get_raw_logs_since_block(&conn)? {
+            Some(since) => eyre!("raw_logs only cover blocks from {since} without failures; set RPC_URL to re-read block {from} on"),
+            None => eyre!("RPC_URL is required: there is no raw log archive (ARCHIVE_RAW_LOGS) to rebuild from"),
+        }),
+    };
+    let dataset = Dataset {
+        token: config.pol_token,
+        binance_addrs: &config.binance_addrs,
+        chain_id: if archived { None } else { Some(provider.chain_id().await?) },
+        source: &format!("rebuild --since-block {from}"),
+    };
+    dataset::check_rebuild(&conn, &dataset)?;
+    let mode = db::get_accounting_mode(&conn)?.unwrap_or(AccountingMode::HotWallet);
+    let deposits = db::list_deposit_addresses(&conn)?
+        .iter()
+        .map(|a| models::parse_address(a))
+        .collect::<Result<_>>()?;
+
+    let indexer = Indexer {
+        binance_topics: config.binance_addrs.iter().map(|a| H256::from(*a)).collect(),
+        provider,
+        pol_token: config.pol_token,
+        binance_addrs: config.binance_addrs.clone(),
+        mode,
+        deposits: RwLock::new(deposits),
+        start_block,
+        clock: config.clock,
+        track_native: false,
+        max_logs: config.max_logs_per_query.max(1),
+        price_feed: None,
+        subgraph: None,
+        archive_raw_logs: false,
+        track_volume: false,
+        track_supply_events: false,
+        track_nfts: false,
+        fast_path: false,
+        contract_labels: None,
+        rules: config.rules,
+        control: Arc::new(Control::new(None)),
+        conn: Arc::new(Mutex::new(conn)),
+        alerts: None,
+        events: crate::events::channel(),
+        session: std::sync::Mutex::new(SessionSummary::default()),
+    };
+    let summary = indexer.patch_range(from, last_indexed, &dataset, archived).await?;
+    info!(from, to = last_indexed, added = summary.added, updated = summary.updated, removed = summary.removed, "Rebuilt");
+    Ok(summary)
+}
+
+/// Sanity-check POL_TOKEN_ADDRESS via `decimals()`; a mismatch usually means a wrong address.
+async fn check_token_decimals(provider: &dyn ChainClient, token: Address) {
+    // keccak256("decimals()")[..4]
+    const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+    match provider.call(token, DECIMALS_SELECTOR.to_vec(), None).await {
+        Ok(out) if out.len() >= 32 => {
+            let decimals = U256::from_big_endian(&out[out.len() - 32..]);
+            if decimals != U256::from(POL_DECIMALS) {
+                warn!(%decimals, expected = POL_DECIMALS, "Token decimals differ from POL; check POL_TOKEN_ADDRESS");
+            }
+        }
+        Ok(_) => warn!(?token, "Token returned no decimals(); check POL_TOKEN_ADDRESS"),
+        Err(e) => warn!(?e, "decimals() call failed"),
+    }
+}
+
+/// The accounting epoch is fixed the first time the indexer runs against a
+/// database: the configured `--start-block`, or the current head if none.
+fn resolve_start_block(conn: &Connection, configured: Option<u64>, head: u64) -> Result<u64> {
+    match (db::get_start_block(conn)?, configured) {
+        (Some(stored), Some(cfg)) if stored != cfg => Err(eyre!(
+            "Database accounting starts at block {stored}; refusing to switch to --start-block {cfg}"
+        )),
+        (Some(stored), _) => Ok(stored),
+        (None, cfg) => {
+            let start = cfg.unwrap_or(head);
+            db::set_start_block(conn, start)?;
+            info!(start_block = start, "Recorded accounting start block");
+            Ok(start)
+        }
+    }
+}
+
+/// Like the start block, the accounting mode can't change under an existing cumulative.
+/// Databases indexed before the mode was recorded are hot-wallet.
+fn resolve_accounting_mode(conn: &Connection, configured: AccountingMode) -> Result<AccountingMode> {
+    let stored = match db::get_accounting_mode(conn)? {
+        Some(mode) => Some(mode),
+        None => db::get_last_indexed_block(conn)?.map(|_| AccountingMode::HotWallet),
+    };
+    match stored {
+        Some(stored) if stored != configured => Err(eyre!(
+            "Database was built with ACCOUNTING_MODE={}; refusing to switch to {}",
+            stored.as_str(), configured.as_str()
+        )),
+        _ => {
+            db::set_accounting_mode(conn, configured)?;
+            Ok(configured)
+        }
+    }
+}
+
+struct Indexer {
+    provider: Box<dyn ChainClient>,
     pol_token: Address,
+    /// Hot wallets
     binance_addrs: Vec<Address>,
-    conn: Connection,
-) -> Result<()> {
-    let ws = Ws::connect(rpc_url).await?;
-    let provider = Provider::new(ws);
-
-    let conn = Arc::new(Mutex::new(conn));
-    let binance_set: Vec<H160> = binance_addrs.clone();
-
-    info!("Indexer started. Subscribing to new heads…");
-
-    let mut stream = provider.subscribe_blocks().await?;
-
-    while let Some(header) = stream.next().await {
-        let number = header.number.ok_or_else(|| eyre!("no block number"))?.as_u64();
-        let hash: H256 = header.hash.unwrap_or_default();
-        info!(block = number, ?hash, "New block");
-
-        // Filter logs for this block, POL token, Transfer topic, and (from OR to) in Binance set
-        let filter = Filter::new()
-            .address(pol_token)
-            .topic0(TRANSFER_TOPIC)
-            .from_block(number)
-            .to_block(number)
-            .or_select({
-                let mut f = Filter::new().address(pol_token).topic0(TRANSFER_TOPIC).from_block(number).to_block(number);
-                f = f.topic1(binance_set.clone()); // from in Binance
-                f
-            })
-            .or_select({
-                let mut f = Filter::new().address(pol_token).topic0(TRANSFER_TOPIC).from_block(number).to_block(number);
-                f = f.topic2(binance_set.clone()); // to in Binance
-                f
+    binance_topics: Vec<H256>,
+    mode: AccountingMode,
+    /// Discovered deposit addresses (deposit-netting mode only)
+    deposits: RwLock<HashSet<Address>>,
+    start_block: u64,
+    clock: Clock,
+    track_native: bool,
+    max_logs: usize,
+    price_feed: Option<PriceFeed>,
+    subgraph: Option<Subgraph>,
+    archive_raw_logs: bool,
+    track_volume: bool,
+    track_supply_events: bool,
+    track_nfts: bool,
+    fast_path: bool,
+    /// `TAG_CONTRACTS`: code hashes to name; `None` leaves counterparties unclassified
+    contract_labels: Option<ContractLabels>,
+    rules: Rules,
+    control: Arc<Control>,
+    conn: Arc<Mutex<Connection>>,
+    alerts: Option<Alerter>,
+    events: EventSender,
+    session: std::sync::Mutex<SessionSummary>,
+}
+
+impl Indexer {
+    /// Close the session: log what it did and store it in `sessions`.
+    async fn finish_session(&self, exit_reason: String) -> Result<()> {
+        let conn = self.conn.lock().await;
+        let mut s = self.session.lock().expect("session lock").clone();
+        s.ended_at_unix = OffsetDateTime::now_utc().unix_timestamp();
+        s.exit_reason = exit_reason;
+        s.final_cumulative = db::get_latest_cumulative(&conn)?.cumulative_netflow_raw;
+        info!(
+            exit = %s.exit_reason,
+            blocks = s.blocks_processed,
+            transfers = s.transfers_indexed,
+            reorgs = s.reorgs,
+            first_block = ?s.first_block,
+            last_block = ?s.last_block,
+            first_block_ts = ?s.first_block_ts_unix,
+            last_block_ts = ?s.last_block_ts_unix,
+            cumulative = %s.final_cumulative,
+            secs = s.ended_at_unix - s.started_at_unix,
+            "Session summary"
+        );
+        db::insert_session(&conn, &s)
+    }
+
+    fn is_binance(&self, addr: &Address) -> bool {
+        self.binance_addrs.contains(addr) || self.deposits.read().expect("deposit set lock").contains(addr)
+    }
+
+    /// How `tr` counts: its direction relative to the tracked addresses, as
+    /// the accounting rules leave it, and the rule bucket it went to, if any.
+    fn classify(&self, tr: &Erc20Transfer) -> (Direction, Option<&str>) {
+        let direction = Direction::of(self.is_binance(&tr.from), self.is_binance(&tr.to));
+        self.rules.apply(&tr.from, &tr.to, tr.value, direction)
+    }
+
+    /// Blocks from webhook deliveries, each with its header read over RPC and
+    /// the delivered logs that `fetch_logs` would have returned. A block whose
+    /// header can't be read is dropped; the next delivery's gap fill covers it.
+    fn delivered_blocks<'a>(&'a self, rx: &'a mut ingest::Receiver) -> impl Stream<Item = (BlockHeader, Option<Vec<RawLog>>)> + Send + 'a {
+        futures_util::stream::unfold((rx, BTreeMap::new()), move |(rx, mut pending)| async move {
+            loop {
+                let Some((number, logs)) = pending.pop_first() else {
+                    let delivered = rx.recv().await?;
+                    pending = ingest::by_block(delivered.into_iter().filter(|l| self.is_tracked(l)).collect());
+                    continue;
+                };
+                match self.provider.get_block(number).await {
+                    // Deliveries carry tracked transfers only; the archive, volume, supply events and NFTs need more
+                    Ok(Some(header)) => return Some(((header, (!self.untracked_logs()).then_some(logs)), (rx, pending))),
+                    Ok(None) => warn!(block = number, "Delivered block not returned by the RPC; left to the next gap fill"),
+                    Err(e) => warn!(block = number, error = %format!("{e:#}"), "Could not read a delivered block's header; left to the next gap fill"),
+                }
+            }
+        })
+    }
+
+    /// Whether every Transfer log of the token is fetched, not only tracked ones.
+    fn all_logs(&self) -> bool {
+        self.archive_raw_logs || self.track_volume
+    }
+
+    /// Whether logs besides the tracked transfers are fetched too: all of
+    /// the token's, the mints and burns for `TRACK_SUPPLY_EVENTS`, or other
+    /// contracts' NFT transfers for `TRACK_NFTS`.
+    fn untracked_logs(&self) -> bool {
+        self.all_logs() || self.track_supply_events || self.track_nfts
+    }
+
+    /// A Transfer log of the POL token from or to a tracked address.
+    fn is_tracked(&self, lg: &RawLog) -> bool {
+        let party = |t: Option<&H256>| t.is_some_and(|t| self.is_binance(&Address::from(*t)));
+        lg.address == self.pol_token
+            && lg.topics.len() == 3
+            && lg.topics[0] == TRANSFER_TOPIC
+            && (party(lg.topics.get(1)) || party(lg.topics.get(2)))
+    }
+
+    /// Logs delivered for an already indexed block: if any transfer among them
+    /// isn't stored, the block is queued for the failed-block retry, which
+    /// reads it again and rebuilds the aggregates.
+    async fn late_delivery(&self, number: u64, logs: &[RawLog]) -> Result<()> {
+        let conn = self.conn.lock().await;
+        for (lg, tr) in logs.iter().filter_map(|lg| Some((lg, decode_transfer(lg)?))) {
+            let block_hash = lg.block_hash.map(|h| HashStr::new(&h));
+            if !db::transfer_exists(&conn, &HashStr::new(&tr.tx_hash), tr.log_index, block_hash.as_deref())? {
+                info!(block = number, tx = ?tr.tx_hash, "Late webhook delivery for an indexed block; re-reading it");
+                return db::record_failed_block(&conn, number, "late webhook delivery");
+            }
+        }
+        Ok(())
+    }
+
+    /// Transfer logs of the POL token in `[from, to]` where from OR to is a Binance address.
+    async fn fetch_logs(&self, from: u64, to: u64) -> Result<Vec<RawLog>> {
+        self.fetch_matching(LogFilter {
+            address: Some(self.pol_token),
+            topic0: TRANSFER_TOPIC,
+            topic1: None,
+            topic2: None,
+            topic3: None,
+            from_block: from,
+            to_block: to,
+            block_hash: None,
+        }).await
+    }
+
+    /// `fetch_logs` for the block with this header's hash, so the logs always
+    /// belong to the header even when another block has since taken its height.
+    async fn fetch_block_logs(&self, header: &BlockHeader) -> Result<Vec<RawLog>> {
+        self.fetch_matching(LogFilter {
+            address: Some(self.pol_token),
+            topic0: TRANSFER_TOPIC,
+            topic1: None,
+            topic2: None,
+            topic3: None,
+            from_block: header.number,
+            to_block: header.number,
+            block_hash: Some(header.hash),
+        }).await
+    }
+
+    async fn fetch_matching(&self, base: LogFilter) -> Result<Vec<RawLog>> {
+        let mut parties = self.binance_topics.clone();
+        parties.extend(self.deposits.read().expect("deposit set lock").iter().map(|a| H256::from(*a)));
+
+        let mut logs = Vec::new();
+        if self.all_logs() {
+            // Every transfer of the token, for the archive or volume; `process_block` picks the tracked ones
+            logs = self.get_logs_complete(base.clone()).await?;
+        } else {
+            // eth_getLogs can't OR across topic positions, so query each side and merge.
+            let mut topics = parties.clone();
+            if self.track_supply_events {
+                // Mints come from the zero address and burns go to it
+                topics.push(H256::zero());
+            }
+            for chunk in topics.chunks(TOPICS_PER_QUERY) {
+                let from_filter = LogFilter { topic1: Some(chunk.to_vec()), ..base.clone() }; // from in Binance
+                let to_filter = LogFilter { topic2: Some(chunk.to_vec()), ..base.clone() }; // to in Binance
+                logs.extend(self.get_logs_complete(from_filter).await?);
+                logs.extend(self.get_logs_complete(to_filter).await?);
+            }
+        }
+        if self.track_nfts {
+            for chunk in parties.chunks(TOPICS_PER_QUERY) {
+                for filter in nft::filters(&base, chunk) {
+                    logs.extend(self.get_logs_complete(filter).await?.into_iter().filter(nft::is_nft_log));
+                }
+            }
+        }
+        // Binance-to-Binance transfers match both queries. The same log from two
+        // versions of a block (a reorg between queries) is kept twice, and
+        // `process_block` rejects the version that isn't canonical.
+        logs.sort_by_key(|l| (l.block_number, l.log_index, l.block_hash));
+        logs.dedup_by_key(|l| (l.block_hash, l.transaction_hash, l.log_index));
+        Ok(logs)
+    }
+
+    /// eth_getLogs that never silently drops logs. A response at `max_logs`
+    /// (possibly truncated) or an over-limit error splits the query: first by
+    /// block range, then, within one block, by topic subset. A single block and
+    /// address that still doesn't fit is an error, not a partial result.
+    async fn get_logs_complete(&self, filter: LogFilter) -> Result<Vec<RawLog>> {
+        let mut pending = vec![filter];
+        let mut out = Vec::new();
+        while let Some(f) = pending.pop() {
+            let reason = match self.provider.get_logs(&f).await {
+                Ok(logs) if logs.len() < self.max_logs => {
+                    out.extend(logs);
+                    continue;
+                }
+                Ok(logs) => format!("{} logs, the RPC_MAX_LOGS cap", logs.len()),
+                Err(e) if is_over_limit(&e) => format!("{e:#}"),
+                Err(e) => return Err(e),
+            };
+            let Some((first, second)) = split_filter(&f) else {
+                return Err(eyre!("eth_getLogs for block {} can't be split further ({reason}); refusing a partial result", f.from_block));
+            };
+            debug!(from = f.from_block, to = f.to_block, %reason, "Splitting eth_getLogs");
+            metrics::GET_LOGS_SPLITS.inc();
+            pending.push(second);
+            pending.push(first);
+        }
+        Ok(out)
+    }
+
+    /// Index `[from, to]` in chunks. Only blocks containing matched transfers are
+    /// fetched and stored in `blocks`; the rest are skipped over.
+    async fn backfill(&self, from: u64, to: u64) -> Result<()> {
+        info!(from, to, "Backfilling");
+        let _progress = progress::start(from, to);
+        let mut subgraph_head = self.subgraph_head().await;
+        let mut chunk_start = from;
+        while chunk_start <= to {
+            let through = self.control.wait_through(chunk_start).await;
+            let (chunk_end, logs) = match subgraph_head.filter(|h| *h >= chunk_start) {
+                Some(head) => {
+                    let chunk_end = (chunk_start + SUBGRAPH_CHUNK - 1).min(to).min(head).min(through);
+                    match self.subgraph_logs(chunk_start, chunk_end).await {
+                        Ok(logs) => (chunk_end, logs),
+                        Err(e) => {
+                            warn!(from = chunk_start, to = chunk_end, error = %format!("{e:#}"), "Subgraph query failed; backfilling the rest with eth_getLogs");
+                            subgraph_head = None;
+                            continue;
+                        }
+                    }
+                }
+                None => {
+                    let chunk_end = (chunk_start + BACKFILL_CHUNK - 1).min(to).min(through);
+                    (chunk_end, self.fetch_logs(chunk_start, chunk_end).await?)
+                }
+            };
+
+            let mut by_block: BTreeMap<u64, Vec<RawLog>> = BTreeMap::new();
+            for lg in logs {
+                if let Some(n) = lg.block_number {
+                    by_block.entry(n).or_default().push(lg);
+                }
+            }
+            let blocks: Vec<(u64, Vec<RawLog>)> = if self.track_native {
+                // Native transfers leave no logs, so every block has to be read
+                (chunk_start..=chunk_end).map(|n| (n, by_block.remove(&n).unwrap_or_default())).collect()
+            } else {
+                by_block.into_iter().collect()
+            };
+            let mut next = chunk_end + 1;
+            for (number, logs) in blocks {
+                let discovered = self.index_or_record(number, None, Some(logs)).await?;
+                progress::advance(number);
+                if discovered {
+                    // Deposits into the new address later in this chunk weren't fetched
+                    next = number + 1;
+                    break;
+                }
+            }
+
+            let c = self.conn.lock().await;
+            db::set_last_indexed_block(&c, next - 1)?;
+            progress::advance(next - 1);
+            chunk_start = next;
+        }
+        Ok(())
+    }
+
+    /// The subgraph's indexed head, or `None` (eth_getLogs only) without a
+    /// subgraph or when it can't be read.
+    async fn subgraph_head(&self) -> Option<u64> {
+        let subgraph = self.subgraph.as_ref()?;
+        match subgraph.indexed_head().await {
+            Ok(head) => {
+                info!(head, "Backfilling from the subgraph up to its indexed block");
+                Some(head)
+            }
+            Err(e) => {
+                warn!(error = %format!("{e:#}"), "Subgraph unavailable; backfilling with eth_getLogs");
+                None
+            }
+        }
+    }
+
+    /// `fetch_logs` over the subgraph.
+    async fn subgraph_logs(&self, from: u64, to: u64) -> Result<Vec<RawLog>> {
+        let subgraph = self.subgraph.as_ref().expect("only called with a subgraph");
+        let mut parties = self.binance_addrs.clone();
+        parties.extend(self.deposits.read().expect("deposit set lock").iter().copied());
+        subgraph.transfers(self.pol_token, TRANSFER_TOPIC, &parties, from, to).await
+    }
+
+    /// Fetch (unless `logs` are given) and store one block: the one `head`
+    /// names if given, else whichever is at `number` now. Fetched logs are
+    /// read by the header's hash.
+    ///
+    /// With `FAST_PATH`, a new block's logs are read first when its hash is
+    /// known, and their net-flow is published before anything else runs.
+    async fn index_block(&self, number: u64, head: Option<BlockHeader>, mut logs: Option<Vec<RawLog>>) -> Result<bool> {
+        let mut timing = Timing::start();
+        if self.fast_path {
+            if let (None, Some(head)) = (&logs, &head) {
+                logs = Some(timing.rpc(self.fetch_block_logs(head)).await?);
+            }
+            if let Some(logs) = &logs {
+                self.publish_provisional(number, logs).await?;
+            }
+        }
+        let logs_hash = logs.as_ref().and_then(|logs| logs.iter().find_map(|l| l.block_hash));
+        let (block, ts_interpolated) = timing.rpc(self.block_header(number, head, logs_hash)).await?;
+        let logs = match logs {
+            Some(logs) => logs,
+            None => timing.rpc(self.fetch_block_logs(&block)).await?,
+        };
+        let native = if self.track_native { timing.rpc(self.native_transfers(number)).await? } else { Vec::new() };
+        self.process_block(number, block.hash, block.timestamp as i64, ts_interpolated, logs, native, timing).await
+    }
+
+    /// `FAST_PATH`: the net-flow of block `number`'s matched transfers on top
+    /// of the stored cumulative, as an event and in the metrics. Nothing is
+    /// stored; `process_block` confirms it.
+    async fn publish_provisional(&self, number: u64, logs: &[RawLog]) -> Result<()> {
+        let (mut inflow, mut outflow, mut count) = (U256::zero(), U256::zero(), 0);
+        for tr in logs.iter().filter(|l| self.is_tracked(l)).filter_map(decode_transfer) {
+            match self.classify(&tr).0 {
+                Direction::In => inflow = inflow.saturating_add(tr.value),
+                Direction::Out => outflow = outflow.saturating_add(tr.value),
+                Direction::Internal | Direction::None => {}
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        let received_at_ms = (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64;
+        let before = aggregates::parse_cumulative(&db::get_latest_cumulative(&*self.conn.lock().await)?.cumulative_netflow_raw);
+        let after = aggregates::Delta::new(inflow, outflow).apply(before);
+        metrics::NETFLOW_PROVISIONAL_BLOCK.set(number as i64);
+        metrics::NETFLOW_PROVISIONAL_CUMULATIVE.set(ValueFormat::Scaled.render(&after.to_string()).parse().unwrap_or(0.0));
+        // Err just means nobody is subscribed
+        let _ = self.events.send(Event::ProvisionalNetflow(ProvisionalNetflow {
+            block_number: number,
+            block_hash: logs.iter().find_map(|l| l.block_hash).map(|h| format!("{h:?}")),
+            inflow: inflow.to_string(),
+            outflow: outflow.to_string(),
+            cumulative: after.to_string(),
+            transfer_count: count,
+            received_at_ms,
+        }).into());
+        Ok(())
+    }
+
+    /// Block `number`'s header with a timestamp, and whether the timestamp
+    /// is an estimate. That is `head` when its timestamp is set. Otherwise
+    /// the header is read over RPC, retrying while the RPC fails, returns
+    /// nothing or leaves the timestamp out. When the retries run out, but the
+    /// block's hash is known (from `head`, `logs_hash` or a header without a
+    /// timestamp), the timestamp is interpolated from the neighbouring stored
+    /// blocks.
+    async fn block_header(&self, number: u64, head: Option<BlockHeader>, logs_hash: Option<H256>) -> Result<(BlockHeader, bool)> {
+        if let Some(h) = head.as_ref().filter(|h| h.timestamp > 0) {
+            return Ok((h.clone(), false));
+        }
+        let mut hashed = head;
+        let mut failure = eyre!("block {number} not returned by the RPC");
+        let mut delay = HEADER_RETRY_DELAY;
+        for attempt in 1..=HEADER_ATTEMPTS {
+            match self.provider.get_block(number).await {
+                // Another hash than the head's is another version of the block; the head's is the one indexed
+                Ok(Some(h)) if h.timestamp > 0 && hashed.as_ref().is_none_or(|k| k.hash == h.hash) => return Ok((h, false)),
+                Ok(Some(h)) => {
+                    failure = eyre!("block {number}'s header has no timestamp");
+                    hashed.get_or_insert(h);
+                }
+                Ok(None) => {}
+                Err(e) => failure = e,
+            }
+            if attempt < HEADER_ATTEMPTS {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+        let (hash, parent_hash) = match (hashed, logs_hash) {
+            (Some(h), _) => (h.hash, h.parent_hash),
+            (None, Some(hash)) => (hash, H256::zero()),
+            (None, None) => return Err(failure),
+        };
+        let Some(ts_unix) = db::interpolated_ts(&*self.conn.lock().await, number)? else {
+            return Err(failure.wrap_err("no stored block to estimate its timestamp from"));
+        };
+        warn!(block = number, ts_unix, error = %format!("{failure:#}"), "Block timestamp unavailable; interpolated from neighbouring blocks");
+        metrics::BLOCK_TS_INTERPOLATED.inc();
+        Ok((BlockHeader { number, hash, parent_hash, timestamp: ts_unix.max(0) as u64 }, true))
+    }
+
+    /// Top-level POL value transfers in block `number` to or from a tracked address.
+    async fn native_transfers(&self, number: u64) -> Result<Vec<NativeTransferRecord>> {
+        let mut out = Vec::new();
+        for tx in self.provider.block_transactions(number).await? {
+            let Some(to) = tx.to else { continue };
+            let direction = Direction::of(self.is_binance(&tx.from), self.is_binance(&to));
+            if tx.value.is_zero() || direction == Direction::None {
+                continue;
+            }
+            let status = self.provider.transaction_status(tx.hash).await?.map(TxStatus::from_receipt);
+            out.push(NativeTransferRecord {
+                block_number: number,
+                tx_hash: format!("{:?}", tx.hash),
+                from: models::address_key(&tx.from),
+                to: models::address_key(&to),
+                value: tx.value.to_string(),
+                direction,
+                status,
             });
+        }
+        Ok(out)
+    }
 
-        let logs = provider.get_logs(&filter).await?;
+    /// Store a block's native transfers and extend the native running totals.
+    fn apply_native(&self, conn: &Connection, number: u64, ts_unix: i64, native: &[NativeTransferRecord]) -> Result<()> {
+        let mut inflow = U256::zero();
+        let mut outflow = U256::zero();
+        let mut count = 0;
+        for t in native {
+            // Already counted when the block was first processed
+            if !db::insert_native_transfer(conn, t)? || t.status == Some(TxStatus::Reverted) {
+                continue;
+            }
+            let value = U256::from_dec_str(&t.value)?;
+            match t.direction {
+                Direction::In => inflow = inflow.saturating_add(value),
+                Direction::Out => outflow = outflow.saturating_add(value),
+                Direction::Internal | Direction::None => {}
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return Ok(());
+        }
+        let (cum_in, cum_out) = db::latest_native_netflow(conn)?
+            .map(|r| (U256::from_dec_str(&r.cumulative_inflow).unwrap_or_default(), U256::from_dec_str(&r.cumulative_outflow).unwrap_or_default()))
+            .unwrap_or_default();
+        db::insert_native_netflow_block(conn, &NativeNetflowBlock {
+            block_number: number,
+            ts_unix,
+            inflow: inflow.to_string(),
+            outflow: outflow.to_string(),
+            cumulative_inflow: cum_in.saturating_add(inflow).to_string(),
+            cumulative_outflow: cum_out.saturating_add(outflow).to_string(),
+            transfer_count: count,
+        })?;
+        info!(block = number, %inflow, %outflow, "Native POL flows recorded");
+        Ok(())
+    }
 
-        // Fetch timestamp
-        let block = provider.get_block(BlockId::Number(BlockNumber::Number(number.into()))).await?;
-        let ts_unix = block
-            .and_then(|b| b.timestamp.as_u64().into())
-            .unwrap_or(0) as i64;
+    /// `index_block`, isolating failures: a block that can't be indexed is
+    /// logged, recorded in `failed_blocks` and skipped so the loop keeps going;
+    /// `retry_failed_blocks` picks it up later. Only DB errors propagate,
+    /// including a busy or full database while indexing the block.
+    async fn index_or_record(&self, number: u64, head: Option<BlockHeader>, logs: Option<Vec<RawLog>>) -> Result<bool> {
+        match self.index_block(number, head, logs).await {
+            Ok(discovered) => Ok(discovered),
+            Err(e) if db::is_transient(&e) => Err(e),
+            Err(e) => {
+                warn!(block = number, error = %format!("{e:#}"), "Failed to index block; recorded for retry");
+                let conn = self.conn.lock().await;
+                db::record_failed_block(&conn, number, &format!("{e:#}"))?;
+                db::advance_last_indexed_block(&conn, number)?;
+                Ok(false)
+            }
+        }
+    }
 
-        // Persist block
+    /// Background retry of `failed_blocks`, every `every`.
+    async fn retry_failed_blocks(self: Arc<Self>, every: Duration) {
+        let mut tick = tokio::time::interval(every);
+        tick.tick().await; // the first tick is immediate
+        loop {
+            tick.tick().await;
+            if !self.control.is_held() {
+                self.retry_failed().await;
+            }
+        }
+    }
+
+    /// One pass over `failed_blocks`. A recovered block is older than ones
+    /// already indexed; `process_block` counts it from its predecessor and
+    /// rebuilds the later cumulatives in the same transaction.
+    async fn retry_failed(&self) {
+        let failed = match db::list_failed_blocks(&*self.conn.lock().await) {
+            Ok(failed) => failed,
+            Err(e) => {
+                warn!(?e, "Could not list failed blocks");
+                return;
+            }
+        };
+        for number in failed {
+            let result = match self.index_block(number, None, None).await {
+                Ok(_) => db::clear_failed_block(&*self.conn.lock().await, number)
+                    .map(|_| info!(block = number, "Recovered failed block")),
+                Err(e) => {
+                    warn!(block = number, error = %format!("{e:#}"), "Retry of failed block failed");
+                    db::record_failed_block(&*self.conn.lock().await, number, &format!("{e:#}"))
+                }
+            };
+            if let Err(e) = result {
+                warn!(?e, block = number, "Could not update failed_blocks");
+            }
+        }
+    }
+
+    /// `rebuild` over `[from, to]`: fetch the transfers the current address
+    /// set matches (from `raw_logs` when `archived`), then in one transaction
+    /// store the missing ones, fix the attribution of the rest, drop stored
+    /// ones nobody tracked is party to, rebuild the aggregates and record
+    /// `dataset` as the configuration.
+    async fn patch_range(&self, from: u64, to: u64, dataset: &Dataset<'_>, archived: bool) -> Result<RebuildSummary> {
+        let mut logs = Vec::new();
+        // Archived blocks' hash, timestamp and whether it is interpolated, for blocks first stored now
+        let mut blocks: BTreeMap<u64, (H256, i64, bool)> = BTreeMap::new();
+        if archived {
+            for block in db::archived_logs(&*self.conn.lock().await, from, to)? {
+                blocks.insert(block.number, (block.block_hash.parse().unwrap_or_default(), block.ts_unix, false));
+                logs.extend(block.logs.into_iter().filter(|l| self.is_tracked(l)));
+            }
+        } else {
+            let _progress = progress::start(from, to);
+            let mut chunk_start = from;
+            while chunk_start <= to {
+                let chunk_end = (chunk_start + BACKFILL_CHUNK - 1).min(to);
+                logs.extend(self.fetch_logs(chunk_start, chunk_end).await?);
+                progress::advance(chunk_end);
+                chunk_start = chunk_end + 1;
+            }
+        }
+
+        // Blocks and receipt statuses are only needed for transfers not stored
+        // yet. A stored block keeps its row, so only its hash matters
+        let mut fresh = HashSet::new();
+        {
+            let conn = self.conn.lock().await;
+            for lg in &logs {
+                let Some(tr) = decode_transfer(lg) else { continue };
+                let block_hash = lg.block_hash.map(|h| HashStr::new(&h));
+                if db::transfer_exists(&conn, &HashStr::new(&tr.tx_hash), tr.log_index, block_hash.as_deref())? {
+                    continue;
+                }
+                if let Some(stored) = db::get_block_hash(&conn, tr.block_number)? {
+                    blocks.insert(tr.block_number, (stored.parse().unwrap_or_default(), 0, false));
+                }
+                fresh.insert((tr.tx_hash, tr.log_index));
+            }
+        }
+        for lg in logs.iter().filter(|l| l.transaction_hash.zip(l.log_index).is_some_and(|k| fresh.contains(&k))) {
+            let number = lg.block_number.unwrap_or_default();
+            if let std::collections::btree_map::Entry::Vacant(e) = blocks.entry(number) {
+                let (block, ts_interpolated) = self.block_header(number, None, lg.block_hash).await?;
+                e.insert((block.hash, block.timestamp as i64, ts_interpolated));
+            }
+            if lg.block_hash.is_some_and(|h| h != blocks[&number].0) {
+                return Err(eyre!("logs for block {number} don't match its stored or canonical hash (a reorg?); run the rebuild again"));
+            }
+        }
+        let fresh_logs: Vec<RawLog> = logs.iter()
+            .filter(|l| l.transaction_hash.zip(l.log_index).is_some_and(|k| fresh.contains(&k)))
+            .cloned()
+            .collect();
+        let statuses = if archived { HashMap::new() } else { self.receipt_statuses(&fresh_logs).await? };
+
+        let mut summary = RebuildSummary { from_block: from, to_block: to, from_archive: archived, ..Default::default() };
+        let mut guard = self.conn.lock().await;
+        let conn = guard.transaction()?;
+        let mut matched = HashSet::new();
+        for lg in &logs {
+            let Some(tr) = decode_transfer(lg) else { continue };
+            let tx_hash = HashStr::new(&tr.tx_hash);
+            let (direction, bucket) = self.classify(&tr);
+            let block_hash = match fresh.contains(&(tr.tx_hash, tr.log_index)) {
+                true => blocks[&tr.block_number].0,
+                false => lg.block_hash.unwrap_or_default(),
+            };
+            let block_hash = HashStr::new(&block_hash);
+            matched.insert((tx_hash.to_string(), tr.log_index, block_hash.to_string()));
+            if fresh.contains(&(tr.tx_hash, tr.log_index)) {
+                let (_, ts_unix, ts_interpolated) = blocks[&tr.block_number];
+                db::insert_block(&conn, tr.block_number, &block_hash, ts_unix)?;
+                if ts_interpolated {
+                    db::mark_ts_interpolated(&conn, tr.block_number)?;
+                }
+                let status = lg.transaction_hash.and_then(|h| statuses.get(&h).copied().flatten());
+                if db::insert_transfer(
+                    &conn,
+                    tr.block_number,
+                    &block_hash,
+                    &tx_hash,
+                    tr.log_index,
+                    &AddressStr::new(&lg.address),
+                    &AddressStr::new(&tr.from),
+                    &AddressStr::new(&tr.to),
+                    &DecimalStr::new(&tr.value),
+                    direction,
+                    status,
+                )? {
+                    if bucket.is_some() {
+                        db::set_transfer_bucket(&conn, &tx_hash, tr.log_index, &block_hash, bucket)?;
+                    }
+                    summary.added += 1;
+                    continue;
+                }
+            }
+            // Stored already, or an import without the block hash just took it
+            let redirected = db::set_transfer_direction(&conn, &tx_hash, tr.log_index, &block_hash, direction)?;
+            let rebucketed = db::set_transfer_bucket(&conn, &tx_hash, tr.log_index, &block_hash, bucket)?;
+            if redirected || rebucketed {
+                summary.updated += 1;
+            }
+        }
+        for (tx_hash, log_index, block_hash) in db::transfer_keys_by_range(&conn, from, to)? {
+            if !matched.contains(&(tx_hash.clone(), log_index, block_hash.clone())) {
+                db::delete_transfer(&conn, &tx_hash, log_index, &block_hash)?;
+                summary.removed += 1;
+            }
+        }
+        aggregates::rebuild(&conn, self.clock)?;
+        dataset::record(&conn, dataset)?;
+        conn.commit()?;
+        Ok(summary)
+    }
+
+    /// Whether `header` shows that the stored chain tip is no longer canonical:
+    /// a different hash at a height we already indexed, or a parent that isn't
+    /// our last block. Blocks stored without a hash (imports) can't be checked.
+    async fn tip_orphaned(&self, header: &BlockHeader, last: u64) -> Result<bool> {
+        let (number, expected) = if header.number <= last {
+            (header.number, header.hash)
+        } else if header.number == last + 1 {
+            (last, header.parent_hash)
+        } else {
+            // Heads were missed; compare our tip with the canonical block at that height
+            match self.provider.get_block(last).await? {
+                Some(b) => (last, b.hash),
+                None => return Ok(false),
+            }
+        };
+        let stored = db::get_block_hash(&*self.conn.lock().await, number)?;
+        Ok(stored.is_some_and(|h| !h.is_empty() && h != format!("{expected:?}")))
+    }
+
+    /// Highest stored block at or below `at_most` whose hash is still canonical.
+    async fn find_fork_point(&self, at_most: u64) -> Result<u64> {
+        let stored = db::recent_block_hashes(&*self.conn.lock().await, at_most, MAX_REORG_DEPTH)?;
+        for (number, hash) in &stored {
+            let canonical = self.provider.get_block(*number).await?;
+            if canonical.is_some_and(|b| format!("{:?}", b.hash) == *hash) {
+                return Ok(*number);
+            }
+        }
+        if stored.len() as u32 == MAX_REORG_DEPTH {
+            return Err(eyre!(
+                "Reorg below block {at_most} is deeper than {MAX_REORG_DEPTH} stored blocks; restore a snapshot and re-index"
+            ));
+        }
+        // No stored block survives: roll back to the start of the epoch
+        Ok(self.start_block.saturating_sub(1))
+    }
+
+    /// Drop everything above `fork`, restore the cumulative as it stood after
+    /// `fork`, and record the reorg. Returns the new last indexed block.
+    async fn roll_back(&self, header: &BlockHeader, fork: u64) -> Result<u64> {
+        let fork = fork.max(self.start_block.saturating_sub(1));
+        let mut guard = self.conn.lock().await;
+        let tx = guard.transaction()?;
+        let last = db::get_last_indexed_block(&tx)?.unwrap_or(fork);
+
+        let depth = last.saturating_sub(fork);
+        let orphaned_hashes = db::recent_block_hashes(&tx, last, depth.min(u32::MAX as u64) as u32)?
+            .into_iter()
+            .filter(|(n, _)| *n > fork)
+            .map(|(_, h)| h)
+            .collect();
+        let removed_transfers = db::get_transfers_by_range(&tx, fork + 1, u64::MAX)?;
+        let mut removed_inflow = U256::zero();
+        let mut removed_outflow = U256::zero();
+        for b in db::get_netflow_blocks_by_range(&tx, fork + 1, u64::MAX)? {
+            removed_inflow = removed_inflow.saturating_add(U256::from_dec_str(&b.inflow).unwrap_or_default());
+            removed_outflow = removed_outflow.saturating_add(U256::from_dec_str(&b.outflow).unwrap_or_default());
+        }
+        let cumulative_before = db::get_latest_cumulative(&tx)?.cumulative_netflow_raw;
+        let (cumulative_block, cumulative_after, usd_after) = db::cumulative_at(&tx, fork)?
+            .unwrap_or((fork, "0".to_string(), None));
+
+        db::delete_after(&tx, fork)?;
+        db::update_cumulative(&tx, cumulative_block, &cumulative_after, usd_after, self.clock.stamp(header.timestamp as i64))?;
+        db::set_last_indexed_block(&tx, fork)?;
+        let record = ReorgRecord {
+            id: 0,
+            detected_at_unix: OffsetDateTime::now_utc().unix_timestamp(),
+            new_head: header.number,
+            new_head_hash: format!("{:?}", header.hash),
+            fork_block: fork,
+            depth,
+            orphaned_hashes,
+            removed_transfers,
+            removed_inflow: removed_inflow.to_string(),
+            removed_outflow: removed_outflow.to_string(),
+            cumulative_before,
+            cumulative_after,
+        };
+        db::insert_reorg(&tx, &record)?;
+        let deposits = db::list_deposit_addresses(&tx)?;
+        tx.commit()?;
+        self.session.lock().expect("session lock").reorgs += 1;
+
+        if self.mode == AccountingMode::DepositNetting {
+            *self.deposits.write().expect("deposit set lock") = deposits.iter()
+                .map(|a| models::parse_address(a))
+                .collect::<Result<_>>()?;
+        }
+        warn!(
+            new_head = header.number, fork_block = fork, depth = record.depth,
+            removed_transfers = record.removed_transfers.len(),
+            cumulative_before = %record.cumulative_before, cumulative_after = %record.cumulative_after,
+            "Chain reorganisation; rolled back to fork block"
+        );
+        Ok(fork)
+    }
+
+    /// Read the balance of each tracked address that has no baseline yet, as
+    /// of the block before the first one indexed for it. Baseline plus the
+    /// stored transfers is then its implied balance (`GET /balances`). An old
+    /// block needs an archive node; a failed read is retried on the next start.
+    async fn record_baselines(&self, last_indexed: Option<u64>) -> Result<()> {
+        let block = last_indexed.map_or(self.start_block, |b| b + 1).max(self.start_block).saturating_sub(1);
+        for address in &self.binance_addrs {
+            let key = models::address_key(address);
+            if db::has_balance_baseline(&*self.conn.lock().await, &key)? {
+                continue;
+            }
+            match self.token_balance(*address, block).await {
+                Ok(balance) => {
+                    db::insert_balance_baseline(&*self.conn.lock().await, &BalanceBaseline {
+                        address: key,
+                        block_number: block,
+                        balance: balance.to_string(),
+                        recorded_at_unix: OffsetDateTime::now_utc().unix_timestamp(),
+                    })?;
+                    info!(?address, block, %balance, "Recorded baseline balance");
+                }
+                Err(e) => warn!(?address, block, error = %format!("{e:#}"), "Could not read the baseline balance; retrying on the next start"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Store `totalSupply()` at `block`; a failure only costs the percentages.
+    async fn refresh_supply(&self, block: u64) {
+        let supply = match supply::fetch(self.provider.as_ref(), self.pol_token, block).await {
+            Ok(supply) => supply,
+            Err(e) => {
+                warn!(?e, "totalSupply() read failed");
+                return;
+            }
+        };
+        if let Err(e) = db::set_total_supply(&*self.conn.lock().await, &supply.to_string(), block) {
+            warn!(?e, "Could not store the total supply");
+        }
+        debug!(%supply, block, "Read total supply");
+    }
+
+    /// Record the token's EIP-1967 implementation and warn/alert when it differs
+    /// from the last one seen. Upgrades can change what `Transfer` events mean.
+    async fn check_implementation(&self, head: u64) -> Result<()> {
+        let slot = self.provider.storage_at(self.pol_token, IMPLEMENTATION_SLOT).await?;
+        if slot.is_zero() {
+            return Ok(()); // not an EIP-1967 proxy
+        }
+        let implementation = Address::from(slot);
+        let token = models::address_key(&self.pol_token);
+        let conn = self.conn.lock().await;
+        let previous = db::get_token_implementation(&conn, &token)?;
+        if previous.as_ref().is_some_and(|(_, prev)| *prev == models::address_key(&implementation)) {
+            return Ok(());
+        }
+        db::insert_token_implementation(&conn, &token, head, &models::address_key(&implementation))?;
+        match previous {
+            None => info!(?implementation, block = head, "Recorded token proxy implementation"),
+            Some((since, prev)) => {
+                warn!(previous = %prev, since, ?implementation, block = head, "Token proxy implementation changed; check event semantics");
+                if let Some(alerts) = &self.alerts {
+                    alerts.send(Alert::ImplementationChanged {
+                        block_number: head,
+                        token: self.pol_token,
+                        previous: prev.parse().unwrap_or_default(),
+                        implementation,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `(kind, label)` of the sender of each inflow and the recipient of each
+    /// outflow in `logs` (`TAG_CONTRACTS`), from `contracts` or read with
+    /// eth_getCode; those read are also returned, for caching with the block.
+    /// An address whose code can't be read stays unclassified until it next
+    /// appears.
+    async fn counterparties(&self, logs: &[RawLog]) -> (HashMap<Address, (CounterpartyKind, Option<String>)>, Vec<(Address, Classification)>) {
+        let mut known = HashMap::new();
+        let mut read = Vec::new();
+        let Some(labels) = &self.contract_labels else { return (known, read) };
+        let mut unseen = Vec::new();
         {
-            let c = conn.lock().await;
-            db::insert_block(&c, number, &format!("{:?}", hash), ts_unix)?;
+            let conn = self.conn.lock().await;
+            for tr in logs.iter().filter_map(decode_transfer) {
+                let other = match self.classify(&tr).0 {
+                    Direction::In => tr.from,
+                    Direction::Out => tr.to,
+                    Direction::Internal | Direction::None => continue,
+                };
+                if known.contains_key(&other) || unseen.contains(&other) {
+                    continue;
+                }
+                match db::get_contract(&conn, &models::address_key(&other)) {
+                    Ok(Some(cached)) => {
+                        known.insert(other, cached);
+                    }
+                    Ok(None) => unseen.push(other),
+                    Err(e) => warn!(?e, "Could not read the contracts cache"),
+                }
+            }
+        }
+        let codes = futures_util::future::join_all(unseen.iter().map(|a| self.provider.code_at(*a))).await;
+        for (address, code) in unseen.into_iter().zip(codes) {
+            match code {
+                Ok(code) => {
+                    let c = contracts::classify(&code, labels);
+                    let kind = if c.code_hash.is_some() { CounterpartyKind::Contract } else { CounterpartyKind::Eoa };
+                    known.insert(address, (kind, c.label.clone()));
+                    read.push((address, c));
+                }
+                Err(e) => warn!(?address, error = %format!("{e:#}"), "eth_getCode failed; counterparty left unclassified"),
+            }
+        }
+        (known, read)
+    }
+
+    /// Deposit-netting discovery: transfers `(tx_hash, log_index)` into a hot wallet
+    /// from a not-yet-known address that leave the sender's token balance at zero
+    /// by the end of the block. Deposit addresses sweep everything; users sending
+    /// part of their holdings don't match.
+    async fn find_sweeps(&self, number: u64, logs: &[RawLog], statuses: &HashMap<H256, Option<TxStatus>>) -> HashSet<(H256, u64)> {
+        let mut out = HashSet::new();
+        if self.mode != AccountingMode::DepositNetting {
+            return out;
+        }
+        let mut checked: HashMap<Address, bool> = HashMap::new();
+        for lg in logs {
+            let Some(tr) = decode_transfer(lg) else { continue };
+            let reverted = lg.transaction_hash
+                .and_then(|h| statuses.get(&h).copied().flatten()) == Some(TxStatus::Reverted);
+            if reverted || !self.binance_addrs.contains(&tr.to) || self.is_binance(&tr.from) {
+                continue;
+            }
+            let emptied = match checked.get(&tr.from) {
+                Some(v) => *v,
+                None => {
+                    let v = match self.token_balance(tr.from, number).await {
+                        Ok(balance) => balance.is_zero(),
+                        Err(e) => {
+                            warn!(?e, address = ?tr.from, block = number, "balanceOf failed; not treating as deposit address");
+                            false
+                        }
+                    };
+                    checked.insert(tr.from, v);
+                    v
+                }
+            };
+            if emptied {
+                out.insert((tr.tx_hash, tr.log_index));
+            }
+        }
+        out
+    }
+
+    /// `balanceOf(holder)` of the POL token at `block` (needs archive state when backfilling).
+    async fn token_balance(&self, holder: Address, block: u64) -> Result<U256> {
+        // keccak256("balanceOf(address)")[..4]
+        const BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+        let mut data = BALANCE_OF_SELECTOR.to_vec();
+        data.extend_from_slice(H256::from(holder).as_bytes());
+        let out = self.provider.call(self.pol_token, data, Some(block)).await?;
+        if out.len() < 32 {
+            return Err(eyre!("balanceOf returned {} bytes", out.len()));
+        }
+        Ok(U256::from_big_endian(&out[out.len() - 32..]))
+    }
+
+    /// Receipt status for each distinct transaction among `logs`.
+    async fn receipt_statuses(&self, logs: &[RawLog]) -> Result<HashMap<H256, Option<TxStatus>>> {
+        let mut out = HashMap::new();
+        for tx in logs.iter().filter_map(|l| l.transaction_hash) {
+            if let Entry::Vacant(e) = out.entry(tx) {
+                let status = self.provider.transaction_status(tx).await?;
+                if status.is_none() {
+                    warn!(?tx, "No receipt status for transaction");
+                }
+                e.insert(status.map(TxStatus::from_receipt));
+            }
+        }
+        Ok(out)
+    }
+
+    fn maybe_alert(&self, tr: &Erc20Transfer, inflow: bool, ts_unix: i64) {
+        let Some(alerts) = &self.alerts else { return };
+        let fresh = OffsetDateTime::now_utc().unix_timestamp() - ts_unix <= ALERT_MAX_AGE_SECS;
+        if fresh && alerts.is_large(tr.value) {
+            alerts.send(Alert::LargeTransfer {
+                block_number: tr.block_number,
+                tx_hash: format!("{:?}", tr.tx_hash),
+                from: tr.from,
+                to: tr.to,
+                value: tr.value,
+                inflow,
+            });
+        }
+    }
+
+    /// Persist one block and its transfers and advance the cumulative, atomically.
+    /// Returns true if new deposit addresses were discovered.
+    #[allow(clippy::too_many_arguments)]
+    async fn process_block(
+        &self, number: u64, hash: H256, ts_unix: i64, ts_interpolated: bool, mut logs: Vec<RawLog>, native: Vec<NativeTransferRecord>,
+        mut timing: Timing,
+    ) -> Result<bool> {
+        if let Some(stale) = logs.iter().find_map(|l| l.block_hash.filter(|h| *h != hash)) {
+            return Err(eyre!("logs for block {number} came from {stale:?}, but the canonical block is {hash:?}; refetching"));
+        }
+        let logs_fetched = logs.len() as u64;
+        let nft_transfers: Vec<NftTransfer> = match self.track_nfts {
+            true => {
+                let (nfts, token_logs) = logs.into_iter().partition(nft::is_nft_log);
+                logs = token_logs;
+                nfts.iter()
+                    .flat_map(|lg: &RawLog| nft::transfers(lg, ts_unix, |from, to| Direction::of(self.is_binance(from), self.is_binance(to))))
+                    .collect()
+            }
+            false => Vec::new(),
+        };
+        let archive = match self.archive_raw_logs {
+            true if !logs.is_empty() => Some(logs.clone()),
+            _ => None,
+        };
+        let volume = match self.track_volume {
+            true if !logs.is_empty() => Some(block_volume(number, ts_unix, &logs)),
+            _ => None,
+        };
+        let supply_transfers: Vec<Erc20Transfer> = match self.track_supply_events {
+            true => logs.iter()
+                .filter(|l| l.address == self.pol_token)
+                .filter_map(decode_transfer)
+                .filter(|tr| SupplyEventKind::of(tr).is_some())
+                .collect(),
+            false => Vec::new(),
+        };
+        if self.untracked_logs() {
+            logs.retain(|l| self.is_tracked(l));
+        }
+        let statuses = timing.rpc(self.receipt_statuses(&logs)).await?;
+        let sweeps = timing.rpc(self.find_sweeps(number, &logs, &statuses)).await;
+        let (counterparties, new_counterparties) = timing.rpc(self.counterparties(&logs)).await;
+        let price = match &self.price_feed {
+            Some(feed) if !logs.is_empty() => Some(timing.rpc(feed.price_at(self.provider.as_ref(), number)).await?),
+            _ => None,
+        };
+
+        let mut guard = self.conn.lock().await;
+        let conn = guard.transaction()?;
+
+        if db::previous_block_ts(&conn, number)?.is_some_and(|prev| ts_unix < prev) {
+            warn!(block = number, ts_unix, "Block timestamp is earlier than a preceding block's; check the RPC provider");
+        }
+
+        // Persist block
+        let block_hash = HashStr::new(&hash);
+        db::insert_block(&conn, number, &block_hash, ts_unix)?;
+        if ts_interpolated {
+            db::mark_ts_interpolated(&conn, number)?;
+        }
+        if let Some(all) = &archive {
+            db::insert_raw_logs(&conn, number, &block_hash, ts_unix, all)?;
+        }
+        if let Some(row) = &volume {
+            db::insert_volume_block(&conn, row)?;
+        }
+        if !supply_transfers.is_empty() {
+            for e in supply::record_events(&conn, number, ts_unix, &supply_transfers)? {
+                info!(block = number, kind = e.kind.as_str(), account = %e.account, value = %e.value, "Supply event");
+            }
+        }
+        for t in &nft_transfers {
+            db::insert_nft_transfer(&conn, t)?;
+        }
+        for (address, c) in &new_counterparties {
+            let code_hash = c.code_hash.map(|h| format!("{h:?}"));
+            db::insert_contract(&conn, &models::address_key(address), code_hash.as_deref(), c.label.as_deref(), number)?;
         }
 
         // Process logs
-        let mut delta: i128 = 0; // signed delta on raw units
+        let mut inflow = U256::zero();
+        let mut outflow = U256::zero();
+        let mut matched: u64 = 0;
+        let mut published = Vec::new();
+        let mut discovered = Vec::new();
         for lg in logs {
             if let Some(tr) = decode_transfer(&lg) {
-                let from_is_binance = binance_addrs.contains(&tr.from);
-                let to_is_binance = binance_addrs.contains(&tr.to);
-
-                // raw value(U256) -> i128 via string (lossless for storage; for math we clamp to i128 range for delta sign, but we also use U256 for accumulation)
-                let value_str = tr.value.to_string();
-
-                {
-                    let c = conn.lock().await;
-                    db::insert_transfer(
-                        &c,
-                        tr.block_number,
-                        &tr.tx_hash,
-                        tr.log_index,
-                        &format!("{:?}", lg.address),
-                        &format!("{:?}", tr.from),
-                        &format!("{:?}", tr.to),
-                        &value_str,
-                        to_is_binance,
-                        from_is_binance,
-                    )?;
+                let status = lg.transaction_hash.and_then(|h| statuses.get(&h).copied().flatten());
+                let (direction, bucket) = self.classify(&tr);
+                if sweeps.contains(&(tr.tx_hash, tr.log_index)) {
+                    // First sweep from an unknown address: counted as an inflow now (its
+                    // deposits predate discovery), and as deposit address from here on
+                    if db::insert_deposit_address(&conn, &models::address_key(&tr.from), &models::address_key(&tr.to), number, &format!("{:?}", tr.tx_hash))? {
+                        info!(address = ?tr.from, block = number, "Discovered deposit address");
+                        discovered.push(tr.from);
+                    }
                 }
 
-                if to_is_binance && !from_is_binance {
-                    // inflow to Binance: +value
-                    // For delta sign only; accumulation below uses U256 safe add/sub
-                    // Convert to i128 safely by capping at i128::MAX if overflow
-                    let part = value_str.parse::<i128>().unwrap_or(i128::MAX);
-                    delta = delta.saturating_add(part);
+                // Keys are rendered on the stack; owned strings only for rows actually stored
+                let tx_hash = HashStr::new(&tr.tx_hash);
+                let token = AddressStr::new(&lg.address);
+                let from = AddressStr::new(&tr.from);
+                let to = AddressStr::new(&tr.to);
+                let value = DecimalStr::new(&tr.value);
+                let inserted = db::insert_transfer(
+                    &conn,
+                    tr.block_number,
+                    &block_hash,
+                    &tx_hash,
+                    tr.log_index,
+                    &token,
+                    &from,
+                    &to,
+                    &value,
+                    direction,
+                    status,
+                )?;
+                if inserted && bucket.is_some() {
+                    db::set_transfer_bucket(&conn, &tx_hash, tr.log_index, &block_hash, bucket)?;
+                }
+                if inserted {
+                    let counterparty = match direction {
+                        Direction::In => counterparties.get(&tr.from),
+                        Direction::Out => counterparties.get(&tr.to),
+                        Direction::Internal | Direction::None => None,
+                    };
+                    published.push(Event::Transfer(TransferRecord {
+                        block_number: tr.block_number,
+                        tx_hash: tx_hash.to_string(),
+                        log_index: tr.log_index,
+                        token: token.to_string(),
+                        from: from.to_string(),
+                        to: to.to_string(),
+                        value: value.to_string(),
+                        direction,
+                        status,
+                        bucket: bucket.map(str::to_string),
+                        counterparty_kind: counterparty.map(|c| c.0),
+                        counterparty_label: counterparty.and_then(|c| c.1.clone()),
+                    }));
                 }
-                if from_is_binance && !to_is_binance {
-                    let part = value_str.parse::<i128>().unwrap_or(i128::MAX);
-                    delta = delta.saturating_sub(part);
+
+                // Stored for inspection, but a reverted transfer never moved funds
+                if status == Some(TxStatus::Reverted) {
+                    warn!(block = number, tx = ?tr.tx_hash, log_index = tr.log_index, "Transfer from reverted transaction; excluded");
+                    continue;
+                }
+                matched += 1;
+
+                match direction {
+                    Direction::In => inflow = inflow.saturating_add(tr.value),
+                    Direction::Out => outflow = outflow.saturating_add(tr.value),
+                    Direction::Internal | Direction::None => {}
+                }
+                if matches!(direction, Direction::In | Direction::Out) {
+                    self.maybe_alert(&tr, direction == Direction::In, ts_unix);
                 }
             }
         }
 
-        if delta != 0 {
-            // Update cumulative using U256 arithmetic for exactness
-            let latest = {
-                let c = conn.lock().await;
-                crate::db::get_latest_cumulative(&c)?
+        // A block behind the head (a failed block being retried) counts on from
+        // its predecessor; the head and the blocks after it are rebuilt below
+        let out_of_order = db::get_last_indexed_block(&conn)?.is_some_and(|last| number <= last);
+        let delta = aggregates::Delta::new(inflow, outflow);
+        let (before, usd_before) = match out_of_order {
+            true => db::cumulative_at(&conn, number.saturating_sub(1))?
+                .map(|(_, raw, usd)| (aggregates::parse_cumulative(&raw), usd))
+                .unwrap_or_default(),
+            false => (
+                aggregates::parse_cumulative(&db::get_latest_cumulative(&conn)?.cumulative_netflow_raw),
+                db::get_cumulative_usd_micro(&conn)?,
+            ),
+        };
+        let after = delta.apply(before);
+        // The USD cumulative follows the change, valued at this block's price
+        let usd_after = match price {
+            Some(p) => Some(usd_before.unwrap_or(0).saturating_add(delta.usd_micro(p))),
+            None => usd_before,
+        };
+        if !delta.is_zero() && !out_of_order {
+            db::update_cumulative(&conn, number, &after.to_string(), usd_after, self.clock.stamp(ts_unix))?;
+            info!(
+                block = number, %delta, cumulative = %after,
+                cumulative_usd = usd_after.map(prices::format_usd).unwrap_or_default(),
+                "Cumulative updated"
+            );
+        }
+
+        if matched > 0 {
+            let row = NetflowBlock {
+                block_number: number,
+                ts_unix,
+                inflow: inflow.to_string(),
+                outflow: outflow.to_string(),
+                cumulative: after.to_string(),
+                transfer_count: matched,
+                cumulative_token: ValueFormat::Scaled.render(&after.to_string()),
+                price_usd_micro: price,
+                cumulative_usd_micro: usd_after,
             };
-            let mut acc = latest.cumulative_netflow_raw.parse::<U256>().unwrap_or(U256::ZERO);
-            if delta > 0 {
-                acc = acc.saturating_add(U256::from(delta as u128));
-            } else {
-                // Avoid underflow: if negative exceeds current acc, clamp to zero
-                let sub = U256::from((-delta) as u128);
-                if sub > acc { acc = U256::ZERO; }
-                else { acc = acc - sub; }
+            db::insert_netflow_block(&conn, &row)?;
+            // Its cumulative and those after it change with the rebuild, so
+            // subscribers only see the transfers
+            if !out_of_order {
+                published.push(Event::Netflow(row));
             }
-            let acc_str = acc.to_string();
-            let c = conn.lock().await;
-            db::update_cumulative(&c, number, &acc_str)?;
-            info!(block = number, delta = delta, cumulative = %acc_str, "Cumulative updated");
         }
+
+        self.apply_native(&conn, number, ts_unix, &native)?;
+        if out_of_order {
+            aggregates::rebuild(&conn, self.clock)?;
+        }
+
+        db::insert_block_stats(&conn, &BlockStats {
+            block_number: number,
+            logs_fetched,
+            transfers_matched: matched,
+            processing_ms: timing.started.elapsed().as_millis() as u64,
+            rpc_ms: timing.rpc.as_millis() as u64,
+            indexed_at_unix: OffsetDateTime::now_utc().unix_timestamp(),
+        })?;
+        let published = published.into_iter().map(|ev| ev.log(&conn)).collect::<Result<Vec<_>>>()?;
+        db::advance_last_indexed_block(&conn, number)?;
+        conn.commit()?;
+        let stored = published.iter().filter(|ev| matches!(ev.event, Event::Transfer(_))).count() as u64;
+        self.session.lock().expect("session lock").record_block(number, ts_unix, stored);
+
+        let any_discovered = !discovered.is_empty();
+        self.deposits.write().expect("deposit set lock").extend(discovered);
+        for ev in published {
+            // Err just means nobody is subscribed
+            let _ = self.events.send(ev);
+        }
+        Ok(any_discovered)
     }
+}
 
-    Ok(())
+/// Time spent indexing one block, for `block_stats`.
+struct Timing {
+    started: Instant,
+    /// Spent waiting on the RPC, retries and their backoff included
+    rpc: Duration,
+}
+
+impl Timing {
+    fn start() -> Self {
+        Timing { started: Instant::now(), rpc: Duration::ZERO }
+    }
+
+    async fn rpc<T>(&mut self, call: impl std::future::Future<Output = T>) -> T {
+        let at = Instant::now();
+        let out = call.await;
+        self.rpc += at.elapsed();
+        out
+    }
+}
+
+/// Sum of every token transfer in a block, tracked or not.
+fn block_volume(number: u64, ts_unix: i64, logs: &[RawLog]) -> db::VolumeBlock {
+    let (volume, count) = logs.iter().filter_map(decode_transfer)
+        .fold((U256::zero(), 0), |(v, n), tr| (v.saturating_add(tr.value), n + 1));
+    db::VolumeBlock { block_number: number, ts_unix, volume: volume.to_string(), transfer_count: count }
+}
+
+/// Provider errors meaning the response would be too large or the range too wide.
+fn is_over_limit(e: &eyre::Report) -> bool {
+    let msg = format!("{e:#}").to_ascii_lowercase();
+    ["query returned more than", "response size", "too many results", "log limit", "block range", "range is too large"]
+        .iter()
+        .any(|m| msg.contains(m))
+}
+
+/// Halve the block range, or for a single block the topic OR-list.
+fn split_filter(f: &LogFilter) -> Option<(LogFilter, LogFilter)> {
+    if f.from_block < f.to_block {
+        let mid = f.from_block + (f.to_block - f.from_block) / 2;
+        return Some((
+            LogFilter { to_block: mid, ..f.clone() },
+            LogFilter { from_block: mid + 1, ..f.clone() },
+        ));
+    }
+    let halves = |topics: &[H256]| {
+        let (a, b) = topics.split_at(topics.len() / 2);
+        (Some(a.to_vec()), Some(b.to_vec()))
+    };
+    match (&f.topic1, &f.topic2, &f.topic3) {
+        (Some(t), _, _) if t.len() > 1 => {
+            let (a, b) = halves(t);
+            Some((LogFilter { topic1: a, ..f.clone() }, LogFilter { topic1: b, ..f.clone() }))
+        }
+        (_, Some(t), _) if t.len() > 1 => {
+            let (a, b) = halves(t);
+            Some((LogFilter { topic2: a, ..f.clone() }, LogFilter { topic2: b, ..f.clone() }))
+        }
+        (_, _, Some(t)) if t.len() > 1 => {
+            let (a, b) = halves(t);
+            Some((LogFilter { topic3: a, ..f.clone() }, LogFilter { topic3: b, ..f.clone() }))
+        }
+        _ => None,
+    }
 }
 
-fn decode_transfer(lg: &Log) -> Option<Erc20Transfer> {
+pub fn decode_transfer(lg: &RawLog) -> Option<Erc20Transfer> {
     if lg.topics.len() != 3 { return None; }
     if lg.topics[0] != TRANSFER_TOPIC { return None; }
 
@@ -146,18 +1902,578 @@ fn decode_transfer(lg: &Log) -> Option<Erc20Transfer> {
     let to = H160::from_slice(lg.topics[2].as_bytes()[12..].try_into().ok()?);
 
     // data is uint256 value (32 bytes)
-    let value = if let Some(data) = lg.data.0.get(0..) {
+    let value = if let Some(data) = lg.data.get(0..) {
         if data.len() >= 32 {
             U256::from_big_endian(&data[data.len()-32..])
         } else { return None; }
     } else { return None; };
 
     Some(Erc20Transfer{
-        block_number: lg.block_number?.as_u64(),
-        tx_hash: format!("{:?}", lg.transaction_hash?),
-        log_index: lg.log_index?.as_u64(),
+        block_number: lg.block_number?,
+        tx_hash: lg.transaction_hash?,
+        log_index: lg.log_index?,
         from,
         to,
         value,
     })
 }
+
+/// The indexer writes through one connection behind an async mutex while the
+/// API reads through others, webhook deliveries arrive over a channel and
+/// events fan out to subscribers. These check that none of them can stall the
+/// rest: the mutex is never held across an RPC call, readers only ever see
+/// whole blocks, and shutdown works wherever the indexer happens to be.
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::{AtomicBool, AtomicUsize, Ordering}, time::Duration};
+
+    use async_trait::async_trait;
+    use futures_util::stream::{self, BoxStream};
+    use tokio::{sync::{Notify, Semaphore}, time::timeout};
+
+    use super::*;
+    use crate::chain::NativeTx;
+    use crate::events;
+
+    const TOKEN: Address = H160([0xaa; 20]);
+    const HOT_WALLET: Address = H160([0xbb; 20]);
+    const USER: Address = H160([0x11; 20]);
+    const START: u64 = 100;
+    const LIMIT: Duration = Duration::from_secs(10);
+
+    /// A chain where block `n` has hash `n` and every transaction succeeded.
+    /// `get_block` waits at `gate`, which starts open unless `gated`.
+    /// From `reorg_at` on, once set, blocks are those of another branch.
+    struct FakeChain {
+        gate: Arc<Semaphore>,
+        entered: Arc<Notify>,
+        /// Block whose header comes without a timestamp
+        no_timestamp: Option<u64>,
+        /// What `get_logs` serves, by block; topics aren't filtered
+        logs: Vec<RawLog>,
+        /// Widest block range `get_logs` answers; wider ones fail as over the limit
+        range_limit: Option<u64>,
+        reorg_at: Arc<std::sync::Mutex<Option<u64>>>,
+    }
+
+    impl FakeChain {
+        fn new(gated: bool) -> (Self, Arc<Semaphore>, Arc<Notify>) {
+            let gate = Arc::new(Semaphore::new(usize::from(!gated)));
+            let entered = Arc::new(Notify::new());
+            (Self { gate: gate.clone(), entered: entered.clone(), no_timestamp: None, logs: Vec::new(), range_limit: None, reorg_at: Arc::default() }, gate, entered)
+        }
+    }
+
+    fn header(number: u64) -> BlockHeader {
+        BlockHeader {
+            number,
+            hash: H256::from_low_u64_be(number),
+            parent_hash: H256::from_low_u64_be(number - 1),
+            timestamp: 1_700_000_000 + 2 * number,
+        }
+    }
+
+    /// Hash of block `n` on the branch that replaced the chain from a reorg.
+    fn reorged_hash(number: u64) -> H256 {
+        H256::from_low_u64_be(1 << 32 | number)
+    }
+
+    /// Block `number` on the chain that reorganised from block `at`.
+    fn reorged_header(number: u64, at: u64) -> BlockHeader {
+        let mut h = header(number);
+        if number >= at {
+            h.hash = reorged_hash(number);
+        }
+        if number > at {
+            h.parent_hash = reorged_hash(number - 1);
+        }
+        h
+    }
+
+    #[async_trait]
+    impl ChainClient for FakeChain {
+        async fn chain_id(&self) -> Result<u64> {
+            Ok(137)
+        }
+        async fn block_number(&self) -> Result<u64> {
+            Ok(START - 1)
+        }
+        async fn subscribe_blocks(&self) -> Result<BoxStream<'_, BlockHeader>> {
+            Ok(stream::empty().boxed())
+        }
+        async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>> {
+            if let Some(limit) = self.range_limit.filter(|limit| filter.to_block - filter.from_block >= *limit) {
+                return Err(eyre!("query exceeds max block range {limit}"));
+            }
+            Ok(self.logs.iter()
+                .filter(|l| l.block_number.is_some_and(|n| (filter.from_block..=filter.to_block).contains(&n)))
+                .filter(|l| filter.block_hash.is_none() || l.block_hash == filter.block_hash)
+                .cloned()
+                .collect())
+        }
+        async fn get_block(&self, number: u64) -> Result<Option<BlockHeader>> {
+            self.entered.notify_one();
+            let _open = self.gate.acquire().await?;
+            let reorg_at = *self.reorg_at.lock().expect("reorg lock");
+            let mut h = reorg_at.map_or_else(|| header(number), |at| reorged_header(number, at));
+            if self.no_timestamp == Some(number) {
+                h.timestamp = 0;
+            }
+            Ok(Some(h))
+        }
+        async fn block_transactions(&self, _number: u64) -> Result<Vec<NativeTx>> {
+            Ok(Vec::new())
+        }
+        async fn call(&self, _to: Address, _data: Vec<u8>, _block: Option<u64>) -> Result<Vec<u8>> {
+            Err(eyre!("no contract state in the fake chain"))
+        }
+        async fn storage_at(&self, _address: Address, _slot: H256) -> Result<H256> {
+            Ok(H256::zero())
+        }
+        async fn code_at(&self, _address: Address) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+        async fn transaction_status(&self, _tx_hash: H256) -> Result<Option<bool>> {
+            Ok(Some(true))
+        }
+    }
+
+    fn indexer(db: &db::Scratch, chain: FakeChain, events: EventSender) -> Arc<Indexer> {
+        Arc::new(Indexer {
+            binance_topics: vec![H256::from(HOT_WALLET)],
+            provider: Box::new(chain),
+            pol_token: TOKEN,
+            binance_addrs: vec![HOT_WALLET],
+            mode: AccountingMode::HotWallet,
+            deposits: RwLock::new(HashSet::new()),
+            start_block: START,
+            clock: Clock::Block,
+            track_native: false,
+            max_logs: DEFAULT_MAX_LOGS,
+            price_feed: None,
+            subgraph: None,
+            archive_raw_logs: false,
+            track_volume: false,
+            track_supply_events: false,
+            track_nfts: false,
+            fast_path: false,
+            contract_labels: None,
+            rules: Rules::default(),
+            control: Arc::new(Control::new(None)),
+            conn: Arc::new(Mutex::new(db::init(db.path()).expect("init db"))),
+            alerts: None,
+            events,
+            session: std::sync::Mutex::new(SessionSummary::default()),
+        })
+    }
+
+    /// A deposit of `value` into the hot wallet, log `index` of block `number`.
+    fn deposit(number: u64, index: u64, value: u64) -> RawLog {
+        let mut data = vec![0u8; 32];
+        U256::from(value).to_big_endian(&mut data);
+        RawLog {
+            address: TOKEN,
+            topics: vec![TRANSFER_TOPIC, H256::from(USER), H256::from(HOT_WALLET)],
+            data,
+            block_number: Some(number),
+            block_hash: Some(H256::from_low_u64_be(number)),
+            transaction_hash: Some(H256::from_low_u64_be(number << 16 | index)),
+            log_index: Some(index),
+        }
+    }
+
+    fn stored_transfers(conn: &Connection) -> i64 {
+        conn.query_row("SELECT COUNT(*) FROM erc20_transfers", [], |r| r.get(0)).expect("count transfers")
+    }
+
+    #[tokio::test]
+    async fn connection_is_free_while_waiting_on_the_rpc() {
+        let db = db::Scratch::new("rpc-wait");
+        let (chain, gate, entered) = FakeChain::new(true);
+        let indexer = indexer(&db, chain, events::channel());
+
+        let task = tokio::spawn({
+            let indexer = indexer.clone();
+            async move { indexer.index_block(START, None, Some(vec![deposit(START, 0, 5)])).await }
+        });
+        timeout(LIMIT, entered.notified()).await.expect("indexer reached the RPC");
+
+        // The API (and the failed-block retry) can still use the database
+        assert!(indexer.conn.try_lock().is_ok(), "connection mutex held across an RPC call");
+        let reader = Connection::open(db.path()).unwrap();
+        assert_eq!(stored_transfers(&reader), 0);
+
+        gate.add_permits(1);
+        timeout(LIMIT, task).await.expect("block indexed").unwrap().unwrap();
+        assert_eq!(stored_transfers(&reader), 1);
+    }
+
+    #[tokio::test]
+    async fn fast_path_publishes_before_the_header_is_read() {
+        let db = db::Scratch::new("fast-path");
+        let (chain, gate, entered) = FakeChain::new(true);
+        let events = events::channel();
+        let mut rx = events.subscribe();
+        let mut indexer = indexer(&db, chain, events);
+        Arc::get_mut(&mut indexer).expect("not shared yet").fast_path = true;
+
+        let task = tokio::spawn({
+            let indexer = indexer.clone();
+            async move { indexer.index_block(START, None, Some(vec![deposit(START, 0, 5)])).await }
+        });
+        timeout(LIMIT, entered.notified()).await.expect("indexer reached the RPC");
+        match rx.try_recv().map(|ev| ev.event) {
+            Ok(Event::ProvisionalNetflow(p)) => assert_eq!((p.block_number, p.inflow.as_str(), p.cumulative.as_str()), (START, "5", "5")),
+            other => panic!("expected a provisional net-flow before the header, got {other:?}"),
+        }
+        assert_eq!(stored_transfers(&Connection::open(db.path()).unwrap()), 0);
+
+        gate.add_permits(1);
+        timeout(LIMIT, task).await.expect("block indexed").unwrap().unwrap();
+        let confirmed: Vec<&str> = std::iter::from_fn(|| rx.try_recv().ok()).map(|ev| ev.event.kind()).collect();
+        assert_eq!(confirmed, ["transfer", "netflow"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn api_reader_only_sees_whole_blocks() {
+        let db = db::Scratch::new("whole-blocks");
+        let (chain, _, _) = FakeChain::new(false);
+        let indexer = indexer(&db, chain, events::channel());
+
+        let done = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicUsize::new(0));
+        let reader = std::thread::spawn({
+            let (path, done, reads) = (db.path().to_string(), done.clone(), reads.clone());
+            move || -> Result<()> {
+                let conn = Connection::open(path)?;
+                while !done.load(Ordering::Relaxed) {
+                    // One statement reads one snapshot
+                    let (transfers, counted, inflow_blocks): (i64, i64, i64) = conn.query_row(
+                        "SELECT (SELECT COUNT(*) FROM erc20_transfers),
+                                (SELECT COALESCE(SUM(transfer_count), 0) FROM netflow_blocks),
+                                (SELECT COUNT(*) FROM blocks)",
+                        [],
+                        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+                    )?;
+                    if transfers != counted || transfers != 3 * inflow_blocks {
+                        return Err(eyre!("partial block visible: {transfers} transfers, {counted} counted, {inflow_blocks} blocks"));
+                    }
+                    reads.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+        });
+
+        for number in START..START + 200 {
+            let logs = (0..3).map(|i| deposit(number, i, 1 + i)).collect();
+            timeout(LIMIT, indexer.index_block(number, None, Some(logs))).await.expect("block indexed").unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap().unwrap();
+        assert!(reads.load(Ordering::Relaxed) > 0);
+        assert_eq!(stored_transfers(&*indexer.conn.lock().await), 600);
+    }
+
+    #[tokio::test]
+    async fn counterparties_are_classified_once_and_served_with_transfers() {
+        let db = db::Scratch::new("tag-contracts");
+        let (chain, _, _) = FakeChain::new(false);
+        let events = events::channel();
+        let mut rx = events.subscribe();
+        let mut indexer = indexer(&db, chain, events);
+        Arc::get_mut(&mut indexer).expect("not shared yet").contract_labels = Some(ContractLabels::default());
+
+        for number in [START, START + 1] {
+            timeout(LIMIT, indexer.index_block(number, None, Some(vec![deposit(number, 0, 5)]))).await.expect("block indexed").unwrap();
+        }
+        match rx.try_recv().map(|ev| ev.event) {
+            Ok(Event::Transfer(t)) => assert_eq!(t.counterparty_kind, Some(CounterpartyKind::Eoa)),
+            other => panic!("expected a transfer event, got {other:?}"),
+        }
+        let conn = indexer.conn.lock().await;
+        let first_seen: Vec<u64> = conn.prepare("SELECT first_seen_block FROM contracts").unwrap()
+            .query_map([], |r| r.get(0)).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(first_seen, [START]);
+        let stored = db::list_transfers(&conn, None, u64::MAX, 10).unwrap();
+        assert!(stored.iter().all(|t| t.counterparty_kind == Some(CounterpartyKind::Eoa) && t.counterparty_label.is_none()));
+    }
+
+    #[tokio::test]
+    async fn unread_event_subscriber_does_not_block_indexing() {
+        let db = db::Scratch::new("slow-subscriber");
+        let (chain, _, _) = FakeChain::new(false);
+        let events = events::channel();
+        let mut idle = events.subscribe();
+        let indexer = indexer(&db, chain, events);
+
+        // Each block publishes a transfer and a netflow event: twice the channel's capacity
+        for number in START..START + 1024 {
+            timeout(LIMIT, indexer.index_block(number, None, Some(vec![deposit(number, 0, 1)]))).await.expect("block indexed").unwrap();
+        }
+        assert!(matches!(idle.recv().await, Err(tokio::sync::broadcast::error::RecvError::Lagged(_))));
+    }
+
+    #[tokio::test]
+    async fn missing_timestamp_is_interpolated_from_neighbours() {
+        let db = db::Scratch::new("missing-ts");
+        let (mut chain, _, _) = FakeChain::new(false);
+        chain.no_timestamp = Some(START + 2);
+        let indexer = indexer(&db, chain, events::channel());
+        for number in [START, START + 4, START + 2] {
+            timeout(LIMIT, indexer.index_block(number, None, Some(vec![deposit(number, 0, 1)]))).await.expect("block indexed").unwrap();
+        }
+
+        let conn = indexer.conn.lock().await;
+        let (ts, interpolated): (i64, bool) = conn.query_row(
+            "SELECT ts_unix, ts_interpolated FROM blocks WHERE block_number = ?",
+            [START + 2],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        // The fake chain's blocks are evenly spaced, so the estimate is exact
+        assert_eq!(ts, header(START + 2).timestamp as i64);
+        assert!(interpolated);
+    }
+
+    #[tokio::test]
+    async fn retried_block_counts_from_its_predecessor() {
+        let db = db::Scratch::new("retry-order");
+        let (mut chain, _, _) = FakeChain::new(false);
+        chain.logs = vec![deposit(START + 1, 0, 3)];
+        let events = events::channel();
+        let mut rx = events.subscribe();
+        let indexer = indexer(&db, chain, events);
+
+        let mut stale = deposit(START + 1, 0, 3);
+        stale.block_hash = Some(H256::repeat_byte(0xee));
+        for (number, logs) in [(START, vec![deposit(START, 0, 5)]), (START + 1, vec![stale]), (START + 2, vec![deposit(START + 2, 0, 7)]), (START + 3, vec![deposit(START + 3, 0, 9)])] {
+            timeout(LIMIT, indexer.index_or_record(number, None, Some(logs))).await.expect("block indexed").unwrap();
+        }
+        assert_eq!(db::list_failed_blocks(&*indexer.conn.lock().await).unwrap(), [START + 1]);
+        while rx.try_recv().is_ok() {}
+
+        timeout(LIMIT, indexer.retry_failed()).await.expect("retry finished");
+        // Only the recovered transfer goes out; the head stays where it was
+        let published: Vec<&str> = std::iter::from_fn(|| rx.try_recv().ok()).map(|ev| ev.event.kind()).collect();
+        assert_eq!(published, ["transfer"]);
+
+        let conn = indexer.conn.lock().await;
+        assert!(db::list_failed_blocks(&conn).unwrap().is_empty());
+        let head = db::get_latest_cumulative(&conn).unwrap();
+        assert_eq!((head.block_number, head.cumulative_netflow_raw.as_str()), (START + 3, "24"));
+        let cumulatives: Vec<String> = conn.prepare("SELECT cumulative FROM netflow_blocks ORDER BY block_number").unwrap()
+            .query_map([], |r| r.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(cumulatives, ["5", "8", "15", "24"]);
+
+        let logged: Vec<u64> = conn.prepare("SELECT block_number FROM event_log WHERE kind = 'netflow' ORDER BY seq").unwrap()
+            .query_map([], |r| r.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(logged, [START, START + 2, START + 3]);
+    }
+
+    #[tokio::test]
+    async fn reorg_rolls_back_to_the_fork_and_records_it() {
+        let db = db::Scratch::new("reorg");
+        let (mut chain, _, _) = FakeChain::new(false);
+        let mut replacement = deposit(START + 4, 0, 4);
+        replacement.block_hash = Some(reorged_hash(START + 4));
+        chain.logs = vec![replacement];
+        let reorg_at = chain.reorg_at.clone();
+        let indexer = indexer(&db, chain, events::channel());
+        for number in START..START + 6 {
+            timeout(LIMIT, indexer.index_block(number, None, Some(vec![deposit(number, 0, 10)]))).await.expect("block indexed").unwrap();
+        }
+
+        // The last three blocks are replaced; the new head builds on the other branch
+        *reorg_at.lock().unwrap() = Some(START + 3);
+        let head = reorged_header(START + 6, START + 3);
+        timeout(LIMIT, handle_head(&indexer, head.clone(), None)).await.expect("head handled").unwrap();
+
+        let conn = indexer.conn.lock().await;
+        let reorgs = db::list_reorgs(&conn, 10).unwrap();
+        assert_eq!(reorgs.len(), 1);
+        let r = &reorgs[0];
+        assert_eq!((r.new_head, r.fork_block, r.depth), (START + 6, START + 2, 3));
+        let orphaned: Vec<String> = (START + 3..START + 6).rev().map(|n| format!("{:?}", H256::from_low_u64_be(n))).collect();
+        assert_eq!(r.orphaned_hashes, orphaned);
+        assert_eq!(r.removed_transfers.iter().map(|t| t.block_number).collect::<Vec<_>>(), [START + 3, START + 4, START + 5]);
+        assert_eq!((r.removed_inflow.as_str(), r.removed_outflow.as_str()), ("30", "0"));
+        assert_eq!((r.cumulative_before.as_str(), r.cumulative_after.as_str()), ("60", "30"));
+
+        // What `/reorgs` serves
+        let served = serde_json::to_value(&reorgs).unwrap();
+        assert_eq!(served[0]["fork_block"], START + 2);
+        assert_eq!(served[0]["new_head_hash"], format!("{:?}", head.hash));
+        assert_eq!(served[0]["removed_transfers"].as_array().map(Vec::len), Some(3));
+        assert_eq!(served[0]["cumulative_after"], "30");
+
+        // Restored at the fork, then the new branch counted on top
+        let latest = db::get_latest_cumulative(&conn).unwrap();
+        assert_eq!((latest.block_number, latest.cumulative_netflow_raw.as_str()), (START + 4, "34"));
+        assert_eq!(db::get_block_hash(&conn, START + 4).unwrap(), Some(format!("{:?}", reorged_hash(START + 4))));
+        assert_eq!(db::get_last_indexed_block(&conn).unwrap(), Some(START + 6));
+    }
+
+    /// Transfers of the token from or to any of `parties`, over `[from, to]`.
+    fn transfers_filter(from: u64, to: u64, parties: &[Address]) -> LogFilter {
+        LogFilter {
+            address: Some(TOKEN),
+            topic0: TRANSFER_TOPIC,
+            topic1: Some(parties.iter().map(|a| H256::from(*a)).collect()),
+            topic2: None,
+            topic3: None,
+            from_block: from,
+            to_block: to,
+            block_hash: None,
+        }
+    }
+
+    #[test]
+    fn split_filter_halves_the_range_then_the_topics() {
+        let (first, second) = split_filter(&transfers_filter(10, 15, &[HOT_WALLET])).unwrap();
+        assert_eq!((first.from_block, first.to_block, second.from_block, second.to_block), (10, 12, 13, 15));
+
+        let (first, second) = split_filter(&transfers_filter(10, 10, &[HOT_WALLET, USER, TOKEN])).unwrap();
+        assert_eq!((first.from_block, first.to_block), (10, 10));
+        assert_eq!((first.topic1.unwrap().len(), second.topic1.unwrap().len()), (1, 2));
+
+        assert!(split_filter(&transfers_filter(10, 10, &[HOT_WALLET])).is_none(), "one block, one address");
+    }
+
+    #[test]
+    fn over_limit_errors_are_recognised() {
+        let over = [
+            "query returned more than 10000 results",
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range",
+            "too many results, pass a smaller range",
+            "exceeded the log limit of this plan",
+            "eth_getLogs block range too large",
+            "range is too large, max is 1k blocks",
+        ];
+        for msg in over {
+            assert!(is_over_limit(&eyre!("{msg}")), "{msg}");
+        }
+        // Matched anywhere in the chain, whatever the case
+        assert!(is_over_limit(&eyre!("Query Returned More Than 1000 results").wrap_err("eth_getLogs failed")));
+        for msg in ["connection reset by peer", "execution reverted", "rate limited"] {
+            assert!(!is_over_limit(&eyre!("{msg}")), "{msg}");
+        }
+    }
+
+    #[tokio::test]
+    async fn logs_at_the_cap_are_split_until_they_fit() {
+        let db = db::Scratch::new("split-logs");
+        let (mut chain, _, _) = FakeChain::new(false);
+        chain.logs = (START..START + 4).flat_map(|n| [deposit(n, 0, 1), deposit(n, 1, 2)]).collect();
+        let mut indexer = indexer(&db, chain, events::channel());
+        Arc::get_mut(&mut indexer).expect("not shared yet").max_logs = 3;
+
+        let logs = timeout(LIMIT, indexer.get_logs_complete(transfers_filter(START, START + 3, &[HOT_WALLET]))).await.expect("split finished").unwrap();
+        assert_eq!(logs.len(), 8, "nothing dropped at the cap");
+    }
+
+    #[tokio::test]
+    async fn over_limit_ranges_are_split_until_they_fit() {
+        let db = db::Scratch::new("split-range");
+        let (mut chain, _, _) = FakeChain::new(false);
+        chain.logs = (START..START + 10).map(|n| deposit(n, 0, 1)).collect();
+        chain.range_limit = Some(2);
+        let indexer = indexer(&db, chain, events::channel());
+
+        let logs = timeout(LIMIT, indexer.get_logs_complete(transfers_filter(START, START + 9, &[HOT_WALLET]))).await.expect("split finished").unwrap();
+        assert_eq!(logs.iter().filter_map(|l| l.block_number).collect::<Vec<_>>(), (START..START + 10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn single_block_over_the_cap_is_an_error() {
+        let db = db::Scratch::new("split-floor");
+        let (mut chain, _, _) = FakeChain::new(false);
+        // The fake chain ignores topics, so halving the address list doesn't help either
+        chain.logs = (0..3).map(|i| deposit(START, i, 1)).collect();
+        let mut indexer = indexer(&db, chain, events::channel());
+        Arc::get_mut(&mut indexer).expect("not shared yet").max_logs = 3;
+
+        let err = timeout(LIMIT, indexer.get_logs_complete(transfers_filter(START, START, &[HOT_WALLET, USER]))).await
+            .expect("gave up rather than looping")
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("can't be split further"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn follow_ends_when_deliveries_stop() {
+        let db = db::Scratch::new("ingest-closed");
+        let (chain, _, _) = FakeChain::new(false);
+        let indexer = indexer(&db, chain, events::channel());
+        let source = Source::Replay(PathBuf::new());
+
+        let (tx, mut rx) = ingest::channel();
+        tx.send(vec![deposit(START, 0, 5), deposit(START + 1, 0, 7)]).await.unwrap();
+        tx.send(vec![deposit(START + 2, 0, 9)]).await.unwrap();
+        drop(tx);
+
+        timeout(LIMIT, follow(&indexer, &source, Some(&mut rx), None, None, None, DEFAULT_DB_RETRY_BUFFER, START - 1, None))
+            .await
+            .expect("follow returned once the sender was gone")
+            .unwrap();
+        let conn = indexer.conn.lock().await;
+        assert_eq!(stored_transfers(&conn), 3);
+        assert_eq!(db::get_last_indexed_block(&conn).unwrap(), Some(START + 2));
+    }
+
+    #[tokio::test]
+    async fn busy_database_holds_heads_until_it_is_writable() {
+        let db = db::Scratch::new("db-busy");
+        let (chain, _, _) = FakeChain::new(false);
+        let indexer = indexer(&db, chain, events::channel());
+        let source = Source::Replay(PathBuf::new());
+
+        let other = Connection::open(db.path()).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let (tx, mut rx) = ingest::channel();
+        tx.send(vec![deposit(START, 0, 5)]).await.unwrap();
+        tx.send(vec![deposit(START + 1, 0, 7)]).await.unwrap();
+        drop(tx);
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            other.execute_batch("COMMIT").unwrap();
+        };
+
+        let (followed, ()) = tokio::join!(
+            timeout(LIMIT, follow(&indexer, &source, Some(&mut rx), None, None, None, DEFAULT_DB_RETRY_BUFFER, START - 1, None)),
+            release,
+        );
+        followed.expect("resumed once the lock was released").unwrap();
+        let conn = indexer.conn.lock().await;
+        assert_eq!(stored_transfers(&conn), 2);
+        let failed: i64 = conn.query_row("SELECT COUNT(*) FROM failed_blocks", [], |r| r.get(0)).unwrap();
+        assert_eq!(failed, 0, "a busy database is not a failed block");
+    }
+
+    #[tokio::test]
+    async fn shutdown_mid_block_stores_nothing_and_records_the_session() {
+        let db = db::Scratch::new("shutdown");
+        let (chain, _gate, entered) = FakeChain::new(true);
+        let indexer = indexer(&db, chain, events::channel());
+        let source = Source::Replay(PathBuf::new());
+
+        let (tx, mut rx) = ingest::channel();
+        tx.send(vec![deposit(START, 0, 5)]).await.unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            entered.notified().await;
+            let _ = stop.send(());
+        });
+
+        // As in `run`, with the signal replaced
+        let reason = tokio::select! {
+            r = follow(&indexer, &source, Some(&mut rx), None, None, None, DEFAULT_DB_RETRY_BUFFER, START - 1, None) => r.map(|()| "stream_ended"),
+            _ = stopped => Ok("signal"),
+        };
+        assert_eq!(reason.unwrap(), "signal");
+        timeout(LIMIT, indexer.finish_session("signal".into())).await.expect("connection released").unwrap();
+
+        let conn = indexer.conn.lock().await;
+        assert_eq!(stored_transfers(&conn), 0);
+        assert_eq!(db::get_last_indexed_block(&conn).unwrap(), None);
+        let exit: String = conn.query_row("SELECT exit_reason FROM sessions", [], |r| r.get(0)).unwrap();
+        assert_eq!(exit, "signal");
+    }
+}
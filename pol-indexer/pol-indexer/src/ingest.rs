@@ -0,0 +1,171 @@
+//! Push ingestion (`INGEST=webhook`): token-transfer webhooks from Alchemy
+//! Notify (Address Activity) or QuickNode Streams (`logs` dataset), posted to
+//! `POST /ingest/alchemy` or `POST /ingest/quicknode`, take the place of the
+//! new-heads subscription.
+//!
+//! A delivery is only a source of logs. The indexer still reads each block's
+//! header over RPC, so reorg checks and the canonical-hash guard apply as
+//! usual, and blocks between deliveries are filled with one ranged
+//! `eth_getLogs`. A delivery for a block already indexed is handed to the
+//! failed-block retry, which re-reads the block and rebuilds the aggregates.
+//! Every request must be signed with `INGEST_SIGNING_KEY`.
+
+use std::collections::BTreeMap;
+
+#[cfg(feature = "api")]
+use axum::http::HeaderMap;
+use ethers::types::{Address, H256};
+use eyre::{eyre, Result};
+#[cfg(feature = "api")]
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+#[cfg(feature = "api")]
+use sha2::Sha256;
+use tokio::sync::mpsc;
+
+use crate::chain::RawLog;
+
+/// Deliveries queued for the indexer before the endpoint answers `503`
+/// (both providers retry).
+const CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Ingest {
+    /// New-heads WebSocket subscription
+    #[default]
+    Subscription,
+    /// Alchemy / QuickNode webhooks posted to the API
+    Webhook,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    Alchemy,
+    QuickNode,
+}
+
+pub type Sender = mpsc::Sender<Vec<RawLog>>;
+pub type Receiver = mpsc::Receiver<Vec<RawLog>>;
+
+pub fn channel() -> (Sender, Receiver) {
+    mpsc::channel(CAPACITY)
+}
+
+/// What the API needs to accept deliveries.
+#[derive(Clone)]
+pub struct Inbox {
+    pub sender: Sender,
+    pub signing_key: String,
+}
+
+#[cfg(feature = "api")]
+impl Inbox {
+    /// Alchemy signs the body (`X-Alchemy-Signature`); QuickNode signs nonce,
+    /// timestamp and body (`X-QN-Nonce`, `X-QN-Timestamp`, `X-QN-Signature`).
+    /// Both are hex HMAC-SHA256.
+    pub fn verify(&self, provider: Provider, headers: &HeaderMap, body: &[u8]) -> bool {
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.signing_key.as_bytes()).expect("HMAC takes any key length");
+        let signature = match provider {
+            Provider::Alchemy => header("x-alchemy-signature"),
+            Provider::QuickNode => {
+                let (Some(nonce), Some(ts)) = (header("x-qn-nonce"), header("x-qn-timestamp")) else { return false };
+                mac.update(nonce.as_bytes());
+                mac.update(ts.as_bytes());
+                header("x-qn-signature")
+            }
+        };
+        let Some(Ok(signature)) = signature.map(hex::decode) else { return false };
+        mac.update(body);
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+/// A log as providers send it (Ethereum JSON-RPC form).
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireLog {
+    address: Address,
+    topics: Vec<H256>,
+    data: String,
+    block_number: String,
+    block_hash: Option<H256>,
+    transaction_hash: H256,
+    log_index: String,
+    #[serde(default)]
+    removed: bool,
+}
+
+impl WireLog {
+    fn into_raw(self) -> Result<RawLog> {
+        Ok(RawLog {
+            address: self.address,
+            topics: self.topics,
+            data: hex::decode(self.data.trim_start_matches("0x")).map_err(|e| eyre!("log data: {e}"))?,
+            block_number: Some(quantity(&self.block_number)?),
+            block_hash: self.block_hash,
+            transaction_hash: Some(self.transaction_hash),
+            log_index: Some(quantity(&self.log_index)?),
+        })
+    }
+}
+
+/// `0x1a` (or a plain decimal) → 26.
+fn quantity(s: &str) -> Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| eyre!("invalid quantity {s:?}"))
+}
+
+/// The logs in a delivery, minus any the provider marks `removed` (a reorg,
+/// which the indexer detects from block hashes itself).
+///
+/// Alchemy: `{"event": {"activity": [{"log": {…}}, …]}}`; activity without a
+/// `log` (native transfers) is skipped. QuickNode: the logs as an array,
+/// possibly nested per block, or under `data`.
+pub fn parse(provider: Provider, body: &[u8]) -> Result<Vec<RawLog>> {
+    let payload: Value = serde_json::from_slice(body)?;
+    let mut wire = Vec::new();
+    match provider {
+        Provider::Alchemy => {
+            let activity = payload.pointer("/event/activity").and_then(Value::as_array)
+                .ok_or_else(|| eyre!("expected an Alchemy Notify payload with event.activity"))?;
+            wire.extend(activity.iter().filter_map(|a| a.get("log")).cloned());
+        }
+        Provider::QuickNode => collect_logs(payload.get("data").unwrap_or(&payload), &mut wire),
+    }
+    let mut out = Vec::new();
+    for value in wire {
+        let log: WireLog = serde_json::from_value(value)?;
+        if !log.removed {
+            out.push(log.into_raw()?);
+        }
+    }
+    Ok(out)
+}
+
+fn collect_logs(value: &Value, out: &mut Vec<Value>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|v| collect_logs(v, out)),
+        Value::Object(o) if o.contains_key("topics") => out.push(value.clone()),
+        _ => {}
+    }
+}
+
+/// A delivery's logs by block, in block order.
+pub fn by_block(logs: Vec<RawLog>) -> BTreeMap<u64, Vec<RawLog>> {
+    let mut out: BTreeMap<u64, Vec<RawLog>> = BTreeMap::new();
+    for lg in logs {
+        if let Some(n) = lg.block_number {
+            out.entry(n).or_default().push(lg);
+        }
+    }
+    for logs in out.values_mut() {
+        logs.sort_by_key(|l| l.log_index);
+        logs.dedup_by_key(|l| (l.transaction_hash, l.log_index));
+    }
+    out
+}
@@ -0,0 +1,167 @@
+//! Single-writer lease: `run` claims the database in `state` and refuses to
+//! start while another indexer holds it, so two instances never both add a
+//! block's flows to the cumulative.
+//!
+//! The lease is the holder's instance id and a heartbeat, renewed every
+//! quarter of `LEASE_STALE_SECS` from a connection of its own. A lease whose
+//! heartbeat is older than that (its holder crashed or was killed) can be
+//! taken over. A holder that finds its lease taken, after being stalled that
+//! long, stops instead of writing on. `rebuild`, `import`, `snapshot restore`
+//! and `checkpoint restore` don't take the lease, but refuse to run while one
+//! is live. A restored snapshot drops the lease it was taken with.
+
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+
+use crate::db;
+
+pub const DEFAULT_STALE_SECS: u64 = 60;
+
+const INSTANCE_KEY: &str = "lease_instance";
+const HEARTBEAT_KEY: &str = "lease_heartbeat_unix";
+/// The holder's `LEASE_STALE_SECS`, so every checker judges it the same way
+const STALE_KEY: &str = "lease_stale_secs";
+
+/// A lease held by this process.
+pub struct Lease {
+    db_path: String,
+    instance: String,
+    stale_after: Duration,
+}
+
+/// Who holds the lease and whether it's still live.
+struct Holder {
+    instance: String,
+    age_secs: i64,
+    live: bool,
+}
+
+fn holder(conn: &Connection) -> Result<Option<Holder>> {
+    let Some(instance) = db::get_state(conn, INSTANCE_KEY)? else { return Ok(None) };
+    let heartbeat: i64 = db::get_state(conn, HEARTBEAT_KEY)?.and_then(|v| v.parse().ok()).unwrap_or(0);
+    let stale_secs: i64 = db::get_state(conn, STALE_KEY)?.and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_STALE_SECS as i64);
+    let age_secs = now() - heartbeat;
+    Ok(Some(Holder { instance, age_secs, live: age_secs < stale_secs }))
+}
+
+fn in_use(h: &Holder) -> eyre::Report {
+    eyre!(
+        "the database is in use by indexer {} (heartbeat {}s ago); stop it first, or wait for its lease to go stale",
+        h.instance, h.age_secs.max(0)
+    )
+}
+
+/// Take the lease on `conn`'s database, unless another instance's is live.
+pub fn acquire(conn: &mut Connection, db_path: &str, stale_after: Duration) -> Result<Lease> {
+    let instance = format!("{}-{:08x}", std::process::id(), rand::random::<u32>());
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    match holder(&tx)? {
+        Some(h) if h.live => return Err(in_use(&h)),
+        Some(h) => warn!(previous = %h.instance, age_secs = h.age_secs, "Taking over a stale database lease"),
+        None => {}
+    }
+    db::set_state(&tx, INSTANCE_KEY, &instance)?;
+    db::set_state(&tx, HEARTBEAT_KEY, &now().to_string())?;
+    db::set_state(&tx, STALE_KEY, &stale_after.as_secs().to_string())?;
+    tx.commit()?;
+    info!(%instance, "Database lease acquired");
+    Ok(Lease { db_path: db_path.to_string(), instance, stale_after })
+}
+
+/// Refuse to go on while an indexer holds a live lease (`rebuild`, `import`,
+/// `snapshot restore`, `checkpoint restore`).
+pub fn ensure_free(conn: &Connection) -> Result<()> {
+    match holder(conn)? {
+        Some(h) if h.live => Err(in_use(&h)),
+        _ => Ok(()),
+    }
+}
+
+impl Lease {
+    /// Renew the heartbeat until the lease is lost, which is the only way
+    /// this returns. A failed renewal (the database busy, say) is retried on
+    /// the next tick.
+    pub async fn hold(&self) -> Result<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        let mut tick = tokio::time::interval((self.stale_after / 4).max(Duration::from_secs(1)));
+        loop {
+            tick.tick().await;
+            match self.renew(&conn) {
+                Ok(true) => {}
+                Ok(false) => {
+                    let taken_by = db::get_state(&conn, INSTANCE_KEY).ok().flatten().unwrap_or_else(|| "nobody".into());
+                    return Err(eyre!("lost the database lease (now held by {taken_by}); stopping so two indexers don't write at once"));
+                }
+                Err(e) => warn!(error = %format!("{e:#}"), "Could not renew the database lease"),
+            }
+        }
+    }
+
+    /// Move the heartbeat on; false if another instance holds the lease now.
+    fn renew(&self, conn: &Connection) -> Result<bool> {
+        let n = conn.execute(
+            "UPDATE state SET value = ?1 WHERE key = ?2 AND (SELECT value FROM state WHERE key = ?3) = ?4",
+            params![now().to_string(), HEARTBEAT_KEY, INSTANCE_KEY, self.instance],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Give the lease up, if it's still ours.
+    pub fn release(&self) -> Result<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let ours = tx.query_row("SELECT value FROM state WHERE key = ?", params![INSTANCE_KEY], |r| r.get::<_, String>(0))
+            .optional()?
+            .is_some_and(|holder| holder == self.instance);
+        if ours {
+            tx.execute("DELETE FROM state WHERE key IN (?1, ?2, ?3)", params![INSTANCE_KEY, HEARTBEAT_KEY, STALE_KEY])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Drop whatever lease `conn`'s database records, holder or not: one copied
+/// in with a snapshot taken while its indexer ran.
+pub fn clear(conn: &Connection) -> Result<()> {
+    conn.execute("DELETE FROM state WHERE key IN (?1, ?2, ?3)", params![INSTANCE_KEY, HEARTBEAT_KEY, STALE_KEY])?;
+    Ok(())
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_writer_waits_for_a_stale_lease() {
+        let scratch = db::Scratch::new("lease");
+        let mut first = db::init(scratch.path()).unwrap();
+        let mut second = db::init(scratch.path()).unwrap();
+        let stale_after = Duration::from_secs(60);
+
+        let a = acquire(&mut first, scratch.path(), stale_after).unwrap();
+        assert!(acquire(&mut second, scratch.path(), stale_after).is_err());
+        assert!(ensure_free(&second).is_err());
+        assert!(a.renew(&first).unwrap());
+
+        // The first instance stalls past the stale limit; the second takes over
+        db::set_state(&first, HEARTBEAT_KEY, &(now() - 61).to_string()).unwrap();
+        let b = acquire(&mut second, scratch.path(), stale_after).unwrap();
+        assert!(!a.renew(&first).unwrap(), "the old holder must notice it lost the lease");
+
+        a.release().unwrap();
+        assert!(ensure_free(&first).is_err(), "releasing a lost lease leaves the new holder's");
+        b.release().unwrap();
+        ensure_free(&first).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+//! The log subscriber, with a filter that can be changed while running.
+//!
+//! The filter starts from `LOG_FILTER_FILE` when set, else `RUST_LOG`, else
+//! `info`. It can then be replaced through `PUT /admin/log-level`, or by
+//! editing the file and sending `SIGHUP`, so debug output for one module can
+//! be turned on without a restart dropping the head subscription.
+//!
+//! With `LOG_FILE`, every line also goes to that file, for hosts without
+//! journald or a container log collector. It is rotated daily or by size and
+//! `LOG_KEEP` files are kept, the current one included. Writes happen on a
+//! background thread, so a slow disk doesn't hold up indexing.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use eyre::{eyre, Result};
+use once_cell::sync::OnceCell;
+use tracing::{info, warn};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry};
+
+use crate::secrets;
+
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// When `LOG_FILE` starts a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rotation {
+    /// At midnight UTC; older files are named `<file>.YYYY-MM-DD`
+    Daily,
+    /// Once the file would pass `LOG_MAX_SIZE_MB`; older files are `<file>.1` (newest) to `<file>.<LOG_KEEP - 1>`
+    Size,
+    /// Never; the file grows without bound
+    Never,
+}
+
+pub struct FileConfig {
+    pub path: PathBuf,
+    pub rotation: Rotation,
+    pub max_bytes: u64,
+    /// Files kept, the current one included
+    pub keep: usize,
+}
+
+/// Install the subscriber. `quiet` keeps lines off the terminal (the TUI owns
+/// it); they still go to the log file, if any. The returned guard flushes the
+/// file when dropped, so hold it until exit.
+pub fn init(filter_file: Option<&Path>, quiet: bool, file: Option<&FileConfig>) -> Result<Option<WorkerGuard>> {
+    let silent = quiet && file.is_none();
+    let filter = match filter_file {
+        _ if silent => EnvFilter::new("off"),
+        Some(path) => parse(&read(path)?)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let (writer, guard) = match file {
+        Some(config) => {
+            let (writer, guard) = tracing_appender::non_blocking(open(config)?);
+            (Some(writer), Some(guard))
+        }
+        None => (None, None),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with((!quiet).then(|| fmt::layer().with_writer(secrets::ScrubbedStdout)))
+        .with(writer.map(|w| fmt::layer().with_ansi(false).with_writer(move || secrets::Scrubbed(w.clone()))))
+        .init();
+    let _ = FILTER.set(handle);
+    if let (Some(path), false) = (filter_file, silent) {
+        tokio::spawn(reload_on_hangup(path.to_path_buf()));
+    }
+    Ok(guard)
+}
+
+/// The file writer `config` describes, its directory created if missing.
+fn open(config: &FileConfig) -> Result<Box<dyn Write + Send>> {
+    let fail = |e: &dyn std::fmt::Display| eyre!("LOG_FILE: opening {}: {e}", config.path.display());
+    let (Some(name), dir) = (config.path.file_name(), config.path.parent().filter(|d| !d.as_os_str().is_empty())) else {
+        return Err(fail(&"not a file path"));
+    };
+    let dir = dir.unwrap_or(Path::new("."));
+    fs::create_dir_all(dir).map_err(|e| fail(&e))?;
+    let keep = config.keep.max(1);
+    Ok(match config.rotation {
+        Rotation::Daily => Box::new(
+            rolling::Builder::new()
+                .rotation(rolling::Rotation::DAILY)
+                .filename_prefix(name.to_string_lossy())
+                .max_log_files(keep)
+                .build(dir)
+                .map_err(|e| fail(&e))?,
+        ),
+        Rotation::Size => Box::new(SizeRotating::open(config.path.clone(), config.max_bytes.max(1), keep).map_err(|e| fail(&e))?),
+        Rotation::Never => Box::new(rolling::never(dir, name)),
+    })
+}
+
+/// A file that is renamed to `<path>.1` once the next write would take it
+/// past `max_bytes`, shifting older ones up and dropping the one past `keep`.
+/// A single write larger than `max_bytes` still goes in one file.
+struct SizeRotating {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRotating {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(SizeRotating { path, max_bytes, keep, file, written })
+    }
+
+    fn archived(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep > 1 {
+            let _ = fs::remove_file(self.archived(self.keep - 1));
+            for n in (1..self.keep - 1).rev() {
+                let _ = fs::rename(self.archived(n), self.archived(n + 1));
+            }
+            fs::rename(&self.path, self.archived(1))?;
+        }
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotating {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The filter in effect, as directives.
+pub fn current() -> Result<String> {
+    handle()?.with_current(ToString::to_string).map_err(|e| eyre!("log filter: {e}"))
+}
+
+/// Replace the filter with `directives` (`RUST_LOG` syntax); returns the
+/// previous one.
+pub fn set(directives: &str) -> Result<String> {
+    let filter = parse(directives)?;
+    let previous = current()?;
+    handle()?.reload(filter).map_err(|e| eyre!("log filter: {e}"))?;
+    info!(filter = %current()?, %previous, "Log filter changed");
+    Ok(previous)
+}
+
+fn handle() -> Result<&'static reload::Handle<EnvFilter, Registry>> {
+    FILTER.get().ok_or_else(|| eyre!("logging is not initialised"))
+}
+
+fn parse(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives.trim()).map_err(|e| eyre!("invalid log filter {directives:?}: {e}"))
+}
+
+fn read(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|e| eyre!("LOG_FILTER_FILE: reading {}: {e}", path.display()))
+}
+
+/// Re-read `path` on every SIGHUP. A bad file keeps the current filter.
+async fn reload_on_hangup(path: PathBuf) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let Ok(mut hangups) = signal(SignalKind::hangup()) else { return };
+        while hangups.recv().await.is_some() {
+            if let Err(e) = read(&path).and_then(|d| set(&d)) {
+                warn!(error = %format!("{e:#}"), "Could not reload the log filter; keeping the current one");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_rotation_keeps_the_newest_files() {
+        let dir = std::env::temp_dir().join(format!("pol-indexer-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("indexer.log");
+        let mut log = SizeRotating::open(path.clone(), 10, 3).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+        let read = |p: &Path| fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "four\nfive\n");
+        assert_eq!(read(&log.archived(1)), "three\n");
+        assert_eq!(read(&log.archived(2)), "one\ntwo\n");
+        assert!(!log.archived(3).exists(), "LOG_KEEP counts the current file");
+
+        // Reopening carries on from the current file's size
+        let mut log = SizeRotating::open(path.clone(), 10, 3).unwrap();
+        log.write_all(b"six\n").unwrap();
+        assert_eq!((read(&path), read(&log.archived(1))), ("six\n".into(), "four\nfive\n".into()));
+        assert_eq!(read(&log.archived(2)), "three\n", "the oldest is dropped");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
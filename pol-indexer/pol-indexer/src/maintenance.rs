@@ -0,0 +1,115 @@
+//! Scheduled SQLite upkeep for long-lived deployments: `PRAGMA integrity_check`
+//! followed by an incremental vacuum, once per configured UTC time of day.
+//!
+//! Runs on its own connection in the blocking pool, so the indexer keeps
+//! writing (WAL) while the check reads. Results go to the log and to the
+//! `db_*` metrics served at `GET /metrics`.
+
+use std::time::{Duration, Instant};
+
+use eyre::{Result, eyre};
+use rusqlite::Connection;
+use time::{OffsetDateTime, Time};
+use tracing::{error, info, warn};
+
+use crate::metrics;
+
+/// Problems reported beyond this many are dropped by SQLite.
+const MAX_INTEGRITY_ERRORS: u32 = 100;
+
+/// Parse `HH:MM[,HH:MM…]` (UTC). Empty means maintenance is disabled.
+pub fn parse_schedule(s: &str) -> Result<Vec<Time>> {
+    let mut times = Vec::new();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (h, m) = part.split_once(':').ok_or_else(|| eyre!("Invalid maintenance time {part:?}; expected HH:MM"))?;
+        let time = h.parse().ok().zip(m.parse().ok())
+            .and_then(|(h, m)| Time::from_hms(h, m, 0).ok())
+            .ok_or_else(|| eyre!("Invalid maintenance time {part:?}; expected HH:MM"))?;
+        times.push(time);
+    }
+    times.sort();
+    times.dedup();
+    Ok(times)
+}
+
+/// Run maintenance against `db_path` at each of `times` (UTC), forever.
+pub async fn run(db_path: String, times: Vec<Time>) {
+    if times.is_empty() {
+        return;
+    }
+    info!(schedule = ?times, "Database maintenance scheduled (UTC)");
+    loop {
+        tokio::time::sleep(until_next(OffsetDateTime::now_utc(), &times)).await;
+        let path = db_path.clone();
+        match tokio::task::spawn_blocking(move || run_once(&path)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!(?e, "Database maintenance failed"),
+            Err(e) => error!(?e, "Database maintenance panicked"),
+        }
+    }
+}
+
+fn until_next(now: OffsetDateTime, times: &[Time]) -> Duration {
+    let today = now.date();
+    let next = times.iter()
+        .map(|t| today.with_time(*t).assume_utc())
+        .find(|at| *at > now)
+        .unwrap_or_else(|| today.next_day().expect("date in range").with_time(times[0]).assume_utc());
+    (next - now).try_into().unwrap_or_default()
+}
+
+/// One integrity check + incremental vacuum pass.
+pub fn run_once(db_path: &str) -> Result<()> {
+    let conn = Connection::open(db_path)?;
+
+    let started = Instant::now();
+    let problems = integrity_check(&conn)?;
+    let elapsed = started.elapsed().as_secs_f64();
+    metrics::DB_MAINTENANCE_DURATION.with_label_values(&["integrity_check"]).set(elapsed);
+    metrics::DB_INTEGRITY_OK.set(problems.is_empty() as i64);
+    if problems.is_empty() {
+        info!(secs = elapsed, "Database integrity check passed");
+    } else {
+        for p in &problems {
+            error!(problem = %p, "Database integrity check");
+        }
+        error!(problems = problems.len(), "Database integrity check FAILED; restore from a snapshot");
+    }
+
+    let started = Instant::now();
+    let freed = incremental_vacuum(&conn)?;
+    metrics::DB_MAINTENANCE_DURATION.with_label_values(&["incremental_vacuum"]).set(started.elapsed().as_secs_f64());
+    if let Some(freed) = freed {
+        metrics::DB_VACUUMED_PAGES.inc_by(freed);
+        info!(pages = freed, "Incremental vacuum done");
+    }
+    metrics::DB_FREELIST_PAGES.set(pragma_i64(&conn, "freelist_count")?);
+    metrics::DB_MAINTENANCE_LAST_RUN.set(OffsetDateTime::now_utc().unix_timestamp());
+    Ok(())
+}
+
+/// Problems reported by `PRAGMA integrity_check`; empty when the file is sound.
+fn integrity_check(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA integrity_check({MAX_INTEGRITY_ERRORS})"))?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(rows.into_iter().filter(|r| r != "ok").collect())
+}
+
+/// Release free pages back to the filesystem. `None` when the database wasn't
+/// created with `auto_vacuum=INCREMENTAL` (files from before it was enabled).
+fn incremental_vacuum(conn: &Connection) -> Result<Option<u64>> {
+    // 0 = NONE, 1 = FULL, 2 = INCREMENTAL
+    if pragma_i64(conn, "auto_vacuum")? != 2 {
+        warn!("auto_vacuum is not INCREMENTAL; skipping vacuum (run `PRAGMA auto_vacuum=INCREMENTAL; VACUUM;` once, with the indexer stopped, to enable it)");
+        return Ok(None);
+    }
+    let before = pragma_i64(conn, "freelist_count")?;
+    conn.execute_batch("PRAGMA incremental_vacuum;")?;
+    let after = pragma_i64(conn, "freelist_count")?;
+    Ok(Some(before.saturating_sub(after).max(0) as u64))
+}
+
+fn pragma_i64(conn: &Connection, name: &str) -> Result<i64> {
+    Ok(conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get(0))?)
+}
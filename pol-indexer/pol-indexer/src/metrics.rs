@@ -0,0 +1,275 @@
+//! Process-wide Prometheus metrics, exposed by the API at `GET /metrics`.
+//!
+//! Built without the `metrics` feature, the same statics exist but drop
+//! every update, so callers don't need a `cfg` of their own.
+
+use once_cell::sync::Lazy;
+#[cfg(feature = "metrics")]
+use prometheus::{Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+#[cfg(not(feature = "metrics"))]
+use noop::{Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts};
+
+#[cfg(feature = "metrics")]
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// API latency by matched route (e.g. `/grafana/query`), method and status code.
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "HTTP API request latency"),
+        &["method", "route", "status"],
+    ))
+});
+
+/// Cached net-flow routes served from memory (`hit`) or the database (`miss`).
+pub static API_CACHE_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("api_cache_requests_total", "Requests to cached API routes by outcome"),
+        &["outcome"],
+    ))
+});
+
+/// 1 if the last `PRAGMA integrity_check` passed, 0 if it reported problems.
+pub static DB_INTEGRITY_OK: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("db_integrity_ok", "Last SQLite integrity check passed (1) or failed (0)"))
+});
+
+/// Row changes waiting in the dual-write log.
+pub static DUAL_WRITE_PENDING: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("dual_write_pending_changes", "SQLite changes logged but not yet mirrored to Postgres"))
+});
+
+pub static DUAL_WRITE_IN_PARITY: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("dual_write_in_parity", "Last dual-write check found SQLite and Postgres in parity (1) or diverged (0)"))
+});
+
+/// Unix time the last maintenance pass finished.
+pub static DB_MAINTENANCE_LAST_RUN: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("db_maintenance_last_run_timestamp_seconds", "Unix time of the last database maintenance pass"))
+});
+
+/// Duration of the last run of each maintenance task (`integrity_check`, `incremental_vacuum`).
+pub static DB_MAINTENANCE_DURATION: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("db_maintenance_duration_seconds", "Duration of the last run of each maintenance task"),
+        &["task"],
+    ))
+});
+
+pub static DB_FREELIST_PAGES: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("db_freelist_pages", "Free pages in the SQLite file after the last maintenance pass"))
+});
+
+pub static DB_VACUUMED_PAGES: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("db_vacuumed_pages_total", "Pages released by incremental vacuum"))
+});
+
+/// Alert deliveries waiting in `alert_queue`.
+pub static ALERT_QUEUE_PENDING: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("alert_queue_pending", "Alert deliveries queued or waiting to be retried"))
+});
+
+/// Alert deliveries that ran out of retries.
+pub static ALERT_QUEUE_DEAD: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("alert_queue_dead_letters", "Alert deliveries given up on after the last retry"))
+});
+
+/// 1 while the indexer is backfilling towards a target block.
+pub static CATCHUP_ACTIVE: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("catchup_active", "Indexer is catching up (1) or following the head (0)"))
+});
+
+pub static CATCHUP_CURRENT_BLOCK: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("catchup_current_block", "Last block processed by the current or last catch-up"))
+});
+
+pub static CATCHUP_TARGET_BLOCK: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("catchup_target_block", "Block the current or last catch-up is heading for"))
+});
+
+pub static CATCHUP_BLOCKS_PER_SECOND: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("catchup_blocks_per_second", "Average catch-up rate since it started"))
+});
+
+/// -1 while the rate is unknown.
+pub static CATCHUP_ETA_SECONDS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("catchup_eta_seconds", "Estimated seconds until the catch-up reaches its target"))
+});
+
+/// eth_getLogs queries split because the response hit the provider's result cap.
+pub static GET_LOGS_SPLITS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("get_logs_splits_total", "eth_getLogs queries split to stay under the provider's result cap"))
+});
+
+/// Blocks stored with a timestamp estimated from their neighbours because the
+/// header's could not be read.
+pub static BLOCK_TS_INTERPOLATED: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("block_timestamps_interpolated_total", "Blocks stored with an interpolated timestamp"))
+});
+
+/// Requests the provider rejected for rate limiting (each is retried after a backoff).
+pub static RPC_RATE_LIMITED: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("rpc_rate_limited_total", "RPC requests rejected by the provider's rate limit"))
+});
+
+/// 0 while requests aren't paced.
+pub static RPC_PACED_RPS: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("rpc_paced_requests_per_second", "Current RPC request pacing, after any 429 slowdown"))
+});
+
+/// Health score (0-1, higher is healthier) of each configured RPC endpoint,
+/// by redacted host; only set when several are configured.
+pub static RPC_ENDPOINT_SCORE: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("rpc_endpoint_score", "Health score of each RPC endpoint from latency, error rate and head lag"),
+        &["endpoint"],
+    ))
+});
+
+pub static RPC_ENDPOINT_LATENCY: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("rpc_endpoint_latency_seconds", "Moving average latency of successful requests per RPC endpoint"),
+        &["endpoint"],
+    ))
+});
+
+pub static RPC_ENDPOINT_ERROR_RATE: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("rpc_endpoint_error_rate", "Moving average share of failed requests per RPC endpoint"),
+        &["endpoint"],
+    ))
+});
+
+pub static RPC_ENDPOINT_HEAD_LAG: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("rpc_endpoint_head_lag_blocks", "Blocks each RPC endpoint's head trails the highest head seen"),
+        &["endpoint"],
+    ))
+});
+
+/// 1 for the endpoint requests currently go to.
+pub static RPC_ENDPOINT_PREFERRED: Lazy<GaugeVec> = Lazy::new(|| {
+    register(GaugeVec::new(
+        Opts::new("rpc_endpoint_preferred", "RPC endpoint currently preferred (1) or standing by (0)"),
+        &["endpoint"],
+    ))
+});
+
+/// Head subscriptions abandoned after `HEAD_TIMEOUT_SECS` without a block.
+pub static HEAD_SUBSCRIPTION_STALLS: Lazy<IntCounter> = Lazy::new(|| {
+    register(IntCounter::new("head_subscription_stalls_total", "New-heads subscriptions that went silent and were reconnected"))
+});
+
+/// Provider head minus `REFERENCE_RPC_URL`'s head, at the last comparison.
+pub static REFERENCE_HEAD_DRIFT: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("reference_head_drift_blocks", "Blocks the RPC provider's head is ahead (positive) or behind (negative) the reference endpoint's"))
+});
+
+pub static REFERENCE_FORKED: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("reference_forked", "RPC provider and reference endpoint disagree on a settled block's hash (1) or agree (0)"))
+});
+
+/// 1 while block writes are held back by a busy, locked or full database.
+pub static DB_WRITES_STALLED: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("db_writes_stalled", "Indexer writes are waiting for the database to accept them again (1) or flowing (0)"))
+});
+
+pub static DB_BUFFERED_HEADS: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("db_buffered_heads", "New heads held in memory while database writes are stalled"))
+});
+
+/// Set as soon as a new block's logs arrive (`FAST_PATH`), ahead of the stored values.
+pub static NETFLOW_PROVISIONAL_BLOCK: Lazy<IntGauge> = Lazy::new(|| {
+    register(IntGauge::new("netflow_provisional_block", "Latest block whose net-flow was published from its logs alone"))
+});
+
+pub static NETFLOW_PROVISIONAL_CUMULATIVE: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("netflow_provisional_cumulative", "Cumulative net-flow including the provisional block, whole POL"))
+});
+
+/// Whole POL per hour over `VELOCITY_WINDOW`; refreshed on each scrape.
+pub static NETFLOW_VELOCITY_PER_HOUR: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("netflow_velocity_per_hour", "Net inflow per hour over the velocity window, whole POL"))
+});
+
+pub static NETFLOW_ACCELERATION_PER_HOUR: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("netflow_acceleration_per_hour", "Change in net-flow velocity versus the previous window, whole POL per hour per hour"))
+});
+
+/// Latest totalSupply() read by the indexer; refreshed on each scrape.
+pub static TOKEN_TOTAL_SUPPLY: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("token_total_supply", "Token totalSupply() at the last read, whole POL"))
+});
+
+/// Mints and burns since `supply_events_since_block` (`TRACK_SUPPLY_EVENTS`); refreshed on each scrape.
+pub static TOKEN_MINTED: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("token_minted", "Token minted (Transfers from the zero address) since supply events were first tracked, whole POL"))
+});
+
+pub static TOKEN_BURNED: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("token_burned", "Token burned (Transfers to the zero address) since supply events were first tracked, whole POL"))
+});
+
+pub static NETFLOW_PCT_OF_SUPPLY: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("netflow_cumulative_pct_of_supply", "Cumulative net-flow as a percentage of total supply"))
+});
+
+/// USD cumulative (`PRICE_FEED_ADDRESS`); refreshed on each scrape.
+pub static NETFLOW_CUMULATIVE_USD: Lazy<Gauge> = Lazy::new(|| {
+    register(Gauge::new("netflow_cumulative_usd", "Changes of the cumulative net-flow valued at each block's price, USD"))
+});
+
+#[cfg(feature = "metrics")]
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.expect("valid metric definition");
+    REGISTRY.register(Box::new(metric.clone())).expect("metric registered once");
+    metric
+}
+
+#[cfg(not(feature = "metrics"))]
+fn register(metric: noop::Metric) -> noop::Metric {
+    metric
+}
+
+/// Prometheus text exposition of every registered metric.
+#[cfg(feature = "metrics")]
+pub fn render() -> String {
+    let mut buf = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buf)
+        .expect("text encoding cannot fail");
+    String::from_utf8(buf).expect("text encoding is UTF-8")
+}
+
+/// The part of prometheus' API the statics above use, doing nothing.
+#[cfg(not(feature = "metrics"))]
+mod noop {
+    pub type Gauge = Metric;
+    pub type GaugeVec = Metric;
+    pub type HistogramVec = Metric;
+    pub type IntCounter = Metric;
+    pub type IntCounterVec = Metric;
+    pub type IntGauge = Metric;
+    pub type Opts = Metric;
+    pub type HistogramOpts = Metric;
+
+    #[derive(Clone, Copy)]
+    pub struct Metric;
+
+    impl Metric {
+        pub fn new<A, B>(_: A, _: B) -> Metric {
+            Metric
+        }
+
+        pub fn with_label_values(&self, _: &[&str]) -> Metric {
+            Metric
+        }
+
+        pub fn set<T>(&self, _: T) {}
+
+        pub fn inc(&self) {}
+
+        pub fn inc_by<T>(&self, _: T) {}
+
+        pub fn observe(&self, _: f64) {}
+    }
+}
@@ -0,0 +1,238 @@
+//! One-shot copy of every SQLite table into a Postgres database.
+//!
+//! Table definitions are derived from SQLite's own metadata (`PRAGMA table_info`
+//! and unique indexes), so new tables are picked up without touching this
+//! module. Inserts use `ON CONFLICT DO NOTHING`, so an interrupted migration can
+//! simply be re-run.
+
+use eyre::{Result, eyre};
+use rusqlite::{types::Value, Connection};
+use tokio_postgres::{types::ToSql, Client, NoTls};
+use tracing::info;
+
+use crate::dual_write;
+
+const BATCH_ROWS: usize = 500;
+
+pub struct Column {
+    pub name: String,
+    pub pg_type: &'static str,
+    not_null: bool,
+}
+
+pub struct Table {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub primary_key: Vec<String>,
+    unique: Vec<Vec<String>>,
+}
+
+impl Table {
+    pub fn column_list(&self) -> String {
+        quote_list(&self.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>())
+    }
+
+    /// `ON CONFLICT` clause replacing the stored row with the new values.
+    pub fn upsert_clause(&self) -> String {
+        let set: Vec<String> = self.columns.iter()
+            .filter(|c| !self.primary_key.contains(&c.name))
+            .map(|c| format!("\"{0}\" = EXCLUDED.\"{0}\"", c.name))
+            .collect();
+        if set.is_empty() {
+            return "ON CONFLICT DO NOTHING".to_string();
+        }
+        format!("ON CONFLICT ({}) DO UPDATE SET {}", quote_list(&self.primary_key), set.join(", "))
+    }
+}
+
+pub async fn connect(url: &str) -> Result<Client> {
+    let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::error!(?e, "Postgres connection error");
+        }
+    });
+    Ok(client)
+}
+
+pub async fn to_postgres(conn: &Connection, url: &str) -> Result<()> {
+    let client = connect(url).await?;
+
+    let tables = list_tables(conn)?;
+    info!(tables = tables.len(), "Migrating SQLite → Postgres");
+
+    for table in &tables {
+        client.batch_execute(&create_table_sql(table)).await?;
+        copy_table(conn, &client, table, "ON CONFLICT DO NOTHING").await?;
+    }
+
+    let mut mismatches = Vec::new();
+    for (table, src, dst) in row_counts(conn, &client, &tables).await? {
+        info!(%table, sqlite = src, postgres = dst, "Verified row counts");
+        if src != dst {
+            mismatches.push(format!("{table}: sqlite={src} postgres={dst}"));
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(eyre!("Row count mismatch after migration: {}", mismatches.join(", ")));
+    }
+    info!("Migration complete");
+    Ok(())
+}
+
+/// `(table, sqlite rows, postgres rows)` for each table.
+pub async fn row_counts(conn: &Connection, client: &Client, tables: &[Table]) -> Result<Vec<(String, i64, i64)>> {
+    let mut counts = Vec::with_capacity(tables.len());
+    for table in tables {
+        let src: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table.name), [], |r| r.get(0))?;
+        let dst: i64 = client.query_one(&format!("SELECT COUNT(*) FROM \"{}\"", table.name), &[]).await?.get(0);
+        counts.push((table.name.clone(), src, dst));
+    }
+    Ok(counts)
+}
+
+/// Every table except SQLite's own and the dual-write change log.
+pub fn list_tables(conn: &Connection) -> Result<Vec<Table>> {
+    let names: Vec<String> = conn
+        .prepare(&format!(
+            "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' AND name != '{}' ORDER BY name",
+            dual_write::LOG_TABLE
+        ))?
+        .query_map([], |r| r.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut tables = Vec::with_capacity(names.len());
+    for name in names {
+        let mut columns = Vec::new();
+        let mut pk: Vec<(i64, String)> = Vec::new();
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info(\"{name}\")"))?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let col: String = row.get(1)?;
+            let decl: String = row.get(2)?;
+            let not_null: bool = row.get(3)?;
+            let pk_pos: i64 = row.get(5)?;
+            if pk_pos > 0 {
+                pk.push((pk_pos, col.clone()));
+            }
+            columns.push(Column { name: col, pg_type: pg_type(&decl), not_null });
+        }
+        pk.sort();
+
+        let mut unique = Vec::new();
+        let mut stmt = conn.prepare(&format!("PRAGMA index_list(\"{name}\")"))?;
+        let indexes: Vec<(String, bool, String)> = stmt
+            .query_map([], |r| Ok((r.get(1)?, r.get(2)?, r.get(3)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (index, is_unique, origin) in indexes {
+            if !is_unique || origin == "pk" {
+                continue;
+            }
+            let cols: Vec<String> = conn
+                .prepare(&format!("PRAGMA index_info(\"{index}\")"))?
+                .query_map([], |r| r.get(2))?
+                .collect::<rusqlite::Result<_>>()?;
+            unique.push(cols);
+        }
+
+        tables.push(Table {
+            name,
+            columns,
+            primary_key: pk.into_iter().map(|(_, c)| c).collect(),
+            unique,
+        });
+    }
+    Ok(tables)
+}
+
+fn pg_type(sqlite_decl: &str) -> &'static str {
+    match sqlite_decl.to_ascii_uppercase().as_str() {
+        "BOOLEAN" => "BOOLEAN",
+        "REAL" | "DOUBLE" | "FLOAT" => "DOUBLE PRECISION",
+        "BLOB" => "BYTEA",
+        d if d.contains("INT") => "BIGINT",
+        _ => "TEXT",
+    }
+}
+
+pub fn create_table_sql(table: &Table) -> String {
+    let mut defs: Vec<String> = table.columns.iter()
+        .map(|c| format!("\"{}\" {}{}", c.name, c.pg_type, if c.not_null { " NOT NULL" } else { "" }))
+        .collect();
+    if !table.primary_key.is_empty() {
+        defs.push(format!("PRIMARY KEY ({})", quote_list(&table.primary_key)));
+    }
+    for cols in &table.unique {
+        defs.push(format!("UNIQUE ({})", quote_list(cols)));
+    }
+    format!("CREATE TABLE IF NOT EXISTS \"{}\" (\n    {}\n)", table.name, defs.join(",\n    "))
+}
+
+pub fn quote_list(cols: &[String]) -> String {
+    cols.iter().map(|c| format!("\"{c}\"")).collect::<Vec<_>>().join(", ")
+}
+
+/// Copy every row of `table`, resolving conflicts with `on_conflict`.
+pub async fn copy_table(conn: &Connection, client: &Client, table: &Table, on_conflict: &str) -> Result<()> {
+    let total: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table.name), [], |r| r.get(0))?;
+    let cols = table.column_list();
+    let select = format!("SELECT rowid, {cols} FROM \"{}\" WHERE rowid > ?1 ORDER BY rowid LIMIT ?2", table.name);
+
+    let mut last_rowid = i64::MIN;
+    let mut copied: i64 = 0;
+    loop {
+        // Read a batch synchronously, then release the statement before awaiting
+        let batch: Vec<Vec<Value>> = {
+            let mut stmt = conn.prepare(&select)?;
+            let mut rows = stmt.query(rusqlite::params![last_rowid, BATCH_ROWS as i64])?;
+            let mut out = Vec::new();
+            while let Some(row) = rows.next()? {
+                last_rowid = row.get(0)?;
+                let vals = (1..=table.columns.len())
+                    .map(|i| row.get::<_, Value>(i))
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                out.push(vals);
+            }
+            out
+        };
+        if batch.is_empty() {
+            break;
+        }
+
+        let params: Vec<Box<dyn ToSql + Sync>> = batch.iter()
+            .flat_map(|row| row.iter().zip(&table.columns).map(|(v, c)| to_pg(v, c.pg_type)))
+            .collect();
+        let width = table.columns.len();
+        let placeholders: Vec<String> = (0..batch.len())
+            .map(|r| format!("({})", (1..=width).map(|c| format!("${}", r * width + c)).collect::<Vec<_>>().join(", ")))
+            .collect();
+        let insert = format!(
+            "INSERT INTO \"{}\" ({cols}) VALUES {} {on_conflict}",
+            table.name,
+            placeholders.join(", ")
+        );
+        let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref()).collect();
+        client.execute(&insert, &refs).await?;
+
+        copied += batch.len() as i64;
+        info!(table = %table.name, copied, total, "Copying");
+    }
+    Ok(())
+}
+
+pub fn to_pg(v: &Value, pg_type: &str) -> Box<dyn ToSql + Sync> {
+    match (v, pg_type) {
+        (Value::Null, "BIGINT") => Box::new(None::<i64>),
+        (Value::Null, "BOOLEAN") => Box::new(None::<bool>),
+        (Value::Null, "DOUBLE PRECISION") => Box::new(None::<f64>),
+        (Value::Null, "BYTEA") => Box::new(None::<Vec<u8>>),
+        (Value::Null, _) => Box::new(None::<String>),
+        (Value::Integer(i), "BOOLEAN") => Box::new(*i != 0),
+        (Value::Integer(i), "DOUBLE PRECISION") => Box::new(*i as f64),
+        (Value::Integer(i), "TEXT") => Box::new(i.to_string()),
+        (Value::Integer(i), _) => Box::new(*i),
+        (Value::Real(f), _) => Box::new(*f),
+        (Value::Text(s), _) => Box::new(s.clone()),
+        (Value::Blob(b), _) => Box::new(b.clone()),
+    }
+}
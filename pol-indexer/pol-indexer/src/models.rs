@@ -1,14 +1,496 @@
+use std::fmt::{self, Write as _};
+
 use eyre::{Result, eyre};
-use ethers::types::{Address, H160};
+use ethers::{types::{Address, H160, H256, U256}, utils::{format_units, to_checksum}};
+use time::OffsetDateTime;
+
+/// POL has 18 decimals; used wherever raw units are shown as whole tokens.
+pub const POL_DECIMALS: u32 = 18;
+
+/// How raw U256 amounts are rendered in API responses (`?format=`, or the
+/// API key's default).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueFormat {
+    /// Raw units as a decimal string (default)
+    #[default]
+    Decimal,
+    /// Raw units as 0x-prefixed lowercase hex
+    Hex,
+    /// Whole tokens (raw / 10^POL_DECIMALS) as a decimal string
+    Scaled,
+    /// US dollars at the price stored for the value's block; only offered
+    /// where each value belongs to one block (see `api::units`)
+    Usd,
+}
+
+impl ValueFormat {
+    /// Render a raw decimal string, which may carry a leading `-` (signed nets).
+    /// Values that don't parse are returned unchanged.
+    pub fn render(self, raw: &str) -> String {
+        let (sign, digits) = match raw.strip_prefix('-') {
+            Some(d) => ("-", d),
+            None => ("", raw),
+        };
+        let Ok(v) = U256::from_dec_str(digits) else { return raw.to_string() };
+        let body = match self {
+            ValueFormat::Decimal => v.to_string(),
+            ValueFormat::Hex => format!("{v:#x}"),
+            // Priced by the caller with `prices::render_usd`; whole tokens are the nearest unit
+            ValueFormat::Scaled | ValueFormat::Usd => scaled(v),
+        };
+        format!("{sign}{body}")
+    }
+
+    /// As in the `format` query parameter and `api_keys.default_format`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ValueFormat::Decimal => "decimal",
+            ValueFormat::Hex => "hex",
+            ValueFormat::Scaled => "scaled",
+            ValueFormat::Usd => "usd",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [ValueFormat::Decimal, ValueFormat::Hex, ValueFormat::Scaled, ValueFormat::Usd].into_iter().find(|f| f.as_str() == s)
+    }
+}
+
+/// Whole tokens without trailing zeros, e.g. `1.5`.
+pub fn scaled(v: U256) -> String {
+    let s = format_units(v, POL_DECIMALS).unwrap_or_else(|_| v.to_string());
+    match s.split_once('.') {
+        Some((int, frac)) if frac.trim_end_matches('0').is_empty() => int.to_string(),
+        Some((int, frac)) => format!("{int}.{}", frac.trim_end_matches('0')),
+        None => s,
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Erc20Transfer {
     pub block_number: u64,
-    pub tx_hash: String,
+    pub tx_hash: H256,
     pub log_index: u64,
     pub from: Address,
     pub to: Address,
-    pub value: ethers::types::U256,
+    pub value: U256,
+}
+
+/// Source of `updated_at` timestamps on chain-derived rows (`CLOCK`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Clock {
+    /// Timestamp of the block the update reflects; immune to host clock skew
+    #[default]
+    Block,
+    /// Host wall clock at write time
+    Wall,
+}
+
+impl Clock {
+    /// `updated_at` for a write reflecting a block with timestamp `block_ts`.
+    pub fn stamp(self, block_ts: i64) -> i64 {
+        match self {
+            Clock::Block => block_ts,
+            Clock::Wall => OffsetDateTime::now_utc().unix_timestamp(),
+        }
+    }
+}
+
+/// How transfers are attributed to Binance. Fixed per database once recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccountingMode {
+    /// Only the configured hot wallets count as Binance
+    #[default]
+    HotWallet,
+    /// Addresses that sweep into a hot wallet are discovered as deposit addresses and
+    /// count as Binance too: deposits are inflows, their sweeps are internal
+    DepositNetting,
+}
+
+impl AccountingMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccountingMode::HotWallet => "hot-wallet",
+            AccountingMode::DepositNetting => "deposit-netting",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "hot-wallet" => Ok(AccountingMode::HotWallet),
+            "deposit-netting" => Ok(AccountingMode::DepositNetting),
+            other => Err(eyre!("Unknown accounting mode: {other}")),
+        }
+    }
+}
+
+/// Role of an exchange address, for tier-level flows (`/netflow/by-tier`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalletTier {
+    Hot,
+    Cold,
+    Deposit,
+}
+
+impl WalletTier {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "hot" => Ok(WalletTier::Hot),
+            "cold" => Ok(WalletTier::Cold),
+            "deposit" => Ok(WalletTier::Deposit),
+            other => Err(eyre!("Unknown wallet tier {other:?}; expected hot, cold or deposit")),
+        }
+    }
+}
+
+/// `WALLET_TIERS`, e.g. `0xabc…=cold,0xdef…=deposit`. Configured addresses
+/// without a tag are hot and discovered deposit addresses are deposit.
+#[derive(Debug, Clone, Default)]
+pub struct WalletTiers(pub Vec<(Address, WalletTier)>);
+
+impl std::str::FromStr for WalletTiers {
+    type Err = String;
+
+    fn from_str(csv: &str) -> std::result::Result<Self, String> {
+        let mut out = Vec::new();
+        for part in csv.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (addr, tier) = part.split_once('=').ok_or_else(|| format!("expected address=tier, got {part:?}"))?;
+            let addr = parse_address(addr.trim()).map_err(|e| e.to_string())?;
+            out.push((addr, WalletTier::parse(tier).map_err(|e| e.to_string())?));
+        }
+        Ok(WalletTiers(out))
+    }
+}
+
+/// Receipt status of the transaction that emitted a transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    Success,
+    Reverted,
+}
+
+impl TxStatus {
+    pub fn from_receipt(ok: bool) -> Self {
+        if ok { TxStatus::Success } else { TxStatus::Reverted }
+    }
+
+    pub fn as_i64(self) -> i64 {
+        match self {
+            TxStatus::Success => 1,
+            TxStatus::Reverted => 0,
+        }
+    }
+
+    pub fn from_i64(v: i64) -> Self {
+        Self::from_receipt(v != 0)
+    }
+}
+
+/// Which way a transfer moved relative to the tracked addresses. Only `in`
+/// and `out` cross the boundary and count towards the net-flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// To a tracked address from an untracked one
+    In,
+    /// From a tracked address to an untracked one
+    Out,
+    /// Between two tracked addresses
+    Internal,
+    /// Neither side tracked (e.g. after the address set changed)
+    None,
+}
+
+impl Direction {
+    pub fn of(from_tracked: bool, to_tracked: bool) -> Self {
+        match (from_tracked, to_tracked) {
+            (false, true) => Direction::In,
+            (true, false) => Direction::Out,
+            (true, true) => Direction::Internal,
+            (false, false) => Direction::None,
+        }
+    }
+
+    /// As stored in the `direction` column and rendered in exports.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+            Direction::Internal => "internal",
+            Direction::None => "none",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "in" => Some(Direction::In),
+            "out" => Some(Direction::Out),
+            "internal" => Some(Direction::Internal),
+            "none" => Some(Direction::None),
+            _ => None,
+        }
+    }
+}
+
+/// What the other side of an inflow or outflow is (`TAG_CONTRACTS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CounterpartyKind {
+    /// No code at the address when first seen
+    Eoa,
+    Contract,
+}
+
+impl CounterpartyKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "eoa" => Some(CounterpartyKind::Eoa),
+            "contract" => Some(CounterpartyKind::Contract),
+            _ => None,
+        }
+    }
+}
+
+/// A stored `erc20_transfers` row, as served by the API.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TransferRecord {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub log_index: u64,
+    pub token: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub direction: Direction,
+    /// `null` when the receipt status wasn't checked (e.g. imported rows)
+    pub status: Option<TxStatus>,
+    /// Accounting-rule bucket, for transfers kept out of the net-flow that way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// The counterparty's classification; absent until it is checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty_kind: Option<CounterpartyKind>,
+    /// Known contract the counterparty's bytecode matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty_label: Option<String>,
+}
+
+/// One `annotations` row: an analyst's note on a transfer.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Annotation {
+    pub id: i64,
+    pub tx_hash: String,
+    pub log_index: u64,
+    pub note: String,
+    pub tags: Vec<String>,
+    pub created_at_unix: i64,
+}
+
+/// One `alert_queue` row: an alert waiting for (or given up on) one sink.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct QueuedAlert {
+    pub id: i64,
+    pub sink: String,
+    pub alert: serde_json::Value,
+    pub attempts: u32,
+    pub next_attempt_at_unix: i64,
+    pub last_error: Option<String>,
+    pub created_at_unix: i64,
+    /// Set once retries ran out
+    pub dead_at_unix: Option<i64>,
+}
+
+/// One `reorgs` row: a fork at the chain tip and how the stored data was rolled back.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ReorgRecord {
+    pub id: i64,
+    pub detected_at_unix: i64,
+    /// Head whose hash or parent didn't match the stored chain
+    pub new_head: u64,
+    pub new_head_hash: String,
+    /// Last block both branches share; everything above it was rolled back
+    pub fork_block: u64,
+    pub depth: u64,
+    /// Stored hashes above the fork, newest first. A backfill stores a `blocks`
+    /// row only for blocks with matched transfers, so backfilled blocks without
+    /// any are missing here (and counted in `depth`); nothing of theirs was dropped
+    pub orphaned_hashes: Vec<String>,
+    pub removed_transfers: Vec<TransferRecord>,
+    /// Counted flows of the dropped blocks (raw units)
+    pub removed_inflow: String,
+    pub removed_outflow: String,
+    pub cumulative_before: String,
+    /// Cumulative after the rollback, before the new branch was indexed
+    pub cumulative_after: String,
+}
+
+/// One `address_set_history` row: an address joining or leaving the tracked set.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AddressChange {
+    pub id: i64,
+    pub changed_at_unix: i64,
+    pub address: String,
+    /// `added` or `removed`
+    pub change: String,
+    /// `configured` (`BINANCE_ADDRESSES`) or `deposit` (discovered in deposit-netting mode)
+    pub kind: String,
+    /// What made the change, e.g. `run --force`, `rebuild --since-block 50000000`, `deposit discovery`
+    pub source: String,
+    /// OS user that ran the command, when known
+    pub actor: Option<String>,
+    /// Last indexed block when the change was recorded; for deposit addresses, the sweep's block
+    pub block_number: Option<u64>,
+}
+
+/// One `balance_baselines` row: a tracked address's token balance before
+/// the first block indexed for it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BalanceBaseline {
+    pub address: String,
+    /// Balance as of the end of this block; later transfers build on it
+    pub block_number: u64,
+    pub balance: String,
+    pub recorded_at_unix: i64,
+}
+
+/// One `block_stats` row: what indexing one block took.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockStats {
+    pub block_number: u64,
+    /// Token logs the block was processed from, tracked or not
+    pub logs_fetched: u64,
+    /// Transfers counted towards the net-flow
+    pub transfers_matched: u64,
+    /// From the start of indexing the block until it was written
+    pub processing_ms: u64,
+    /// The part of `processing_ms` spent waiting on the RPC. A backfill reads
+    /// logs for a whole range at once; that doesn't count towards any block.
+    pub rpc_ms: u64,
+    pub indexed_at_unix: i64,
+}
+
+/// What one `run` session did; logged on exit and stored in `sessions`.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SessionSummary {
+    pub started_at_unix: i64,
+    pub ended_at_unix: i64,
+    /// `signal`, `stream_ended`, or the error that stopped the indexer
+    pub exit_reason: String,
+    pub blocks_processed: u64,
+    /// Transfers newly stored this session (re-seen duplicates aren't counted)
+    pub transfers_indexed: u64,
+    pub reorgs: u64,
+    pub first_block: Option<u64>,
+    pub last_block: Option<u64>,
+    pub first_block_ts_unix: Option<i64>,
+    pub last_block_ts_unix: Option<i64>,
+    pub final_cumulative: String,
+}
+
+impl SessionSummary {
+    /// Count one committed block.
+    pub fn record_block(&mut self, number: u64, ts_unix: i64, transfers: u64) {
+        self.blocks_processed += 1;
+        self.transfers_indexed += transfers;
+        if self.first_block.is_none_or(|b| number < b) {
+            self.first_block = Some(number);
+            self.first_block_ts_unix = Some(ts_unix);
+        }
+        if self.last_block.is_none_or(|b| number > b) {
+            self.last_block = Some(number);
+            self.last_block_ts_unix = Some(ts_unix);
+        }
+    }
+}
+
+impl TransferRecord {
+    /// Prepare for an API response: amount in `format`, addresses EIP-55 checksummed.
+    pub fn render(&mut self, format: ValueFormat) {
+        self.value = format.render(&self.value);
+        self.token = checksummed(&self.token);
+        self.from = checksummed(&self.from);
+        self.to = checksummed(&self.to);
+    }
+}
+
+/// Canonical stored form of an address: lowercase, 0x-prefixed. Every address
+/// column is written this way so lookups and `UNIQUE` constraints never depend
+/// on the case a caller (or CSV) happened to use.
+pub fn address_key(addr: &Address) -> String {
+    format!("{addr:?}")
+}
+
+/// A stored key (address, hash or decimal amount) rendered into a fixed-size
+/// buffer instead of a `String`, for the per-transfer insert path, which runs
+/// millions of times in a backfill. Derefs to the same text as `address_key`
+/// and `format!("{:?}")`/`to_string()`.
+pub struct KeyStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+/// `0x` + 40 hex digits
+pub type AddressStr = KeyStr<42>;
+/// `0x` + 64 hex digits
+pub type HashStr = KeyStr<66>;
+/// Up to 78 decimal digits, enough for `U256::MAX`
+pub type DecimalStr = KeyStr<78>;
+
+impl AddressStr {
+    pub fn new(addr: &Address) -> Self {
+        Self::render(format_args!("{addr:?}"))
+    }
+}
+
+impl HashStr {
+    pub fn new(hash: &H256) -> Self {
+        Self::render(format_args!("{hash:?}"))
+    }
+}
+
+impl DecimalStr {
+    pub fn new(value: &U256) -> Self {
+        Self::render(format_args!("{value}"))
+    }
+}
+
+impl<const N: usize> KeyStr<N> {
+    fn render(args: fmt::Arguments) -> Self {
+        let mut out = Self { buf: [0; N], len: 0 };
+        out.write_fmt(args).expect("key fits its buffer");
+        out
+    }
+}
+
+impl<const N: usize> fmt::Write for KeyStr<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+impl<const N: usize> std::ops::Deref for KeyStr<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        // Only whole `&str`s are ever copied in
+        std::str::from_utf8(&self.buf[..self.len]).expect("utf-8")
+    }
+}
+
+/// EIP-55 mixed-case rendering of a stored address, for API responses.
+/// Strings that aren't addresses are returned unchanged.
+pub fn checksummed(addr: &str) -> String {
+    match addr.parse::<Address>() {
+        Ok(a) => to_checksum(&a, None),
+        Err(_) => addr.to_string(),
+    }
 }
 
 pub fn parse_address(s: &str) -> Result<Address> {
@@ -16,6 +498,20 @@ pub fn parse_address(s: &str) -> Result<Address> {
         .map_err(|_| eyre!("Invalid address: {}", s))
 }
 
+/// `90m`, `24h`, `7d`, `2w` → seconds.
+pub fn parse_window(s: &str) -> Option<i64> {
+    let (n, unit) = s.split_at(s.len().checked_sub(1)?);
+    let n: i64 = n.parse().ok().filter(|n| *n > 0)?;
+    let unit = match unit {
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => return None,
+    };
+    n.checked_mul(unit)
+}
+
 pub fn parse_addresses(csv: &str) -> Result<Vec<Address>> {
     let mut out = Vec::new();
     for part in csv.split(',') {
@@ -33,5 +529,161 @@ pub fn parse_addresses(csv: &str) -> Result<Vec<Address>> {
 pub struct NetflowSnapshot {
     pub block_number: u64,
     pub cumulative_netflow_raw: String, // as U256 string (wei units of token decimals, i.e. raw)
+    /// The same in whole tokens
+    pub cumulative_token: String,
+    /// Changes of the cumulative valued at each block's price, in USD; `None` without a price feed
+    pub cumulative_usd: Option<String>,
+    /// First block with a recorded price; the USD cumulative covers changes from there on
+    pub usd_since_block: Option<u64>,
     pub updated_at_unix: i64,
+    /// Accounting epoch: the cumulative is the net-flow since this block
+    pub start_block: Option<u64>,
+}
+
+/// One `native_transfers` row: a transaction's POL value to or from a tracked address.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NativeTransferRecord {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub from: String,
+    pub to: String,
+    pub value: String,
+    pub direction: Direction,
+    pub status: Option<TxStatus>,
+}
+
+/// Whether a Transfer created tokens (from the zero address) or destroyed them (to it).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SupplyEventKind {
+    Mint,
+    Burn,
+}
+
+impl SupplyEventKind {
+    /// `None` for a transfer between two other addresses, or from zero to zero.
+    pub fn of(tr: &Erc20Transfer) -> Option<Self> {
+        match (tr.from.is_zero(), tr.to.is_zero()) {
+            (true, false) => Some(SupplyEventKind::Mint),
+            (false, true) => Some(SupplyEventKind::Burn),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SupplyEventKind::Mint => "mint",
+            SupplyEventKind::Burn => "burn",
+        }
+    }
+}
+
+/// One `supply_events` row: a mint or burn of the token, with the running
+/// totals after it.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SupplyEvent {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub log_index: u64,
+    pub kind: SupplyEventKind,
+    /// Recipient of a mint, sender of a burn
+    pub account: String,
+    pub value: String,
+    pub ts_unix: i64,
+    pub minted_total: String,
+    pub burned_total: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NftStandard {
+    Erc721,
+    Erc1155,
+}
+
+impl NftStandard {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NftStandard::Erc721 => "erc721",
+            NftStandard::Erc1155 => "erc1155",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "erc721" => Some(NftStandard::Erc721),
+            "erc1155" => Some(NftStandard::Erc1155),
+            _ => None,
+        }
+    }
+}
+
+/// One `nft_transfers` row: an ERC-721 or ERC-1155 token moved to or from a
+/// tracked address (`TRACK_NFTS`). An ERC-1155 batch is one row per token id.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct NftTransfer {
+    pub block_number: u64,
+    pub tx_hash: String,
+    pub log_index: u64,
+    /// Position in an ERC-1155 batch; `0` otherwise
+    pub batch_index: u64,
+    /// Collection contract (lowercase)
+    pub contract: String,
+    pub standard: NftStandard,
+    /// Decimal
+    pub token_id: String,
+    /// Decimal; always `1` for ERC-721
+    pub amount: String,
+    pub from: String,
+    pub to: String,
+    pub direction: Direction,
+    pub ts_unix: i64,
+}
+
+/// One row of `native_netflow_blocks`. Inflow and outflow totals are kept
+/// separately so the (possibly negative) net stays exact.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct NativeNetflowBlock {
+    pub block_number: u64,
+    pub ts_unix: i64,
+    pub inflow: String,
+    pub outflow: String,
+    pub cumulative_inflow: String,
+    pub cumulative_outflow: String,
+    pub transfer_count: u64,
+}
+
+/// Net-flow of a new block's matched transfers, published as soon as its
+/// logs arrive (`FAST_PATH`): before the header, receipts and the database
+/// write that confirm it as a [`NetflowBlock`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ProvisionalNetflow {
+    pub block_number: u64,
+    pub block_hash: Option<String>,
+    pub inflow: String,
+    pub outflow: String,
+    /// The stored cumulative with this block's flows applied
+    pub cumulative: String,
+    pub transfer_count: u64,
+    /// When the logs were in hand, Unix milliseconds
+    pub received_at_ms: i64,
+}
+
+/// One row of `netflow_blocks`: raw-unit aggregates for a single block, with
+/// the cumulative also in whole tokens and USD.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NetflowBlock {
+    pub block_number: u64,
+    pub ts_unix: i64,
+    pub inflow: String,
+    pub outflow: String,
+    pub cumulative: String,
+    pub transfer_count: u64,
+    /// `cumulative` in whole tokens
+    #[serde(default)]
+    pub cumulative_token: String,
+    /// Token price at this block (`PRICE_FEED_ADDRESS`), micro-USD
+    pub price_usd_micro: Option<i64>,
+    /// USD cumulative after this block, micro-USD
+    pub cumulative_usd_micro: Option<i64>,
 }
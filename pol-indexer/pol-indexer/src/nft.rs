@@ -0,0 +1,172 @@
+//! NFT inventory of the tracked addresses (`TRACK_NFTS`), for exchanges that
+//! also custody Polygon NFTs: every ERC-721 `Transfer` and ERC-1155
+//! `TransferSingle`/`TransferBatch` to or from a tracked address, of any
+//! collection, goes in `nft_transfers`, served by `GET /nfts/transfers` and
+//! `GET /nfts/inventory`.
+//!
+//! ERC-721 and ERC-20 share the `Transfer` signature; ERC-721 indexes the
+//! token id as a fourth topic, which is how the two are told apart.
+
+use ethers::types::{Address, H256, U256};
+
+use crate::{
+    chain::{LogFilter, RawLog},
+    indexer::TRANSFER_TOPIC,
+    models::{self, Direction, NftStandard, NftTransfer},
+};
+
+// keccak256("TransferSingle(address,address,address,uint256,uint256)")
+pub const TRANSFER_SINGLE_TOPIC: H256 = H256([
+    0xc3, 0xd5, 0x81, 0x68, 0xc5, 0xae, 0x73, 0x97,
+    0x73, 0x1d, 0x06, 0x3d, 0x5b, 0xbf, 0x3d, 0x65,
+    0x78, 0x54, 0x42, 0x73, 0x43, 0xf4, 0xc0, 0x83,
+    0x24, 0x0f, 0x7a, 0xac, 0xaa, 0x2d, 0x0f, 0x62
+]);
+
+// keccak256("TransferBatch(address,address,address,uint256[],uint256[])")
+pub const TRANSFER_BATCH_TOPIC: H256 = H256([
+    0x4a, 0x39, 0xdc, 0x06, 0xd4, 0xc0, 0xdb, 0xc6,
+    0x4b, 0x70, 0xaf, 0x90, 0xfd, 0x69, 0x8a, 0x23,
+    0x3a, 0x51, 0x8a, 0xa5, 0xd0, 0x7e, 0x59, 0x5d,
+    0x98, 0x3b, 0x8c, 0x05, 0x26, 0xc8, 0xf7, 0xfb
+]);
+
+/// An ERC-721 or ERC-1155 transfer log (of any contract).
+pub fn is_nft_log(lg: &RawLog) -> bool {
+    lg.topics.len() == 4 && [TRANSFER_TOPIC, TRANSFER_SINGLE_TOPIC, TRANSFER_BATCH_TOPIC].contains(&lg.topics[0])
+}
+
+/// Queries for the NFT transfers from or to `parties` within `base`'s range,
+/// across every contract. The ERC-721 ones also return ERC-20 transfers of
+/// the same addresses, which [`is_nft_log`] drops.
+pub fn filters(base: &LogFilter, parties: &[H256]) -> Vec<LogFilter> {
+    let base = LogFilter { address: None, topic1: None, topic2: None, topic3: None, ..base.clone() };
+    let mut out = vec![
+        LogFilter { topic1: Some(parties.to_vec()), ..base.clone() },
+        LogFilter { topic2: Some(parties.to_vec()), ..base.clone() },
+    ];
+    // ERC-1155: topic1 is the operator; from and to follow
+    for topic0 in [TRANSFER_SINGLE_TOPIC, TRANSFER_BATCH_TOPIC] {
+        out.push(LogFilter { topic0, topic2: Some(parties.to_vec()), ..base.clone() });
+        out.push(LogFilter { topic0, topic3: Some(parties.to_vec()), ..base.clone() });
+    }
+    out
+}
+
+/// The rows for one NFT log, one per token id of a batch; `direction` places
+/// each relative to the tracked addresses, and rows with no tracked party are
+/// left out. Malformed logs yield nothing.
+pub fn transfers(lg: &RawLog, ts_unix: i64, direction: impl Fn(&Address, &Address) -> Direction) -> Vec<NftTransfer> {
+    let (Some(block_number), Some(tx_hash), Some(log_index)) = (lg.block_number, lg.transaction_hash, lg.log_index) else {
+        return Vec::new();
+    };
+    let Some((standard, from, to, moved)) = decode(lg) else { return Vec::new() };
+    let direction = direction(&from, &to);
+    if direction == Direction::None {
+        return Vec::new();
+    }
+    moved.into_iter().enumerate().map(|(i, (token_id, amount))| NftTransfer {
+        block_number,
+        tx_hash: format!("{tx_hash:?}"),
+        log_index,
+        batch_index: i as u64,
+        contract: models::address_key(&lg.address),
+        standard,
+        token_id: token_id.to_string(),
+        amount: amount.to_string(),
+        from: models::address_key(&from),
+        to: models::address_key(&to),
+        direction,
+        ts_unix,
+    }).collect()
+}
+
+type Decoded = (NftStandard, Address, Address, Vec<(U256, U256)>);
+
+fn decode(lg: &RawLog) -> Option<Decoded> {
+    if !is_nft_log(lg) {
+        return None;
+    }
+    let address = |t: &H256| Address::from_slice(&t.as_bytes()[12..]);
+    let topic0 = lg.topics[0];
+    if topic0 == TRANSFER_TOPIC {
+        let token_id = U256::from_big_endian(lg.topics[3].as_bytes());
+        return Some((NftStandard::Erc721, address(&lg.topics[1]), address(&lg.topics[2]), vec![(token_id, U256::one())]));
+    }
+    let (from, to) = (address(&lg.topics[2]), address(&lg.topics[3]));
+    let moved = if topic0 == TRANSFER_SINGLE_TOPIC {
+        vec![(word(&lg.data, 0)?, word(&lg.data, 1)?)]
+    } else {
+        let ids = array(&lg.data, word(&lg.data, 0)?)?;
+        let values = array(&lg.data, word(&lg.data, 1)?)?;
+        if ids.len() != values.len() {
+            return None;
+        }
+        ids.into_iter().zip(values).collect()
+    };
+    Some((NftStandard::Erc1155, from, to, moved))
+}
+
+/// The `i`th 32-byte word of ABI-encoded `data`.
+fn word(data: &[u8], i: usize) -> Option<U256> {
+    let start = i.checked_mul(32)?;
+    data.get(start..start.checked_add(32)?).map(U256::from_big_endian)
+}
+
+/// A dynamic `uint256[]` at byte `offset` of `data`: its length, then the items.
+fn array(data: &[u8], offset: U256) -> Option<Vec<U256>> {
+    if offset > U256::from(data.len()) || !offset.low_u64().is_multiple_of(32) {
+        return None;
+    }
+    let head = offset.as_usize() / 32;
+    let len = word(data, head)?;
+    if len > U256::from(data.len() / 32) {
+        return None;
+    }
+    (0..len.as_usize()).map(|i| word(data, head + 1 + i)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::utils::keccak256;
+
+    use super::*;
+
+    #[test]
+    fn decodes_a_batch_into_one_row_per_token() {
+        assert_eq!(TRANSFER_SINGLE_TOPIC.0, keccak256("TransferSingle(address,address,address,uint256,uint256)"));
+        assert_eq!(TRANSFER_BATCH_TOPIC.0, keccak256("TransferBatch(address,address,address,uint256[],uint256[])"));
+
+        let hot = Address::repeat_byte(0xb1);
+        let user = Address::repeat_byte(0x11);
+        let words: Vec<U256> = [0x40, 0xa0, 2, 7, 9, 2, 1, 5].into_iter().map(U256::from).collect();
+        let mut data = vec![0; words.len() * 32];
+        for (i, w) in words.iter().enumerate() {
+            w.to_big_endian(&mut data[i * 32..(i + 1) * 32]);
+        }
+        let mut lg = RawLog {
+            address: Address::repeat_byte(0xcc),
+            topics: vec![TRANSFER_BATCH_TOPIC, H256::from(user), H256::from(user), H256::from(hot)],
+            data,
+            block_number: Some(5),
+            block_hash: None,
+            transaction_hash: Some(H256::repeat_byte(1)),
+            log_index: Some(3),
+        };
+        let direction = |from: &Address, to: &Address| Direction::of(*from == hot, *to == hot);
+        let rows = transfers(&lg, 0, direction);
+        let moved: Vec<_> = rows.iter().map(|r| (r.batch_index, r.token_id.as_str(), r.amount.as_str())).collect();
+        assert_eq!(moved, [(0, "7", "1"), (1, "9", "5")]);
+        assert!(rows.iter().all(|r| r.standard == NftStandard::Erc1155 && r.direction == Direction::In));
+
+        lg.data.truncate(7 * 32);
+        assert!(transfers(&lg, 0, direction).is_empty(), "a truncated batch is dropped");
+
+        // An ERC-20 Transfer has no fourth topic
+        lg.topics = vec![TRANSFER_TOPIC, H256::from(hot), H256::from(user)];
+        assert!(!is_nft_log(&lg));
+        lg.topics.push(H256::from_low_u64_be(42));
+        let rows = transfers(&lg, 0, direction);
+        assert_eq!((rows[0].standard, rows[0].token_id.as_str(), rows[0].amount.as_str(), rows[0].direction), (NftStandard::Erc721, "42", "1", Direction::Out));
+    }
+}
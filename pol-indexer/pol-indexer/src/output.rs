@@ -0,0 +1,107 @@
+//! `--output json|table|csv` for what subcommands print: `query`, `keys list`,
+//! `correlate`, `db analyze`, and the summaries of `import`, `rebuild`,
+//! `export` and `checkpoint verify`.
+//!
+//! Any serializable value renders in all three: an object is one row, an array
+//! of objects is one row each, and columns follow the struct's field order.
+//! In table and CSV cells, `null` is empty, arrays of scalars are
+//! comma-joined and anything more nested is compact JSON.
+
+use std::io::Write;
+
+use clap::ValueEnum;
+use eyre::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Pretty-printed JSON (default)
+    #[default]
+    Json,
+    /// Aligned columns for reading in a terminal
+    Table,
+    /// Header row plus one line per record, for scripts and spreadsheets
+    Csv,
+}
+
+pub fn print<T: Serialize>(format: OutputFormat, value: &T) -> Result<()> {
+    write(format, value, std::io::stdout().lock())
+}
+
+/// [`print`] to `out`, e.g. stderr when stdout carries the data itself.
+pub fn write<T: Serialize>(format: OutputFormat, value: &T, mut out: impl Write) -> Result<()> {
+    match format {
+        OutputFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(value)?)?,
+        OutputFormat::Table => {
+            let (columns, rows) = tabulate(serde_json::to_value(value)?);
+            write!(out, "{}", render_table(&columns, &rows))?;
+        }
+        OutputFormat::Csv => {
+            let (columns, rows) = tabulate(serde_json::to_value(value)?);
+            let mut w = csv::Writer::from_writer(out);
+            w.write_record(&columns)?;
+            for row in &rows {
+                w.write_record(row)?;
+            }
+            w.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Column names in first-seen order, and each record's cells in that order.
+fn tabulate(value: Value) -> (Vec<String>, Vec<Vec<String>>) {
+    let records: Vec<Map<String, Value>> = match value {
+        Value::Array(items) => items.into_iter().map(record).collect(),
+        other => vec![record(other)],
+    };
+    let mut columns: Vec<String> = Vec::new();
+    for key in records.iter().flat_map(|r| r.keys()) {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+    let rows = records.iter()
+        .map(|r| columns.iter().map(|c| r.get(c).map(cell).unwrap_or_default()).collect())
+        .collect();
+    (columns, rows)
+}
+
+/// Scalars become a single `value` column.
+fn record(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        other => Map::from_iter([("value".to_string(), other)]),
+    }
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) if items.iter().all(|v| !v.is_array() && !v.is_object()) => {
+            items.iter().map(cell).collect::<Vec<_>>().join(",")
+        }
+        other => other.to_string(),
+    }
+}
+
+fn render_table(columns: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    for row in rows {
+        for (w, c) in widths.iter_mut().zip(row) {
+            *w = (*w).max(c.chars().count());
+        }
+    }
+    let line = |cells: &[String]| {
+        let padded: Vec<String> = cells.iter().zip(&widths).map(|(c, w)| format!("{c:<w$}")).collect();
+        format!("{}\n", padded.join("  ").trim_end())
+    };
+    let mut out = line(columns);
+    out += &line(&widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>());
+    for row in rows {
+        out += &line(row);
+    }
+    out
+}
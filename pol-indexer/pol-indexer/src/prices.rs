@@ -0,0 +1,90 @@
+//! USD value of the net-flow, from a Chainlink aggregator on Polygon
+//! (`PRICE_FEED_ADDRESS`, e.g. the POL/USD feed).
+//!
+//! The indexer reads `latestRoundData()` at every block with matched transfers
+//! and stores the price with the block's aggregates. The USD cumulative then
+//! values each change of the cumulative at the price when it happened, and a
+//! rebuild reuses the stored prices instead of reading them again. Backfilling
+//! needs archive state; a failed read fails the block, which is retried like
+//! any other. Amounts are whole micro-dollars.
+
+use ethers::types::{Address, I256, U256};
+use eyre::{eyre, Result};
+use rusqlite::Connection;
+
+use crate::{aggregates::Delta, chain::ChainClient, db, metrics};
+
+/// keccak256("decimals()")[..4]
+const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+/// keccak256("latestRoundData()")[..4]
+const LATEST_ROUND_DATA_SELECTOR: [u8; 4] = [0xfe, 0xaf, 0x96, 0x8c];
+const USD_DECIMALS: u32 = 6;
+
+pub struct PriceFeed {
+    pub address: Address,
+    decimals: u32,
+}
+
+impl PriceFeed {
+    /// Read the feed's `decimals()`; fails if `address` isn't an aggregator.
+    pub async fn connect(provider: &dyn ChainClient, address: Address) -> Result<Self> {
+        let out = provider.call(address, DECIMALS_SELECTOR.to_vec(), None).await?;
+        if out.len() < 32 {
+            return Err(eyre!("price feed {address:?} returned no decimals(); check PRICE_FEED_ADDRESS"));
+        }
+        let decimals = U256::from_big_endian(&out[out.len() - 32..]);
+        if decimals > U256::from(36) {
+            return Err(eyre!("price feed {address:?} reports {decimals} decimals; check PRICE_FEED_ADDRESS"));
+        }
+        Ok(Self { address, decimals: decimals.as_u32() })
+    }
+
+    /// Price of one whole token at `block`, micro-USD.
+    pub async fn price_at(&self, provider: &dyn ChainClient, block: u64) -> Result<i64> {
+        let out = provider.call(self.address, LATEST_ROUND_DATA_SELECTOR.to_vec(), Some(block)).await?;
+        if out.len() < 64 {
+            return Err(eyre!("latestRoundData() returned {} bytes", out.len()));
+        }
+        // (roundId, answer, startedAt, updatedAt, answeredInRound)
+        let answer = I256::from_raw(U256::from_big_endian(&out[32..64]));
+        if answer <= I256::zero() {
+            return Err(eyre!("price feed answered {answer} at block {block}"));
+        }
+        let answer = answer.into_raw();
+        let micro = if self.decimals >= USD_DECIMALS {
+            answer / U256::exp10((self.decimals - USD_DECIMALS) as usize)
+        } else {
+            answer.saturating_mul(U256::exp10((USD_DECIMALS - self.decimals) as usize))
+        };
+        Ok(micro.min(U256::from(i64::MAX as u64)).as_u64() as i64)
+    }
+}
+
+/// `-1234567` → `"-1.234567"`.
+pub fn format_usd(micro: i64) -> String {
+    let sign = if micro < 0 { "-" } else { "" };
+    let abs = micro.unsigned_abs();
+    let unit = 10u64.pow(USD_DECIMALS);
+    format!("{sign}{}.{:06}", abs / unit, abs % unit)
+}
+
+/// A raw decimal amount (`-`-prefixed when negative) in USD at
+/// `price_usd_micro` per whole token, e.g. `"1234.500000"`. Values that don't
+/// parse are returned unchanged.
+pub fn render_usd(raw: &str, price_usd_micro: i64) -> String {
+    let (negative, digits) = match raw.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, raw),
+    };
+    let Ok(v) = U256::from_dec_str(digits) else { return raw.to_string() };
+    let delta = if negative { Delta::new(U256::zero(), v) } else { Delta::new(v, U256::zero()) };
+    format_usd(delta.usd_micro(price_usd_micro))
+}
+
+/// Refresh the USD gauge from the stored cumulative.
+pub fn publish(conn: &Connection) -> Result<()> {
+    if let Some(micro) = db::get_cumulative_usd_micro(conn)? {
+        metrics::NETFLOW_CUMULATIVE_USD.set(micro as f64 / 10f64.powi(USD_DECIMALS as i32));
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Catch-up progress, so a long backfill isn't silent.
+//!
+//! The indexer reports the block it has reached; the rate and ETA are derived
+//! here and published to the log (at most every `LOG_EVERY`), the `catchup_*`
+//! metrics and `GET /status`. Only the indexer in this process is visible, so
+//! a standalone API always reports no catch-up.
+
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tracing::info;
+
+use crate::metrics;
+
+const LOG_EVERY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CatchUp {
+    pub from_block: u64,
+    /// Last block processed
+    pub current_block: u64,
+    pub target_block: u64,
+    pub blocks_per_sec: f64,
+    /// `None` until a rate is known
+    pub eta_secs: Option<u64>,
+}
+
+struct Tracker {
+    progress: CatchUp,
+    started: Instant,
+    logged: Instant,
+}
+
+static CURRENT: Lazy<RwLock<Option<Tracker>>> = Lazy::new(|| RwLock::new(None));
+
+/// Ends the catch-up when dropped, however the backfill exits.
+pub struct Guard(());
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        finish();
+    }
+}
+
+/// A catch-up over `from..=to` begins; it lasts as long as the returned guard.
+pub fn start(from: u64, to: u64) -> Guard {
+    let now = Instant::now();
+    let progress = CatchUp {
+        from_block: from,
+        current_block: from.saturating_sub(1),
+        target_block: to,
+        blocks_per_sec: 0.0,
+        eta_secs: None,
+    };
+    publish(&progress);
+    metrics::CATCHUP_ACTIVE.set(1);
+    *CURRENT.write().expect("progress lock") = Some(Tracker { progress, started: now, logged: now });
+    Guard(())
+}
+
+/// Blocks up to `block` are done.
+pub fn advance(block: u64) {
+    let mut guard = CURRENT.write().expect("progress lock");
+    let Some(t) = guard.as_mut() else { return };
+    let p = &mut t.progress;
+    p.current_block = block.max(p.current_block);
+    let done = (p.current_block + 1).saturating_sub(p.from_block);
+    let elapsed = t.started.elapsed().as_secs_f64();
+    if elapsed > 0.0 && done > 0 {
+        p.blocks_per_sec = done as f64 / elapsed;
+        p.eta_secs = Some((p.target_block.saturating_sub(p.current_block) as f64 / p.blocks_per_sec).ceil() as u64);
+    }
+    publish(p);
+    if t.logged.elapsed() >= LOG_EVERY {
+        t.logged = Instant::now();
+        info!(
+            current = p.current_block,
+            target = p.target_block,
+            blocks_per_sec = format!("{:.1}", p.blocks_per_sec),
+            eta_secs = ?p.eta_secs,
+            "Catching up"
+        );
+    }
+}
+
+fn finish() {
+    let done = CURRENT.write().expect("progress lock").take();
+    // Short gap fills (missed heads) aren't worth a line
+    if let Some(t) = done.filter(|t| t.started.elapsed() >= LOG_EVERY) {
+        let p = &t.progress;
+        let outcome = if p.current_block >= p.target_block { "Catch-up finished" } else { "Catch-up stopped" };
+        info!(from = p.from_block, to = p.current_block, target = p.target_block, secs = t.started.elapsed().as_secs(), "{outcome}");
+    }
+    metrics::CATCHUP_ACTIVE.set(0);
+}
+
+/// The running catch-up, if any.
+pub fn current() -> Option<CatchUp> {
+    CURRENT.read().expect("progress lock").as_ref().map(|t| t.progress.clone())
+}
+
+fn publish(p: &CatchUp) {
+    metrics::CATCHUP_CURRENT_BLOCK.set(p.current_block as i64);
+    metrics::CATCHUP_TARGET_BLOCK.set(p.target_block as i64);
+    metrics::CATCHUP_BLOCKS_PER_SECOND.set(p.blocks_per_sec);
+    metrics::CATCHUP_ETA_SECONDS.set(p.eta_secs.map_or(-1, |s| s as i64));
+}
@@ -0,0 +1,162 @@
+//! Accounting rules (`ACCOUNTING_RULES_FILE`): exceptions to how a transfer
+//! counts, for special cases such as bridge mints, burn addresses or escrow
+//! contracts, without code changes.
+//!
+//! The file is a JSON array of rules, tried in order on every matched
+//! transfer; the first whose conditions all hold decides, and a transfer no
+//! rule matches counts as usual. Conditions, all optional:
+//! - `from`, `to`: the sender / recipient is one of these addresses
+//! - `direction`: the usual classification (`in`, `out`, `internal`, `none`)
+//! - `min_value`, `max_value`: raw token units, inclusive
+//!
+//! The `action` is `"inflow"`, `"outflow"`, `"ignore"` (left out of the
+//! net-flow) or `{"bucket": "name"}`: left out of the net-flow too, but
+//! stored on the transfer and totalled by `GET /buckets`.
+//!
+//! ```json
+//! [
+//!   {"name": "bridge mints", "from": ["0x0000000000000000000000000000000000000000"], "action": {"bucket": "minted"}},
+//!   {"name": "escrow", "to": ["0x…"], "direction": "in", "action": "ignore"}
+//! ]
+//! ```
+//!
+//! Rules only see transfers the indexer reads, i.e. those with a tracked
+//! address on at least one side. Stored transfers keep the outcome of the
+//! rules in force when they were indexed; `rebuild` re-applies the current ones.
+
+use std::path::Path;
+
+use ethers::types::{Address, U256};
+use eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use crate::models::{self, Direction};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    #[serde(default)]
+    from: Vec<String>,
+    #[serde(default)]
+    to: Vec<String>,
+    direction: Option<Direction>,
+    min_value: Option<String>,
+    max_value: Option<String>,
+    action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Inflow,
+    Outflow,
+    Ignore,
+    Bucket(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    from: Vec<Address>,
+    to: Vec<Address>,
+    direction: Option<Direction>,
+    min_value: Option<U256>,
+    max_value: Option<U256>,
+    action: Action,
+}
+
+impl Rule {
+    fn matches(&self, from: &Address, to: &Address, value: U256, direction: Direction) -> bool {
+        (self.from.is_empty() || self.from.contains(from))
+            && (self.to.is_empty() || self.to.contains(to))
+            && self.direction.is_none_or(|d| d == direction)
+            && self.min_value.is_none_or(|min| value >= min)
+            && self.max_value.is_none_or(|max| value <= max)
+    }
+}
+
+/// A loaded rule set; the default has no rules.
+#[derive(Debug, Default)]
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("ACCOUNTING_RULES_FILE: reading {}", path.display()))?;
+        Self::parse(&text).wrap_err_with(|| format!("ACCOUNTING_RULES_FILE {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let specs: Vec<RuleSpec> = serde_json::from_str(text)?;
+        let rules = specs.into_iter().enumerate().map(|(i, spec)| {
+            let rule = (|| {
+                let value = |v: Option<String>| v
+                    .map(|v| U256::from_dec_str(&v).map_err(|_| eyre!("invalid value {v:?}; expected raw units in decimal")))
+                    .transpose();
+                if let Action::Bucket(name) = &spec.action {
+                    if name.is_empty() {
+                        return Err(eyre!("bucket name is empty"));
+                    }
+                }
+                Ok(Rule {
+                    from: spec.from.iter().map(|a| models::parse_address(a)).collect::<Result<_>>()?,
+                    to: spec.to.iter().map(|a| models::parse_address(a)).collect::<Result<_>>()?,
+                    direction: spec.direction,
+                    min_value: value(spec.min_value)?,
+                    max_value: value(spec.max_value)?,
+                    action: spec.action,
+                    name: spec.name.clone(),
+                })
+            })();
+            rule.wrap_err_with(|| format!("rule {} ({:?})", i + 1, spec.name))
+        });
+        Ok(Self(rules.collect::<Result<_>>()?))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// How a transfer the tracked set classifies as `direction` counts: the
+    /// first matching rule's action, else `direction` itself. A bucket comes
+    /// with `Direction::None`, keeping it out of the net-flow.
+    pub fn apply(&self, from: &Address, to: &Address, value: U256, direction: Direction) -> (Direction, Option<&str>) {
+        let Some(rule) = self.0.iter().find(|r| r.matches(from, to, value, direction)) else {
+            return (direction, None);
+        };
+        tracing::trace!(rule = %rule.name, ?direction, "Accounting rule matched");
+        match &rule.action {
+            Action::Inflow => (Direction::In, None),
+            Action::Outflow => (Direction::Out, None),
+            Action::Ignore => (Direction::None, None),
+            Action::Bucket(name) => (Direction::None, Some(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let rules = Rules::parse(r#"[
+            {"name": "mints", "from": ["0x0000000000000000000000000000000000000000"], "action": {"bucket": "minted"}},
+            {"name": "small", "direction": "in", "max_value": "100", "action": "ignore"},
+            {"name": "escrow", "to": ["0x00000000000000000000000000000000000000e5"], "action": "outflow"}
+        ]"#).unwrap();
+        let (zero, escrow, other) = (Address::zero(), Address::from_low_u64_be(0xe5), Address::from_low_u64_be(7));
+
+        assert_eq!(rules.apply(&zero, &other, 5.into(), Direction::In), (Direction::None, Some("minted")));
+        assert_eq!(rules.apply(&other, &escrow, 5.into(), Direction::In), (Direction::None, None));
+        assert_eq!(rules.apply(&other, &escrow, 500.into(), Direction::In), (Direction::Out, None));
+        assert_eq!(rules.apply(&other, &other, 500.into(), Direction::In), (Direction::In, None));
+        assert!(Rules::parse(r#"[{"name": "x", "action": "burn"}]"#).is_err());
+        assert!(Rules::parse(r#"[{"name": "x", "min_value": "1e18", "action": "ignore"}]"#).is_err());
+    }
+}
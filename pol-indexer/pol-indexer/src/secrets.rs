@@ -0,0 +1,133 @@
+//! Secret settings (RPC URLs with embedded keys, webhook URLs, bot tokens,
+//! connection strings) and keeping them out of the logs.
+//!
+//! Each such setting can be given directly, read from a file with its
+//! `--…-file` flag / `…_FILE` variable (Docker and Kubernetes secrets), or
+//! point elsewhere with `env:OTHER_VAR` or `file:/path`. Every resolved value
+//! is registered, and the log writer replaces any registered value, or the
+//! key-bearing parts of a URL, with `***` before a line is written.
+
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+    path::Path,
+    sync::RwLock,
+};
+
+use eyre::{eyre, Result};
+use once_cell::sync::Lazy;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// URL parts shorter than this (`v2`, `rpc`, `mainnet`…) aren't treated as keys.
+const MIN_KEY_LEN: usize = 12;
+
+const MASK: &str = "***";
+
+/// Registered secrets, longest first so a value is masked before its parts.
+static SECRETS: Lazy<RwLock<Vec<String>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// The value of setting `name`: from `file` if given, else `value`, following
+/// `env:` / `file:` indirection. A found value is registered for redaction.
+pub fn resolve(name: &str, value: Option<String>, file: Option<&Path>) -> Result<Option<String>> {
+    let raw = match file {
+        Some(path) => Some(read(name, path)?),
+        None => value,
+    };
+    let Some(raw) = raw else { return Ok(None) };
+    let resolved = if let Some(var) = raw.strip_prefix("env:") {
+        std::env::var(var).map_err(|_| eyre!("{name} refers to env:{var}, which is not set"))?
+    } else if let Some(path) = raw.strip_prefix("file:") {
+        read(name, Path::new(path))?
+    } else {
+        raw
+    };
+    register(&resolved);
+    Ok(Some(resolved))
+}
+
+fn read(name: &str, path: &Path) -> Result<String> {
+    let s = std::fs::read_to_string(path).map_err(|e| eyre!("{name}: reading {}: {e}", path.display()))?;
+    Ok(s.trim().to_string())
+}
+
+/// Mask `secret` wherever it appears in log output. For URLs (or
+/// comma-separated lists of them) the password, and any path segment or
+/// query value long enough to be a key, are masked on their own too, since
+/// errors often quote a URL in part.
+pub fn register(secret: &str) {
+    let mut parts = vec![secret.to_string()];
+    for url in secret.split(',').map(str::trim).filter(|u| u.contains("://")) {
+        parts.push(url.to_string());
+        let rest = url.split_once("://").map_or(url, |(_, r)| r);
+        let (authority, path) = rest.split_once(['/', '?', '#']).unwrap_or((rest, ""));
+        if let Some((userinfo, _)) = authority.rsplit_once('@') {
+            parts.push(userinfo.to_string());
+            parts.extend(userinfo.split_once(':').map(|(_, password)| password.to_string()));
+        }
+        parts.extend(
+            path.split(['/', '?', '&', '=', '#'])
+                .filter(|p| p.len() >= MIN_KEY_LEN)
+                .map(str::to_string),
+        );
+    }
+    let mut secrets = SECRETS.write().expect("secrets lock");
+    for p in parts.into_iter().filter(|p| p.len() >= 4) {
+        if !secrets.contains(&p) {
+            secrets.push(p);
+        }
+    }
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+/// `text` with every registered secret masked.
+pub fn scrub(text: &str) -> Cow<'_, str> {
+    let secrets = SECRETS.read().expect("secrets lock");
+    let mut out = Cow::Borrowed(text);
+    for s in secrets.iter() {
+        if out.contains(s.as_str()) {
+            out = Cow::Owned(out.replace(s.as_str(), MASK));
+        }
+    }
+    out
+}
+
+/// Stdout for the log subscriber, with secrets masked. The formatter writes
+/// each event in one call, so a secret is never split across writes.
+pub struct ScrubbedStdout;
+
+impl<'a> MakeWriter<'a> for ScrubbedStdout {
+    type Writer = ScrubWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ScrubWriter
+    }
+}
+
+pub struct ScrubWriter;
+
+impl Write for ScrubWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        io::stdout().lock().write_all(scrub(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Any other log writer (the `LOG_FILE` one), with secrets masked as on stdout.
+pub struct Scrubbed<W>(pub W);
+
+impl<W: Write> Write for Scrubbed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(scrub(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,148 @@
+//! `run --selftest`: before indexing, read a historical block whose token
+//! transfers are known and check what comes back, so a provider that returns
+//! incomplete or reshaped logs, or a decoding regression, fails the deploy
+//! instead of skewing the cumulative.
+//!
+//! The expected transfers come from `SELFTEST_FIXTURE`, a JSON file:
+//! `{"block_number": N, "block_hash": "0x…", "transfers": [...]}`. Each
+//! transfer needs `tx_hash`, `log_index`, `from`, `to` and `value` (decimal),
+//! and may give `direction`, checked against the configured addresses, so
+//! rows of `GET /tx/{hash}` from a trusted deployment can be pasted in. The
+//! block's logs are decoded, stored in an in-memory database and aggregated
+//! the way the indexer does; nothing touches `DB_PATH`.
+
+use std::{collections::HashMap, path::Path};
+
+use ethers::types::{Address, H256, U256};
+use eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    aggregates,
+    chain::{ChainClient, LogFilter},
+    db,
+    indexer::{decode_transfer, TRANSFER_TOPIC},
+    models::{self, Clock, Direction, HashStr},
+};
+
+#[derive(Deserialize)]
+pub struct Fixture {
+    block_number: u64,
+    block_hash: Option<H256>,
+    transfers: Vec<Expected>,
+}
+
+#[derive(Deserialize)]
+struct Expected {
+    tx_hash: H256,
+    log_index: u64,
+    from: Address,
+    to: Address,
+    value: String,
+    direction: Option<Direction>,
+}
+
+pub fn load(path: &Path) -> Result<Fixture> {
+    let text = std::fs::read_to_string(path).wrap_err_with(|| format!("SELFTEST_FIXTURE: reading {}", path.display()))?;
+    let fixture: Fixture = serde_json::from_str(&text).wrap_err_with(|| format!("SELFTEST_FIXTURE: parsing {}", path.display()))?;
+    if fixture.transfers.is_empty() {
+        return Err(eyre!("SELFTEST_FIXTURE lists no transfers to check"));
+    }
+    Ok(fixture)
+}
+
+/// Fails with every mismatch found.
+pub async fn run(client: &dyn ChainClient, fixture: &Fixture, token: Address, tracked: &[Address]) -> Result<()> {
+    let number = fixture.block_number;
+    let header = client.get_block(number).await?
+        .ok_or_else(|| eyre!("self-test: the provider has no block {number}"))?;
+    let logs = client.get_logs(&LogFilter {
+        address: Some(token),
+        topic0: TRANSFER_TOPIC,
+        topic1: None,
+        topic2: None,
+        topic3: None,
+        from_block: number,
+        to_block: number,
+        block_hash: Some(header.hash),
+    }).await?;
+
+    let mut problems = Vec::new();
+    if let Some(expected) = fixture.block_hash.filter(|h| *h != header.hash) {
+        problems.push(format!("block hash is {:?}, expected {expected:?}", header.hash));
+    }
+    let mut decoded = HashMap::new();
+    for lg in &logs {
+        if lg.address != token || lg.block_number != Some(number) || lg.block_hash.is_some_and(|h| h != header.hash) {
+            problems.push(format!("log {:?}/{:?} is not from the token in block {number}", lg.transaction_hash, lg.log_index));
+            continue;
+        }
+        match decode_transfer(lg) {
+            Some(tr) => { decoded.insert((tr.tx_hash, tr.log_index), tr); }
+            None => problems.push(format!("log {:?}/{:?} did not decode as a Transfer", lg.transaction_hash, lg.log_index)),
+        }
+    }
+
+    let is_tracked = |a: &Address| tracked.contains(a);
+    let (mut inflow, mut outflow) = (U256::zero(), U256::zero());
+    for e in &fixture.transfers {
+        let at = format!("{:?}/{}", e.tx_hash, e.log_index);
+        let Some(tr) = decoded.get(&(e.tx_hash, e.log_index)) else {
+            problems.push(format!("{at}: missing from the provider's logs"));
+            continue;
+        };
+        if (tr.from, tr.to) != (e.from, e.to) {
+            problems.push(format!("{at}: {:?} → {:?}, expected {:?} → {:?}", tr.from, tr.to, e.from, e.to));
+        }
+        match U256::from_dec_str(&e.value) {
+            Ok(v) if v == tr.value => {}
+            Ok(v) => problems.push(format!("{at}: value {}, expected {v}", tr.value)),
+            Err(_) => problems.push(format!("{at}: fixture value {:?} is not a decimal amount", e.value)),
+        }
+        let direction = Direction::of(is_tracked(&e.from), is_tracked(&e.to));
+        if e.direction.is_some_and(|d| d != direction) {
+            problems.push(format!("{at}: direction {} with the configured addresses, expected {}", direction.as_str(), e.direction.map_or("", Direction::as_str)));
+        }
+        match direction {
+            Direction::In => inflow = inflow.saturating_add(tr.value),
+            Direction::Out => outflow = outflow.saturating_add(tr.value),
+            Direction::Internal | Direction::None => {}
+        }
+    }
+
+    // The storage and aggregation steps of the pipeline, on a throwaway database
+    let conn = db::init(":memory:")?;
+    let block_hash = HashStr::new(&header.hash);
+    db::insert_block(&conn, number, &block_hash, header.timestamp as i64)?;
+    for tr in decoded.values() {
+        db::insert_transfer(
+            &conn,
+            number,
+            &block_hash,
+            &HashStr::new(&tr.tx_hash),
+            tr.log_index,
+            &models::address_key(&token),
+            &models::address_key(&tr.from),
+            &models::address_key(&tr.to),
+            &tr.value.to_string(),
+            Direction::of(is_tracked(&tr.from), is_tracked(&tr.to)),
+            None,
+        )?;
+    }
+    aggregates::rebuild(&conn, Clock::Block)?;
+    let stored = db::get_netflow_blocks_by_range(&conn, number, number)?;
+    let (got_in, got_out) = stored.first()
+        .map(|b| (U256::from_dec_str(&b.inflow).unwrap_or_default(), U256::from_dec_str(&b.outflow).unwrap_or_default()))
+        .unwrap_or_default();
+    // Logs beyond the fixture's may add to the block's flows, never take away
+    if got_in < inflow || got_out < outflow {
+        problems.push(format!("aggregated inflow/outflow {got_in}/{got_out}, expected at least {inflow}/{outflow}"));
+    }
+
+    if !problems.is_empty() {
+        return Err(eyre!("self-test against block {number} failed:\n  {}", problems.join("\n  ")));
+    }
+    info!(block = number, logs = logs.len(), checked = fixture.transfers.len(), "Self-test passed");
+    Ok(())
+}
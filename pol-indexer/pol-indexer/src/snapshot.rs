@@ -0,0 +1,72 @@
+//! Gzip-compressed database snapshots built on SQLite's online backup API.
+//!
+//! `create` is safe while the indexer is running: the backup copies pages in
+//! small steps and yields between them, so writers are only blocked briefly.
+//! `restore` overwrites the target database, so it refuses to run while an
+//! indexer holds the lease on it.
+
+use std::{fs::File, io::{self, BufReader, BufWriter}, path::{Path, PathBuf}, time::Duration};
+
+use eyre::Result;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rusqlite::{backup::{Backup, Progress}, Connection, DatabaseName};
+use tracing::info;
+
+use crate::lease;
+
+const PAGES_PER_STEP: std::os::raw::c_int = 256;
+const STEP_PAUSE: Duration = Duration::from_millis(10);
+
+pub fn create(conn: &Connection, out: &Path) -> Result<()> {
+    let tmp = temp_path(out);
+    {
+        let mut dst = Connection::open(&tmp)?;
+        let backup = Backup::new(conn, &mut dst)?;
+        backup.run_to_completion(PAGES_PER_STEP, STEP_PAUSE, Some(log_progress))?;
+    }
+
+    let mut input = BufReader::new(File::open(&tmp)?);
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(out)?), Compression::default());
+    let raw = io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    std::fs::remove_file(&tmp)?;
+
+    let compressed = std::fs::metadata(out)?.len();
+    info!(path = %out.display(), raw, compressed, "Snapshot created");
+    Ok(())
+}
+
+pub fn restore(conn: &mut Connection, input: &Path) -> Result<()> {
+    let tmp = temp_path(input);
+    {
+        let mut decoder = GzDecoder::new(BufReader::new(File::open(input)?));
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        io::copy(&mut decoder, &mut out)?;
+    }
+
+    let result = (|| -> Result<()> {
+        let src = Connection::open(&tmp)?;
+        let integrity: String = src.query_row("PRAGMA integrity_check", [], |r| r.get(0))?;
+        if integrity != "ok" {
+            return Err(eyre::eyre!("Snapshot failed integrity check: {integrity}"));
+        }
+        conn.restore(DatabaseName::Main, &tmp, Some(log_progress))?;
+        // A snapshot of a running indexer carries its lease, which nothing here holds
+        lease::clear(conn)
+    })();
+    std::fs::remove_file(&tmp)?;
+    result?;
+
+    info!(path = %input.display(), "Snapshot restored");
+    Ok(())
+}
+
+fn temp_path(p: &Path) -> PathBuf {
+    let mut name = p.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp-sqlite");
+    p.with_file_name(name)
+}
+
+fn log_progress(p: Progress) {
+    tracing::debug!(remaining = p.remaining, total = p.pagecount, "Backup progress");
+}
@@ -0,0 +1,157 @@
+//! Backfill from a subgraph (`SUBGRAPH_URL`): transfers of the POL token read
+//! over GraphQL instead of `eth_getLogs`, for RPC endpoints without archive
+//! log access or with tight range caps.
+//!
+//! The subgraph must index the POL token only, with a transfer entity
+//! (`SUBGRAPH_ENTITY`, default `transfers`) carrying `id`, `from`, `to`,
+//! `value`, `blockNumber`, `transactionHash` and `logIndex`. Rows become the
+//! same logs `eth_getLogs` would return, without a block hash, and block
+//! headers, receipts and balances still come from the RPC. Blocks past the
+//! subgraph's indexed head, and any chunk the subgraph fails to answer, are
+//! read with `eth_getLogs` as usual.
+//!
+//! Queries need the `subgraph` feature (its HTTP client); `run` refuses
+//! `SUBGRAPH_URL` in a build without it.
+
+use ethers::types::{Address, H256, U256};
+use eyre::{eyre, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use crate::chain::RawLog;
+
+/// Rows per GraphQL page; graph-node's cap on `first`.
+const PAGE_SIZE: usize = 1_000;
+
+/// Addresses per `from_in` / `to_in` list in one query.
+const ADDRESSES_PER_QUERY: usize = 500;
+
+pub struct Subgraph {
+    url: String,
+    entity: String,
+    #[cfg(feature = "subgraph")]
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Row {
+    id: String,
+    from: Address,
+    to: Address,
+    value: String,
+    block_number: String,
+    transaction_hash: H256,
+    log_index: String,
+}
+
+impl Row {
+    fn into_log(self, token: Address, topic0: H256) -> Result<RawLog> {
+        let value = U256::from_dec_str(&self.value).map_err(|_| eyre!("transfer {}: invalid value {:?}", self.id, self.value))?;
+        let mut data = vec![0u8; 32];
+        value.to_big_endian(&mut data);
+        Ok(RawLog {
+            address: token,
+            topics: vec![topic0, H256::from(self.from), H256::from(self.to)],
+            data,
+            block_number: Some(big_int(&self.block_number)?),
+            block_hash: None,
+            transaction_hash: Some(self.transaction_hash),
+            log_index: Some(big_int(&self.log_index)?),
+        })
+    }
+}
+
+/// Subgraph `BigInt`s arrive as decimal strings.
+fn big_int(s: &str) -> Result<u64> {
+    s.parse().map_err(|_| eyre!("invalid BigInt {s:?}"))
+}
+
+impl Subgraph {
+    pub fn new(url: &str, entity: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            entity: entity.to_string(),
+            #[cfg(feature = "subgraph")]
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Last block the subgraph has indexed. A subgraph with indexing errors
+    /// may be missing transfers, so it is refused.
+    pub async fn indexed_head(&self) -> Result<u64> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Meta { block: MetaBlock, has_indexing_errors: bool }
+        #[derive(Deserialize)]
+        struct MetaBlock { number: u64 }
+        #[derive(Deserialize)]
+        struct Data { _meta: Meta }
+
+        let data: Data = self.query("{ _meta { block { number } hasIndexingErrors } }", json!({})).await?;
+        if data._meta.has_indexing_errors {
+            return Err(eyre!("subgraph reports indexing errors"));
+        }
+        Ok(data._meta.block.number)
+    }
+
+    /// Transfers in `[from, to]` sent from or to any of `parties`, as logs
+    /// of `token` with `topic0`. Sorted and deduplicated like `fetch_logs`.
+    pub async fn transfers(&self, token: Address, topic0: H256, parties: &[Address], from: u64, to: u64) -> Result<Vec<RawLog>> {
+        let mut logs = Vec::new();
+        for chunk in parties.chunks(ADDRESSES_PER_QUERY) {
+            for side in ["from_in", "to_in"] {
+                for row in self.rows(side, chunk, from, to).await? {
+                    logs.push(row.into_log(token, topic0)?);
+                }
+            }
+        }
+        // Transfers between two parties match both sides
+        logs.sort_by_key(|l| (l.block_number, l.log_index));
+        logs.dedup_by_key(|l| (l.transaction_hash, l.log_index));
+        Ok(logs)
+    }
+
+    /// Every row matching one side, paged by `id`.
+    async fn rows(&self, side: &str, parties: &[Address], from: u64, to: u64) -> Result<Vec<Row>> {
+        #[derive(Deserialize)]
+        struct Data { rows: Vec<Row> }
+
+        let query = format!(
+            "query($first: Int!, $after: String!, $from: BigInt!, $to: BigInt!, $parties: [Bytes!]!) {{ \
+               rows: {}(first: $first, orderBy: id, orderDirection: asc, \
+                 where: {{ id_gt: $after, {side}: $parties, blockNumber_gte: $from, blockNumber_lte: $to }}) \
+               {{ id from to value blockNumber transactionHash logIndex }} }}",
+            self.entity,
+        );
+        let parties: Vec<String> = parties.iter().map(|a| format!("{a:?}")).collect();
+        let mut out = Vec::new();
+        let mut after = String::new();
+        loop {
+            let vars = json!({ "first": PAGE_SIZE, "after": after, "from": from.to_string(), "to": to.to_string(), "parties": parties });
+            let page: Data = self.query(&query, vars).await?;
+            // Until an empty page: hosted endpoints may cap `first` lower
+            let Some(last) = page.rows.last() else { return Ok(out) };
+            after = last.id.clone();
+            out.extend(page.rows);
+        }
+    }
+
+    #[cfg(feature = "subgraph")]
+    async fn query<T: DeserializeOwned>(&self, query: &str, variables: Value) -> Result<T> {
+        let resp = self.http.post(&self.url)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send().await?
+            .error_for_status()?;
+        let mut body: Value = resp.json().await?;
+        if let Some(errors) = body.get("errors").filter(|e| !e.is_null()) {
+            return Err(eyre!("subgraph query failed: {errors}"));
+        }
+        Ok(serde_json::from_value(body["data"].take())?)
+    }
+
+    #[cfg(not(feature = "subgraph"))]
+    async fn query<T: DeserializeOwned>(&self, _query: &str, _variables: Value) -> Result<T> {
+        Err(eyre!("can't query {}: this build has no `subgraph` feature", self.url))
+    }
+}
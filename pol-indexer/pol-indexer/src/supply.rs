@@ -0,0 +1,148 @@
+//! The token's total supply, to show whether a net-flow is material: 5M POL
+//! moving onto an exchange means little without knowing the supply it is
+//! part of.
+//!
+//! The indexer reads `totalSupply()` at startup and every `SUPPLY_REFRESH_SECS`
+//! and keeps the latest value in `state`; the API derives percentages from it.
+//!
+//! With `TRACK_SUPPLY_EVENTS` it also records what changes the supply: every
+//! mint (Transfer from the zero address) and burn (to it) goes in
+//! `supply_events` with running totals, served by `GET /supply-events`.
+
+use ethers::types::{Address, U256};
+use eyre::{eyre, Result};
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::{
+    chain::ChainClient, db, metrics,
+    models::{self, Erc20Transfer, SupplyEvent, SupplyEventKind, ValueFormat},
+};
+
+/// keccak256("totalSupply()")[..4]
+const TOTAL_SUPPLY_SELECTOR: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd];
+
+/// `totalSupply()` of `token` at `block`.
+pub async fn fetch(provider: &dyn ChainClient, token: Address, block: u64) -> Result<U256> {
+    let out = provider.call(token, TOTAL_SUPPLY_SELECTOR.to_vec(), Some(block)).await?;
+    if out.len() < 32 {
+        return Err(eyre!("totalSupply() returned {} bytes", out.len()));
+    }
+    Ok(U256::from_big_endian(&out[out.len() - 32..]))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupplyShare {
+    /// Raw units, rendered per `?format=`
+    pub total_supply: String,
+    /// Block the supply was read at
+    pub block_number: u64,
+    /// The response's net-flow as a percentage of `total_supply`
+    pub pct: Option<f64>,
+}
+
+/// `net` (signed raw units) against the stored supply; `None` until the
+/// indexer has read it.
+pub fn share(conn: &Connection, net: &str, format: ValueFormat) -> Result<Option<SupplyShare>> {
+    Ok(db::get_total_supply(conn)?.map(|(supply, block_number)| SupplyShare {
+        pct: pct_of(net, &supply),
+        total_supply: format.render(&supply),
+        block_number,
+    }))
+}
+
+/// `value` (raw, optionally `-`-prefixed) as a percentage of `supply`.
+pub fn pct_of(value: &str, supply: &str) -> Option<f64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, value),
+    };
+    let value: f64 = digits.parse().ok()?;
+    let supply: f64 = supply.parse().ok()?;
+    if supply <= 0.0 {
+        return None;
+    }
+    let pct = value / supply * 100.0;
+    Some(if negative { -pct } else { pct })
+}
+
+/// Store the mints and burns among block `number`'s transfers, on top of the
+/// totals of the blocks before it (so a block read again replaces its rows).
+pub fn record_events(conn: &Connection, number: u64, ts_unix: i64, transfers: &[Erc20Transfer]) -> Result<Vec<SupplyEvent>> {
+    let parse = |v: String| U256::from_dec_str(&v).unwrap_or_default();
+    let (mut minted, mut burned) = db::supply_totals_at(conn, number.saturating_sub(1))?
+        .map(|(m, b)| (parse(m), parse(b)))
+        .unwrap_or_default();
+    let mut events = Vec::new();
+    for tr in transfers {
+        let Some(kind) = SupplyEventKind::of(tr) else { continue };
+        let account = match kind {
+            SupplyEventKind::Mint => {
+                minted = minted.saturating_add(tr.value);
+                tr.to
+            }
+            SupplyEventKind::Burn => {
+                burned = burned.saturating_add(tr.value);
+                tr.from
+            }
+        };
+        let event = SupplyEvent {
+            block_number: number,
+            tx_hash: format!("{:?}", tr.tx_hash),
+            log_index: tr.log_index,
+            kind,
+            account: models::address_key(&account),
+            value: tr.value.to_string(),
+            ts_unix,
+            minted_total: minted.to_string(),
+            burned_total: burned.to_string(),
+        };
+        db::insert_supply_event(conn, &event)?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Refresh the supply gauges from the stored supply, cumulative and supply events.
+pub fn publish(conn: &Connection) -> Result<()> {
+    if let Some((minted, burned)) = db::supply_totals_at(conn, u64::MAX)? {
+        metrics::TOKEN_MINTED.set(ValueFormat::Scaled.render(&minted).parse().unwrap_or(0.0));
+        metrics::TOKEN_BURNED.set(ValueFormat::Scaled.render(&burned).parse().unwrap_or(0.0));
+    }
+    let Some((supply, _)) = db::get_total_supply(conn)? else { return Ok(()) };
+    let cumulative = db::get_latest_cumulative(conn)?.cumulative_netflow_raw;
+    metrics::TOKEN_TOTAL_SUPPLY.set(ValueFormat::Scaled.render(&supply).parse().unwrap_or(0.0));
+    metrics::NETFLOW_PCT_OF_SUPPLY.set(pct_of(&cumulative, &supply).unwrap_or(0.0));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, H256};
+
+    use super::*;
+
+    fn transfer(log_index: u64, from: Address, to: Address, value: u64) -> Erc20Transfer {
+        Erc20Transfer { block_number: 0, tx_hash: H256::from_low_u64_be(log_index), log_index, from, to, value: value.into() }
+    }
+
+    #[test]
+    fn totals_run_across_blocks_and_a_reread_block_replaces_its_rows() {
+        let conn = db::init(":memory:").unwrap();
+        let user = Address::from_low_u64_be(7);
+        let mint = transfer(0, Address::zero(), user, 50);
+        let burn = transfer(1, user, Address::zero(), 20);
+        let plain = transfer(2, user, Address::from_low_u64_be(8), 5);
+
+        record_events(&conn, 10, 0, &[mint.clone(), plain]).unwrap();
+        let events = record_events(&conn, 11, 0, &[mint.clone(), burn.clone()]).unwrap();
+        assert_eq!(events.len(), 2);
+        record_events(&conn, 11, 0, &[mint, burn]).unwrap();
+
+        assert_eq!(db::supply_totals_at(&conn, 10).unwrap(), Some(("50".into(), "0".into())));
+        assert_eq!(db::supply_totals_at(&conn, u64::MAX).unwrap(), Some(("100".into(), "20".into())));
+        let listed = db::list_supply_events(&conn, 0, u64::MAX, 10).unwrap();
+        assert_eq!(listed.len(), 3);
+        assert_eq!((listed[0].kind, listed[0].account.as_str()), (SupplyEventKind::Burn, models::address_key(&user).as_str()));
+    }
+}
@@ -0,0 +1,80 @@
+//! `pol-indexer tail`: follow a running instance's `GET /events` stream and
+//! print transfers and net-flow updates as they are indexed.
+
+use eyre::{Result, eyre};
+
+use crate::events::{Envelope, Event};
+use crate::models::{Direction, NetflowBlock, TransferRecord, TxStatus, ValueFormat};
+
+/// `filter` is passed on as `GET /events` query parameters, so the server
+/// only sends matching events.
+pub async fn run(url: &str, api_key: Option<&str>, filter: &[(&str, String)]) -> Result<()> {
+    let mut req = reqwest::Client::new().get(url).query(filter).header("accept", "text/event-stream");
+    if let Some(key) = api_key {
+        req = req.bearer_auth(key);
+    }
+    let mut res = req.send().await?;
+    if !res.status().is_success() {
+        let status = res.status();
+        return Err(eyre!("{url} returned {status}: {}", res.text().await.unwrap_or_default()));
+    }
+    eprintln!("Following {url} (ctrl-c to stop)");
+
+    let mut buf = String::new();
+    while let Some(chunk) = res.chunk().await? {
+        buf.push_str(&String::from_utf8_lossy(&chunk));
+        // SSE frames are separated by a blank line
+        while let Some(end) = buf.find("\n\n") {
+            let frame: String = buf.drain(..end + 2).collect();
+            let data: String = frame.lines()
+                .filter_map(|l| l.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect();
+            if data.is_empty() {
+                continue; // keep-alive or comment
+            }
+            match Envelope::decode(&data) {
+                Ok(ev) => println!("{}", render(&ev)),
+                Err(e) => tracing::warn!(error = %e, %data, "Unrecognised event"),
+            }
+        }
+    }
+    Err(eyre!("Event stream closed by server"))
+}
+
+fn render(ev: &Event) -> String {
+    match ev {
+        Event::Transfer(t) => render_transfer(t),
+        Event::Netflow(b) => render_netflow(b),
+        Event::ProvisionalNetflow(p) => format!(
+            "#{:<10} NET in {} / out {} POL, cumulative ~{} POL ({} transfers, provisional)",
+            p.block_number, pol(&p.inflow), pol(&p.outflow), pol(&p.cumulative), p.transfer_count
+        ),
+    }
+}
+
+fn render_transfer(t: &TransferRecord) -> String {
+    let direction = match t.direction {
+        Direction::In => "IN ",
+        Direction::Out => "OUT",
+        Direction::Internal => "INT", // Binance-to-Binance
+        Direction::None => " - ",
+    };
+    let flag = if t.status == Some(TxStatus::Reverted) { "  [reverted]" } else { "" };
+    format!(
+        "#{:<10} {direction} {:>24} POL  {} → {}  {}{flag}",
+        t.block_number, pol(&t.value), t.from, t.to, t.tx_hash
+    )
+}
+
+fn render_netflow(b: &NetflowBlock) -> String {
+    format!(
+        "#{:<10} NET in {} / out {} POL, cumulative {} POL ({} transfers)",
+        b.block_number, pol(&b.inflow), pol(&b.outflow), pol(&b.cumulative), b.transfer_count
+    )
+}
+
+/// Raw decimal string → whole POL without trailing zeros.
+fn pol(raw: &str) -> String {
+    ValueFormat::Scaled.render(raw)
+}
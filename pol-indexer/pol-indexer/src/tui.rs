@@ -0,0 +1,325 @@
+//! `pol-indexer tui`: a terminal dashboard for operators without Grafana.
+//!
+//! Reads the database a running indexer writes (block height, recent
+//! transfers, per-block net flow, reorgs, failed blocks) and polls the RPC
+//! head for the lag. Alerts are reconstructed from stored data, so the feed
+//! works whether or not Discord/Telegram are configured.
+
+use std::time::Duration;
+
+use ethers::types::U256;
+use eyre::Result;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    symbols::Marker,
+    text::{Line, Span},
+    widgets::{Axis, Block, Chart, Dataset, GraphType, List, ListItem, Paragraph, Row, Table},
+    Frame,
+};
+use rusqlite::Connection;
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+use crate::chain;
+use crate::db;
+use crate::models::{Direction, NetflowSnapshot, TransferRecord, TxStatus, ValueFormat};
+
+/// Chart windows the operator can cycle through, in seconds.
+const WINDOWS: [(i64, &str); 4] = [(3600, "1h"), (6 * 3600, "6h"), (24 * 3600, "24h"), (7 * 86400, "7d")];
+
+/// Bars in the net-flow chart, whatever the window.
+const BUCKETS: i64 = 60;
+
+const RECENT_TRANSFERS: u32 = 50;
+const FEED_LEN: usize = 50;
+
+pub struct TuiConfig {
+    pub source: chain::Source,
+    /// Raw value at or above which a transfer shows up in the alert feed
+    pub alert_min_value: Option<U256>,
+    pub refresh: Duration,
+}
+
+pub async fn run(conn: Connection, config: TuiConfig) -> Result<()> {
+    let (head_tx, head_rx) = watch::channel(None);
+    let poller = tokio::spawn(poll_head(config.source.clone(), config.refresh, head_tx));
+
+    let ui = tokio::task::spawn_blocking(move || {
+        let mut terminal = ratatui::init();
+        let result = event_loop(&mut terminal, &conn, &config, &head_rx);
+        ratatui::restore();
+        result
+    });
+    let result = ui.await.map_err(|e| eyre::eyre!("TUI thread panicked: {e}"))?;
+    poller.abort();
+    result
+}
+
+/// Keep `head` at the chain height; `None` while the RPC is unreachable.
+async fn poll_head(source: chain::Source, every: Duration, head: watch::Sender<Option<u64>>) {
+    loop {
+        if let Ok(client) = chain::connect(&source).await {
+            while let Ok(n) = client.block_number().await {
+                head.send_replace(Some(n));
+                tokio::time::sleep(every).await;
+            }
+        }
+        head.send_replace(None);
+        tokio::time::sleep(every.max(Duration::from_secs(5))).await;
+    }
+}
+
+fn event_loop(
+    terminal: &mut ratatui::DefaultTerminal,
+    conn: &Connection,
+    config: &TuiConfig,
+    head: &watch::Receiver<Option<u64>>,
+) -> Result<()> {
+    let mut window = 0;
+    loop {
+        let view = View::load(conn, config, *head.borrow(), WINDOWS[window])?;
+        terminal.draw(|frame| view.draw(frame))?;
+
+        if event::poll(config.refresh)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Right | KeyCode::Char('+') => window = (window + 1) % WINDOWS.len(),
+                    KeyCode::Left | KeyCode::Char('-') => window = (window + WINDOWS.len() - 1) % WINDOWS.len(),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// One frame's worth of data.
+struct View {
+    head: Option<u64>,
+    last_indexed: Option<u64>,
+    last_block_ts: Option<i64>,
+    latest: NetflowSnapshot,
+    window: (i64, &'static str),
+    /// (seconds into the window, net POL) per bucket
+    net: Vec<(f64, f64)>,
+    /// (seconds into the window, cumulative POL) per block with flows
+    cumulative: Vec<(f64, f64)>,
+    transfers: Vec<TransferRecord>,
+    feed: Vec<(u64, String)>,
+}
+
+impl View {
+    fn load(conn: &Connection, config: &TuiConfig, head: Option<u64>, window: (i64, &'static str)) -> Result<View> {
+        let last_indexed = db::get_last_indexed_block(conn)?;
+        let last_block_ts = match last_indexed {
+            Some(n) => db::previous_block_ts(conn, n + 1)?,
+            None => None,
+        };
+        let latest = db::get_latest_cumulative(conn)?;
+
+        // Chart against chain time, so a lagging indexer still shows its latest hour
+        let end = last_block_ts.unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp());
+        let start = end - window.0;
+        let width = (window.0 / BUCKETS).max(1);
+        let mut net = vec![0.0; BUCKETS as usize];
+        let mut cumulative = Vec::new();
+        for b in db::get_netflow_blocks_by_time(conn, start, end)? {
+            let bucket = (((b.ts_unix - start) / width) as usize).min(net.len() - 1);
+            net[bucket] += pol(&b.inflow) - pol(&b.outflow);
+            cumulative.push(((b.ts_unix - start) as f64, pol(&b.cumulative)));
+        }
+        let net = net.into_iter().enumerate()
+            .map(|(i, v)| (((i as i64 * width) + width / 2) as f64, v))
+            .collect();
+
+        let transfers = db::latest_transfers(conn, None, db::indexed_through_block(conn)?, RECENT_TRANSFERS)?;
+
+        let mut feed = Vec::new();
+        if let Some(min) = config.alert_min_value {
+            for t in &transfers {
+                let large = U256::from_dec_str(&t.value).is_ok_and(|v| v >= min);
+                let dir = match t.direction {
+                    Direction::In => "inflow to",
+                    Direction::Out => "outflow from",
+                    Direction::Internal | Direction::None => continue,
+                };
+                if large && t.status != Some(TxStatus::Reverted) {
+                    feed.push((t.block_number, format!("Large {dir} Binance: {} POL ({})", scaled(&t.value), short(&t.tx_hash))));
+                }
+            }
+        }
+        for r in db::list_reorgs(conn, 10)? {
+            feed.push((r.new_head, format!("Reorg: {} block(s) above {} rolled back", r.depth, r.fork_block)));
+        }
+        for n in db::list_failed_blocks(conn)? {
+            feed.push((n, "Block failed to index; retrying".to_string()));
+        }
+        feed.sort_by_key(|f| std::cmp::Reverse(f.0));
+        feed.truncate(FEED_LEN);
+
+        Ok(View { head, last_indexed, last_block_ts, latest, window, net, cumulative, transfers, feed })
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, charts, tables, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Percentage(45),
+            Constraint::Min(6),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+        let [net_area, cum_area] = Layout::horizontal([Constraint::Percentage(50); 2]).areas(charts);
+        let [transfers_area, feed_area] = Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(tables);
+
+        self.draw_status(frame, status);
+        self.draw_net(frame, net_area);
+        self.draw_cumulative(frame, cum_area);
+        self.draw_transfers(frame, transfers_area);
+        self.draw_feed(frame, feed_area);
+        frame.render_widget(
+            Paragraph::new(format!("q quit · ←/→ chart window ({})", self.window.1)).dim(),
+            help,
+        );
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let fmt = |v: Option<u64>| v.map_or_else(|| "—".to_string(), |v| v.to_string());
+        let lag = match (self.head, self.last_indexed) {
+            (Some(h), Some(l)) => {
+                let blocks = h.saturating_sub(l);
+                let style = if blocks > 10 { Style::new().red() } else { Style::new().green() };
+                (format!("{blocks} blocks"), style)
+            }
+            _ => ("—".to_string(), Style::new()),
+        };
+        let age = self.last_block_ts
+            .map(|ts| format!("{}s ago", (OffsetDateTime::now_utc().unix_timestamp() - ts).max(0)))
+            .unwrap_or_else(|| "—".to_string());
+        let line = Line::from(vec![
+            "Head ".dim(), fmt(self.head).bold(),
+            "   Indexed ".dim(), fmt(self.last_indexed).bold(),
+            "   Lag ".dim(), Span::styled(lag.0, lag.1),
+            "   Last block ".dim(), age.into(),
+            "   Cumulative ".dim(), format!("{} POL", scaled(&self.latest.cumulative_netflow_raw)).bold(),
+            " (block ".dim(), self.latest.block_number.to_string().into(), ")".dim(),
+        ]);
+        frame.render_widget(Paragraph::new(line).block(Block::bordered().title(" POL ↔ Binance ")), area);
+    }
+
+    fn draw_net(&self, frame: &mut Frame, area: Rect) {
+        let (lo, hi) = bounds(self.net.iter().map(|p| p.1).chain([0.0]));
+        let data = Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Bar)
+            .style(Style::new().cyan())
+            .data(&self.net);
+        let chart = Chart::new(vec![data])
+            .block(Block::bordered().title(format!(" Net flow per {} (POL, last {}) ", bucket_label(self.window.0), self.window.1)))
+            .x_axis(Axis::default().bounds([0.0, self.window.0 as f64]).labels([format!("-{}", self.window.1), "now".to_string()]))
+            .y_axis(Axis::default().bounds([lo, hi]).labels([compact(lo), compact(hi)]));
+        frame.render_widget(chart, area);
+    }
+
+    fn draw_cumulative(&self, frame: &mut Frame, area: Rect) {
+        let (lo, hi) = bounds(self.cumulative.iter().map(|p| p.1));
+        let data = Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::new().yellow())
+            .data(&self.cumulative);
+        let chart = Chart::new(vec![data])
+            .block(Block::bordered().title(format!(" Cumulative (POL, last {}) ", self.window.1)))
+            .x_axis(Axis::default().bounds([0.0, self.window.0 as f64]).labels([format!("-{}", self.window.1), "now".to_string()]))
+            .y_axis(Axis::default().bounds([lo, hi]).labels([compact(lo), compact(hi)]));
+        frame.render_widget(chart, area);
+    }
+
+    fn draw_transfers(&self, frame: &mut Frame, area: Rect) {
+        let rows = self.transfers.iter().map(|t| {
+            let (dir, color) = match t.direction {
+                Direction::In => ("IN", Color::Green),
+                Direction::Out => ("OUT", Color::Red),
+                Direction::Internal => ("INT", Color::Gray),
+                Direction::None => ("-", Color::Gray),
+            };
+            let mut row = Row::new(vec![
+                t.block_number.to_string(),
+                dir.to_string(),
+                scaled(&t.value),
+                short(&t.from),
+                short(&t.to),
+            ]).style(Style::new().fg(color));
+            if t.status == Some(TxStatus::Reverted) {
+                row = row.style(Style::new().dark_gray().crossed_out());
+            }
+            row
+        });
+        let widths = [
+            Constraint::Length(10),
+            Constraint::Length(4),
+            Constraint::Min(14),
+            Constraint::Length(13),
+            Constraint::Length(13),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["Block", "Dir", "POL", "From", "To"]).bold())
+            .block(Block::bordered().title(" Recent transfers "));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_feed(&self, frame: &mut Frame, area: Rect) {
+        let items = self.feed.iter().map(|(block, text)| ListItem::new(Line::from(vec![
+            format!("#{block} ").dim(),
+            text.clone().into(),
+        ])));
+        let title = if self.feed.is_empty() { " Alerts (none) " } else { " Alerts " };
+        frame.render_widget(List::new(items).block(Block::bordered().title(title)), area);
+    }
+}
+
+fn pol(raw: &str) -> f64 {
+    scaled(raw).parse().unwrap_or(0.0)
+}
+
+fn scaled(raw: &str) -> String {
+    ValueFormat::Scaled.render(raw)
+}
+
+/// `0x1234…abcd`
+fn short(hex: &str) -> String {
+    match (hex.get(..6), hex.get(hex.len().saturating_sub(4)..)) {
+        (Some(head), Some(tail)) if hex.len() > 12 => format!("{head}…{tail}"),
+        _ => hex.to_string(),
+    }
+}
+
+/// Y bounds with a little headroom; a flat series still gets a visible range.
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (lo, hi) = values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    if lo > hi {
+        return (0.0, 1.0);
+    }
+    let pad = ((hi - lo) * 0.05).max(1.0);
+    (lo - pad, hi + pad)
+}
+
+fn compact(v: f64) -> String {
+    match v.abs() {
+        a if a >= 1e9 => format!("{:.1}B", v / 1e9),
+        a if a >= 1e6 => format!("{:.1}M", v / 1e6),
+        a if a >= 1e3 => format!("{:.1}k", v / 1e3),
+        _ => format!("{v:.0}"),
+    }
+}
+
+fn bucket_label(window: i64) -> String {
+    match window / BUCKETS {
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
@@ -0,0 +1,166 @@
+//! Flow velocity: net-flow per hour over a sliding window, and how fast it
+//! is changing. Accelerating inflows to the exchange are a sell-pressure signal.
+//!
+//! The window ends at the newest indexed block's timestamp rather than the
+//! wall clock, so the figures stay meaningful while the indexer catches up.
+//! Acceleration compares the window with the one just before it.
+//!
+//! With `VELOCITY_VALUE_CAP`, transfers above the cap are left out of both
+//! windows, so one cold-wallet rebalancing doesn't dominate the figures for a
+//! whole window. The cumulative and every other total still count them.
+
+use eyre::Result;
+use ethers::types::{I256, U256};
+use rusqlite::Connection;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::{db, metrics, models::{self, NetflowBlock, ValueFormat}};
+
+const HOUR: i64 = 3_600;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Velocity {
+    pub window_secs: i64,
+    /// Window is `(to_ts - window_secs, to_ts]`
+    pub to_ts: i64,
+    /// Net inflow over the window, raw units, `-`-prefixed when negative
+    pub net: String,
+    /// `net` per hour
+    pub per_hour: String,
+    /// Change in `per_hour` versus the previous window, per hour
+    pub acceleration_per_hour: String,
+    /// `VELOCITY_VALUE_CAP`; `None` when every transfer counts
+    pub value_cap: Option<String>,
+    /// Transfers above `value_cap` left out of the current window
+    pub excluded_transfers: u64,
+}
+
+impl Velocity {
+    pub fn render(&mut self, format: ValueFormat) {
+        for v in [&mut self.net, &mut self.per_hour, &mut self.acceleration_per_hour] {
+            *v = format.render(v);
+        }
+        if let Some(cap) = &mut self.value_cap {
+            *cap = format.render(cap);
+        }
+    }
+}
+
+/// Transfers of `token` (lowercase) above `max`, raw units, are left out.
+#[derive(Debug, Clone)]
+pub struct ValueCap {
+    pub token: String,
+    pub max: U256,
+}
+
+pub fn compute(conn: &Connection, window_secs: i64, cap: Option<&ValueCap>) -> Result<Velocity> {
+    let to_ts = match db::latest_block_ts(conn)? {
+        Some(ts) => ts,
+        None => OffsetDateTime::now_utc().unix_timestamp(),
+    };
+    compute_at(conn, window_secs, cap, to_ts)
+}
+
+/// Velocity over the window ending at `to_ts` (`/netflow?as_of_block=`).
+pub fn compute_at(conn: &Connection, window_secs: i64, cap: Option<&ValueCap>, to_ts: i64) -> Result<Velocity> {
+    let mid_ts = to_ts.saturating_sub(window_secs);
+    let (current, excluded_transfers) = capped_net(conn, cap, mid_ts + 1, to_ts)?;
+    let (previous, _) = capped_net(conn, cap, mid_ts.saturating_sub(window_secs) + 1, mid_ts)?;
+
+    let window = I256::from(window_secs);
+    let hour = I256::from(HOUR);
+    let per_hour = current.saturating_mul(hour) / window;
+    // (current - previous) / window, scaled from per-second² to per-hour²
+    let acceleration = current.saturating_sub(previous).saturating_mul(hour).saturating_mul(hour) / window / window;
+    Ok(Velocity {
+        window_secs,
+        to_ts,
+        net: current.to_string(),
+        per_hour: per_hour.to_string(),
+        acceleration_per_hour: acceleration.to_string(),
+        value_cap: cap.map(|c| c.max.to_string()),
+        excluded_transfers,
+    })
+}
+
+/// Net-flow of the blocks timestamped `[from_ts, to_ts]` without the
+/// transfers above the cap, and how many those were.
+fn capped_net(conn: &Connection, cap: Option<&ValueCap>, from_ts: i64, to_ts: i64) -> Result<(I256, u64)> {
+    let total = net(&db::get_netflow_blocks_by_time(conn, from_ts, to_ts)?);
+    let Some(cap) = cap else { return Ok((total, 0)) };
+    let (inflow, outflow, count) = db::transfers_above(conn, &cap.token, cap.max, from_ts, to_ts)?;
+    Ok((total.saturating_sub(signed(inflow)).saturating_add(signed(outflow)), count))
+}
+
+/// Refresh the `netflow_velocity_*` gauges (whole POL).
+pub fn publish(v: &Velocity) {
+    metrics::NETFLOW_VELOCITY_PER_HOUR.set(whole_tokens(&v.per_hour));
+    metrics::NETFLOW_ACCELERATION_PER_HOUR.set(whole_tokens(&v.acceleration_per_hour));
+}
+
+fn net(rows: &[NetflowBlock]) -> I256 {
+    let (mut inflow, mut outflow) = (U256::zero(), U256::zero());
+    for r in rows {
+        inflow = inflow.saturating_add(U256::from_dec_str(&r.inflow).unwrap_or_default());
+        outflow = outflow.saturating_add(U256::from_dec_str(&r.outflow).unwrap_or_default());
+    }
+    signed(inflow).saturating_sub(signed(outflow))
+}
+
+fn signed(v: U256) -> I256 {
+    I256::try_from(v).unwrap_or(I256::MAX)
+}
+
+fn whole_tokens(raw: &str) -> f64 {
+    ValueFormat::Scaled.render(raw).parse().unwrap_or(0.0)
+}
+
+/// `VELOCITY_VALUE_CAP` value parser: raw units, decimal.
+pub fn parse_cap_arg(s: &str) -> Result<U256, String> {
+    U256::from_dec_str(s).map_err(|_| format!("invalid value cap {s:?}; expected raw units, e.g. 1000000000000000000000000"))
+}
+
+/// `VELOCITY_WINDOW` value parser.
+pub fn parse_window_arg(s: &str) -> Result<i64, String> {
+    models::parse_window(s).ok_or_else(|| format!("invalid window {s:?}; expected e.g. 90m, 1h, 1d"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Direction;
+
+    #[test]
+    fn capped_velocity_leaves_out_large_transfers_only() {
+        let scratch = db::Scratch::new("velocity");
+        let conn = db::init(scratch.path()).unwrap();
+        // A 900 cold-wallet move among ordinary inflows of 5 and an outflow of 2
+        let transfers = [(1_u64, 5_u64, Direction::In), (2, 900, Direction::In), (2, 2, Direction::Out), (3, 5, Direction::In)];
+        for (i, (block, value, direction)) in transfers.into_iter().enumerate() {
+            let hash = format!("0x{i:064x}");
+            db::insert_transfer(&conn, block, &hash, &hash, 0, "0xt", "0xa", "0xb", &value.to_string(), direction, None).unwrap();
+        }
+        for (block, inflow, outflow) in [(1_u64, "5", "0"), (2, "900", "2"), (3, "5", "0")] {
+            db::insert_netflow_block(&conn, &NetflowBlock {
+                block_number: block,
+                ts_unix: block as i64 * 60,
+                inflow: inflow.into(),
+                outflow: outflow.into(),
+                cumulative: "0".into(),
+                transfer_count: 1,
+                cumulative_token: "0".into(),
+                price_usd_micro: None,
+                cumulative_usd_micro: None,
+            }).unwrap();
+        }
+
+        let exact = compute_at(&conn, HOUR, None, 180).unwrap();
+        assert_eq!((exact.net.as_str(), exact.excluded_transfers), ("908", 0));
+        let cap = ValueCap { token: "0xt".into(), max: U256::from(100) };
+        let capped = compute_at(&conn, HOUR, Some(&cap), 180).unwrap();
+        assert_eq!((capped.net.as_str(), capped.excluded_transfers, capped.value_cap.as_deref()), ("8", 1, Some("100")));
+        let other = ValueCap { token: "0xother".into(), ..cap };
+        assert_eq!(compute_at(&conn, HOUR, Some(&other), 180).unwrap().net, "908", "only the configured token is capped");
+    }
+}